@@ -3,4 +3,4 @@
 
 mod provider;
 
-pub use provider::Provider;
+pub use provider::{AesGcmProvider, CipherSuite, Provider};
@@ -4,7 +4,7 @@
 use std::ops::Deref;
 
 use crypto::{
-    ciphers::{chacha::XChaCha20Poly1305, traits::Aead},
+    ciphers::{aes_gcm::Aes256Gcm, chacha::XChaCha20Poly1305, traits::Aead},
     utils::rand::fill,
 };
 
@@ -12,15 +12,78 @@ use engine::vault::{BoxProvider, Key};
 use serde::{Deserialize, Serialize};
 use zeroize::Zeroize;
 
+/// Length of the raw key of each cipher suite.
+const KEY_LEN: usize = 32;
+
+/// The cipher suite that encrypts the records of a vault, see [`crate::Stronghold::with_cipher_suite`].
+///
+/// The cipher suite is stored with the key of each vault, so vaults of different cipher suites can be mixed in
+/// one snapshot. Keys of [`CipherSuite::XChaCha20Poly1305`] are plain 32 byte keys as in earlier versions, keys
+/// of other cipher suites are prefixed with the identifier of the cipher suite.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CipherSuite {
+    /// XChaCha20-Poly1305.
+    #[default]
+    XChaCha20Poly1305,
+    /// AES-256-GCM, see [`AesGcmProvider`].
+    Aes256Gcm,
+}
+
+impl CipherSuite {
+    /// Identifier of the cipher suite in prefixed keys.
+    fn id(&self) -> u8 {
+        match self {
+            CipherSuite::XChaCha20Poly1305 => 0,
+            CipherSuite::Aes256Gcm => 1,
+        }
+    }
+
+    /// Splits a vault key into its cipher suite and the raw key.
+    fn of_key(key: &[u8]) -> Result<(Self, &[u8]), crypto::Error> {
+        match key {
+            raw if raw.len() == KEY_LEN => Ok((CipherSuite::XChaCha20Poly1305, raw)),
+            [1, raw @ ..] if raw.len() == KEY_LEN => Ok((CipherSuite::Aes256Gcm, raw)),
+            _ => Err(crypto::Error::InvalidArgumentError {
+                alg: "vault key",
+                expected: "key of a known cipher suite",
+            }),
+        }
+    }
+}
+
 /// An implementation of the Vault's [`BoxProvider`] type.  Used to encrypt and decrypt the data in this Stronghold.
+///
+/// The records of a vault are encrypted with the [`CipherSuite`] of the vault key, see [`Provider::random_key`].
 #[derive(Ord, PartialEq, Eq, PartialOrd, Clone, Debug, Serialize, Deserialize, Default, Zeroize)]
 
 pub struct Provider;
+
 impl Provider {
-    /// Nonce length.
-    const NONCE_LEN: usize = XChaCha20Poly1305::NONCE_LENGTH;
-    /// Tag Length.
-    const TAG_LEN: usize = XChaCha20Poly1305::TAG_LENGTH;
+    /// Creates a random vault key of `cipher_suite`.
+    pub fn random_key(cipher_suite: CipherSuite) -> Result<Key<Self>, crypto::Error> {
+        let mut key = match cipher_suite {
+            CipherSuite::XChaCha20Poly1305 => vec![0; KEY_LEN],
+            suite => {
+                let mut key = vec![0; KEY_LEN + 1];
+                key[0] = suite.id();
+                key
+            }
+        };
+        let offset = key.len() - KEY_LEN;
+        if let Err(e) = fill(&mut key[offset..]) {
+            key.zeroize();
+            return Err(e);
+        }
+        Key::load(key).ok_or(crypto::Error::InvalidArgumentError {
+            alg: "vault key",
+            expected: "key of a known cipher suite",
+        })
+    }
+
+    /// Returns the [`CipherSuite`] of a vault key.
+    pub fn cipher_suite(key: &Key<Self>) -> Result<CipherSuite, crypto::Error> {
+        CipherSuite::of_key(&key.key.borrow()).map(|(suite, _)| suite)
+    }
 }
 
 impl Unpin for Provider {}
@@ -30,46 +93,77 @@ impl BoxProvider for Provider {
 
     /// Key size.
     fn box_key_len() -> usize {
-        32
+        KEY_LEN
     }
 
-    /// Nonce length plus Tag length.
+    /// Nonce length plus Tag length of xchacha20-poly1305, which is the largest of all cipher suites.
     fn box_overhead() -> usize {
-        Self::NONCE_LEN + Self::TAG_LEN
+        XChaCha20Poly1305::NONCE_LENGTH + XChaCha20Poly1305::TAG_LENGTH
     }
 
-    /// Encrypts the data using the xchacha20-poly1305 algorithm.
-    fn box_seal(key: &Key<Self>, ad: &[u8], data: &[u8]) -> Result<Vec<u8>, Self::Error> {
-        let mut cipher = vec![0u8; data.len()];
+    /// Accepts plain keys and keys that are prefixed with their cipher suite.
+    fn is_valid_key_len(len: usize) -> bool {
+        len == KEY_LEN || len == KEY_LEN + 1
+    }
 
-        let mut tag = vec![0u8; 16];
-        let mut nonce: [u8; 24] = [0u8; Self::NONCE_LEN];
+    /// Encrypts the data using the algorithm of the cipher suite of the key.
+    fn box_seal(key: &Key<Self>, ad: &[u8], data: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        // Key should impl Deref
+        let key = key.key.borrow();
 
-        Self::random_buf(&mut nonce)?;
+        match CipherSuite::of_key(key.deref())? {
+            (CipherSuite::XChaCha20Poly1305, key) => seal::<XChaCha20Poly1305>(key, ad, data),
+            (CipherSuite::Aes256Gcm, key) => seal::<Aes256Gcm>(key, ad, data),
+        }
+    }
 
+    /// Decrypts the data using the algorithm of the cipher suite of the key.
+    fn box_open(key: &Key<Self>, ad: &[u8], data: &[u8]) -> Result<Vec<u8>, Self::Error> {
         // Key should impl Deref
         let key = key.key.borrow();
 
-        XChaCha20Poly1305::try_encrypt(key.deref(), &nonce, ad, data, &mut cipher, &mut tag)?;
-
-        let r#box = [tag.to_vec(), nonce.to_vec(), cipher].concat();
+        match CipherSuite::of_key(key.deref())? {
+            (CipherSuite::XChaCha20Poly1305, key) => open::<XChaCha20Poly1305>(key, ad, data),
+            (CipherSuite::Aes256Gcm, key) => open::<Aes256Gcm>(key, ad, data),
+        }
+    }
 
-        Ok(r#box)
+    /// fills a buffer with random bytes.
+    fn random_buf(buf: &mut [u8]) -> Result<(), Self::Error> {
+        fill(buf)
     }
+}
 
-    /// Decrypts the data using the xchacha20-poly1305 algorithm.
-    fn box_open(key: &Key<Self>, ad: &[u8], data: &[u8]) -> Result<Vec<u8>, Self::Error> {
-        let (tag, ct) = data.split_at(Self::TAG_LEN);
-        let (nonce, cipher) = ct.split_at(Self::NONCE_LEN);
+/// An implementation of the Vault's [`BoxProvider`] type that encrypts the data with AES-256-GCM.
+///
+/// A [`crate::Stronghold`] always uses [`Provider`], and encrypts new vaults with AES-256-GCM if
+/// [`CipherSuite::Aes256Gcm`] has been chosen.
+#[derive(Ord, PartialEq, Eq, PartialOrd, Clone, Debug, Serialize, Deserialize, Default, Zeroize)]
+pub struct AesGcmProvider;
 
-        let mut plain = vec![0; cipher.len()];
+impl Unpin for AesGcmProvider {}
 
-        // Key should impl Deref
-        let key = key.key.borrow();
+impl BoxProvider for AesGcmProvider {
+    type Error = crypto::Error;
+
+    /// Key size.
+    fn box_key_len() -> usize {
+        KEY_LEN
+    }
 
-        XChaCha20Poly1305::try_decrypt(key.deref(), nonce, ad, &mut plain, cipher, tag)?;
+    /// Nonce length plus Tag length.
+    fn box_overhead() -> usize {
+        Aes256Gcm::NONCE_LENGTH + Aes256Gcm::TAG_LENGTH
+    }
+
+    /// Encrypts the data using the aes-256-gcm algorithm.
+    fn box_seal(key: &Key<Self>, ad: &[u8], data: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        seal::<Aes256Gcm>(key.key.borrow().deref(), ad, data)
+    }
 
-        Ok(plain)
+    /// Decrypts the data using the aes-256-gcm algorithm.
+    fn box_open(key: &Key<Self>, ad: &[u8], data: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        open::<Aes256Gcm>(key.key.borrow().deref(), ad, data)
     }
 
     /// fills a buffer with random bytes.
@@ -77,3 +171,37 @@ impl BoxProvider for Provider {
         fill(buf)
     }
 }
+
+/// Encrypts the data with a random nonce, into a box of the tag, the nonce and the ciphertext.
+fn seal<A: Aead>(key: &[u8], ad: &[u8], data: &[u8]) -> Result<Vec<u8>, crypto::Error> {
+    let mut cipher = vec![0u8; data.len()];
+    let mut tag = vec![0u8; A::TAG_LENGTH];
+    let mut nonce = vec![0u8; A::NONCE_LENGTH];
+
+    fill(&mut nonce)?;
+
+    A::try_encrypt(key, &nonce, ad, data, &mut cipher, &mut tag)?;
+
+    let r#box = [tag, nonce, cipher].concat();
+
+    Ok(r#box)
+}
+
+/// Decrypts a box of [`seal`].
+fn open<A: Aead>(key: &[u8], ad: &[u8], data: &[u8]) -> Result<Vec<u8>, crypto::Error> {
+    if data.len() < A::TAG_LENGTH + A::NONCE_LENGTH {
+        return Err(crypto::Error::BufferSize {
+            name: "box",
+            needs: A::TAG_LENGTH + A::NONCE_LENGTH,
+            has: data.len(),
+        });
+    }
+    let (tag, ct) = data.split_at(A::TAG_LENGTH);
+    let (nonce, cipher) = ct.split_at(A::NONCE_LENGTH);
+
+    let mut plain = vec![0; cipher.len()];
+
+    A::try_decrypt(key, nonce, ad, &mut plain, cipher, tag)?;
+
+    Ok(plain)
+}
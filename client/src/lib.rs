@@ -9,7 +9,12 @@
 #![allow(unused_variables, unused_imports, dead_code)]
 
 #[cfg(feature = "std")]
-pub use crate::{internal::Provider, security::*, types::*, utils::*};
+pub use crate::{
+    internal::{AesGcmProvider, CipherSuite, Provider},
+    security::*,
+    types::*,
+    utils::*,
+};
 
 #[cfg(feature = "std")]
 pub use engine::runtime::MemoryError;
@@ -17,6 +17,7 @@ pub use primitives::{
     Slip10DeriveInput, Slip10Generate, StrongholdProcedure, WriteVault, X25519DiffieHellman,
 };
 pub use types::{
-    DeriveSecret, FatalProcedureError, GenerateSecret, Procedure, ProcedureError, ProcedureOutput, UseSecret,
+    DeriveSecret, FatalProcedureError, GenerateSecret, KeyOperation, KeyUsagePolicy, Procedure, ProcedureError,
+    ProcedureOutput, UseSecret,
 };
 pub(crate) use types::{Products, Runner};
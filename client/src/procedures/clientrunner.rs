@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::{
+    convert::Infallible,
     error::Error,
     sync::{Arc, RwLock},
+    time::SystemTime,
 };
 
 use engine::{
@@ -12,9 +14,10 @@ use engine::{
 };
 
 use crate::{
-    derive_vault_id,
+    check_vault_access, check_vault_reserved, derive_vault_id,
     procedures::{
-        FatalProcedureError, Procedure, ProcedureError, ProcedureOutput, Products, Runner, StrongholdProcedure,
+        FatalProcedureError, KeyOperation, Procedure, ProcedureError, ProcedureOutput, Products, Runner,
+        StrongholdProcedure,
     },
     Client, ClientError, ClientVault, KeyStore, Location, Provider, RecordError, Store, VaultError,
 };
@@ -26,11 +29,12 @@ type ResolvedLocation = (Key<Provider>, VaultId, RecordId);
 /// We use a macro instead of a function to avoid data races due to locks being
 /// dropped at the end of a function
 macro_rules! resolve_locations {
-    ($client:expr, $locations:expr, $keystore:expr) => {{
+    ($client:expr, $locations:expr, $keystore:expr, $db:expr) => {{
         let mut ids: Vec<(Key<Provider>, VaultId, RecordId)> = Vec::with_capacity(N);
 
         for location in ($locations) {
             let (vault_id, record_id) = location.resolve();
+            check_vault_access(&($keystore), &($db), vault_id)?;
             let key: Key<Provider> = ($keystore)
                 .get_key(vault_id)
                 .ok_or(VaultError::VaultNotFound(vault_id))?;
@@ -47,6 +51,7 @@ impl Runner for Client {
     fn get_guards<F, T, const N: usize>(
         &self,
         locations: [Location; N],
+        operation: KeyOperation,
         f: F,
     ) -> Result<T, VaultError<FatalProcedureError>>
     where
@@ -58,22 +63,45 @@ impl Runner for Client {
             Ok(())
         };
 
+        // the keys are only read below, so a due rotation of the master key is not triggered by the keystore.
+        if self
+            .keystore
+            .read()
+            .map_err(|_| VaultError::LockPoisoned)?
+            .is_rotation_due()
+        {
+            self.keystore
+                .write()
+                .map_err(|_| VaultError::LockPoisoned)?
+                .rotate_if_due();
+        }
+
         let keystore = self.keystore.read().map_err(|_| VaultError::LockPoisoned)?;
         let db = self.db.read().map_err(|_| VaultError::LockPoisoned)?;
-        let ids: [(Key<Provider>, VaultId, RecordId); N] = resolve_locations!(self, locations, keystore)?;
-
-        let res = db.get_guards(ids, execute_procedure);
+        let ids: [(Key<Provider>, VaultId, RecordId); N] = resolve_locations!(self, locations.clone(), keystore, db)?;
 
-        match res {
-            Ok(()) => Ok(ret.unwrap()),
-            Err(e) => Err(e),
+        let limited = operation != KeyOperation::PublicKey && db.check_records(&ids, operation.bit())?;
+        if !limited {
+            db.get_guards(ids, execute_procedure)?;
+            return Ok(ret.unwrap());
         }
+        drop(db);
+
+        // the write lock is only required to count the uses of records with a usage limit.
+        let mut db = self.db.write().map_err(|_| VaultError::LockPoisoned)?;
+        let ids: [(Key<Provider>, VaultId, RecordId); N] = resolve_locations!(self, locations, keystore, db)?;
+        db.check_records(&ids, operation.bit())?;
+        db.get_guards(ids.clone(), execute_procedure)?;
+        // a use is only counted if the procedure succeeded.
+        db.consume_uses(&ids)?;
+        Ok(ret.unwrap())
     }
 
     fn exec_proc<F, T, const N: usize>(
         &self,
         source_locations: [Location; N],
         target_location: &Location,
+        operation: KeyOperation,
         f: F,
     ) -> Result<T, VaultError<FatalProcedureError>>
     where
@@ -91,13 +119,21 @@ impl Runner for Client {
         let random_hint = RecordHint::new(rand::variable_bytestring(DEFAULT_RANDOM_HINT_SIZE)).unwrap();
 
         let mut keystore = self.keystore.write().map_err(|_| VaultError::LockPoisoned)?;
+        keystore.rotate_if_due();
         let mut db = self.db.write().map_err(|_| VaultError::LockPoisoned)?;
 
-        let sources: [(Key<Provider>, VaultId, RecordId); N] = resolve_locations!(self, source_locations, keystore)?;
+        let sources: [(Key<Provider>, VaultId, RecordId); N] =
+            resolve_locations!(self, source_locations, keystore, db)?;
+
+        if operation != KeyOperation::PublicKey {
+            db.check_records(&sources, operation.bit())?;
+        }
 
+        check_vault_access(&keystore, &db, target_vid)?;
         if !keystore.vault_exists(target_vid) {
-            let key1 = keystore
-                .create_key(target_vid)
+            let key1 = self
+                .new_vault_key()
+                .and_then(|key| keystore.get_or_insert_key(target_vid, key))
                 .map_err(|_| VaultError::Procedure("failed to generate key from keystore".to_string().into()))?;
             db.init_vault(&key1, target_vid);
         }
@@ -106,41 +142,23 @@ impl Runner for Client {
             .get_key(target_vid)
             .ok_or(VaultError::VaultNotFound(target_vid))?;
 
-        let res = db.exec_procedure(
-            sources,
+        db.exec_procedure(
+            sources.clone(),
             &target_key,
             target_vid,
             target_rid,
             random_hint,
             execute_procedure,
-        );
-
-        match res {
-            Ok(()) => Ok(ret.unwrap()),
-            Err(e) => Err(e),
+        )?;
+        // a use is only counted if the procedure succeeded.
+        if operation != KeyOperation::PublicKey {
+            db.consume_uses(&sources)?;
         }
+        Ok(ret.unwrap())
     }
 
     fn write_to_vault(&self, location: &Location, value: Vec<u8>) -> Result<(), RecordError> {
-        let (vault_id, record_id) = location.resolve();
-
-        let mut keystore = self.keystore.write().map_err(|_| RecordError::LockPoisoned)?;
-        let mut db = self.db.write().map_err(|_| RecordError::LockPoisoned)?;
-
-        if !keystore.vault_exists(vault_id) {
-            // The error type mapped to the possible key creation error is semantically incorrect
-            let key = keystore.create_key(vault_id).map_err(|_| RecordError::InvalidKey)?;
-            db.init_vault(&key, vault_id);
-        }
-        let random_hint = RecordHint::new(rand::variable_bytestring(DEFAULT_RANDOM_HINT_SIZE)).unwrap();
-        let key = keystore.take_key(vault_id).unwrap();
-        let res = db.write(&key, vault_id, record_id, &value, random_hint);
-
-        // this should return an error
-        keystore
-            .get_or_insert_key(vault_id, key)
-            .expect("Inserting key into vault failed");
-        res
+        self.write_record(location, value, None)
     }
 
     fn revoke_data(&self, location: &Location) -> Result<(), RecordError> {
@@ -149,6 +167,7 @@ impl Runner for Client {
         let mut keystore = self.keystore.write().map_err(|_| RecordError::LockPoisoned)?;
         let mut db = self.db.write().map_err(|_| RecordError::LockPoisoned)?;
 
+        check_vault_access(&keystore, &db, vault_id)?;
         if let Some(key) = keystore.take_key(vault_id) {
             let res = db.revoke_record(&key, vault_id, record_id);
 
@@ -165,6 +184,7 @@ impl Runner for Client {
         let mut keystore = self.keystore.write().map_err(|_| VaultError::LockPoisoned)?;
         let mut db = self.db.write().map_err(|_| VaultError::LockPoisoned)?;
 
+        check_vault_access(&keystore, &db, vault_id)?;
         let key = match keystore.take_key(vault_id) {
             Some(key) => key,
             None => return Ok(false),
@@ -178,6 +198,42 @@ impl Runner for Client {
 }
 
 impl Client {
+    /// Writes `value` into the record at `location`, and sets its expiry in the same step if `expires_at` is
+    /// given.
+    pub(crate) fn write_record(
+        &self,
+        location: &Location,
+        value: Vec<u8>,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), RecordError> {
+        let (vault_id, record_id) = location.resolve();
+
+        let mut keystore = self.keystore.write().map_err(|_| RecordError::LockPoisoned)?;
+        let mut db = self.db.write().map_err(|_| RecordError::LockPoisoned)?;
+
+        check_vault_access(&keystore, &db, vault_id)?;
+        if !keystore.vault_exists(vault_id) {
+            // The error type mapped to the possible key creation error is semantically incorrect
+            let key = self
+                .new_vault_key()
+                .and_then(|key| keystore.get_or_insert_key(vault_id, key))
+                .map_err(|_| RecordError::InvalidKey)?;
+            db.init_vault(&key, vault_id);
+        }
+        let random_hint = RecordHint::new(rand::variable_bytestring(DEFAULT_RANDOM_HINT_SIZE)).unwrap();
+        let key = keystore.take_key(vault_id).unwrap();
+        let res = match expires_at {
+            Some(expires_at) => db.write_with_expiry(&key, vault_id, record_id, &value, random_hint, expires_at),
+            None => db.write(&key, vault_id, record_id, &value, random_hint),
+        };
+
+        // this should return an error
+        keystore
+            .get_or_insert_key(vault_id, key)
+            .expect("Inserting key into vault failed");
+        res
+    }
+
     /// Applies `f` to the buffer from the given `location`.
     pub(crate) fn get_guard<F, T>(&self, location: &Location, f: F) -> Result<T, VaultError<FatalProcedureError>>
     where
@@ -188,6 +244,7 @@ impl Client {
         let mut keystore = self.keystore.write().map_err(|_| VaultError::LockPoisoned)?;
         let db = self.db.read().map_err(|_| VaultError::LockPoisoned)?;
 
+        check_vault_access(&keystore, &db, vault_id)?;
         let key = keystore.take_key(vault_id).ok_or(VaultError::VaultNotFound(vault_id))?;
 
         let mut ret = None;
@@ -208,4 +265,72 @@ impl Client {
             Err(e) => Err(e),
         }
     }
+
+    /// Removes the revocation of the record at `location`, if it has not been garbage collected yet.
+    pub(crate) fn restore_data(&self, location: &Location) -> Result<(), ClientError> {
+        let (vault_id, record_id) = location.resolve();
+        check_vault_reserved(vault_id)?;
+
+        let keystore = self.keystore.read()?;
+        let mut db = self.db.write()?;
+
+        let key = keystore
+            .get_key(vault_id)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+        db.restore_record(&key, vault_id, record_id)?;
+        Ok(())
+    }
+
+    /// Lists the [`RecordId`]s of all revoked records in the vault that have not been garbage collected yet.
+    pub(crate) fn list_revoked(&self, vault_id: VaultId) -> Result<Vec<RecordId>, ClientError> {
+        check_vault_reserved(vault_id)?;
+        let keystore = self.keystore.read()?;
+        let db = self.db.read()?;
+
+        let key = keystore
+            .get_key(vault_id)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+        let revoked = db.list_revoked_records(&key, vault_id)?;
+        Ok(revoked)
+    }
+
+    /// Garbage collects all vaults of the client, except for the vault of the protected vaults. If
+    /// `revoked_before` is `Some`, only records that have been revoked before that time are removed.
+    pub(crate) fn garbage_collect_all(&self, revoked_before: Option<SystemTime>) -> Result<(), RecordError> {
+        let keystore = self.keystore.read().map_err(|_| RecordError::LockPoisoned)?;
+        let mut db = self.db.write().map_err(|_| RecordError::LockPoisoned)?;
+
+        for vault_id in db.list_vaults() {
+            if check_vault_reserved(vault_id).is_err() {
+                continue;
+            }
+            let key = match keystore.get_key(vault_id) {
+                Some(key) => key,
+                None => continue,
+            };
+            match revoked_before {
+                Some(time) => db.garbage_collect_vault_revoked_before(&key, vault_id, time)?,
+                None => db.garbage_collect_vault(&key, vault_id),
+            }
+        }
+        Ok(())
+    }
+
+    /// Revokes and garbage collects the expired records in all vaults of the client, except for the vault of the
+    /// protected vaults.
+    pub(crate) fn garbage_collect_expired(&self) -> Result<(), RecordError> {
+        let keystore = self.keystore.read().map_err(|_| RecordError::LockPoisoned)?;
+        let mut db = self.db.write().map_err(|_| RecordError::LockPoisoned)?;
+
+        let now = SystemTime::now();
+        for vault_id in db.list_vaults() {
+            if check_vault_reserved(vault_id).is_err() {
+                continue;
+            }
+            if let Some(key) = keystore.get_key(vault_id) {
+                db.garbage_collect_vault_expired(&key, vault_id, now)?;
+            }
+        }
+        Ok(())
+    }
 }
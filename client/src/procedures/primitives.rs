@@ -502,6 +502,10 @@ impl DeriveSecret<1> for Slip10Derive {
     fn target(&self) -> &Location {
         &self.output
     }
+
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::Derive
+    }
 }
 
 fn x25519_secret_key(raw: Ref<u8>) -> Result<x25519::SecretKey, crypto::Error> {
@@ -584,6 +588,10 @@ impl UseSecret<1> for PublicKey {
     fn source(&self) -> [Location; 1] {
         [self.private_key.clone()]
     }
+
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::PublicKey
+    }
 }
 
 /// Use the specified Ed25519 compatible key to sign the given message
@@ -609,6 +617,10 @@ impl UseSecret<1> for Ed25519Sign {
     fn source(&self) -> [Location; 1] {
         [self.private_key.clone()]
     }
+
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::Sign
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -641,6 +653,10 @@ impl DeriveSecret<1> for X25519DiffieHellman {
     fn target(&self) -> &Location {
         &self.shared_key
     }
+
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::DiffieHellman
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -678,6 +694,10 @@ impl UseSecret<1> for Hmac {
     fn source(&self) -> [Location; 1] {
         [self.key.clone()]
     }
+
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::Sign
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -726,6 +746,10 @@ impl DeriveSecret<1> for Hkdf {
     fn target(&self) -> &Location {
         &self.okm
     }
+
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::Derive
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -918,6 +942,10 @@ impl DeriveSecret<1> for ConcatKdf {
     fn target(&self) -> &Location {
         &self.output
     }
+
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::Derive
+    }
 }
 
 impl ConcatKdf {
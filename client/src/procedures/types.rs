@@ -1,10 +1,10 @@
 // Copyright 2020-2021 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{FatalEngineError, Location, Provider, RecordError, VaultError};
+use crate::{ClientError, FatalEngineError, Location, Provider, RecordError, VaultError};
 use engine::{
     runtime::memories::buffer::Buffer,
-    vault::{BoxProvider, VaultId},
+    vault::{BoxProvider, RecordUsage, VaultId},
 };
 use serde::{Deserialize, Serialize};
 use std::{fmt::Debug, string::FromUtf8Error};
@@ -12,21 +12,25 @@ use thiserror::Error as DeriveError;
 
 /// Bridge to the engine that is required for using / writing / revoking secrets in the vault.
 pub trait Runner {
-    /// Applies `f` to the buffers from the given `locations`.
+    /// Applies `f` to the buffers from the given `locations`, after the usage policy of the records
+    /// has been enforced for `operation`.
     fn get_guards<F, T, const N: usize>(
         &self,
         locations: [Location; N],
+        operation: KeyOperation,
         f: F,
     ) -> Result<T, VaultError<FatalProcedureError>>
     where
         F: FnOnce([Buffer<u8>; N]) -> Result<T, FatalProcedureError>;
 
     // Execute a function that uses the secret stored at `source_locations`. From the returned `Products` the secret is
-    // written into `target_location` and the output is returned.
+    // written into `target_location` and the output is returned. The usage policy of the source records is enforced
+    // for `operation`.
     fn exec_proc<F, T, const N: usize>(
         &self,
         source_locations: [Location; N],
         target_location: &Location,
+        operation: KeyOperation,
         f: F,
     ) -> Result<T, VaultError<FatalProcedureError>>
     where
@@ -85,12 +89,18 @@ pub trait DeriveSecret<const N: usize>: Sized {
 
     fn target(&self) -> &Location;
 
+    /// The operation that is performed with the source secrets.
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::Other
+    }
+
     fn exec<R: Runner>(self, runner: &R) -> Result<Self::Output, ProcedureError> {
         let sources: [Location; N] = self.source();
         let target = self.target();
         let target = target.clone();
+        let operation = self.operation();
         let f = |guard| self.derive(guard);
-        let output = runner.exec_proc(sources, &target, f)?;
+        let output = runner.exec_proc(sources, &target, operation, f)?;
         Ok(output)
     }
 }
@@ -103,14 +113,99 @@ pub trait UseSecret<const N: usize>: Sized {
 
     fn source(&self) -> [Location; N];
 
+    /// The operation that is performed with the source secrets.
+    fn operation(&self) -> KeyOperation {
+        KeyOperation::Other
+    }
+
     fn exec<R: Runner>(self, runner: &R) -> Result<Self::Output, ProcedureError> {
         let source: [Location; N] = self.source();
+        let operation = self.operation();
         let f = |guard| self.use_secret(guard);
-        let output = runner.get_guards(source, f)?;
+        let output = runner.get_guards(source, operation, f)?;
         Ok(output)
     }
 }
 
+/// Operation that a procedure performs with an existing secret. The operations a record may be used
+/// for can be restricted with a [`KeyUsagePolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyOperation {
+    /// Creating a signature or message authentication code, e.g. with [`Ed25519Sign`][super::Ed25519Sign] or
+    /// [`Hmac`][super::Hmac].
+    Sign,
+    /// Deriving a new secret, e.g. with [`Slip10Derive`][super::Slip10Derive] or [`Hkdf`][super::Hkdf].
+    Derive,
+    /// Key agreement with [`X25519DiffieHellman`][super::X25519DiffieHellman].
+    DiffieHellman,
+    /// Deriving the public key of a private key. This is always allowed and does not count as a use.
+    PublicKey,
+    /// Any other operation, e.g. encryption or copying the secret. Allowing it for a record with a limited
+    /// number of uses permits to copy the secret into a record without limits.
+    Other,
+}
+
+impl KeyOperation {
+    /// Bit that represents the operation in the usage policy stored in the vault.
+    pub(crate) fn bit(self) -> u64 {
+        match self {
+            KeyOperation::Sign => 1,
+            KeyOperation::Derive => 1 << 1,
+            KeyOperation::DiffieHellman => 1 << 2,
+            KeyOperation::PublicKey => 1 << 3,
+            KeyOperation::Other => 1 << 4,
+        }
+    }
+}
+
+/// Bit that marks the operations of a record as restricted, so that an empty set of allowed
+/// operations can be distinguished from an unrestricted record.
+const RESTRICTED_OPERATIONS: u64 = 1 << 63;
+
+/// Restricts the usage of a record. The policy is enforced by the vault before a secret is handed
+/// to a procedure, and it is persisted in the snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyUsagePolicy {
+    /// Number of remaining uses of the record, `None` for unlimited usage.
+    pub remaining_uses: Option<u64>,
+
+    /// Operations that the record may be used for, `None` to allow all operations.
+    pub allowed_operations: Option<Vec<KeyOperation>>,
+}
+
+impl From<KeyUsagePolicy> for RecordUsage {
+    fn from(policy: KeyUsagePolicy) -> Self {
+        let allowed_operations = policy
+            .allowed_operations
+            .map(|ops| ops.iter().fold(RESTRICTED_OPERATIONS, |bits, op| bits | op.bit()))
+            .unwrap_or_default();
+        RecordUsage {
+            remaining_uses: policy.remaining_uses,
+            allowed_operations,
+        }
+    }
+}
+
+impl From<RecordUsage> for KeyUsagePolicy {
+    fn from(usage: RecordUsage) -> Self {
+        let all = [
+            KeyOperation::Sign,
+            KeyOperation::Derive,
+            KeyOperation::DiffieHellman,
+            KeyOperation::PublicKey,
+            KeyOperation::Other,
+        ];
+        let allowed_operations = match usage.allowed_operations {
+            0 => None,
+            bits => Some(all.into_iter().filter(|op| bits & op.bit() != 0).collect()),
+        };
+        KeyUsagePolicy {
+            remaining_uses: usage.remaining_uses,
+            allowed_operations,
+        }
+    }
+}
+
 /// Output of a [`StrongholdProcedure`][super::StrongholdProcedure].
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ProcedureOutput(Vec<u8>);
@@ -174,6 +269,10 @@ pub enum ProcedureError {
     /// Operation on the vault failed.
     #[error("procedure: {0}")]
     Procedure(#[from] FatalProcedureError),
+
+    /// The vault is protected with its own key and has not been unlocked, see [`crate::ClientVault::unlock`].
+    #[error("vault {0:?} is locked")]
+    VaultLocked(VaultId),
 }
 
 impl<T> From<VaultError<T>> for ProcedureError
@@ -183,14 +282,24 @@ where
     fn from(e: VaultError<T>) -> Self {
         match e {
             VaultError::Procedure(e) => ProcedureError::Procedure(e.into()),
+            VaultError::Record(RecordError::VaultLocked(vault_id)) => ProcedureError::VaultLocked(vault_id),
             other => ProcedureError::Engine(other.to_string().into()),
         }
     }
 }
 
+impl From<ClientError> for ProcedureError {
+    fn from(e: ClientError) -> Self {
+        ProcedureError::Engine(e.to_string().into())
+    }
+}
+
 impl From<RecordError> for ProcedureError {
     fn from(e: RecordError) -> Self {
-        ProcedureError::Engine(e.into())
+        match e {
+            RecordError::VaultLocked(vault_id) => ProcedureError::VaultLocked(vault_id),
+            e => ProcedureError::Engine(e.into()),
+        }
     }
 }
 
@@ -1,9 +1,14 @@
 // Copyright 2020-2022 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
+mod key_encryption;
 mod keyprovider;
 mod keystore;
+mod threshold;
 
 // re-export modules
+pub use engine::snapshot::{Argon2Params, Argon2Variant, KeyDerivation, KeySlot, KeySlotKind};
+pub use key_encryption::{FileKeyEncryptionProvider, InMemoryKeyEncryptionProvider, KeyEncryptionProvider};
 pub use keyprovider::KeyProvider;
 pub use keystore::KeyStore;
+pub use threshold::ThresholdUnlock;
@@ -0,0 +1,168 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crypto::{ciphers::aes_kw::Aes256Kw, utils::rand};
+use engine::runtime::memories::buffer::Buffer;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+use stronghold_utils::GuardDebug;
+use zeroize::Zeroize;
+
+use crate::ClientError;
+
+/// Size of the key encryption key of the providers in this module.
+const KEK_SIZE: usize = 32;
+
+/// Wraps and unwraps the key of a snapshot with a key encryption key that is held by an external system, e.g. a
+/// local KMS daemon or a TPM-backed agent.
+///
+/// A provider is added to a snapshot as [`crate::NewKeySlot::External`], and the snapshot is unlocked with the
+/// [`crate::KeyProvider`] returned by [`crate::KeyProvider::from_key_encryption_provider`]. Failures of the
+/// external system should be reported as [`ClientError::KeyEncryption`].
+pub trait KeyEncryptionProvider {
+    /// Identifies the provider and its key encryption key. The id is stored unencrypted in the key slot, and is
+    /// used to find the key slot of the provider.
+    fn id(&self) -> Vec<u8>;
+
+    /// Wraps `key`, and returns the wrapped key that is stored in the key slot.
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>, ClientError>;
+
+    /// Unwraps a key that has been wrapped with [`Self::wrap_key`]. The returned key is zeroed after use.
+    fn unwrap_key(&self, wrapped_key: &[u8]) -> Result<Vec<u8>, ClientError>;
+}
+
+/// In-process [`KeyEncryptionProvider`] with a random key encryption key in guarded memory. The key is lost when
+/// the provider is dropped, so it is mostly useful as mock in tests.
+#[derive(GuardDebug)]
+pub struct InMemoryKeyEncryptionProvider {
+    id: Vec<u8>,
+    kek: Buffer<u8>,
+}
+
+impl InMemoryKeyEncryptionProvider {
+    /// Creates a provider with the given `id` and a new random key encryption key.
+    pub fn new(id: Vec<u8>) -> Result<Self, ClientError> {
+        let mut kek = [0u8; KEK_SIZE];
+        rand::fill(&mut kek).map_err(|e| ClientError::KeyEncryption(e.to_string()))?;
+        Ok(Self {
+            id,
+            kek: Buffer::from(kek.as_mut_slice()),
+        })
+    }
+}
+
+impl KeyEncryptionProvider for InMemoryKeyEncryptionProvider {
+    fn id(&self) -> Vec<u8> {
+        self.id.clone()
+    }
+
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>, ClientError> {
+        wrap(self.kek.borrow().deref(), key)
+    }
+
+    fn unwrap_key(&self, wrapped_key: &[u8]) -> Result<Vec<u8>, ClientError> {
+        unwrap(self.kek.borrow().deref(), wrapped_key)
+    }
+}
+
+/// [`KeyEncryptionProvider`] that reads its key encryption key from a file with 32 raw bytes, for tests and as
+/// stand-in for a key that is provisioned by the infrastructure. The key is read on each use and is not kept in
+/// memory.
+///
+/// The id of the provider is chosen by the caller, so that the key slot is still found if the file is moved.
+#[derive(Debug, Clone)]
+pub struct FileKeyEncryptionProvider {
+    id: Vec<u8>,
+    path: PathBuf,
+}
+
+impl FileKeyEncryptionProvider {
+    /// Uses the key encryption key in the file at `path`, with the given `id`.
+    pub fn new<P: Into<PathBuf>>(id: Vec<u8>, path: P) -> Self {
+        Self { id, path: path.into() }
+    }
+
+    /// Writes a new random key encryption key to a new file at `path`, and uses it with the given `id`. On unix,
+    /// the file is only readable and writable by its owner.
+    ///
+    /// Fails with [`ClientError::KeyEncryption`] if the file already exists, so that an existing key encryption
+    /// key is not replaced.
+    pub fn generate<P: Into<PathBuf>>(id: Vec<u8>, path: P) -> Result<Self, ClientError> {
+        let provider = Self::new(id, path);
+        let mut kek = [0u8; KEK_SIZE];
+        rand::fill(&mut kek).map_err(|e| ClientError::KeyEncryption(e.to_string()))?;
+        let written = write_kek(&provider.path, &kek);
+        kek.zeroize();
+        written.map_err(|e| ClientError::KeyEncryption(e.to_string()))?;
+        Ok(provider)
+    }
+
+    fn with_kek<T, F>(&self, f: F) -> Result<T, ClientError>
+    where
+        F: FnOnce(&[u8]) -> Result<T, ClientError>,
+    {
+        let mut kek = fs::read(&self.path).map_err(|e| ClientError::KeyEncryption(e.to_string()))?;
+        let result = match kek.len() {
+            KEK_SIZE => f(&kek),
+            len => Err(ClientError::KeyEncryption(format!(
+                "key encryption key has {} bytes instead of {}",
+                len, KEK_SIZE
+            ))),
+        };
+        kek.zeroize();
+        result
+    }
+}
+
+impl KeyEncryptionProvider for FileKeyEncryptionProvider {
+    fn id(&self) -> Vec<u8> {
+        self.id.clone()
+    }
+
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>, ClientError> {
+        self.with_kek(|kek| wrap(kek, key))
+    }
+
+    fn unwrap_key(&self, wrapped_key: &[u8]) -> Result<Vec<u8>, ClientError> {
+        self.with_kek(|kek| unwrap(kek, wrapped_key))
+    }
+}
+
+/// Writes the key encryption key to a new file without access for other users. An existing file is never
+/// overwritten, because the key slots that have been wrapped with its key could not be opened anymore.
+fn write_kek(path: &Path, kek: &[u8]) -> Result<(), std::io::Error> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    file.write_all(kek)?;
+    file.sync_all()
+}
+
+fn wrap(kek: &[u8], key: &[u8]) -> Result<Vec<u8>, ClientError> {
+    let mut wrapped_key = vec![0; key.len() + Aes256Kw::BLOCK];
+    Aes256Kw::new(kek)
+        .wrap_key(key, &mut wrapped_key)
+        .map_err(|e| ClientError::KeyEncryption(e.to_string()))?;
+    Ok(wrapped_key)
+}
+
+fn unwrap(kek: &[u8], wrapped_key: &[u8]) -> Result<Vec<u8>, ClientError> {
+    let len = wrapped_key
+        .len()
+        .checked_sub(Aes256Kw::BLOCK)
+        .ok_or_else(|| ClientError::KeyEncryption("wrapped key is too short".into()))?;
+    let mut key = vec![0; len];
+    if let Err(e) = Aes256Kw::new(kek).unwrap_key(wrapped_key, &mut key) {
+        key.zeroize();
+        return Err(ClientError::KeyEncryption(e.to_string()));
+    }
+    Ok(key)
+}
@@ -1,30 +1,41 @@
 // Copyright 2020-2022 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
-use crypto::hashes::Digest;
+use crypto::hashes::{sha::Sha256, Digest};
 use engine::{
     runtime::{
         locked_memory::LockedMemory,
         memories::buffer::{Buffer, Ref},
         Bytes, MemoryError,
     },
+    snapshot::{read_header, Argon2Params, Argon2Variant, Header, KeyDerivation, KeySlotKind, ReadError},
     vault::NCKey,
 };
-use std::ops::Deref;
-use stronghold_utils::GuardDebug;
+use hkdf::Hkdf;
+use std::{ops::Deref, path::Path};
+use stronghold_utils::{random, GuardDebug};
 use zeroize::Zeroize;
 
-use crate::{internal::Provider, ClientError};
+use crate::{internal::Provider, ClientError, KeyEncryptionProvider, SnapshotError, SnapshotPath};
 
 /// This constant will be used to truncate a supplied passphrase
 const KEY_SIZE_HASHED: usize = 32;
 
+/// Size of the random salt for new snapshots.
+const SALT_SIZE: usize = 16;
+
+/// HKDF info of the pepper of a composite key.
+const COMPOSITE_KEY_INFO: &[u8] = b"stronghold composite key";
+
 /// The [`KeyProvider`] keeps secrets in [`NCKey`] at rest,
 /// such that no key can be directly read out from memory. The memory fragments
 /// of the key provider will be rotated continuously while not in use.
 #[derive(GuardDebug)]
 pub struct KeyProvider {
     inner: engine::vault::NCKey<Provider>,
+
+    // How the key has been derived from a passphrase, if it is known.
+    key_derivation: Option<KeyDerivation>,
 }
 
 impl TryFrom<Vec<u8>> for KeyProvider {
@@ -32,7 +43,10 @@ impl TryFrom<Vec<u8>> for KeyProvider {
 
     fn try_from(data: Vec<u8>) -> Result<Self, MemoryError> {
         match NCKey::load(data) {
-            Some(inner) => Ok(Self { inner }),
+            Some(inner) => Ok(Self {
+                inner,
+                key_derivation: None,
+            }),
             None => Err(MemoryError::NCSizeNotAllowed),
         }
     }
@@ -95,7 +109,7 @@ impl KeyProvider {
         key.zeroize();
         passphrase.zeroize();
 
-        result
+        result.map(|provider| provider.with_key_derivation(KeyDerivation::Truncated))
     }
 
     /// Creates a new [`KeyProvider`] from a passphrase, that will be hashed by a custom supplied hashing
@@ -213,6 +227,7 @@ impl KeyProvider {
         P: AsRef<[u8]> + Zeroize,
     {
         Self::with_passphrase_hashed(passphrase, crypto::hashes::blake2b::Blake2b256::new())
+            .map(|provider| provider.with_key_derivation(KeyDerivation::Blake2b))
     }
 
     /// Creates a new [``KeyProvider] from a passphrase, that will be hashed with `argon2`.
@@ -258,24 +273,345 @@ impl KeyProvider {
     ///
     /// assert_eq!(key, expected);
     /// ```
-    pub fn with_passphrase_hashed_argon2<P>(mut passphrase: P, mut salt: P) -> Result<Self, ClientError>
+    pub fn with_passphrase_hashed_argon2<P>(passphrase: P, mut salt: P) -> Result<Self, ClientError>
     where
         P: AsRef<[u8]> + Zeroize,
     {
         let config = argon2::Config::default();
+        let variant = match config.variant {
+            argon2::Variant::Argon2d => Argon2Variant::Argon2d,
+            argon2::Variant::Argon2i => Argon2Variant::Argon2i,
+            argon2::Variant::Argon2id => Argon2Variant::Argon2id,
+        };
+        let params = Argon2Params {
+            variant,
+            salt: salt.as_ref().to_vec(),
+            mem_cost: config.mem_cost,
+            time_cost: config.time_cost,
+            lanes: config.lanes,
+        };
+        salt.zeroize();
 
-        let key = argon2::hash_raw(passphrase.as_ref(), salt.as_ref(), &config)
-            .map_err(|e| ClientError::Inner(e.to_string()))?;
+        Self::with_passphrase_argon2_params(passphrase, params)
+    }
 
-        let result = Self::try_from(key.to_vec()).map_err(|e| ClientError::Inner(e.to_string()));
+    /// Creates a new [`KeyProvider`] from a passphrase, that will be hashed with `argon2` and the given
+    /// parameters. Parameters that would not be accepted in the header of a snapshot are rejected, see
+    /// [`Argon2Params::check_limits`].
+    pub fn with_passphrase_argon2_params<P>(mut passphrase: P, params: Argon2Params) -> Result<Self, ClientError>
+    where
+        P: AsRef<[u8]> + Zeroize,
+    {
+        if let Err(e) = params.check_limits() {
+            passphrase.zeroize();
+            return Err(ClientError::Inner(e));
+        }
+        let config = argon2::Config {
+            variant: match params.variant {
+                Argon2Variant::Argon2d => argon2::Variant::Argon2d,
+                Argon2Variant::Argon2i => argon2::Variant::Argon2i,
+                Argon2Variant::Argon2id => argon2::Variant::Argon2id,
+            },
+            mem_cost: params.mem_cost,
+            time_cost: params.time_cost,
+            lanes: params.lanes,
+            hash_length: KEY_SIZE_HASHED as u32,
+            ..Default::default()
+        };
+
+        let key = argon2::hash_raw(passphrase.as_ref(), &params.salt, &config);
         passphrase.zeroize();
-        salt.zeroize();
+        let key = key.map_err(|e| ClientError::Inner(e.to_string()))?;
+
+        Self::try_from(key)
+            .map_err(|e| ClientError::Inner(e.to_string()))
+            .map(|provider| provider.with_key_derivation(KeyDerivation::Argon2(params)))
+    }
+
+    /// Creates a new [`KeyProvider`] from a passphrase with the function described by `key_derivation`.
+    pub fn with_passphrase_key_derivation<P>(passphrase: P, key_derivation: KeyDerivation) -> Result<Self, ClientError>
+    where
+        P: AsRef<[u8]> + Zeroize,
+    {
+        match key_derivation {
+            KeyDerivation::Truncated => Self::with_passphrase_truncated(passphrase),
+            KeyDerivation::Blake2b => Self::with_passphrase_hashed_blake2b(passphrase),
+            KeyDerivation::Argon2(params) => Self::with_passphrase_argon2_params(passphrase, params),
+            KeyDerivation::Composite { .. } => Err(ClientError::MissingKeyFile),
+        }
+    }
+
+    /// Creates a new [`KeyProvider`] for a new snapshot from a passphrase, that will be hashed with `argon2id`
+    /// and a random salt. The salt and parameters are stored in the header of the snapshot once it is
+    /// committed with this [`KeyProvider`], so it can later be unlocked with
+    /// [`KeyProvider::from_password_for_snapshot`].
+    pub fn with_passphrase_for_new_snapshot<P>(passphrase: P) -> Result<Self, ClientError>
+    where
+        P: AsRef<[u8]> + Zeroize,
+    {
+        let params = Argon2Params {
+            variant: Argon2Variant::Argon2id,
+            salt: random::fixed_bytestring(SALT_SIZE),
+            mem_cost: 19 * 1024,
+            time_cost: 2,
+            lanes: 1,
+        };
+        Self::with_passphrase_argon2_params(passphrase, params)
+    }
+
+    /// Creates the [`KeyProvider`] for the snapshot at `snapshot_path` from a password, with the key derivation
+    /// that is described in the header of the snapshot.
+    ///
+    /// If the snapshot has key slots, the password is tried with the key derivation of each password and
+    /// recovery code slot, and fails with [`ClientError::WrongKey`] if it unlocks none of them.
+    ///
+    /// Fails with [`ClientError::MissingKeyDerivation`] if the snapshot does not describe how its key has been
+    /// derived, e.g. because it has been committed with a [`KeyProvider`] that was created from raw key bytes,
+    /// and with [`ClientError::MissingKeyFile`] if the key is a composite key, see
+    /// [`KeyProvider::from_composite_for_snapshot`].
+    pub fn from_password_for_snapshot<P>(snapshot_path: &SnapshotPath, mut password: P) -> Result<Self, ClientError>
+    where
+        P: AsRef<[u8]> + Zeroize,
+    {
+        let header = read_header(snapshot_path.as_path()).map_err(SnapshotError::from)?;
+        if header.key_slots.is_empty() {
+            let key_derivation = header.key_derivation.ok_or(ClientError::MissingKeyDerivation)?;
+            return Self::with_passphrase_key_derivation(password, key_derivation);
+        }
 
+        let result = Self::unlock_key_slots(&header, |key_derivation| match key_derivation {
+            KeyDerivation::Composite { .. } => None,
+            _ => Some(Self::with_passphrase_key_derivation(
+                password.as_ref().to_vec(),
+                key_derivation.clone(),
+            )),
+        });
+        password.zeroize();
         result
     }
+
+    /// Creates a new [`KeyProvider`] from a passphrase combined with the contents of the file at `key_file`,
+    /// and optionally a `pepper`, e.g. provided by the environment. Like KeePass composite keys, a stolen
+    /// snapshot and a weak passphrase are not sufficient without the separate key file.
+    ///
+    /// The passphrase and the key file are each hashed with SHA-256, and the concatenated hashes are hashed
+    /// with `argon2` and the given parameters. The pepper is mixed into the result with HKDF-SHA256.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use iota_stronghold::{Argon2Params, Argon2Variant, KeyProvider};
+    ///
+    /// let params = Argon2Params {
+    ///     variant: Argon2Variant::Argon2id,
+    ///     salt: b"saltyvalue".to_vec(),
+    ///     mem_cost: 19 * 1024,
+    ///     time_cost: 2,
+    ///     lanes: 1,
+    /// };
+    /// let pepper = std::env::var("STRONGHOLD_PEPPER").ok();
+    /// let keyprovider = KeyProvider::with_passphrase_and_key_file(
+    ///     b"passphrase".to_vec(),
+    ///     "/path/to/key/file".as_ref(),
+    ///     pepper.as_ref().map(|pepper| pepper.as_bytes()),
+    ///     params,
+    /// );
+    /// assert!(keyprovider.is_ok());
+    /// ```
+    pub fn with_passphrase_and_key_file<P>(
+        mut passphrase: P,
+        key_file: &Path,
+        pepper: Option<&[u8]>,
+        params: Argon2Params,
+    ) -> Result<Self, ClientError>
+    where
+        P: AsRef<[u8]> + Zeroize,
+    {
+        let mut composite = Sha256::digest(passphrase.as_ref()).to_vec();
+        passphrase.zeroize();
+        let mut key_file_content = std::fs::read(key_file).map_err(|e| ClientError::Inner(e.to_string()))?;
+        composite.extend_from_slice(&Sha256::digest(&key_file_content));
+        key_file_content.zeroize();
+
+        let mut keyprovider = Self::with_passphrase_argon2_params(composite, params.clone())?;
+        if let Some(pepper) = pepper {
+            let buffer = keyprovider
+                .try_unlock()
+                .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+            let mut key = vec![0u8; KEY_SIZE_HASHED];
+            Hkdf::<Sha256>::new(Some(pepper), &buffer.borrow())
+                .expand(COMPOSITE_KEY_INFO, &mut key)
+                .map_err(|e| ClientError::Inner(e.to_string()))?;
+            keyprovider = Self::try_from(key).map_err(|e| ClientError::Inner(e.to_string()))?;
+        }
+        Ok(keyprovider.with_key_derivation(KeyDerivation::Composite {
+            argon2: params,
+            pepper: pepper.is_some(),
+        }))
+    }
+
+    /// Creates a new composite [`KeyProvider`] (see [`KeyProvider::with_passphrase_and_key_file`]) for a new
+    /// snapshot, with `argon2id` and a random salt, like [`KeyProvider::with_passphrase_for_new_snapshot`].
+    pub fn with_passphrase_and_key_file_for_new_snapshot<P>(
+        passphrase: P,
+        key_file: &Path,
+        pepper: Option<&[u8]>,
+    ) -> Result<Self, ClientError>
+    where
+        P: AsRef<[u8]> + Zeroize,
+    {
+        let params = Argon2Params {
+            variant: Argon2Variant::Argon2id,
+            salt: random::fixed_bytestring(SALT_SIZE),
+            mem_cost: 19 * 1024,
+            time_cost: 2,
+            lanes: 1,
+        };
+        Self::with_passphrase_and_key_file(passphrase, key_file, pepper, params)
+    }
+
+    /// Creates the composite [`KeyProvider`] (see [`KeyProvider::with_passphrase_and_key_file`]) for the
+    /// snapshot at `snapshot_path`, with the parameters that are described in the header of the snapshot.
+    /// If the snapshot has key slots, the parameters of each composite password and recovery code slot are tried.
+    /// As for [`KeyProvider::from_password_for_snapshot`], a header with parameters outside the limits of
+    /// [`Argon2Params::check_limits`] is rejected before any key is derived.
+    ///
+    /// Fails with [`ClientError::MissingKeyDerivation`] if the snapshot key has not been derived from a
+    /// composite key, and with [`ClientError::PepperMismatch`] if it has been derived with a pepper but none
+    /// is given, or vice versa.
+    pub fn from_composite_for_snapshot<P>(
+        snapshot_path: &SnapshotPath,
+        mut passphrase: P,
+        key_file: &Path,
+        pepper: Option<&[u8]>,
+    ) -> Result<Self, ClientError>
+    where
+        P: AsRef<[u8]> + Zeroize,
+    {
+        let header = read_header(snapshot_path.as_path()).map_err(SnapshotError::from)?;
+        // whether the pepper of a composite key derivation has been used, if it does not match `pepper`
+        let pepper_mismatch = |key_derivation: &KeyDerivation| match key_derivation {
+            KeyDerivation::Composite {
+                pepper: with_pepper, ..
+            } if *with_pepper != pepper.is_some() => Some(*with_pepper),
+            _ => None,
+        };
+        let derive = |key_derivation: &KeyDerivation| match key_derivation {
+            KeyDerivation::Composite { argon2, .. } if pepper_mismatch(key_derivation).is_none() => Some(
+                Self::with_passphrase_and_key_file(passphrase.as_ref().to_vec(), key_file, pepper, argon2.clone()),
+            ),
+            _ => None,
+        };
+        let result = if header.key_slots.is_empty() {
+            header
+                .key_derivation
+                .as_ref()
+                .and_then(derive)
+                .unwrap_or(Err(ClientError::MissingKeyDerivation))
+        } else {
+            Self::unlock_key_slots(&header, derive)
+        };
+        passphrase.zeroize();
+
+        // no composite key derivation has been tried, because none of them matches the pepper
+        if let Err(ClientError::MissingKeyDerivation) = result {
+            let key_derivations = header
+                .key_derivation
+                .iter()
+                .chain(header.key_slots.iter().filter_map(|slot| match &slot.kind {
+                    KeySlotKind::Password(key_derivation) | KeySlotKind::RecoveryCode(key_derivation) => {
+                        Some(key_derivation)
+                    }
+                    _ => None,
+                }));
+            if let Some(with_pepper) = key_derivations.filter_map(pepper_mismatch).next() {
+                return Err(ClientError::PepperMismatch(with_pepper));
+            }
+        }
+        result
+    }
+
+    /// Creates the [`KeyProvider`] of the external key slot of `provider` in the snapshot at `snapshot_path`
+    /// (see [`crate::NewKeySlot::External`]), by unwrapping the key of the slot with `provider`.
+    ///
+    /// Fails with [`ClientError::KeySlot`] if the snapshot has no key slot with the id of `provider`.
+    pub fn from_key_encryption_provider<K>(snapshot_path: &SnapshotPath, provider: &K) -> Result<Self, ClientError>
+    where
+        K: KeyEncryptionProvider + ?Sized,
+    {
+        let header = read_header(snapshot_path.as_path()).map_err(SnapshotError::from)?;
+        let id = provider.id();
+        let wrapped_key = header
+            .key_slots
+            .iter()
+            .find_map(|slot| match &slot.kind {
+                KeySlotKind::External {
+                    id: slot_id,
+                    sealed_key,
+                } if *slot_id == id => Some(sealed_key),
+                _ => None,
+            })
+            .ok_or_else(|| ClientError::KeySlot("snapshot has no key slot for the key encryption provider".into()))?;
+        let mut key = provider.unwrap_key(wrapped_key)?;
+        // moves the key into guarded memory and zeroes it
+        let result = Self::from_buffer(&Buffer::from(key.as_mut_slice()));
+        key.zeroize();
+        result.map_err(|e| ClientError::Inner(e.to_string()))
+    }
+
+    /// Derives a [`KeyProvider`] with `derive` for the key derivation of each password and recovery code slot
+    /// of `header`, and returns the first one that unlocks its slot. Slots for which `derive` returns `None`
+    /// are skipped.
+    fn unlock_key_slots<F>(header: &Header, mut derive: F) -> Result<Self, ClientError>
+    where
+        F: FnMut(&KeyDerivation) -> Option<Result<Self, ClientError>>,
+    {
+        let mut result = Err(ClientError::MissingKeyDerivation);
+        for slot in header.key_slots.iter() {
+            let keyprovider = match &slot.kind {
+                KeySlotKind::Password(key_derivation) | KeySlotKind::RecoveryCode(key_derivation) => {
+                    match derive(key_derivation) {
+                        Some(keyprovider) => keyprovider?,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            let buffer = keyprovider
+                .try_unlock()
+                .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+            let key = buffer.borrow();
+            let key: &[u8; KEY_SIZE_HASHED] = key.deref().try_into().unwrap();
+            if slot.unwrap_key(key).is_ok() {
+                return Ok(keyprovider);
+            }
+            result = Err(SnapshotError::from(ReadError::NoMatchingKeySlot).into());
+        }
+        result
+    }
+
+    /// Creates a new [`KeyProvider`] from a key in guarded memory, without copying it into unguarded memory.
+    pub(crate) fn from_buffer(key: &Buffer<u8>) -> Result<Self, MemoryError> {
+        match NCKey::load_buffer(key) {
+            Some(inner) => Ok(Self {
+                inner,
+                key_derivation: None,
+            }),
+            None => Err(MemoryError::NCSizeNotAllowed),
+        }
+    }
+
+    fn with_key_derivation(mut self, key_derivation: KeyDerivation) -> Self {
+        self.key_derivation = Some(key_derivation);
+        self
+    }
 }
 
 impl KeyProvider {
+    /// Returns how the key has been derived from a passphrase, if it is known.
+    pub fn key_derivation(&self) -> Option<&KeyDerivation> {
+        self.key_derivation.as_ref()
+    }
+
     /// Tries to unlock the inner key and returns it.
     /// If unlocking fails, a [`MemoryError`] will be returned
     /// This operations ensures, that the unlocked key will be fragmented,
@@ -339,4 +675,32 @@ mod tests {
 
         assert_eq!(keydata, inner_key.to_vec());
     }
+
+    #[test]
+    fn test_keyprovider_key_derivation() {
+        let key = |keyprovider: &KeyProvider| keyprovider.try_unlock().unwrap().borrow().deref().to_vec();
+        let params = Argon2Params {
+            variant: Argon2Variant::Argon2id,
+            salt: b"saltyvalue".to_vec(),
+            mem_cost: 64,
+            time_cost: 1,
+            lanes: 1,
+        };
+
+        let keyprovider = KeyProvider::with_passphrase_argon2_params(b"passphrase".to_vec(), params.clone()).unwrap();
+        assert_eq!(keyprovider.key_derivation(), Some(&KeyDerivation::Argon2(params)));
+        let derived = KeyProvider::with_passphrase_key_derivation(
+            b"passphrase".to_vec(),
+            keyprovider.key_derivation().cloned().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(key(&derived), key(&keyprovider));
+
+        let keyprovider = KeyProvider::with_passphrase_hashed_blake2b(b"passphrase".to_vec()).unwrap();
+        assert_eq!(keyprovider.key_derivation(), Some(&KeyDerivation::Blake2b));
+        let keyprovider = KeyProvider::with_passphrase_truncated(b"passphrase".to_vec()).unwrap();
+        assert_eq!(keyprovider.key_derivation(), Some(&KeyDerivation::Truncated));
+        let keyprovider = KeyProvider::try_from(vec![6; 32]).unwrap();
+        assert!(keyprovider.key_derivation().is_none());
+    }
 }
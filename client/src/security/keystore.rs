@@ -2,24 +2,33 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::Provider;
-use engine::vault::{BoxProvider, Key, NCKey, VaultId};
+use engine::vault::{BoxProvider, DecryptError, Key, NCKey, VaultId};
 
 // use crate::vault::{
 //     crypto_box::{BoxProvider, Key, NCKey},
 //     VaultId,
 // };
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 /// The [`KeyStore`] keeps a map of [`VaultId`] -> [`Vec<u8>`] representing
 /// encrypted [`Key<P>`] using the `master_key`.
 /// `master_key` is stored in a non-contiguous data structure [`NCKey<P>`]
 /// for more security
+///
+/// The `master_key` can be rotated, which re-encrypts all keys with a new `master_key`.
+/// With a rotation interval, the `master_key` is rotated on the first modifying access
+/// after the interval has passed, see [`KeyStore::rotate_if_due`].
 pub struct KeyStore<P>
 where
     P: BoxProvider,
 {
     store: HashMap<VaultId, Vec<u8>>,
     master_key: NCKey<P>,
+    rotation_interval: Option<Duration>,
+    rotated_at: Instant,
 }
 
 impl<P> Default for KeyStore<P>
@@ -30,6 +39,8 @@ where
         Self {
             store: HashMap::new(),
             master_key: NCKey::<P>::random(),
+            rotation_interval: None,
+            rotated_at: Instant::now(),
         }
     }
 }
@@ -41,6 +52,7 @@ where
     /// Gets the encrypted key from the [`KeyStore`] and removes it.
     /// Decrypt it with the `master_key` and `vault_id` as salt.
     pub fn take_key(&mut self, id: VaultId) -> Option<Key<P>> {
+        self.rotate_if_due();
         let enc_key = self.store.remove(&id)?;
         self.master_key.decrypt_key(enc_key, id).ok()
     }
@@ -61,6 +73,7 @@ where
     /// Returns None if it fails
     /// Returns None if it fails
     pub fn create_key(&mut self, id: VaultId) -> Result<Key<P>, P::Error> {
+        self.rotate_if_due();
         let vault_key = Key::random();
         self.get_or_insert_key(id, vault_key)
     }
@@ -68,6 +81,7 @@ where
     /// Inserts a key into the [`KeyStore`] by [`VaultId`].
     /// If the [`VaultId`] already exists, it just returns the existing [`Key<P>`]
     pub fn get_or_insert_key(&mut self, id: VaultId, key: Key<P>) -> Result<Key<P>, P::Error> {
+        self.rotate_if_due();
         let vault_key = if let Some(key) = self.get_key(id) { key } else { key };
         let enc_key = self.master_key.encrypt_key(&vault_key, id)?;
         self.store.insert(id, enc_key);
@@ -76,6 +90,7 @@ where
 
     /// Inserts a key into the [`KeyStore`] by [`VaultId`] and overrides the old key.
     pub fn insert_key(&mut self, id: VaultId, key: Key<P>) -> Result<(), P::Error> {
+        self.rotate_if_due();
         let vault_key = key;
         let enc_key = self.master_key.encrypt_key(&vault_key, id)?;
         self.store.insert(id, enc_key);
@@ -86,7 +101,10 @@ where
     /// [`Vec<Key<P>>`] and returns then a [`Vec<VaultId>`]; primarily used to repopulate the state from a
     /// snapshot.
     pub fn rebuild_keystore(&mut self, keys: HashMap<VaultId, Key<P>>) -> Result<(), P::Error> {
-        let mut new_ks = KeyStore::default();
+        let mut new_ks = KeyStore {
+            rotation_interval: self.rotation_interval,
+            ..Default::default()
+        };
         for (id, key) in keys.into_iter() {
             new_ks.insert_key(id, key)?;
         }
@@ -114,4 +132,41 @@ where
     pub fn clear_keys(&mut self) {
         self.store.clear();
     }
+
+    /// Replaces the `master_key` with a new random key and re-encrypts all keys with it.
+    /// If re-encrypting any key fails, the [`KeyStore`] is left unchanged.
+    pub fn rotate_master_key(&mut self) -> Result<(), DecryptError<P::Error>> {
+        let master_key = NCKey::<P>::random();
+        let mut store = HashMap::with_capacity(self.store.len());
+        for (id, enc_key) in self.store.iter() {
+            let key = self.master_key.decrypt_key(enc_key.clone(), *id)?;
+            let enc_key = master_key.encrypt_key(&key, *id).map_err(DecryptError::Provider)?;
+            store.insert(*id, enc_key);
+        }
+        self.store = store;
+        self.master_key = master_key;
+        self.rotated_at = Instant::now();
+        Ok(())
+    }
+
+    /// Sets the interval after which the `master_key` is rotated. `None` disables the rotation.
+    pub fn set_rotation_interval(&mut self, interval: Option<Duration>) {
+        self.rotation_interval = interval;
+    }
+
+    /// Checks whether the rotation interval has passed since the last rotation of the `master_key`.
+    pub fn is_rotation_due(&self) -> bool {
+        matches!(self.rotation_interval, Some(interval) if self.rotated_at.elapsed() >= interval)
+    }
+
+    /// Rotates the `master_key` if the rotation interval has passed. A failed rotation is
+    /// retried on the next access.
+    ///
+    /// This is called by all modifying methods. Callers that only read keys with [`Self::get_key`] call it
+    /// explicitly, because rotating requires a mutable [`KeyStore`].
+    pub fn rotate_if_due(&mut self) {
+        if self.is_rotation_due() {
+            let _ = self.rotate_master_key();
+        }
+    }
 }
@@ -0,0 +1,115 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use engine::{
+    runtime::memories::buffer::Buffer,
+    snapshot::{read_header, shamir, KeySlot, KeySlotKind},
+};
+use std::ops::Deref;
+use stronghold_utils::GuardDebug;
+
+use crate::{ClientError, KeyProvider, SnapshotError, SnapshotPath};
+
+/// Size of the key that is split into the shares of a threshold key slot.
+const THRESHOLD_KEY_SIZE: usize = 32;
+
+/// Collects the shares of the holders of a threshold key slot (see [`crate::NewKeySlot::Threshold`]),
+/// until enough of them have been provided to reconstruct the key of the snapshot.
+///
+/// The decrypted shares and the reconstructed key are only kept in guarded memory.
+///
+/// # Example
+/// ```no_run
+/// use iota_stronghold::{KeyProvider, SnapshotPath, Stronghold, ThresholdUnlock};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let snapshot_path = SnapshotPath::from_path("treasury.stronghold");
+/// let mut unlock = ThresholdUnlock::for_snapshot(&snapshot_path)?;
+/// let first_officer = KeyProvider::with_passphrase_hashed_blake2b(b"first officer".to_vec())?;
+/// unlock.add_share(&first_officer)?;
+/// let second_officer = KeyProvider::with_passphrase_hashed_blake2b(b"second officer".to_vec())?;
+/// unlock.add_share(&second_officer)?;
+///
+/// let keyprovider = unlock.unlock()?;
+/// Stronghold::default().load_snapshot(&keyprovider, &snapshot_path)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(GuardDebug)]
+pub struct ThresholdUnlock {
+    slot: KeySlot,
+    threshold: usize,
+
+    // Decrypted shares by their index in the key slot.
+    shares: Vec<(usize, Buffer<u8>)>,
+}
+
+impl ThresholdUnlock {
+    /// Starts to unlock the first threshold key slot of the snapshot at `snapshot_path`.
+    pub fn for_snapshot(snapshot_path: &SnapshotPath) -> Result<Self, ClientError> {
+        let header = read_header(snapshot_path.as_path()).map_err(SnapshotError::from)?;
+        let slot = header
+            .key_slots
+            .into_iter()
+            .find(|slot| matches!(slot.kind, KeySlotKind::Threshold { .. }))
+            .ok_or_else(|| ClientError::KeySlot("snapshot has no threshold key slot".into()))?;
+        Ok(Self::new(slot))
+    }
+
+    /// Starts to unlock the given threshold key slot.
+    pub fn new(slot: KeySlot) -> Self {
+        let threshold = match &slot.kind {
+            KeySlotKind::Threshold { threshold, .. } => *threshold as usize,
+            _ => 0,
+        };
+        Self {
+            slot,
+            threshold,
+            shares: Vec::new(),
+        }
+    }
+
+    /// Decrypts the share of the holder of `keyprovider`, and returns how many shares are still missing.
+    ///
+    /// Fails with [`ClientError::KeySlot`] if the key does not belong to any holder, or if the share of
+    /// the holder has already been added.
+    pub fn add_share(&mut self, keyprovider: &KeyProvider) -> Result<usize, ClientError> {
+        let buffer = keyprovider
+            .try_unlock()
+            .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+        let key = buffer.borrow();
+        let key: &[u8; THRESHOLD_KEY_SIZE] = key.deref().try_into().unwrap();
+        let (index, share) = self
+            .slot
+            .decrypt_share(key)
+            .ok_or_else(|| ClientError::KeySlot("key does not belong to any holder".into()))?;
+        if self.shares.iter().any(|(i, _)| *i == index) {
+            return Err(ClientError::KeySlot("share has already been added".into()));
+        }
+        self.shares.push((index, share));
+        Ok(self.remaining())
+    }
+
+    /// Returns how many shares are still missing to reconstruct the key.
+    pub fn remaining(&self) -> usize {
+        self.threshold.saturating_sub(self.shares.len())
+    }
+
+    /// Reconstructs the key from the collected shares, and returns it as [`KeyProvider`] that unlocks the
+    /// snapshot.
+    pub fn unlock(&self) -> Result<KeyProvider, ClientError> {
+        if self.threshold == 0 || self.remaining() > 0 {
+            return Err(ClientError::KeySlot(format!(
+                "{} more shares are required",
+                self.remaining()
+            )));
+        }
+
+        let shares: Vec<_> = self.shares.iter().map(|(_, share)| share.borrow()).collect();
+        let shares: Vec<&[u8]> = shares.iter().map(|share| share.deref()).collect();
+        let mut key = Buffer::<u8>::zero(THRESHOLD_KEY_SIZE);
+        shamir::combine(&shares, &mut key.borrow_mut()).map_err(SnapshotError::from)?;
+
+        KeyProvider::from_buffer(&key).map_err(|e| ClientError::Inner(e.to_string()))
+    }
+}
@@ -1,26 +1,244 @@
 // Copyright 2020-2022 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
-use engine::vault::{view::Record, BlobId, ClientId, DbView, Key, RecordId, VaultId};
+use engine::vault::{view::Record, ClientId, DbView, Key, RecordId, RecordMetadata, VaultId, VaultVersion};
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap},
+    fmt,
     ops::{Deref, DerefMut},
-    sync::{RwLockReadGuard, RwLockWriteGuard},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLockReadGuard, RwLockWriteGuard,
+    },
 };
 
+mod protocol;
+
+pub use protocol::*;
+
 use crate::{
     derive_record_id, derive_vault_id, Client, ClientError, ClientState, KeyStore, LoadFromPath, Provider, RecordError,
     SnapshotError, SnapshotState, VaultError,
 };
 
 /// Policy for conflicts when merging two vaults.
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Default, Clone)]
 pub enum MergePolicy {
     /// Do not copy the record, instead keep the existing one.
     KeepOld,
     /// Replace the existing record.
     #[default]
     Replace,
+    /// Keep the record that has been modified last. The existing record is kept if both
+    /// have the same modification time.
+    KeepNewest,
+    /// Let a callback decide how to resolve each conflict.
+    Custom(Arc<dyn Fn(&MergeConflict) -> MergeDecision + Send + Sync>),
+}
+
+impl MergePolicy {
+    /// Create a [`MergePolicy::Custom`] that calls `f` for each conflicting record.
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&MergeConflict) -> MergeDecision + Send + Sync + 'static,
+    {
+        MergePolicy::Custom(Arc::new(f))
+    }
+
+    fn decide(&self, conflict: &MergeConflict) -> MergeDecision {
+        match self {
+            MergePolicy::KeepOld => MergeDecision::KeepOld,
+            MergePolicy::Replace => MergeDecision::Replace,
+            MergePolicy::KeepNewest => {
+                if conflict.incoming.modified_at > conflict.existing.modified_at {
+                    MergeDecision::Replace
+                } else {
+                    MergeDecision::KeepOld
+                }
+            }
+            MergePolicy::Custom(f) => f(conflict),
+        }
+    }
+}
+
+impl fmt::Debug for MergePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergePolicy::KeepOld => write!(f, "KeepOld"),
+            MergePolicy::Replace => write!(f, "Replace"),
+            MergePolicy::KeepNewest => write!(f, "KeepNewest"),
+            MergePolicy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl PartialEq for MergePolicy {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (MergePolicy::Custom(a), MergePolicy::Custom(b)) => Arc::ptr_eq(a, b),
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+impl Eq for MergePolicy {}
+
+/// A record that exists both at the source and the target of a sync, with different content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    /// Id of the vault at the target.
+    pub vault_id: VaultId,
+    /// Id of the record at the target.
+    pub record_id: RecordId,
+    /// Metadata of the record that already exists at the target.
+    pub existing: RecordMetadata,
+    /// Metadata of the record from the source.
+    pub incoming: RecordMetadata,
+}
+
+/// Resolution of a [`MergeConflict`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeDecision {
+    /// Do not copy the record, instead keep the existing one.
+    KeepOld,
+    /// Replace the existing record.
+    Replace,
+    /// Keep the existing record and copy the incoming record to the given record path
+    /// in the target vault. A record that already exists at that path is replaced.
+    KeepBoth(Vec<u8>),
+}
+
+/// Changes that a sync would apply to a single vault at the target.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VaultSyncReport {
+    /// Records that would be added to the target, by their [`RecordId`] at the target. This includes
+    /// copies of conflicting records that are kept under a new record path.
+    pub added: Vec<RecordId>,
+    /// Records that exist at the target and would be replaced.
+    pub replaced: Vec<RecordId>,
+    /// Records that exist at the target and would be kept unchanged, either because they are equal
+    /// at source and target or because the [`MergePolicy`] keeps the existing record.
+    pub skipped: Vec<RecordId>,
+    /// Records that exist at source and target with different content, independent of how the
+    /// [`MergePolicy`] resolves them.
+    pub conflicts: Vec<MergeConflict>,
+    /// Records that would be revoked at the target, because they have been revoked at the source.
+    /// Only set if the propagation of revocations is enabled in the config.
+    pub revoked: Vec<RecordId>,
+    /// Version of the vault at the source that the report is based on. It can be passed to
+    /// [`SyncClientsConfig::sync_changes_since`] to only sync the later changes the next time.
+    pub source_version: Option<VaultVersion>,
+    // Pairs of source and target record id for the records that would be copied.
+    pub(crate) copy: Vec<(RecordId, RecordId)>,
+}
+
+/// Report of the changes a client sync would apply, per vault id at the source.
+pub type ClientSyncReport = HashMap<VaultId, VaultSyncReport>;
+
+/// Sets the [`VaultSyncReport::source_version`] of each vault in the report.
+pub(crate) fn set_source_versions(report: &mut ClientSyncReport, versions: &ClientVersions) {
+    for (vid, vault_report) in report.iter_mut() {
+        vault_report.source_version = versions.get(vid).copied();
+    }
+}
+
+/// Selects the records of a [`ClientSyncReport`] that should be copied.
+pub(crate) fn select_copies(report: &ClientSyncReport) -> ClientHierarchy<(RecordId, RecordId)> {
+    report.iter().map(|(vid, r)| (*vid, r.copy.clone())).collect()
+}
+
+/// Report of the changes a snapshot merge would apply, per client id at the source.
+pub type SnapshotSyncReport = HashMap<ClientId, ClientSyncReport>;
+
+/// Progress of a sync, reported after each vault to the observer set with
+/// [`SyncClientsConfig::observe_progress`] or [`SyncSnapshotsConfig::observe_progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncProgress {
+    /// Id of the client at the source. `None` for a sync between two clients.
+    pub client_id: Option<ClientId>,
+    /// Id of the vault at the source that has been processed.
+    pub vault_id: VaultId,
+    /// Number of records that have been copied or revoked in this vault.
+    pub vault_records: usize,
+    /// Number of records that have been copied or revoked so far, in all vaults.
+    pub processed: usize,
+    /// Number of records that the sync copies or revokes, in all vaults.
+    pub total: usize,
+}
+
+/// Token to cancel a running sync, e.g. from another thread. The sync checks the token between vaults and
+/// rolls back the changes that it already applied.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Create a new token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the syncs that use this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Check if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancellationToken {}
+
+/// Progress observer and cancellation token of a sync.
+#[derive(Default, Clone, PartialEq, Eq)]
+pub(crate) struct SyncControl {
+    observer: Option<ProgressObserver>,
+    cancellation: Option<CancellationToken>,
+}
+
+#[derive(Clone)]
+struct ProgressObserver(Arc<dyn Fn(&SyncProgress) + Send + Sync>);
+
+impl PartialEq for ProgressObserver {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProgressObserver {}
+
+impl fmt::Debug for SyncControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncControl")
+            .field("observer", &self.observer.is_some())
+            .field("cancellation", &self.cancellation)
+            .finish()
+    }
+}
+
+impl SyncControl {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation.as_ref().map(|t| t.is_cancelled()).unwrap_or(false)
+    }
+
+    pub(crate) fn report(&self, progress: &SyncProgress) {
+        if let Some(observer) = self.observer.as_ref() {
+            (observer.0)(progress)
+        }
+    }
+}
+
+/// Number of records that a sync copies or revokes in a vault.
+pub(crate) fn count_changes(report: &VaultSyncReport) -> usize {
+    report.copy.len() + report.revoked.len()
 }
 
 /// Config for synching two clients.
@@ -29,7 +247,11 @@ pub struct SyncClientsConfig {
     pub(crate) select_vaults: Option<Vec<VaultId>>,
     pub(crate) select_records: HashMap<VaultId, Vec<RecordId>>,
     pub(crate) map_vaults: HashMap<VaultId, VaultId>,
+    pub(crate) map_records: HashMap<VaultId, HashMap<RecordId, RecordId>>,
     pub(crate) merge_policy: MergePolicy,
+    pub(crate) propagate_revocations: bool,
+    pub(crate) known_versions: HashMap<VaultId, VaultVersion>,
+    pub(crate) control: SyncControl,
 }
 
 impl SyncClientsConfig {
@@ -76,6 +298,99 @@ impl SyncClientsConfig {
             .map(|(path_a, path_b)| (derive_vault_id(path_a), derive_vault_id(path_b)));
         self.map_vaults.extend(map_vaults)
     }
+
+    /// Map records of the vault at `source_vault_path` to other record paths in the vault at
+    /// `target_vault_path`, with `map_record_paths` mapping the record path at the source to the record
+    /// path at the target. This also maps the vault paths like [`SyncClientsConfig::map_vaults`].
+    ///
+    /// Records that have no mapping keep their [`RecordId`] in the target vault.
+    pub fn map_records<V, R>(&mut self, source_vault_path: V, target_vault_path: V, map_record_paths: HashMap<R, R>)
+    where
+        V: AsRef<[u8]>,
+        R: AsRef<[u8]>,
+    {
+        let source_vid = derive_vault_id(&source_vault_path);
+        let target_vid = derive_vault_id(&target_vault_path);
+        let map_records = map_record_paths.into_iter().map(|(path_a, path_b)| {
+            (
+                derive_record_id(&source_vault_path, path_a),
+                derive_record_id(&target_vault_path, path_b),
+            )
+        });
+        self.map_vaults.insert(source_vid, target_vid);
+        self.map_records.entry(source_vid).or_default().extend(map_records);
+    }
+
+    /// Map the `record_paths` in the vault at `source_vault_path` that start with `source_prefix` to
+    /// the record path with the prefix replaced by `target_prefix` in the vault at `target_vault_path`.
+    /// Record paths that don't start with `source_prefix` are ignored.
+    ///
+    /// Records are only identified by the hash of their path, therefore the paths that should be
+    /// rewritten have to be listed. See [`SyncClientsConfig::map_records`].
+    pub fn map_record_prefix<V, R>(
+        &mut self,
+        source_vault_path: V,
+        target_vault_path: V,
+        source_prefix: &[u8],
+        target_prefix: &[u8],
+        record_paths: Vec<R>,
+    ) where
+        V: AsRef<[u8]>,
+        R: AsRef<[u8]>,
+    {
+        let map_record_paths: HashMap<Vec<u8>, Vec<u8>> = record_paths
+            .iter()
+            .filter_map(|path| {
+                let suffix = path.as_ref().strip_prefix(source_prefix)?;
+                Some((path.as_ref().to_vec(), [target_prefix, suffix].concat()))
+            })
+            .collect();
+        self.map_records(source_vault_path, target_vault_path, map_record_paths);
+    }
+
+    /// Propagate revocations from the source to the target. A record that has been revoked at the source
+    /// and not yet garbage collected is revoked at the target as well, unless the record at the target
+    /// has been modified after the revocation. Disabled by default.
+    pub fn propagate_revocations(&mut self, propagate: bool) {
+        self.propagate_revocations = propagate;
+    }
+
+    /// Only sync the records of a vault that have been added or updated at the source after `version`, e.g. the
+    /// [`VaultVersion`] returned by [`Client::vault_version`] on the source before the last sync. If `version`
+    /// is not known to the source vault, a full sync is performed for the vault.
+    ///
+    /// Note: This is referring to the path as it is on the source client, not to the mapped path.
+    pub fn sync_changes_since<P: AsRef<[u8]>>(&mut self, vault_path: P, version: VaultVersion) {
+        self.known_versions.insert(derive_vault_id(vault_path), version);
+    }
+
+    /// Call `f` with the [`SyncProgress`] after each vault that [`Client::sync_with`] has applied.
+    pub fn observe_progress<F>(&mut self, f: F)
+    where
+        F: Fn(&SyncProgress) + Send + Sync + 'static,
+    {
+        self.control.observer = Some(ProgressObserver(Arc::new(f)));
+    }
+
+    /// Cancel [`Client::sync_with`] once `token` is cancelled. Changes that have already been applied to
+    /// the target are rolled back, and the sync fails with [`ClientError::SyncCancelled`].
+    pub fn cancel_with(&mut self, token: CancellationToken) {
+        self.control.cancellation = Some(token);
+    }
+
+    /// Get the [`VaultId`] at the target for the vault `vid` at the source.
+    pub(crate) fn map_vault(&self, vid: VaultId) -> VaultId {
+        self.map_vaults.get(&vid).copied().unwrap_or(vid)
+    }
+
+    /// Get the [`RecordId`] at the target for the record `rid` in the vault `vid` at the source.
+    pub(crate) fn map_record(&self, vid: VaultId, rid: RecordId) -> RecordId {
+        self.map_records
+            .get(&vid)
+            .and_then(|m| m.get(&rid))
+            .copied()
+            .unwrap_or(rid)
+    }
 }
 
 pub(crate) enum KeyProvider<'a> {
@@ -85,6 +400,9 @@ pub(crate) enum KeyProvider<'a> {
 
 pub(crate) type ClientHierarchy<T> = HashMap<VaultId, Vec<T>>;
 
+/// Versions of the vaults of a client.
+pub(crate) type ClientVersions = HashMap<VaultId, VaultVersion>;
+
 pub(crate) trait SyncClients<'a> {
     type Db: Deref<Target = DbView<Provider>>;
 
@@ -94,99 +412,188 @@ pub(crate) trait SyncClients<'a> {
     fn get_hierarchy(
         &'a self,
         vaults: Option<Vec<VaultId>>,
-    ) -> Result<ClientHierarchy<(RecordId, BlobId)>, ClientError> {
+    ) -> Result<ClientHierarchy<(RecordId, RecordMetadata)>, ClientError> {
+        self.get_hierarchy_since(vaults, &HashMap::new())
+    }
+
+    /// Collect the records of the vaults. For vaults with a version in `since`, only the records that changed
+    /// after that version are collected.
+    fn get_hierarchy_since(
+        &'a self,
+        vaults: Option<Vec<VaultId>>,
+        since: &HashMap<VaultId, VaultVersion>,
+    ) -> Result<ClientHierarchy<(RecordId, RecordMetadata)>, ClientError> {
+        self.get_hierarchy_with_versions(vaults, since)
+            .map(|(hierarchy, _)| hierarchy)
+    }
+
+    /// Collect the records like [`SyncClients::get_hierarchy_since`], together with the current version of
+    /// each of the vaults.
+    fn get_hierarchy_with_versions(
+        &'a self,
+        vaults: Option<Vec<VaultId>>,
+        since: &HashMap<VaultId, VaultVersion>,
+    ) -> Result<(ClientHierarchy<(RecordId, RecordMetadata)>, ClientVersions), ClientError> {
         let key_provider = self.get_key_provider()?;
         let db = self.get_db()?;
         let vaults = vaults.unwrap_or_else(|| db.list_vaults());
         let mut hierarchy = HashMap::new();
+        let mut versions = HashMap::new();
         for vid in vaults {
-            let list = match &key_provider {
-                KeyProvider::KeyStore(ks) => {
-                    let key = match ks.get_key(vid) {
-                        Some(k) => k,
-                        None => continue,
-                    };
-                    db.list_records_with_blob_id(&key, vid)?
-                }
-                KeyProvider::KeyMap(map) => {
-                    let key = match map.get(&vid) {
-                        Some(k) => k,
-                        None => continue,
-                    };
-                    db.list_records_with_blob_id(key, vid)?
-                }
+            let key = match &key_provider {
+                KeyProvider::KeyStore(ks) => ks.get_key(vid),
+                KeyProvider::KeyMap(map) => map.get(&vid).cloned(),
+            };
+            let key = match key {
+                Some(k) => k,
+                None => continue,
+            };
+            let list = match since.get(&vid) {
+                Some(version) => db.list_records_changed_since(&key, vid, version)?,
+                None => db.list_records_with_metadata(&key, vid)?,
             };
             hierarchy.insert(vid, list);
+            if let Some(version) = db.vault_version(vid) {
+                versions.insert(vid, version);
+            }
         }
-        Ok(hierarchy)
+        Ok((hierarchy, versions))
     }
 
-    fn get_diff(
+    /// Collect the revoked records with the time of their revocation, if revocations should be propagated
+    /// according to `config`.
+    fn get_revocations(&'a self, config: &SyncClientsConfig) -> Result<ClientHierarchy<(RecordId, u64)>, ClientError> {
+        let mut revocations = HashMap::new();
+        if !config.propagate_revocations {
+            return Ok(revocations);
+        }
+        let key_provider = self.get_key_provider()?;
+        let db = self.get_db()?;
+        let vaults = config.select_vaults.clone().unwrap_or_else(|| db.list_vaults());
+        for vid in vaults {
+            let key = match &key_provider {
+                KeyProvider::KeyStore(ks) => ks.get_key(vid),
+                KeyProvider::KeyMap(map) => map.get(&vid).cloned(),
+            };
+            if let Some(key) = key {
+                revocations.insert(vid, db.list_revocations(&key, vid)?);
+            }
+        }
+        Ok(revocations)
+    }
+
+    /// Compare the records in `other` with the local ones and report the changes that a sync would apply.
+    fn get_sync_report(
         &'a self,
-        other: ClientHierarchy<(RecordId, BlobId)>,
+        mut other: ClientHierarchy<(RecordId, RecordMetadata)>,
+        mut revocations: ClientHierarchy<(RecordId, u64)>,
         config: &SyncClientsConfig,
-    ) -> Result<ClientHierarchy<RecordId>, ClientError> {
+    ) -> Result<ClientSyncReport, ClientError> {
         let key_provider = self.get_key_provider()?;
         let db = self.get_db()?;
-        let mut diff = HashMap::new();
-        for (vid, list) in other {
+        let mut report = HashMap::new();
+        let mut vaults: Vec<VaultId> = other.keys().copied().collect();
+        vaults.extend(revocations.keys().filter(|vid| !other.contains_key(vid)));
+        for vid in vaults {
+            let list = other.remove(&vid).unwrap_or_default();
+            let revoked = revocations.remove(&vid).unwrap_or_default();
             if let Some(select_vaults) = config.select_vaults.as_ref() {
                 if !select_vaults.contains(&vid) {
                     continue;
                 }
             }
-            let mapped_vid = config.map_vaults.get(&vid).copied().unwrap_or(vid);
+            let mapped_vid = config.map_vault(vid);
+            let select_records = config.select_records.get(&vid);
+            let is_selected = |rid: &RecordId| select_records.map(|s| s.contains(rid)).unwrap_or(true);
+            let mut vault_report = VaultSyncReport::default();
             if !db.contains_vault(&mapped_vid) {
-                let d = list.into_iter().map(|(rid, _)| rid).collect();
-                diff.insert(vid, d);
+                for (rid, _) in list.into_iter().filter(|(rid, _)| is_selected(rid)) {
+                    let mapped_rid = config.map_record(vid, rid);
+                    vault_report.added.push(mapped_rid);
+                    vault_report.copy.push((rid, mapped_rid));
+                }
+                report.insert(vid, vault_report);
                 continue;
             }
-            let select_records = config.select_records.get(&vid);
-            let mut record_diff = Vec::new();
-            for (rid, bid) in list {
-                if let Some(select_records) = select_records {
-                    if !select_records.contains(&rid) {
+            let target_key = match &key_provider {
+                KeyProvider::KeyStore(ks) => ks.get_key(mapped_vid),
+                KeyProvider::KeyMap(map) => map.get(&mapped_vid).cloned(),
+            };
+            for (rid, incoming) in list {
+                if !is_selected(&rid) {
+                    continue;
+                }
+                let mapped_rid = config.map_record(vid, rid);
+                let target_key = match target_key.as_ref() {
+                    Some(k) if db.contains_record(mapped_vid, mapped_rid) => k,
+                    _ => {
+                        vault_report.added.push(mapped_rid);
+                        vault_report.copy.push((rid, mapped_rid));
                         continue;
                     }
-                }
-                if !db.contains_record(mapped_vid, rid) {
-                    record_diff.push(rid);
+                };
+                let existing = db.get_record_metadata(target_key, mapped_vid, mapped_rid)?;
+                if existing.blob_id == incoming.blob_id {
+                    vault_report.skipped.push(mapped_rid);
                     continue;
                 }
-                if matches!(config.merge_policy, MergePolicy::KeepOld) {
-                    continue;
+                let conflict = MergeConflict {
+                    vault_id: mapped_vid,
+                    record_id: mapped_rid,
+                    existing,
+                    incoming,
+                };
+                match config.merge_policy.decide(&conflict) {
+                    MergeDecision::KeepOld => vault_report.skipped.push(mapped_rid),
+                    MergeDecision::Replace => {
+                        vault_report.replaced.push(mapped_rid);
+                        vault_report.copy.push((rid, mapped_rid));
+                    }
+                    MergeDecision::KeepBoth(path) => {
+                        let new_rid = RecordId::load_from_path(mapped_vid.as_ref(), &path);
+                        vault_report.skipped.push(mapped_rid);
+                        vault_report.added.push(new_rid);
+                        vault_report.copy.push((rid, new_rid));
+                    }
                 }
-                match &key_provider {
-                    KeyProvider::KeyStore(ks) => {
-                        if let Some(target_key) = ks.get_key(vid) {
-                            if db.get_blob_id(&target_key, mapped_vid, rid)? == bid {
-                                continue;
-                            }
-                        }
+                vault_report.conflicts.push(conflict);
+            }
+            if let (Some(target_key), true) = (target_key.as_ref(), config.propagate_revocations && !revoked.is_empty())
+            {
+                let already_revoked = db.list_revoked_records(target_key, mapped_vid)?;
+                for (rid, revoked_at) in revoked {
+                    if !is_selected(&rid) {
+                        continue;
                     }
-                    KeyProvider::KeyMap(map) => {
-                        if let Some(target_key) = map.get(&vid) {
-                            if db.get_blob_id(target_key, mapped_vid, rid)? == bid {
-                                continue;
-                            }
-                        }
+                    let mapped_rid = config.map_record(vid, rid);
+                    if !db.contains_record(mapped_vid, mapped_rid) || already_revoked.contains(&mapped_rid) {
+                        continue;
+                    }
+                    let existing = db.get_record_metadata(target_key, mapped_vid, mapped_rid)?;
+                    if existing.modified_at <= revoked_at {
+                        vault_report.revoked.push(mapped_rid);
                     }
                 }
-                record_diff.push(rid);
             }
-            diff.insert(vid, record_diff);
+            report.insert(vid, vault_report);
         }
-        Ok(diff)
+        Ok(report)
     }
 
+    /// Export the selected records, keyed by the [`RecordId`] that they should have at the target.
     fn export_entries(
         &'a self,
-        select: ClientHierarchy<RecordId>,
+        select: ClientHierarchy<(RecordId, RecordId)>,
     ) -> Result<ClientHierarchy<(RecordId, Record)>, ClientError> {
         let db = self.get_db()?;
         let mut export = HashMap::new();
         for (vid, select) in select {
-            let records = db.export_records(vid, select)?;
+            let mapping: HashMap<RecordId, RecordId> = select.into_iter().collect();
+            let records = db
+                .export_records(vid, mapping.keys().copied())?
+                .into_iter()
+                .map(|(rid, record)| (mapping[&rid], record))
+                .collect();
             export.insert(vid, records);
         }
         Ok(export)
@@ -195,6 +602,9 @@ pub(crate) trait SyncClients<'a> {
 
 pub(crate) type SnapshotHierarchy<T> = HashMap<ClientId, HashMap<VaultId, Vec<T>>>;
 
+/// Versions of the vaults of each client.
+pub(crate) type SnapshotVersions = HashMap<ClientId, ClientVersions>;
+
 /// Config for synching two snapshots.
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct SyncSnapshotsConfig {
@@ -202,6 +612,9 @@ pub struct SyncSnapshotsConfig {
     pub(crate) client_config: HashMap<ClientId, SyncClientsConfig>,
     pub(crate) map_clients: HashMap<ClientId, ClientId>,
     pub(crate) merge_policy: MergePolicy,
+    pub(crate) propagate_revocations: bool,
+    pub(crate) allow_unauthenticated_peers: bool,
+    pub(crate) control: SyncControl,
 }
 
 impl SyncSnapshotsConfig {
@@ -247,6 +660,63 @@ impl SyncSnapshotsConfig {
         });
         self.map_clients.extend(map_clients)
     }
+
+    /// Propagate revocations for all clients that don't have a specific config set with
+    /// [`SyncSnapshotsConfig::config_client_sync`]. See [`SyncClientsConfig::propagate_revocations`].
+    pub fn propagate_revocations(&mut self, propagate: bool) {
+        self.propagate_revocations = propagate;
+    }
+
+    /// Allow merging the state of a remote party that is not authenticated, with
+    /// [`Snapshot::import_from_serialized_state`](crate::Snapshot::import_from_serialized_state) or
+    /// [`Snapshot::sync_with_peer`](crate::Snapshot::sync_with_peer). Bundles of trusted peers are imported with
+    /// [`Snapshot::import_signed_bundle`](crate::Snapshot::import_signed_bundle) instead. Disabled by default.
+    pub fn allow_unauthenticated_peers(&mut self, allow: bool) {
+        self.allow_unauthenticated_peers = allow;
+    }
+
+    /// Call `f` with the [`SyncProgress`] after each vault that a snapshot merge has applied.
+    /// The observers in the configs of the single clients are not called.
+    pub fn observe_progress<F>(&mut self, f: F)
+    where
+        F: Fn(&SyncProgress) + Send + Sync + 'static,
+    {
+        self.control.observer = Some(ProgressObserver(Arc::new(f)));
+    }
+
+    /// Cancel a snapshot merge once `token` is cancelled. Changes that have already been applied to the
+    /// target are rolled back, and the merge fails with [`SnapshotError::SyncCancelled`].
+    /// The tokens in the configs of the single clients are not checked.
+    pub fn cancel_with(&mut self, token: CancellationToken) {
+        self.control.cancellation = Some(token);
+    }
+
+    /// Get the [`ClientId`] at the target for the client `cid` at the source.
+    pub(crate) fn map_client(&self, cid: ClientId) -> ClientId {
+        self.map_clients.get(&cid).copied().unwrap_or(cid)
+    }
+
+    /// Get the versions of the source vaults that have been set with [`SyncClientsConfig::sync_changes_since`]
+    /// in the configs of the clients.
+    pub(crate) fn known_versions(&self) -> SnapshotVersions {
+        self.client_config
+            .iter()
+            .filter(|(_, c)| !c.known_versions.is_empty())
+            .map(|(cid, c)| (*cid, c.known_versions.clone()))
+            .collect()
+    }
+
+    /// Get the config for syncing the client `cid`.
+    pub(crate) fn get_client_config(&self, cid: &ClientId) -> Cow<'_, SyncClientsConfig> {
+        match self.client_config.get(cid) {
+            Some(c) => Cow::Borrowed(c),
+            None => Cow::Owned(SyncClientsConfig {
+                merge_policy: self.merge_policy.clone(),
+                propagate_revocations: self.propagate_revocations,
+                ..Default::default()
+            }),
+        }
+    }
 }
 
 pub(crate) trait SyncSnapshots {
@@ -257,69 +727,101 @@ pub(crate) trait SyncSnapshots {
     fn update_state<F>(&mut self, cid: ClientId, f: F) -> Result<(), SnapshotError>
     where
         F: FnOnce(&mut ClientState) -> Result<(), SnapshotError>;
+    /// Set the state of a client, or remove the client if `state` is `None`.
+    fn replace_state(&mut self, cid: ClientId, state: Option<ClientState>) -> Result<(), SnapshotError>;
 
     fn get_hierarchy(
         &self,
         clients: Option<Vec<ClientId>>,
-    ) -> Result<SnapshotHierarchy<(RecordId, BlobId)>, SnapshotError> {
+    ) -> Result<SnapshotHierarchy<(RecordId, RecordMetadata)>, SnapshotError> {
+        self.get_hierarchy_since(clients, &HashMap::new())
+            .map(|(hierarchy, _)| hierarchy)
+    }
+
+    /// Collect the records of the clients, together with the current version of each vault. For vaults with a
+    /// version in `since`, only the records that changed after that version are collected.
+    fn get_hierarchy_since(
+        &self,
+        clients: Option<Vec<ClientId>>,
+        since: &SnapshotVersions,
+    ) -> Result<(SnapshotHierarchy<(RecordId, RecordMetadata)>, SnapshotVersions), SnapshotError> {
         let clients = clients.unwrap_or_else(|| self.clients());
+        let no_versions = HashMap::new();
         let mut hierarchy = HashMap::new();
+        let mut versions = HashMap::new();
         for cid in clients {
             let f = |state: Option<&ClientState>| -> Result<_, SnapshotError> {
                 let state = match state {
                     Some(s) => s,
                     None => return Ok(None),
                 };
-                let hierarchy = state.get_hierarchy(None)?;
+                let since = since.get(&cid).unwrap_or(&no_versions);
+                let hierarchy = state.get_hierarchy_with_versions(None, since)?;
                 Ok(Some(hierarchy))
             };
-            if let Some(h) = self.get_from_state(cid, f)? {
+            if let Some((h, v)) = self.get_from_state(cid, f)? {
                 hierarchy.insert(cid, h);
+                versions.insert(cid, v);
             }
         }
-        Ok(hierarchy)
+        Ok((hierarchy, versions))
     }
 
-    fn get_diff(
+    fn get_revocations(
         &self,
-        other: SnapshotHierarchy<(RecordId, BlobId)>,
         config: &SyncSnapshotsConfig,
-    ) -> Result<SnapshotHierarchy<RecordId>, SnapshotError> {
-        let mut diff = HashMap::new();
+    ) -> Result<SnapshotHierarchy<(RecordId, u64)>, SnapshotError> {
+        let clients = config.select_clients.clone().unwrap_or_else(|| self.clients());
+        let mut revocations = HashMap::new();
+        for cid in clients {
+            let f = |state: Option<&ClientState>| -> Result<_, SnapshotError> {
+                let state = match state {
+                    Some(s) => s,
+                    None => return Ok(None),
+                };
+                let revocations = state.get_revocations(&config.get_client_config(&cid))?;
+                Ok(Some(revocations))
+            };
+            if let Some(r) = self.get_from_state(cid, f)? {
+                revocations.insert(cid, r);
+            }
+        }
+        Ok(revocations)
+    }
+
+    fn get_sync_report(
+        &self,
+        other: SnapshotHierarchy<(RecordId, RecordMetadata)>,
+        mut revocations: SnapshotHierarchy<(RecordId, u64)>,
+        config: &SyncSnapshotsConfig,
+    ) -> Result<SnapshotSyncReport, SnapshotError> {
+        let mut report = HashMap::new();
         for (cid, hierarchy) in other {
+            let revocations = revocations.remove(&cid).unwrap_or_default();
             if let Some(select_clients) = config.select_clients.as_ref() {
                 if !select_clients.contains(&cid) {
                     continue;
                 }
             }
-            let mapped_cid = config.map_clients.get(&cid).copied().unwrap_or(cid);
+            let mapped_cid = config.map_client(cid);
             let f = |state: Option<&ClientState>| -> Result<_, SnapshotError> {
-                let state = match state {
-                    Some(s) => s,
-                    None => return Ok(None),
-                };
-                let client_diff = match config.client_config.get(&cid) {
-                    Some(c) => state.get_diff(hierarchy, c)?,
-                    None => {
-                        let config = SyncClientsConfig {
-                            merge_policy: config.merge_policy,
-                            ..Default::default()
-                        };
-                        state.get_diff(hierarchy, &config)?
-                    }
+                let client_config = config.get_client_config(&cid);
+                let client_report = match state {
+                    Some(s) => s.get_sync_report(hierarchy, revocations, &client_config)?,
+                    // The client does not exist at the target, so all records are added.
+                    None => ClientState::default().get_sync_report(hierarchy, revocations, &client_config)?,
                 };
-                Ok(Some(client_diff))
+                Ok(client_report)
             };
-            if let Some(client_diff) = self.get_from_state(cid, f)? {
-                diff.insert(cid, client_diff);
-            }
+            let client_report = self.get_from_state(mapped_cid, f)?;
+            report.insert(cid, client_report);
         }
-        Ok(diff)
+        Ok(report)
     }
 
     fn export_entries(
         &self,
-        select: SnapshotHierarchy<RecordId>,
+        select: SnapshotHierarchy<(RecordId, RecordId)>,
     ) -> Result<SnapshotHierarchy<(RecordId, Record)>, SnapshotError> {
         let mut export = HashMap::new();
         for (cid, select) in select {
@@ -353,37 +855,138 @@ pub(crate) trait SyncSnapshots {
             let old_keystore = old_keys
                 .get(&cid)
                 .ok_or_else(|| SnapshotError::Inner(format!("Missing KeyStore for client {:?}", cid)))?;
-            let mapped_cid = config.map_clients.get(&cid).copied().unwrap_or(cid);
-            let import_records = |state: &mut ClientState, config: &SyncClientsConfig| {
-                for (vid, mut records) in records {
-                    if let Some(select_vaults) = config.select_vaults.as_ref() {
+            let mapped_cid = config.map_client(cid);
+            let client_config = config.get_client_config(&cid);
+            self.update_state(mapped_cid, |state| {
+                for (vid, records) in records {
+                    if let Some(select_vaults) = client_config.select_vaults.as_ref() {
                         if !select_vaults.contains(&vid) {
                             continue;
                         }
                     }
-                    if let Some(select_records) = config.select_records.get(&vid) {
-                        records.retain(|(rid, _)| select_records.contains(rid));
-                    }
-                    let mapped_vid = config.map_vaults.get(&vid).copied().unwrap_or(vid);
-                    state.0.entry(vid).or_insert_with(Key::random);
+                    let mapped_vid = client_config.map_vault(vid);
                     let old_key = old_keystore
                         .get(&vid)
                         .ok_or_else(|| SnapshotError::Inner(format!("Missing Key for vault {:?}", vid)))?;
-                    let new_key = state.0.get(&mapped_vid).expect("Key was inserted.");
-                    state.1.import_records(old_key, new_key, vid, records)?;
+                    // a new vault keeps the cipher suite of the source vault
+                    let new_key = match state.0.entry(mapped_vid) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => entry.insert(Provider::random_key(Provider::cipher_suite(old_key)?)?),
+                    };
+                    state.1.import_records(old_key, new_key, mapped_vid, records)?;
                 }
                 Ok(())
-            };
-            let f = match config.client_config.get(&cid) {
-                Some(c) => self.update_state(cid, |state| import_records(state, c)),
-                None => {
-                    let config = SyncClientsConfig {
-                        merge_policy: config.merge_policy,
-                        ..Default::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Merge `state` into this state: import the records selected by the sync report and revoke
+    /// the records that have been revoked at the source. The changes are applied vault by vault. If the
+    /// merge fails or is cancelled, the clients that have already been changed are restored.
+    fn merge_from(&mut self, mut state: SnapshotState, config: &SyncSnapshotsConfig) -> Result<(), SnapshotError> {
+        let (hierarchy, _) = state.get_hierarchy_since(config.select_clients.clone(), &config.known_versions())?;
+        let revocations = state.get_revocations(config)?;
+        let report = self.get_sync_report(hierarchy, revocations, config)?;
+        let diff = report.iter().map(|(cid, r)| (*cid, select_copies(r))).collect();
+        let mut exported = state.export_entries(diff)?;
+        let mut old_keys = HashMap::new();
+        for cid in exported.keys() {
+            let ks = state
+                .0
+                .remove(cid)
+                .ok_or_else(|| SnapshotError::Inner(format!("Missing KeyStore for client {:?}", cid)))?
+                .0;
+            old_keys.insert(*cid, ks);
+        }
+
+        let mut backups = HashMap::new();
+        let result = self.apply_merge(report, &mut exported, &old_keys, config, &mut backups);
+        if result.is_err() {
+            for (cid, backup) in backups {
+                self.replace_state(cid, backup)?;
+            }
+        }
+        result
+    }
+
+    /// Apply the changes of a merge report vault by vault. Before a client at the target is changed for the
+    /// first time, a copy of its state is added to `backups`.
+    fn apply_merge(
+        &mut self,
+        report: SnapshotSyncReport,
+        exported: &mut SnapshotHierarchy<(RecordId, Record)>,
+        old_keys: &HashMap<ClientId, HashMap<VaultId, Key<Provider>>>,
+        config: &SyncSnapshotsConfig,
+        backups: &mut HashMap<ClientId, Option<ClientState>>,
+    ) -> Result<(), SnapshotError> {
+        let total = report.values().flat_map(|r| r.values()).map(count_changes).sum();
+        let mut processed = 0;
+        for (cid, client_report) in report {
+            let mapped_cid = config.map_client(cid);
+            for (vid, vault_report) in client_report {
+                let vault_records = count_changes(&vault_report);
+                if vault_records == 0 {
+                    continue;
+                }
+                if config.control.is_cancelled() {
+                    return Err(SnapshotError::SyncCancelled);
+                }
+                if let Entry::Vacant(entry) = backups.entry(mapped_cid) {
+                    let backup = if self.clients().contains(&mapped_cid) {
+                        self.get_from_state(mapped_cid, |state| Ok(state.cloned()))?
+                    } else {
+                        None
                     };
-                    self.update_state(cid, |state| import_records(state, &config))
+                    entry.insert(backup);
                 }
-            };
+
+                if let Some(records) = exported.get_mut(&cid).and_then(|e| e.remove(&vid)) {
+                    self.import_records([(cid, [(vid, records)].into())].into(), old_keys, config)?;
+                }
+                let vault_report = [(cid, [(vid, vault_report)].into())].into();
+                self.revoke_records(&vault_report, config)?;
+
+                processed += vault_records;
+                config.control.report(&SyncProgress {
+                    client_id: Some(cid),
+                    vault_id: vid,
+                    vault_records,
+                    processed,
+                    total,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Revoke the records that have been reported as revoked at the source.
+    fn revoke_records(
+        &mut self,
+        report: &SnapshotSyncReport,
+        config: &SyncSnapshotsConfig,
+    ) -> Result<(), SnapshotError> {
+        for (cid, client_report) in report {
+            if client_report.values().all(|r| r.revoked.is_empty()) {
+                continue;
+            }
+            let client_config = config.get_client_config(cid);
+            self.update_state(config.map_client(*cid), |state| {
+                for (vid, vault_report) in client_report {
+                    if vault_report.revoked.is_empty() {
+                        continue;
+                    }
+                    let mapped_vid = client_config.map_vault(*vid);
+                    let key = state
+                        .0
+                        .get(&mapped_vid)
+                        .ok_or_else(|| SnapshotError::Inner(format!("Missing Key for vault {:?}", mapped_vid)))?;
+                    for rid in &vault_report.revoked {
+                        state.1.revoke_record(key, mapped_vid, *rid)?;
+                    }
+                }
+                Ok(())
+            })?;
         }
         Ok(())
     }
@@ -598,11 +1201,11 @@ mod test {
             .get_hierarchy(None)?
             .remove(&vault_path_to_id("vault_2"))
             .expect("Vault does not exist.");
-        source_vault_2_hierarchy.sort();
+        source_vault_2_hierarchy.sort_by_key(|(rid, _)| *rid);
         let source_v2_r2_bid = source_vault_2_hierarchy
             .iter()
             .find(|(rid, _)| rid == &r_ctr_to_id("vault_2", 22))
-            .map(|(_, bid)| *bid)
+            .map(|(_, meta)| meta.blob_id)
             .expect("Record does not exist.");
 
         let set_up_target = || -> Result<Client, VaultError<Infallible>> {
@@ -617,7 +1220,7 @@ mod test {
             Ok(target)
         };
 
-        let assert_for_distinct_vaults = |hierarchy: &mut HashMap<VaultId, Vec<(RecordId, BlobId)>>| {
+        let assert_for_distinct_vaults = |hierarchy: &mut HashMap<VaultId, Vec<(RecordId, RecordMetadata)>>| {
             // Imported full vault-1;
             assert_eq!(hierarchy.keys().len(), 3);
             let v_1_entries = hierarchy
@@ -643,7 +1246,7 @@ mod test {
             .get_hierarchy(None)?
             .remove(&vault_path_to_id("vault_2"))
             .and_then(|vec| vec.into_iter().find(|(rid, _)| rid == &r_ctr_to_id("vault_2", 22)))
-            .map(|(_, bid)| bid)
+            .map(|(_, meta)| meta.blob_id)
             .expect("Record does not exist.");
         let config = SyncClientsConfig {
             merge_policy: MergePolicy::KeepOld,
@@ -664,7 +1267,7 @@ mod test {
         let v2_r2_bid = v_2_entries
             .into_iter()
             .find(|(rid, _)| rid == &r_ctr_to_id("vault_2", 22))
-            .map(|(_, bid)| bid)
+            .map(|(_, meta)| meta.blob_id)
             .expect("Record does not exist.");
         assert_eq!(v2_r2_bid, old_v2_r2_bid);
 
@@ -690,10 +1293,497 @@ mod test {
         let v2_r2_bid = v_2_entries
             .into_iter()
             .find(|(rid, _)| rid == &r_ctr_to_id("vault_2", 22))
-            .map(|(_, bid)| bid)
+            .map(|(_, meta)| meta.blob_id)
             .expect("Record does not exist.");
         assert_eq!(v2_r2_bid, source_v2_r2_bid);
 
         Ok(())
     }
+
+    #[test]
+    fn test_merge_policy_keep_newest() -> Result<(), Box<dyn std::error::Error>> {
+        let location = Location::counter("vault", 0usize);
+        let (vid, rid) = location.resolve();
+        let get_blob_id = |client: &Client| -> Result<_, ClientError> {
+            let meta = client
+                .get_hierarchy(None)?
+                .remove(&vid)
+                .and_then(|vec| vec.into_iter().find(|(r, _)| r == &rid))
+                .map(|(_, meta)| meta)
+                .expect("Record does not exist.");
+            Ok(meta.blob_id)
+        };
+
+        let old_1 = Client::default();
+        old_1.write_to_vault(&location, test_value())?;
+        let old_2 = Client::default();
+        old_2.write_to_vault(&location, test_value())?;
+
+        // Modification times have a resolution of seconds.
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        let new = Client::default();
+        new.write_to_vault(&location, test_value())?;
+        let new_bid = get_blob_id(&new)?;
+
+        // The newer record replaces the older one.
+        old_1.sync_with(&new, SyncClientsConfig::new(MergePolicy::KeepNewest))?;
+        assert_eq!(get_blob_id(&old_1)?, new_bid);
+
+        // The older record does not replace the newer one.
+        new.sync_with(&old_2, SyncClientsConfig::new(MergePolicy::KeepNewest))?;
+        assert_eq!(get_blob_id(&new)?, new_bid);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_policy_custom() -> Result<(), Box<dyn std::error::Error>> {
+        let source = Client::default();
+        let target = Client::default();
+        for i in 0..2usize {
+            let location = Location::counter("vault", i);
+            source.write_to_vault(&location, test_value())?;
+            target.write_to_vault(&location, test_value())?;
+        }
+        let vid = vault_path_to_id("vault");
+        let target_blob_ids: HashMap<_, _> = target
+            .get_hierarchy(None)?
+            .remove(&vid)
+            .expect("Vault does not exist.")
+            .into_iter()
+            .map(|(rid, meta)| (rid, meta.blob_id))
+            .collect();
+
+        // Keep both versions of record 0, keep the old version of record 1.
+        let conflict_rid = r_ctr_to_id("vault", 0);
+        let policy = MergePolicy::custom(move |conflict| {
+            assert_eq!(conflict.vault_id, vid);
+            assert_ne!(conflict.existing.blob_id, conflict.incoming.blob_id);
+            if conflict.record_id == conflict_rid {
+                MergeDecision::KeepBoth(b"conflict".to_vec())
+            } else {
+                MergeDecision::KeepOld
+            }
+        });
+        target.sync_with(&source, SyncClientsConfig::new(policy))?;
+
+        let entries = target.get_hierarchy(None)?.remove(&vid).expect("Vault does not exist.");
+        assert_eq!(entries.len(), 3);
+        for (rid, blob_id) in target_blob_ids {
+            let (_, meta) = entries.iter().find(|(r, _)| r == &rid).expect("Record does not exist.");
+            assert_eq!(meta.blob_id, blob_id);
+        }
+        let copy = Location::generic("vault", "conflict");
+        assert_eq!(copy.resolve(), (vid, derive_record_id("vault", "conflict")));
+        assert!(target.record_exists(&copy)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preview_sync() -> Result<(), Box<dyn std::error::Error>> {
+        let source = Client::default();
+        let target = Client::default();
+        for i in 0..3usize {
+            source.write_to_vault(&Location::counter("vault_1", i), test_value())?;
+        }
+        target.write_to_vault(&Location::counter("vault_1", 0usize), test_value())?;
+        source.write_to_vault(&Location::counter("vault_2", 0usize), test_value())?;
+
+        let mut config = SyncClientsConfig::new(MergePolicy::KeepOld);
+        let report = target.preview_sync(&source, &config)?;
+        let vault_1 = &report[&vault_path_to_id("vault_1")];
+        assert_eq!(vault_1.added.len(), 2);
+        assert!(vault_1.added.contains(&r_ctr_to_id("vault_1", 1)));
+        assert!(vault_1.added.contains(&r_ctr_to_id("vault_1", 2)));
+        assert!(vault_1.replaced.is_empty());
+        assert_eq!(vault_1.skipped, vec![r_ctr_to_id("vault_1", 0)]);
+        assert_eq!(vault_1.conflicts.len(), 1);
+        assert_eq!(
+            report[&vault_path_to_id("vault_2")].added,
+            vec![r_ctr_to_id("vault_2", 0)]
+        );
+
+        // Nothing was copied.
+        assert_eq!(target.get_hierarchy(None)?.len(), 1);
+
+        config.merge_policy = MergePolicy::Replace;
+        config.sync_selected_vaults(vec!["vault_1"]);
+        let report = target.preview_sync(&source, &config)?;
+        assert_eq!(report.len(), 1);
+        assert_eq!(
+            report[&vault_path_to_id("vault_1")].replaced,
+            vec![r_ctr_to_id("vault_1", 0)]
+        );
+
+        // Applying the sync results in the previewed changes.
+        target.sync_with(&source, config.clone())?;
+        let report = target.preview_sync(&source, &config)?;
+        let vault_1 = &report[&vault_path_to_id("vault_1")];
+        assert!(vault_1.added.is_empty() && vault_1.replaced.is_empty() && vault_1.conflicts.is_empty());
+        assert_eq!(vault_1.skipped.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_changes_since() -> Result<(), Box<dyn std::error::Error>> {
+        let source = Client::default();
+        let target = Client::default();
+        for i in 0..3usize {
+            source.write_to_vault(&Location::counter("vault_1", i), test_value())?;
+        }
+        assert!(source.vault_version("vault_2")?.is_none());
+
+        let version = source.vault_version("vault_1")?.expect("Vault exists.");
+        target.sync_with(&source, SyncClientsConfig::new(MergePolicy::Replace))?;
+
+        // Only the records that changed after the known version are exchanged.
+        source.write_to_vault(&Location::counter("vault_1", 1usize), test_value())?;
+        source.write_to_vault(&Location::counter("vault_1", 3usize), test_value())?;
+        let mut config = SyncClientsConfig::new(MergePolicy::Replace);
+        config.sync_changes_since("vault_1", version);
+        let hierarchy = source.get_hierarchy_since(None, &config.known_versions)?;
+        let vault_1 = &hierarchy[&vault_path_to_id("vault_1")];
+        assert_eq!(vault_1.len(), 2);
+        assert!(vault_1.iter().any(|(rid, _)| *rid == r_ctr_to_id("vault_1", 1)));
+        assert!(vault_1.iter().any(|(rid, _)| *rid == r_ctr_to_id("vault_1", 3)));
+
+        let report = target.preview_sync(&source, &config)?;
+        let vault_1 = &report[&vault_path_to_id("vault_1")];
+        assert_eq!(vault_1.added, vec![r_ctr_to_id("vault_1", 3)]);
+        assert_eq!(vault_1.replaced, vec![r_ctr_to_id("vault_1", 1)]);
+        assert!(vault_1.skipped.is_empty());
+
+        target.sync_with(&source, config)?;
+        let mut expected = source
+            .get_hierarchy(None)?
+            .remove(&vault_path_to_id("vault_1"))
+            .unwrap();
+        let mut actual = target
+            .get_hierarchy(None)?
+            .remove(&vault_path_to_id("vault_1"))
+            .unwrap();
+        expected.sort_by_key(|(rid, _)| *rid);
+        actual.sort_by_key(|(rid, _)| *rid);
+        assert_eq!(
+            expected.iter().map(|(rid, m)| (rid, m.blob_id)).collect::<Vec<_>>(),
+            actual.iter().map(|(rid, m)| (rid, m.blob_id)).collect::<Vec<_>>()
+        );
+
+        // A version that is unknown to the source results in a full sync.
+        let mut config = SyncClientsConfig::new(MergePolicy::Replace);
+        config.sync_changes_since("vault_1", target.vault_version("vault_1")?.unwrap());
+        let hierarchy = source.get_hierarchy_since(None, &config.known_versions)?;
+        assert_eq!(hierarchy[&vault_path_to_id("vault_1")].len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_propagate_revocations() -> Result<(), Box<dyn std::error::Error>> {
+        let source = Client::default();
+        for i in 0..3usize {
+            source.write_to_vault(&Location::counter("vault", i), test_value())?;
+        }
+        let target = Client::default();
+        target.sync_with(&source, SyncClientsConfig::default())?;
+
+        source.revoke_data(&Location::counter("vault", 0usize))?;
+        source.revoke_data(&Location::counter("vault", 1usize))?;
+
+        // Record 1 is modified at the target after it was revoked at the source.
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        target.write_to_vault(&Location::counter("vault", 1usize), test_value())?;
+
+        // Revocations are not propagated by default.
+        target.sync_with(&source, SyncClientsConfig::default())?;
+        assert!(target.list_revoked(vault_path_to_id("vault"))?.is_empty());
+
+        let mut config = SyncClientsConfig::default();
+        config.propagate_revocations(true);
+        let report = target.preview_sync(&source, &config)?;
+        assert_eq!(
+            report[&vault_path_to_id("vault")].revoked,
+            vec![r_ctr_to_id("vault", 0)]
+        );
+
+        target.sync_with(&source, config.clone())?;
+        assert_eq!(
+            target.list_revoked(vault_path_to_id("vault"))?,
+            vec![r_ctr_to_id("vault", 0)]
+        );
+
+        // Revocations are propagated between vaults of the same client.
+        target.sync_vaults_with_config(b"vault".to_vec(), b"vault_copy".to_vec(), SyncClientsConfig::default())?;
+        let copy_vid = vault_path_to_id("vault_copy");
+        assert_eq!(target.get_hierarchy(Some(vec![copy_vid]))?[&copy_vid].len(), 2);
+        target.revoke_data(&Location::counter("vault", 2usize))?;
+        target.sync_vaults_with_config(b"vault".to_vec(), b"vault_copy".to_vec(), config)?;
+        assert_eq!(target.list_revoked(copy_vid)?, vec![r_ctr_to_id("vault", 2)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_state_propagates_revocations() -> Result<(), Box<dyn std::error::Error>> {
+        let cid = ClientId::load_from_path(b"client", b"client");
+        let client_state = |client: &Client| -> Result<ClientState, ClientError> {
+            let keys = client.keystore.write()?.get_data();
+            let db = client.db.read()?.clone();
+            Ok((keys, db, Default::default()))
+        };
+
+        let client = Client::default();
+        for i in 0..2usize {
+            client.write_to_vault(&Location::counter("vault", i), test_value())?;
+        }
+        let mut snapshot = crate::Snapshot::default();
+        snapshot.add_data(cid, client_state(&client)?)?;
+
+        client.revoke_data(&Location::counter("vault", 0usize))?;
+        let state =
+            || -> Result<SnapshotState, ClientError> { Ok(SnapshotState([(cid, client_state(&client)?)].into())) };
+
+        // Revocations are not propagated by default.
+        snapshot.merge_state(state()?, SyncSnapshotsConfig::default())?;
+        let (keys, db, _) = snapshot.get_state(cid)?;
+        let vid = vault_path_to_id("vault");
+        assert!(db.list_revoked_records(&keys[&vid], vid)?.is_empty());
+
+        let mut config = SyncSnapshotsConfig::default();
+        config.propagate_revocations(true);
+        snapshot.merge_state(state()?, config)?;
+        let (keys, db, _) = snapshot.get_state(cid)?;
+        assert_eq!(
+            db.list_revoked_records(&keys[&vid], vid)?,
+            vec![r_ctr_to_id("vault", 0)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_with_record_mapping() -> Result<(), Box<dyn std::error::Error>> {
+        let source = Client::default();
+        let target = Client::default();
+        for path in ["keys/1", "keys/2", "other"] {
+            source.write_to_vault(&Location::generic("vault_a", path), test_value())?;
+        }
+
+        let mut config = SyncClientsConfig::new(MergePolicy::Replace);
+        config.map_record_prefix(
+            "vault_a",
+            "vault_b",
+            b"keys/",
+            b"backup/",
+            vec!["keys/1", "keys/2", "other"],
+        );
+        target.sync_with(&source, config.clone())?;
+
+        let vid_a = vault_path_to_id("vault_a");
+        let vid_b = vault_path_to_id("vault_b");
+        assert!(!target.vault_exists("vault_a")?);
+        let blob_id = |client: &Client, vault_path: &str, record_path: &str| {
+            let location = Location::generic(vault_path, record_path);
+            let (vid, rid) = location.resolve();
+            let key = client.keystore.read().unwrap().get_key(vid).unwrap();
+            client.db.read().unwrap().get_blob_id(&key, vid, rid).unwrap()
+        };
+        assert_eq!(
+            blob_id(&target, "vault_b", "backup/1"),
+            blob_id(&source, "vault_a", "keys/1")
+        );
+        assert_eq!(
+            blob_id(&target, "vault_b", "backup/2"),
+            blob_id(&source, "vault_a", "keys/2")
+        );
+        // Records without a mapping keep their id.
+        let contains_other = target
+            .db
+            .read()
+            .map_err(ClientError::from)?
+            .contains_record(vid_b, derive_record_id("vault_a", "other"));
+        assert!(contains_other);
+        assert_eq!(target.get_hierarchy(None)?[&vid_b].len(), 3);
+
+        // Reports refer to the mapped records.
+        source.write_to_vault(&Location::generic("vault_a", "keys/1"), test_value())?;
+        let report = target.preview_sync(&source, &config)?;
+        assert_eq!(report[&vid_a].replaced, vec![derive_record_id("vault_b", "backup/1")]);
+        assert_eq!(
+            report[&vid_a].conflicts[0].record_id,
+            derive_record_id("vault_b", "backup/1")
+        );
+        target.sync_with(&source, config)?;
+        assert_eq!(
+            blob_id(&target, "vault_b", "backup/1"),
+            blob_id(&source, "vault_a", "keys/1")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_state_with_mapping() -> Result<(), Box<dyn std::error::Error>> {
+        let source_cid = ClientId::load_from_path(b"source", b"source");
+        let target_cid = ClientId::load_from_path(b"target", b"target");
+        let client_state = |client: &Client| -> Result<ClientState, ClientError> {
+            let keys = client.keystore.write()?.get_data();
+            let db = client.db.read()?.clone();
+            Ok((keys, db, Default::default()))
+        };
+
+        let client = Client::default();
+        for path in ["keys/1", "other"] {
+            client.write_to_vault(&Location::generic("vault_a", path), test_value())?;
+        }
+        let target = Client::default();
+        target.write_to_vault(&Location::generic("vault", "record"), test_value())?;
+        let mut snapshot = crate::Snapshot::default();
+        snapshot.add_data(target_cid, client_state(&target)?)?;
+
+        let mut client_config = SyncClientsConfig::new(MergePolicy::Replace);
+        client_config.map_records("vault_a", "vault_b", [("keys/1", "restored/1")].into());
+        client_config.propagate_revocations(true);
+        let mut config = SyncSnapshotsConfig::default();
+        config.map_clients([("source", "target")].into());
+        config.config_client_sync("source", client_config);
+
+        let state = || -> Result<SnapshotState, ClientError> {
+            Ok(SnapshotState([(source_cid, client_state(&client)?)].into()))
+        };
+        let report = snapshot.preview_merge(&state()?, &config)?;
+        let vault_report = &report[&source_cid][&vault_path_to_id("vault_a")];
+        assert!(vault_report.added.contains(&derive_record_id("vault_b", "restored/1")));
+        snapshot.merge_state(state()?, config.clone())?;
+
+        assert!(!snapshot.has_data(source_cid));
+        let (keys, db, _) = snapshot.get_state(target_cid)?;
+        let vid_b = vault_path_to_id("vault_b");
+        assert!(keys.contains_key(&vid_b));
+        assert!(!keys.contains_key(&vault_path_to_id("vault_a")));
+        let (vid, rid) = Location::generic("vault_b", "restored/1").resolve();
+        assert!(db.get_record_metadata(&keys[&vid], vid, rid).is_ok());
+        assert!(db.contains_record(vid_b, derive_record_id("vault_a", "other")));
+        assert!(db.contains_record(vault_path_to_id("vault"), derive_record_id("vault", "record")));
+
+        // Revocations are applied to the mapped record.
+        client.revoke_data(&Location::generic("vault_a", "keys/1"))?;
+        snapshot.merge_state(state()?, config)?;
+        let (keys, db, _) = snapshot.get_state(target_cid)?;
+        assert_eq!(db.list_revoked_records(&keys[&vid_b], vid_b)?, vec![rid]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_progress_and_cancellation() -> Result<(), Box<dyn std::error::Error>> {
+        let source = Client::default();
+        for vault_path in ["vault_1", "vault_2"] {
+            for i in 0..2usize {
+                source.write_to_vault(&Location::counter(vault_path, i), test_value())?;
+            }
+        }
+        let target = Client::default();
+        target.write_to_vault(&Location::counter("vault_1", 5usize), test_value())?;
+        let version = target.vault_version("vault_1")?;
+        let hierarchy = || -> Result<_, ClientError> {
+            let mut hierarchy: Vec<_> = target
+                .get_hierarchy(None)?
+                .into_iter()
+                .map(|(vid, records)| (vid, records.len()))
+                .collect();
+            hierarchy.sort();
+            Ok(hierarchy)
+        };
+        let before = hierarchy()?;
+
+        // The sync is cancelled after the first vault and the changes to it are rolled back.
+        let token = CancellationToken::new();
+        let mut config = SyncClientsConfig::default();
+        config.cancel_with(token.clone());
+        let cancel = token.clone();
+        config.observe_progress(move |_| cancel.cancel());
+        assert!(matches!(
+            target.sync_with(&source, config),
+            Err(ClientError::SyncCancelled)
+        ));
+        assert!(token.is_cancelled());
+        assert_eq!(hierarchy()?, before);
+        assert_eq!(target.vault_version("vault_1")?, version);
+        assert!(!target.vault_exists("vault_2")?);
+
+        let progress = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut config = SyncClientsConfig::default();
+        let observed = progress.clone();
+        config.observe_progress(move |p| observed.lock().unwrap().push(*p));
+        target.sync_with(&source, config)?;
+        let progress = progress.lock().unwrap();
+        assert_eq!(progress.len(), 2);
+        assert!(progress
+            .iter()
+            .all(|p| p.total == 4 && p.vault_records == 2 && p.client_id.is_none()));
+        assert_eq!(progress[0].processed, 2);
+        assert_eq!(progress[1].processed, 4);
+        assert_eq!(target.get_hierarchy(None)?[&vault_path_to_id("vault_1")].len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_state_cancellation() -> Result<(), Box<dyn std::error::Error>> {
+        let cid = ClientId::load_from_path(b"client", b"client");
+        let new_cid = ClientId::load_from_path(b"new_client", b"new_client");
+        let client_state = |client: &Client| -> Result<ClientState, ClientError> {
+            let keys = client.keystore.write()?.get_data();
+            let db = client.db.read()?.clone();
+            Ok((keys, db, Default::default()))
+        };
+
+        let source = Client::default();
+        for vault_path in ["vault_1", "vault_2"] {
+            source.write_to_vault(&Location::counter(vault_path, 0usize), test_value())?;
+        }
+        let target = Client::default();
+        target.write_to_vault(&Location::counter("vault_1", 5usize), test_value())?;
+        let mut snapshot = crate::Snapshot::default();
+        snapshot.add_data(cid, client_state(&target)?)?;
+        let state = || -> Result<SnapshotState, ClientError> {
+            Ok(SnapshotState(
+                [(cid, client_state(&source)?), (new_cid, client_state(&source)?)].into(),
+            ))
+        };
+
+        let token = CancellationToken::new();
+        let mut config = SyncSnapshotsConfig::default();
+        config.cancel_with(token.clone());
+        let progress = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let observed = progress.clone();
+        config.observe_progress(move |p| {
+            observed.lock().unwrap().push(*p);
+            if p.processed == 3 {
+                token.cancel();
+            }
+        });
+        assert!(matches!(
+            snapshot.merge_state(state()?, config),
+            Err(SnapshotError::SyncCancelled)
+        ));
+        assert_eq!(progress.lock().unwrap().len(), 3);
+        assert!(progress.lock().unwrap().iter().all(|p| p.total == 4));
+        assert!(!snapshot.has_data(new_cid));
+        let (keys, db, _) = snapshot.get_state(cid)?;
+        assert_eq!(db.list_vaults(), vec![vault_path_to_id("vault_1")]);
+        assert_eq!(keys.len(), 1);
+        assert_eq!(db.list_records(&vault_path_to_id("vault_1")).len(), 1);
+
+        snapshot.merge_state(state()?, SyncSnapshotsConfig::default())?;
+        assert!(snapshot.has_data(new_cid));
+        let (_, db, _) = snapshot.get_state(cid)?;
+        assert_eq!(db.list_vaults().len(), 2);
+        assert_eq!(db.list_records(&vault_path_to_id("vault_1")).len(), 2);
+
+        Ok(())
+    }
 }
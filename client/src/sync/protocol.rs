@@ -0,0 +1,584 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transport-agnostic protocol to synchronize the [`Snapshot`] state of two parties over a byte stream.
+//!
+//! Both parties exchange the hierarchy of their state, request the records that they are missing,
+//! send each other the requested records encrypted with a key that is agreed on with x25519, and
+//! acknowledge the import. Each message is answered by the other party before the next one is sent,
+//! with the [`SyncRole::Initiator`] sending first:
+//!
+//! 1. `Hello`: protocol version, public session key and the versions of the remote vaults that are already
+//!    known, see [`crate::sync::SyncClientsConfig::sync_changes_since`].
+//! 2. `Hierarchy`: hierarchy, revocations and versions of the local state. Only the records that changed after
+//!    the versions known by the remote party are listed.
+//! 3. `Request`: the records that should be sent by the remote party.
+//! 4. `Export`: the requested records, encrypted for the remote party.
+//! 5. `Ack`: the result of importing the received records.
+//!
+//! The session keys are generated for each run, so the protocol does not authenticate the remote party.
+//! Messages are limited to [`MAX_MESSAGE_SIZE`], and only the records that have been requested are imported
+//! from the `Export` of the remote party.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+use crypto::keys::x25519;
+use engine::vault::{RecordId, RecordMetadata};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sync::{
+        select_copies, set_source_versions, SnapshotHierarchy, SnapshotSyncReport, SnapshotVersions, SyncSnapshots,
+        SyncSnapshotsConfig,
+    },
+    RemoteMergeError, Snapshot, SnapshotError, SyncProtocolError,
+};
+
+/// Version of the sync protocol.
+pub const SYNC_PROTOCOL_VERSION: u8 = 1;
+
+/// Maximum size in bytes of a single message. Larger messages are rejected before they are read.
+pub const MAX_MESSAGE_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Role of a party in the sync protocol. One of the two parties has to be the initiator, the other one the
+/// responder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncRole {
+    /// Sends the first message of each exchange.
+    Initiator,
+    /// Waits for the message of the initiator before answering it.
+    Responder,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Message {
+    Hello {
+        version: u8,
+        public_key: [u8; x25519::PUBLIC_KEY_LENGTH],
+        known_versions: SnapshotVersions,
+    },
+    Hierarchy {
+        hierarchy: SnapshotHierarchy<(RecordId, RecordMetadata)>,
+        revocations: SnapshotHierarchy<(RecordId, u64)>,
+        versions: SnapshotVersions,
+    },
+    Request(SnapshotHierarchy<RecordId>),
+    Export {
+        public_key: [u8; x25519::PUBLIC_KEY_LENGTH],
+        data: Vec<u8>,
+    },
+    Ack(Result<(), RemoteMergeError>),
+}
+
+impl Message {
+    fn name(&self) -> &'static str {
+        match self {
+            Message::Hello { .. } => "Hello",
+            Message::Hierarchy { .. } => "Hierarchy",
+            Message::Request(_) => "Request",
+            Message::Export { .. } => "Export",
+            Message::Ack(_) => "Ack",
+        }
+    }
+}
+
+/// Writes a length-prefixed message to the stream.
+fn send<S: Write>(stream: &mut S, message: &Message) -> Result<(), SyncProtocolError> {
+    let bytes = bincode::serialize(message).map_err(|e| SyncProtocolError::InvalidMessage(e.to_string()))?;
+    let len = u32::try_from(bytes.len())
+        .ok()
+        .filter(|len| *len <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| SyncProtocolError::InvalidMessage("message exceeds maximum size".to_string()))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(&bytes)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Reads a length-prefixed message from the stream.
+fn receive<S: Read>(stream: &mut S) -> Result<Message, SyncProtocolError> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_MESSAGE_SIZE {
+        return Err(SyncProtocolError::InvalidMessage(
+            "message exceeds maximum size".to_string(),
+        ));
+    }
+    let len = len as u64;
+    let mut bytes = Vec::new();
+    stream.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    bincode::deserialize(&bytes).map_err(|e| SyncProtocolError::InvalidMessage(e.to_string()))
+}
+
+/// Sends `message` and receives the message of the remote party, in the order given by `role`.
+fn exchange<S: Read + Write>(stream: &mut S, role: SyncRole, message: Message) -> Result<Message, SyncProtocolError> {
+    match role {
+        SyncRole::Initiator => {
+            send(stream, &message)?;
+            receive(stream)
+        }
+        SyncRole::Responder => {
+            let received = receive(stream)?;
+            send(stream, &message)?;
+            Ok(received)
+        }
+    }
+}
+
+/// State that is synchronized with the protocol. The state is only accessed between the exchanged messages,
+/// so that it doesn't have to be locked while waiting for the remote party.
+pub(crate) trait SyncState {
+    /// Calls `f` with the current state.
+    fn read<T, F>(&mut self, f: F) -> Result<T, SyncProtocolError>
+    where
+        F: FnOnce(&Snapshot) -> Result<T, SyncProtocolError>;
+
+    /// Calls `f` with the current state to modify it.
+    fn update<T, F>(&mut self, f: F) -> Result<T, SyncProtocolError>
+    where
+        F: FnOnce(&mut Snapshot) -> Result<T, SyncProtocolError>;
+}
+
+impl SyncState for Snapshot {
+    fn read<T, F>(&mut self, f: F) -> Result<T, SyncProtocolError>
+    where
+        F: FnOnce(&Snapshot) -> Result<T, SyncProtocolError>,
+    {
+        f(self)
+    }
+
+    fn update<T, F>(&mut self, f: F) -> Result<T, SyncProtocolError>
+    where
+        F: FnOnce(&mut Snapshot) -> Result<T, SyncProtocolError>,
+    {
+        f(self)
+    }
+}
+
+/// Runs the protocol for `state`, see [`Snapshot::sync_with_peer`].
+pub(crate) fn sync_state_with_peer<St, S>(
+    state: &mut St,
+    stream: &mut S,
+    role: SyncRole,
+    config: SyncSnapshotsConfig,
+) -> Result<SnapshotSyncReport, SyncProtocolError>
+where
+    St: SyncState,
+    S: Read + Write,
+{
+    if !config.allow_unauthenticated_peers {
+        return Err(SnapshotError::UnauthenticatedPeer.into());
+    }
+    let sk = x25519::SecretKey::generate().map_err(|e| SyncProtocolError::Snapshot(e.into()))?;
+
+    let hello = Message::Hello {
+        version: SYNC_PROTOCOL_VERSION,
+        public_key: sk.public_key().to_bytes(),
+        known_versions: config.known_versions(),
+    };
+    let (remote_pk, remote_known_versions) = match exchange(stream, role, hello)? {
+        Message::Hello {
+            version: SYNC_PROTOCOL_VERSION,
+            public_key,
+            known_versions,
+        } => (x25519::PublicKey::from_bytes(public_key), known_versions),
+        Message::Hello { version, .. } => return Err(SyncProtocolError::UnsupportedVersion(version)),
+        other => return Err(SyncProtocolError::UnexpectedMessage(other.name())),
+    };
+
+    // All revocations are sent, the remote party decides if they should be applied.
+    let mut all_revocations = SyncSnapshotsConfig::default();
+    all_revocations.propagate_revocations(true);
+    let local = state.read(|snapshot| {
+        let (hierarchy, versions) = snapshot.get_hierarchy_since(None, &remote_known_versions)?;
+        let revocations = snapshot.get_revocations(&all_revocations)?;
+        Ok(Message::Hierarchy {
+            hierarchy,
+            revocations,
+            versions,
+        })
+    })?;
+    let (hierarchy, revocations, versions) = match exchange(stream, role, local)? {
+        Message::Hierarchy {
+            hierarchy,
+            revocations,
+            versions,
+        } => (hierarchy, revocations, versions),
+        other => return Err(SyncProtocolError::UnexpectedMessage(other.name())),
+    };
+
+    let mut report = state.read(|snapshot| Ok(snapshot.get_sync_report(hierarchy, revocations, &config)?))?;
+    for (cid, client_report) in report.iter_mut() {
+        if let Some(versions) = versions.get(cid) {
+            set_source_versions(client_report, versions);
+        }
+    }
+    let request: SnapshotHierarchy<RecordId> = report
+        .iter()
+        .map(|(cid, r)| {
+            let select = select_copies(r)
+                .into_iter()
+                .map(|(vid, copy)| (vid, copy.into_iter().map(|(rid, _)| rid).collect()))
+                .collect::<HashMap<_, Vec<_>>>();
+            (*cid, select)
+        })
+        .collect();
+    let remote_request = match exchange(stream, role, Message::Request(request.clone()))? {
+        Message::Request(request) => request,
+        other => return Err(SyncProtocolError::UnexpectedMessage(other.name())),
+    };
+
+    let (public_key, data) =
+        state.read(|snapshot| Ok(snapshot.export_to_serialized_state(remote_request, remote_pk)?))?;
+    let export = Message::Export {
+        public_key: public_key.to_bytes(),
+        data,
+    };
+    let (export_pk, data) = match exchange(stream, role, export)? {
+        Message::Export { public_key, data } => (x25519::PublicKey::from_bytes(public_key), data),
+        other => return Err(SyncProtocolError::UnexpectedMessage(other.name())),
+    };
+
+    // Records of the export that have not been requested are ignored.
+    let imported = state.update(|snapshot| {
+        snapshot.import_from_serialized_state_with_key(&data, &sk, export_pk, Some(request), config.clone())?;
+        snapshot.revoke_records(&report, &config)?;
+        Ok(())
+    });
+    let ack = imported
+        .as_ref()
+        .map(|_| ())
+        .map_err(|e| RemoteMergeError::ReadExported(e.to_string()));
+    let remote_ack = exchange(stream, role, Message::Ack(ack))?;
+    imported?;
+    match remote_ack {
+        Message::Ack(Ok(())) => Ok(report),
+        Message::Ack(Err(e)) => Err(SyncProtocolError::Remote(e)),
+        other => Err(SyncProtocolError::UnexpectedMessage(other.name())),
+    }
+}
+
+impl Snapshot {
+    /// Synchronize the loaded state with a remote party over `stream`. Both parties exchange the hierarchy of
+    /// their state, records that are missing locally are requested from the remote party and merged according
+    /// to `config`, while the remote party requests and merges records from the local state according to its
+    /// own config. The records are encrypted with a key that is agreed on with x25519 session keys, which do
+    /// not authenticate the remote party. Records that the remote party sends without being requested are
+    /// ignored. Vaults of the remote party with a version set with [`crate::sync::SyncClientsConfig::sync_changes_since`]
+    /// in the config of their client only list the records that changed after that version, the current versions
+    /// are returned in [`crate::sync::VaultSyncReport::source_version`].
+    ///
+    /// Because the remote party is not authenticated, the sync fails with [`SnapshotError::UnauthenticatedPeer`]
+    /// unless it is allowed with [`SyncSnapshotsConfig::allow_unauthenticated_peers`].
+    ///
+    /// Returns the changes that have been applied to the local state.
+    pub fn sync_with_peer<S>(
+        &mut self,
+        stream: &mut S,
+        role: SyncRole,
+        config: SyncSnapshotsConfig,
+    ) -> Result<SnapshotSyncReport, SyncProtocolError>
+    where
+        S: Read + Write,
+    {
+        sync_state_with_peer(self, stream, role, config)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    use std::{os::unix::net::UnixStream, thread};
+
+    use engine::vault::ClientId;
+    use stronghold_utils::random;
+
+    use crate::{
+        derive_vault_id, procedures::Runner, sync::SyncClientsConfig, Client, ClientError, ClientState, LoadFromPath,
+        Location, Stronghold,
+    };
+
+    fn client_state(client: &Client) -> Result<ClientState, ClientError> {
+        let keys = client.keystore.write()?.get_data();
+        let db = client.db.read()?.checkpoint();
+        Ok((keys, db, Default::default()))
+    }
+
+    /// Config that allows the sync with the unauthenticated remote party of the tests.
+    fn config() -> SyncSnapshotsConfig {
+        let mut config = SyncSnapshotsConfig::default();
+        config.allow_unauthenticated_peers(true);
+        config
+    }
+
+    /// Runs the initiator and responder on a connected pair of sockets.
+    fn run<I, R, T>(f_initiator: I, f_responder: R) -> (T, T)
+    where
+        I: FnOnce(&mut UnixStream) -> T,
+        R: FnOnce(&mut UnixStream) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        let responder = thread::spawn(move || f_responder(&mut b));
+        let initiator = f_initiator(&mut a);
+        (initiator, responder.join().unwrap())
+    }
+
+    #[test]
+    fn test_sync_snapshots_with_peer() -> Result<(), Box<dyn std::error::Error>> {
+        let cid = ClientId::load_from_path(b"client", b"client");
+        let vid = derive_vault_id("vault");
+
+        let client_a = Client::default();
+        let client_b = Client::default();
+        for i in 0..2usize {
+            client_a.write_to_vault(&Location::counter("vault", i), random::variable_bytestring(64))?;
+        }
+        client_b.write_to_vault(&Location::counter("vault", 2usize), random::variable_bytestring(64))?;
+        let mut snapshot_a = Snapshot::default();
+        snapshot_a.add_data(cid, client_state(&client_a)?)?;
+        let mut snapshot_b = Snapshot::default();
+        snapshot_b.add_data(cid, client_state(&client_b)?)?;
+
+        let (report_a, report_b) = run(
+            move |stream| {
+                let report = snapshot_a.sync_with_peer(stream, SyncRole::Initiator, config());
+                report.map(|r| (r, snapshot_a))
+            },
+            move |stream| {
+                let report = snapshot_b.sync_with_peer(stream, SyncRole::Responder, config());
+                report.map(|r| (r, snapshot_b))
+            },
+        );
+        let (report_a, snapshot_a) = report_a?;
+        let (report_b, snapshot_b) = report_b?;
+
+        assert_eq!(report_a[&cid][&vid].added.len(), 1);
+        assert_eq!(report_b[&cid][&vid].added.len(), 2);
+
+        for snapshot in [snapshot_a, snapshot_b] {
+            let (_, db, _) = snapshot.get_state(cid)?;
+            for i in 0..3usize {
+                let (vid, rid) = Location::counter("vault", i).resolve();
+                assert!(db.contains_record(vid, rid));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stronghold_sync_with_peer() -> Result<(), Box<dyn std::error::Error>> {
+        let location_a = Location::generic("vault", "record_a");
+        let location_b = Location::generic("vault", "record_b");
+
+        let stronghold_a = Stronghold::default();
+        let client_a = stronghold_a.create_client("client")?;
+        client_a.write_to_vault(&location_a, b"secret a".to_vec())?;
+        let stronghold_b = Stronghold::default();
+        let client_b = stronghold_b.create_client("client")?;
+        client_b.write_to_vault(&location_b, b"secret b".to_vec())?;
+        let other_b = stronghold_b.create_client("other")?;
+        other_b.write_to_vault(&location_b, b"secret b".to_vec())?;
+
+        let (report_a, report_b) = run(
+            move |stream| stronghold_a.sync_with_peer(stream, SyncRole::Initiator, config()),
+            move |stream| stronghold_b.sync_with_peer(stream, SyncRole::Responder, config()),
+        );
+        assert_eq!(report_a?.len(), 2);
+        assert_eq!(report_b?.len(), 1);
+
+        // Loaded clients are updated.
+        for client in [&client_a, &client_b] {
+            assert!(client.record_exists(&location_a)?);
+            assert!(client.record_exists(&location_b)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unexpected_message() {
+        let (result, _) = run(
+            |stream| Snapshot::default().sync_with_peer(stream, SyncRole::Initiator, config()),
+            |stream| {
+                let _ = receive(stream);
+                send(stream, &Message::Ack(Ok(())))?;
+                Ok(Default::default())
+            },
+        );
+        assert!(matches!(result, Err(SyncProtocolError::UnexpectedMessage("Ack"))));
+    }
+
+    #[test]
+    fn test_unauthenticated_peer_is_rejected() {
+        let (mut a, _b) = UnixStream::pair().unwrap();
+        let result = Snapshot::default().sync_with_peer(&mut a, SyncRole::Initiator, SyncSnapshotsConfig::default());
+        assert!(matches!(
+            result,
+            Err(SyncProtocolError::Snapshot(SnapshotError::UnauthenticatedPeer))
+        ));
+
+        let remote_pk = x25519::SecretKey::generate().unwrap().public_key();
+        let result = Snapshot::default().import_from_serialized_state(
+            Vec::new(),
+            Location::generic("vault", "record"),
+            remote_pk,
+            SyncSnapshotsConfig::default(),
+        );
+        assert!(matches!(result, Err(SnapshotError::UnauthenticatedPeer)));
+        assert!(matches!(
+            result.map_err(ClientError::from),
+            Err(ClientError::UnauthenticatedPeer)
+        ));
+    }
+
+    #[test]
+    fn test_oversized_message() {
+        let (result, _) = run(
+            |stream| Snapshot::default().sync_with_peer(stream, SyncRole::Initiator, config()),
+            |stream| {
+                let _ = receive(stream);
+                stream.write_all(&(MAX_MESSAGE_SIZE + 1).to_be_bytes())?;
+                Ok(Default::default())
+            },
+        );
+        assert!(matches!(result, Err(SyncProtocolError::InvalidMessage(_))));
+    }
+
+    #[test]
+    fn test_unrequested_records_are_ignored() -> Result<(), Box<dyn std::error::Error>> {
+        let cid = ClientId::load_from_path(b"client", b"client");
+        let (_, requested) = Location::counter("vault", 0usize).resolve();
+        let (_, unrequested) = Location::counter("vault", 1usize).resolve();
+
+        let client = Client::default();
+        for i in 0..2usize {
+            client.write_to_vault(&Location::counter("vault", i), random::variable_bytestring(64))?;
+        }
+        let mut remote = Snapshot::default();
+        remote.add_data(cid, client_state(&client)?)?;
+
+        let (local, _) = run(
+            |stream| {
+                let mut local = Snapshot::default();
+                local.sync_with_peer(stream, SyncRole::Initiator, config())?;
+                Ok(local)
+            },
+            move |stream| {
+                let local_pk = match receive(stream)? {
+                    Message::Hello { public_key, .. } => x25519::PublicKey::from_bytes(public_key),
+                    other => return Err(SyncProtocolError::UnexpectedMessage(other.name())),
+                };
+                let sk = x25519::SecretKey::generate().unwrap();
+                send(
+                    stream,
+                    &Message::Hello {
+                        version: SYNC_PROTOCOL_VERSION,
+                        public_key: sk.public_key().to_bytes(),
+                        known_versions: Default::default(),
+                    },
+                )?;
+                // Only the first record is announced, but all records are exported.
+                let full = remote.get_hierarchy(None)?;
+                let mut announced = full.clone();
+                for records in announced.values_mut().flat_map(|vaults| vaults.values_mut()) {
+                    records.retain(|(rid, _)| *rid == requested);
+                }
+                let _ = receive(stream)?;
+                send(
+                    stream,
+                    &Message::Hierarchy {
+                        hierarchy: announced,
+                        revocations: Default::default(),
+                        versions: Default::default(),
+                    },
+                )?;
+                let _ = receive(stream)?;
+                send(stream, &Message::Request(Default::default()))?;
+                let _ = receive(stream)?;
+                let select = full
+                    .into_iter()
+                    .map(|(cid, vaults)| {
+                        let vaults = vaults
+                            .into_iter()
+                            .map(|(vid, records)| (vid, records.into_iter().map(|(rid, _)| rid).collect()))
+                            .collect();
+                        (cid, vaults)
+                    })
+                    .collect();
+                let (public_key, data) = remote.export_to_serialized_state(select, local_pk)?;
+                send(
+                    stream,
+                    &Message::Export {
+                        public_key: public_key.to_bytes(),
+                        data,
+                    },
+                )?;
+                let _ = receive(stream)?;
+                send(stream, &Message::Ack(Ok(())))?;
+                Ok(Snapshot::default())
+            },
+        );
+        let (_, db, _) = local?.get_state(cid)?;
+        let (vid, _) = Location::counter("vault", 0usize).resolve();
+        assert!(db.contains_record(vid, requested));
+        assert!(!db.contains_record(vid, unrequested));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_changes_since() -> Result<(), Box<dyn std::error::Error>> {
+        let cid = ClientId::load_from_path(b"client", b"client");
+        let vid = derive_vault_id("vault");
+
+        let client = Client::default();
+        for i in 0..2usize {
+            client.write_to_vault(&Location::counter("vault", i), random::variable_bytestring(64))?;
+        }
+        let mut remote = Snapshot::default();
+        remote.add_data(cid, client_state(&client)?)?;
+
+        // Syncs the snapshots and returns the report and the snapshot of the initiator.
+        fn sync(
+            mut local: Snapshot,
+            mut remote: Snapshot,
+            local_config: SyncSnapshotsConfig,
+        ) -> Result<(SnapshotSyncReport, Snapshot, Snapshot), SyncProtocolError> {
+            let (local, remote): (Result<_, SyncProtocolError>, Result<_, SyncProtocolError>) = run(
+                move |stream| {
+                    let report = local.sync_with_peer(stream, SyncRole::Initiator, local_config)?;
+                    Ok((report, local))
+                },
+                move |stream| {
+                    remote.sync_with_peer(stream, SyncRole::Responder, config())?;
+                    Ok((Default::default(), remote))
+                },
+            );
+            let (report, local) = local?;
+            Ok((report, local, remote?.1))
+        }
+
+        let (report, local, mut remote) = sync(Snapshot::default(), remote, config())?;
+        assert_eq!(report[&cid][&vid].added.len(), 2);
+        let version = report[&cid][&vid].source_version.expect("version of the remote vault");
+
+        // Only the records that changed after the known version are listed by the remote party.
+        client.write_to_vault(&Location::counter("vault", 2usize), random::variable_bytestring(64))?;
+        remote.add_data(cid, client_state(&client)?)?;
+        let mut client_config = SyncClientsConfig::default();
+        client_config.sync_changes_since("vault", version);
+        let mut config = config();
+        config.config_client_sync("client", client_config);
+        let (report, _, _) = sync(local, remote, config)?;
+        assert_eq!(report[&cid][&vid].added.len(), 1);
+        assert!(report[&cid][&vid].skipped.is_empty());
+        Ok(())
+    }
+}
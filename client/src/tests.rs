@@ -1,6 +1,8 @@
 // Copyright 2020-2022 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(feature = "async")]
+mod async_tests;
 mod fresh;
 mod interface_tests;
 mod procedure_tests;
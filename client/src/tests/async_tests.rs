@@ -0,0 +1,73 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Arc;
+
+use stronghold_utils::random;
+
+use crate::{
+    procedures::{GenerateKey, KeyType, PublicKey, StrongholdProcedure},
+    tests::fresh,
+    AsyncStronghold, ClientError, KeyProvider, Location, SnapshotPath, Stronghold,
+};
+
+#[tokio::test]
+async fn test_async_execute_procedure_and_commit() {
+    let stronghold = AsyncStronghold::new(Stronghold::default()).expect("Failed to create thread pool");
+    let client_path = b"client_path".to_vec();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+
+    let key_location: Location = fresh::location();
+    let generate_key = GenerateKey {
+        ty: KeyType::Ed25519,
+        output: key_location.clone(),
+    };
+    let public_key = PublicKey {
+        ty: KeyType::Ed25519,
+        private_key: key_location.clone(),
+    };
+    assert!(client.execute_procedure(generate_key).await.is_ok());
+    let expected = client.execute_procedure(public_key.clone()).await.unwrap();
+
+    let chained = client
+        .execute_procedure_chained(vec![StrongholdProcedure::PublicKey(public_key.clone())])
+        .await
+        .unwrap();
+    assert_eq!(chained.len(), 1);
+
+    // errors are returned with the same types as the synchronous api
+    let missing = PublicKey {
+        ty: KeyType::Ed25519,
+        private_key: fresh::location(),
+    };
+    assert!(client.execute_procedure(missing).await.is_err());
+
+    let mut path = std::env::temp_dir();
+    path.push(base64::encode(random::fixed_bytestring(32)).replace('/', "n"));
+    let snapshot_path = SnapshotPath::from_path(&path);
+    let keyprovider = Arc::new(KeyProvider::try_from(random::fixed_bytestring(32)).unwrap());
+
+    stronghold.stronghold().write_client(client_path.clone()).unwrap();
+    stronghold
+        .commit_with_keyprovider(snapshot_path.clone(), keyprovider.clone())
+        .await
+        .unwrap();
+
+    let stronghold = AsyncStronghold::new(Stronghold::default()).unwrap();
+    let client = stronghold
+        .load_client_from_snapshot(client_path, keyprovider, snapshot_path)
+        .await
+        .unwrap();
+    assert_eq!(client.execute_procedure(public_key).await.unwrap(), expected);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn test_panicking_blocking_task() {
+    let pool = futures::executor::ThreadPool::new().unwrap();
+    let task = crate::types::spawn_blocking(&pool, || -> Result<(), ClientError> {
+        panic!("blocking task panicked")
+    });
+    assert!(matches!(task.await, Err(ClientError::BlockingTaskCancelled)));
+}
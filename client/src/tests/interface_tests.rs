@@ -6,13 +6,26 @@ use std::{
     error::Error,
     ops::{Deref, DerefMut},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 use crate::{
-    procedures::{GenerateKey, KeyType, StrongholdProcedure},
-    Client, ClientError, ClientVault, KeyProvider, Location, Snapshot, SnapshotPath, Store, Stronghold,
+    derive_vault_id,
+    procedures::{
+        Ed25519Sign, GenerateKey, KeyOperation, KeyType, KeyUsagePolicy, ProcedureError, StrongholdProcedure,
+    },
+    sync::{MergePolicy, SyncSnapshotsConfig},
+    Argon2Params, Argon2Variant, CipherSuite, Client, ClientError, ClientVault, FileKeyEncryptionProvider,
+    GarbageCollectPolicy, IdleLockPolicy, InMemoryKeyEncryptionProvider, KeyDerivation, KeyEncryptionProvider,
+    KeyProvider, KeySlotKind, LoadFromPath, Location, NewKeySlot, PeerIdentity, PeerStatus, RemoteMergeError, Snapshot,
+    SnapshotPath, Store, Stronghold, ThresholdUnlock, UnlockPolicy,
 };
-use engine::vault::RecordHint;
+use crypto::{keys::x25519, signatures::ed25519};
+use engine::vault::{ClientId, RecordHint};
 use regex::Replacer;
 use stronghold_utils::random as rand;
 use zeroize::Zeroize;
@@ -711,3 +724,1839 @@ fn test_load_unload_client() {
     assert!(stronghold.unload_client(client).is_ok());
     assert!(stronghold.load_client(client_path).is_ok());
 }
+
+#[test]
+fn test_restore_revoked_secret() {
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .create_client("client_path")
+        .expect("Failed to create client");
+    let vault_path = b"vault_path".to_vec();
+    let record_path = b"record_path".to_vec();
+    let payload = b"payload".to_vec();
+    let vault = client.vault(vault_path.clone());
+
+    assert!(vault
+        .write_secret(
+            Location::generic(vault_path.clone(), record_path.clone()),
+            payload.clone()
+        )
+        .is_ok());
+
+    // restoring a secret, that has not been revoked fails
+    assert!(vault.restore_secret(record_path.clone()).is_err());
+
+    assert!(vault.revoke_secret(record_path.clone()).is_ok());
+    assert!(vault.read_secret(record_path.clone()).is_err());
+
+    let revoked = vault.list_revoked().expect("Failed to list revoked records");
+    let (_, record_id) = Location::generic(vault_path.clone(), record_path.clone()).resolve();
+    assert_eq!(revoked, vec![record_id]);
+
+    assert!(vault.restore_secret(record_path.clone()).is_ok());
+    assert!(vault.list_revoked().expect("Failed to list revoked records").is_empty());
+
+    let secret = vault.read_secret(record_path.clone());
+    assert!(secret.is_ok());
+    assert_eq!(secret.unwrap(), payload);
+
+    // a garbage collected secret can not be restored
+    assert!(vault.delete_secret(record_path.clone()).is_ok());
+    assert!(vault.restore_secret(record_path).is_err());
+}
+
+#[test]
+fn test_move_secret_and_rename_vault() {
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .create_client("client_path")
+        .expect("Failed to create client");
+    let vault_path = b"vault_path".to_vec();
+    let other_vault_path = b"other_vault_path".to_vec();
+    let payload = b"payload".to_vec();
+    let vault = client.vault(vault_path.clone());
+
+    assert!(vault
+        .write_secret(
+            Location::generic(vault_path.clone(), b"record".to_vec()),
+            payload.clone()
+        )
+        .is_ok());
+
+    // move inside the vault
+    assert!(vault.move_secret(b"record".to_vec(), b"moved".to_vec()).is_ok());
+    assert!(vault.read_secret(b"record").is_err());
+    assert_eq!(vault.read_secret(b"moved").unwrap(), payload);
+
+    // moving a missing secret fails
+    assert!(vault.move_secret(b"record".to_vec(), b"moved".to_vec()).is_err());
+
+    // move into another vault
+    let source = Location::generic(vault_path.clone(), b"moved".to_vec());
+    let target = Location::generic(other_vault_path.clone(), b"record".to_vec());
+    assert!(client.move_record(&source, &target).is_ok());
+    assert!(!client.record_exists(&source).unwrap());
+    assert!(client.vault_exists(other_vault_path.clone()).unwrap());
+    assert_eq!(
+        client.vault(other_vault_path.clone()).read_secret(b"record").unwrap(),
+        payload
+    );
+
+    // a failed move does not create the target vault
+    assert!(client
+        .move_record(&source, &Location::generic(b"missing".to_vec(), b"record".to_vec()))
+        .is_err());
+    assert!(!client.vault_exists(b"missing").unwrap());
+
+    // renaming fails if a record path is missing
+    let renamed_path = b"renamed_vault_path".to_vec();
+    assert!(client
+        .rename_vault(other_vault_path.clone(), renamed_path.clone(), &[b"other"])
+        .is_err());
+    assert!(client.vault_exists(other_vault_path.clone()).unwrap());
+    assert!(!client.vault_exists(renamed_path.clone()).unwrap());
+
+    // rename keeps the record paths
+    assert!(client
+        .rename_vault(other_vault_path.clone(), renamed_path.clone(), &[b"record"])
+        .is_ok());
+    assert!(!client.vault_exists(other_vault_path.clone()).unwrap());
+    assert!(client.vault_exists(renamed_path.clone()).unwrap());
+    assert_eq!(client.vault(renamed_path).read_secret(b"record").unwrap(), payload);
+
+    assert!(client
+        .rename_vault(other_vault_path, b"any".to_vec(), &[b"record"])
+        .is_err());
+}
+
+#[test]
+fn test_garbage_collect_policy_on_commit() {
+    let vault_path = b"vault_path".to_vec();
+    let key_provider = KeyProvider::try_from(fixed_random_bytes(32)).expect("Failed to create keyprovider");
+
+    let filename = base64::encode(fixed_random_bytes(32));
+    let filename = filename.replace('/', "n");
+    let mut snapshot_path = std::env::temp_dir();
+    snapshot_path.push(filename);
+
+    let defer = Defer::from((snapshot_path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot = SnapshotPath::from_path(&*defer);
+
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .create_client("client_path")
+        .expect("Failed to create client");
+    let vault = client.vault(vault_path.clone());
+
+    let revoke_new_secret = || {
+        let record_path = fixed_random_bytes(32);
+        let location = Location::generic(vault_path.clone(), record_path.clone());
+        assert!(vault.write_secret(location, b"payload".to_vec()).is_ok());
+        assert!(vault.revoke_secret(record_path).is_ok());
+    };
+
+    // the default policy never collects automatically
+    revoke_new_secret();
+    assert!(stronghold.commit_with_keyprovider(&snapshot, &key_provider).is_ok());
+    assert_eq!(vault.list_revoked().unwrap().len(), 1);
+
+    // collect on every second commit
+    assert!(stronghold
+        .set_garbage_collect_policy(GarbageCollectPolicy::EveryNthCommit(2))
+        .is_ok());
+    assert!(stronghold.commit_with_keyprovider(&snapshot, &key_provider).is_ok());
+    assert_eq!(vault.list_revoked().unwrap().len(), 1);
+    assert!(stronghold.commit_with_keyprovider(&snapshot, &key_provider).is_ok());
+    assert!(vault.list_revoked().unwrap().is_empty());
+
+    // only collect records that have been revoked longer than the grace period ago
+    assert!(stronghold
+        .set_garbage_collect_policy(GarbageCollectPolicy::GracePeriod(Duration::from_secs(3600)))
+        .is_ok());
+    revoke_new_secret();
+    assert!(stronghold.commit_with_keyprovider(&snapshot, &key_provider).is_ok());
+    assert_eq!(vault.list_revoked().unwrap().len(), 1);
+
+    // without grace period all revoked records are collected
+    assert!(stronghold
+        .set_garbage_collect_policy(GarbageCollectPolicy::GracePeriod(Duration::ZERO))
+        .is_ok());
+    assert!(stronghold.commit_with_keyprovider(&snapshot, &key_provider).is_ok());
+    assert!(vault.list_revoked().unwrap().is_empty());
+}
+
+#[test]
+fn test_expired_secrets_are_removed_on_commit() {
+    let vault_path = b"vault_path".to_vec();
+    let key_provider = KeyProvider::try_from(fixed_random_bytes(32)).expect("Failed to create keyprovider");
+
+    let filename = base64::encode(fixed_random_bytes(32));
+    let filename = filename.replace('/', "n");
+    let mut snapshot_path = std::env::temp_dir();
+    snapshot_path.push(filename);
+
+    let defer = Defer::from((snapshot_path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot = SnapshotPath::from_path(&*defer);
+
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .create_client("client_path")
+        .expect("Failed to create client");
+    let vault = client.vault(vault_path.clone());
+
+    let expired = Location::generic(vault_path.clone(), b"expired".to_vec());
+    let ephemeral = Location::generic(vault_path.clone(), b"ephemeral".to_vec());
+    let persistent = Location::generic(vault_path.clone(), b"persistent".to_vec());
+
+    assert!(vault
+        .write_secret_with_ttl(expired.clone(), b"payload".to_vec(), Duration::ZERO)
+        .is_ok());
+    assert!(vault
+        .write_secret_with_ttl(ephemeral.clone(), b"payload".to_vec(), Duration::from_secs(3600))
+        .is_ok());
+    assert!(vault.write_secret(persistent.clone(), b"payload".to_vec()).is_ok());
+
+    // a time-to-live out of range fails without writing the secret
+    let unbounded = Location::generic(vault_path.clone(), b"unbounded".to_vec());
+    assert!(vault
+        .write_secret_with_ttl(unbounded.clone(), b"payload".to_vec(), Duration::MAX)
+        .is_err());
+    assert!(!client.record_exists(&unbounded).unwrap());
+    assert!(client.set_record_ttl(&persistent, Some(Duration::MAX)).is_err());
+
+    // expired secrets can not be used anymore
+    assert!(vault.read_secret(b"expired").is_err());
+    assert!(vault.read_secret(b"ephemeral").is_ok());
+
+    // procedures fail on expired secrets
+    let key_location = Location::generic(vault_path.clone(), b"key".to_vec());
+    let generate_key = GenerateKey {
+        ty: KeyType::Ed25519,
+        output: key_location.clone(),
+    };
+    assert!(client.execute_procedure(generate_key).is_ok());
+    let public_key = crate::procedures::PublicKey {
+        ty: KeyType::Ed25519,
+        private_key: key_location.clone(),
+    };
+    assert!(client.execute_procedure(public_key.clone()).is_ok());
+    assert!(client.set_record_ttl(&key_location, Some(Duration::ZERO)).is_ok());
+    assert!(client.execute_procedure(public_key).is_err());
+
+    // removing the expiry makes the secret usable again
+    assert!(client.set_record_ttl(&expired, None).is_ok());
+    assert!(vault.read_secret(b"expired").is_ok());
+    assert!(client.set_record_ttl(&expired, Some(Duration::ZERO)).is_ok());
+
+    // the expiry is kept when the secret is updated
+    assert!(vault.write_secret(expired.clone(), b"updated".to_vec()).is_ok());
+    assert!(vault.read_secret(b"expired").is_err());
+
+    assert!(stronghold.commit_with_keyprovider(&snapshot, &key_provider).is_ok());
+    assert!(!client.record_exists(&expired).unwrap());
+    assert!(!client.record_exists(&key_location).unwrap());
+    assert!(client.record_exists(&ephemeral).unwrap());
+    assert!(client.record_exists(&persistent).unwrap());
+    assert!(vault.list_revoked().unwrap().is_empty());
+}
+
+#[test]
+fn test_usage_policy_persists_in_snapshot() {
+    let client_path = b"client_path".to_vec();
+    let location = Location::generic(b"vault_path".to_vec(), b"record_path".to_vec());
+    let key_provider = KeyProvider::try_from(fixed_random_bytes(32)).expect("Failed to create keyprovider");
+
+    let filename = base64::encode(fixed_random_bytes(32));
+    let filename = filename.replace('/', "n");
+    let mut snapshot_path = std::env::temp_dir();
+    snapshot_path.push(filename);
+
+    let defer = Defer::from((snapshot_path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot = SnapshotPath::from_path(&*defer);
+
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .create_client(client_path.clone())
+        .expect("Failed to create client");
+    let generate_key = GenerateKey {
+        ty: KeyType::Ed25519,
+        output: location.clone(),
+    };
+    assert!(client.execute_procedure(generate_key).is_ok());
+
+    let policy = KeyUsagePolicy {
+        remaining_uses: Some(2),
+        allowed_operations: Some(vec![KeyOperation::Sign]),
+    };
+    assert!(client.set_usage_policy(&location, policy).is_ok());
+
+    let sign = Ed25519Sign {
+        private_key: location.clone(),
+        msg: b"message".to_vec(),
+    };
+    assert!(client.execute_procedure(sign.clone()).is_ok());
+    assert!(stronghold.write_client(client_path.clone()).is_ok());
+    assert!(stronghold.commit_with_keyprovider(&snapshot, &key_provider).is_ok());
+
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .load_client_from_snapshot(client_path, &key_provider, &snapshot)
+        .expect("Failed to load client from snapshot");
+
+    let policy = client.usage_policy(&location).expect("Failed to get usage policy");
+    assert_eq!(policy.remaining_uses, Some(1));
+    assert_eq!(policy.allowed_operations, Some(vec![KeyOperation::Sign]));
+
+    assert!(client.execute_procedure(sign.clone()).is_ok());
+    assert!(client.execute_procedure(sign).is_err());
+}
+
+#[test]
+fn test_preview_snapshot_merge() {
+    let client_path = b"client_path".to_vec();
+    let vault_path = b"vault_path".to_vec();
+    let location = |record_path: &[u8]| Location::generic(vault_path.clone(), record_path.to_vec());
+    let key_provider = KeyProvider::try_from(fixed_random_bytes(32)).expect("Failed to create keyprovider");
+
+    let snapshot_path = |_| {
+        let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+        let mut path = std::env::temp_dir();
+        path.push(filename);
+        Defer::from((path, |path: &'_ PathBuf| {
+            let _ = std::fs::remove_file(path);
+        }))
+    };
+    let defer_a = snapshot_path(());
+    let defer_b = snapshot_path(());
+    let snapshot_a = SnapshotPath::from_path(&*defer_a);
+    let snapshot_b = SnapshotPath::from_path(&*defer_b);
+
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .create_client(client_path.clone())
+        .expect("Failed to create client");
+    let vault = client.vault(vault_path.clone());
+    assert!(vault.write_secret(location(b"record_1"), b"secret".to_vec()).is_ok());
+    assert!(vault.write_secret(location(b"record_2"), b"secret".to_vec()).is_ok());
+    assert!(stronghold.write_client(client_path.clone()).is_ok());
+    assert!(stronghold.commit_with_keyprovider(&snapshot_a, &key_provider).is_ok());
+
+    // Replace record 1 and add record 3.
+    assert!(vault.write_secret(location(b"record_1"), b"changed".to_vec()).is_ok());
+    assert!(vault.write_secret(location(b"record_3"), b"secret".to_vec()).is_ok());
+    assert!(stronghold.write_client(client_path.clone()).is_ok());
+    assert!(stronghold.commit_with_keyprovider(&snapshot_b, &key_provider).is_ok());
+
+    let stronghold = Stronghold::default();
+    assert!(stronghold.load_snapshot(&key_provider, &snapshot_a).is_ok());
+    let mut report = stronghold
+        .preview_snapshot_merge(&key_provider, &snapshot_b, &SyncSnapshotsConfig::default())
+        .expect("Failed to preview merge");
+
+    let cid = ClientId::load_from_path(&client_path, &client_path);
+    let vault_report = report
+        .remove(&cid)
+        .and_then(|mut r| r.remove(&derive_vault_id(vault_path.clone())))
+        .expect("Missing report for vault");
+    assert_eq!(vault_report.added, vec![location(b"record_3").resolve().1]);
+    assert_eq!(vault_report.replaced, vec![location(b"record_1").resolve().1]);
+    assert_eq!(vault_report.skipped, vec![location(b"record_2").resolve().1]);
+    assert_eq!(vault_report.conflicts.len(), 1);
+    assert_eq!(vault_report.conflicts[0].record_id, location(b"record_1").resolve().1);
+
+    // Previewing does not modify the loaded snapshot.
+    let client = stronghold.load_client(client_path).expect("Failed to load client");
+    assert!(!client.record_exists(&location(b"record_3")).unwrap());
+}
+
+#[test]
+fn test_import_signed_bundle() {
+    let cid = ClientId::load_from_path(b"client", b"client");
+    let location = Location::generic(b"vault".to_vec(), b"record".to_vec());
+    let (vid, rid) = location.resolve();
+
+    let client = Client::default();
+    client
+        .vault(b"vault")
+        .write_secret(location.clone(), b"secret".to_vec())
+        .unwrap();
+    let keys = client.keystore.write().unwrap().get_data();
+    let db = client.db.read().unwrap().clone();
+
+    let signing_key = Location::generic(b"keys".to_vec(), b"signing".to_vec());
+    let exchange_key = Location::generic(b"keys".to_vec(), b"exchange".to_vec());
+    let new_snapshot = || {
+        let mut snapshot = Snapshot::default();
+        let signing_sk = ed25519::SecretKey::generate().unwrap();
+        snapshot
+            .store_secret_key(signing_sk.to_bytes(), signing_key.clone())
+            .unwrap();
+        let exchange_sk = x25519::SecretKey::generate().unwrap();
+        snapshot
+            .store_secret_key(exchange_sk.to_bytes(), exchange_key.clone())
+            .unwrap();
+        snapshot
+    };
+    let mut snapshot_a = new_snapshot();
+    snapshot_a.add_data(cid, (keys, db, Default::default())).unwrap();
+    let mut snapshot_b = new_snapshot();
+    let identity_a = snapshot_a
+        .peer_identity(signing_key.clone(), exchange_key.clone())
+        .unwrap();
+    let identity_b = snapshot_b
+        .peer_identity(signing_key.clone(), exchange_key.clone())
+        .unwrap();
+
+    let select = [(cid, [(vid, vec![rid])].into())].into();
+    let bundle = snapshot_a
+        .export_signed_bundle(select, &identity_b, signing_key.clone())
+        .unwrap();
+
+    // Bundles from unknown peers are rejected.
+    let res = snapshot_b.import_signed_bundle(&bundle, exchange_key.clone(), SyncSnapshotsConfig::default());
+    assert!(matches!(res, Err(RemoteMergeError::UntrustedPeer(pk)) if pk == identity_a.signing_key));
+    assert!(!snapshot_b.has_data(cid));
+
+    // Modified bundles are rejected.
+    snapshot_b.trust_store_mut().trust(identity_a);
+    let mut modified = bundle.clone();
+    let last = modified.len() - 1;
+    modified[last] ^= 1;
+    let res = snapshot_b.import_signed_bundle(&modified, exchange_key.clone(), SyncSnapshotsConfig::default());
+    assert!(matches!(res, Err(RemoteMergeError::InvalidSignature)));
+
+    snapshot_b
+        .import_signed_bundle(&bundle, exchange_key.clone(), SyncSnapshotsConfig::default())
+        .unwrap();
+    let (_, db, _) = snapshot_b.get_state(cid).unwrap();
+    assert!(db.contains_record(vid, rid));
+
+    // Bundles from revoked peers are rejected.
+    assert!(snapshot_b.trust_store_mut().revoke(&identity_a.signing_key));
+    let res = snapshot_b.import_signed_bundle(&bundle, exchange_key, SyncSnapshotsConfig::default());
+    assert!(matches!(res, Err(RemoteMergeError::UntrustedPeer(_))));
+}
+
+#[test]
+fn test_trust_store_persists_in_snapshot() {
+    let client_path = b"client_path".to_vec();
+    let key_provider = KeyProvider::try_from(fixed_random_bytes(32)).expect("Failed to create keyprovider");
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+
+    let trusted = PeerIdentity {
+        signing_key: rand::random(),
+        exchange_key: rand::random(),
+    };
+    let revoked = PeerIdentity {
+        signing_key: rand::random(),
+        exchange_key: rand::random(),
+    };
+    let stronghold = Stronghold::default();
+    stronghold.create_client(client_path.clone()).unwrap();
+    stronghold.trust_peer(trusted).unwrap();
+    stronghold.trust_peer(revoked).unwrap();
+    assert!(stronghold.revoke_peer(&revoked.signing_key).unwrap());
+    assert!(!stronghold.revoke_peer(&rand::random()).unwrap());
+    stronghold.write_client(client_path.clone()).unwrap();
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &key_provider)
+        .unwrap();
+
+    let stronghold = Stronghold::default();
+    stronghold.load_snapshot(&key_provider, &snapshot_path).unwrap();
+    let trust_store = stronghold.trust_store().unwrap();
+    assert_eq!(
+        trust_store.get(&trusted.signing_key),
+        Some((trusted, PeerStatus::Trusted))
+    );
+    assert_eq!(
+        trust_store.get(&revoked.signing_key),
+        Some((revoked, PeerStatus::Revoked))
+    );
+    assert!(stronghold.load_client(client_path).is_ok());
+
+    // The state can still be read without the trust store.
+    assert!(stronghold
+        .preview_snapshot_merge(&key_provider, &snapshot_path, &SyncSnapshotsConfig::default())
+        .is_ok());
+}
+
+#[test]
+fn test_export_import_vault() {
+    let vault_path = b"account".to_vec();
+    let location = |record_path: &[u8]| Location::generic(vault_path.clone(), record_path.to_vec());
+    let key_provider = KeyProvider::try_from(fixed_random_bytes(32)).expect("Failed to create keyprovider");
+    let blob_id = |client: &Client, location: &Location| {
+        let (vid, rid) = location.resolve();
+        let key = client.keystore.read().unwrap().get_key(vid).unwrap();
+        client.db.read().unwrap().get_blob_id(&key, vid, rid).unwrap()
+    };
+
+    let source = Client::default();
+    let vault = source.vault(vault_path.clone());
+    for record_path in [b"record_1", b"record_2", b"record_3"] {
+        vault.write_secret(location(record_path), b"secret".to_vec()).unwrap();
+    }
+    vault.revoke_secret(b"record_3").unwrap();
+    source
+        .vault(b"other")
+        .write_secret(
+            Location::generic(b"other".to_vec(), b"record".to_vec()),
+            b"secret".to_vec(),
+        )
+        .unwrap();
+
+    let bundle = source.export_vault(vault_path.clone(), &key_provider).unwrap();
+    assert!(source.export_vault(b"missing", &key_provider).is_err());
+
+    // The bundle can only be imported with the same key.
+    let target = Client::default();
+    let wrong_key = KeyProvider::try_from(fixed_random_bytes(32)).expect("Failed to create keyprovider");
+    assert!(target.import_vault(&bundle, &wrong_key, MergePolicy::Replace).is_err());
+
+    target
+        .import_vault(&bundle, &key_provider, MergePolicy::Replace)
+        .unwrap();
+    assert!(target.record_exists(&location(b"record_1")).unwrap());
+    assert!(target.record_exists(&location(b"record_2")).unwrap());
+    assert!(!target.record_exists(&location(b"record_3")).unwrap());
+    assert!(!target.vault_exists(b"other").unwrap());
+    assert_eq!(
+        blob_id(&target, &location(b"record_1")),
+        blob_id(&source, &location(b"record_1"))
+    );
+
+    // Conflicting records are resolved with the merge policy.
+    let target_vault = target.vault(vault_path.clone());
+    target_vault
+        .write_secret(location(b"record_1"), b"changed".to_vec())
+        .unwrap();
+    let changed = blob_id(&target, &location(b"record_1"));
+    target
+        .import_vault(&bundle, &key_provider, MergePolicy::KeepOld)
+        .unwrap();
+    assert_eq!(blob_id(&target, &location(b"record_1")), changed);
+    target
+        .import_vault(&bundle, &key_provider, MergePolicy::Replace)
+        .unwrap();
+    assert_eq!(
+        blob_id(&target, &location(b"record_1")),
+        blob_id(&source, &location(b"record_1"))
+    );
+}
+
+#[test]
+fn test_merge_snapshot_files() {
+    let client_path = b"device".to_vec();
+    let snapshot_file = || {
+        let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+        let mut path = std::env::temp_dir();
+        path.push(filename);
+        Defer::from((path, |path: &'_ PathBuf| {
+            let _ = std::fs::remove_file(path);
+        }))
+    };
+    let target_file = snapshot_file();
+    let source_file = snapshot_file();
+    let target_path = SnapshotPath::from_path(&*target_file);
+    let source_path = SnapshotPath::from_path(&*source_file);
+    let target_key = fixed_random_bytes(32);
+    let source_key = fixed_random_bytes(32);
+    let key_provider = |key: &Vec<u8>| KeyProvider::try_from(key.clone()).expect("Failed to create keyprovider");
+
+    let peer = PeerIdentity {
+        signing_key: rand::random(),
+        exchange_key: rand::random(),
+    };
+    let write_snapshot = |snapshot_path: &SnapshotPath, key: &Vec<u8>, record_path: &[u8]| {
+        let stronghold = Stronghold::default();
+        let client = stronghold.create_client(client_path.clone()).unwrap();
+        client
+            .vault(b"vault")
+            .write_secret(
+                Location::generic(b"vault".to_vec(), record_path.to_vec()),
+                b"secret".to_vec(),
+            )
+            .unwrap();
+        stronghold.trust_peer(peer).unwrap();
+        stronghold.write_client(client_path.clone()).unwrap();
+        stronghold
+            .commit_with_keyprovider(snapshot_path, &key_provider(key))
+            .unwrap();
+    };
+    write_snapshot(&target_path, &target_key, b"target_record");
+    write_snapshot(&source_path, &source_key, b"source_record");
+
+    crate::merge_snapshot_files(
+        &target_path,
+        target_key.clone().try_into().unwrap(),
+        &source_path,
+        source_key.clone().try_into().unwrap(),
+        SyncSnapshotsConfig::default(),
+    )
+    .unwrap();
+
+    // The source file can not be used as target with the wrong key.
+    assert!(crate::merge_snapshot_files(
+        &source_path,
+        target_key.clone().try_into().unwrap(),
+        &target_path,
+        target_key.clone().try_into().unwrap(),
+        SyncSnapshotsConfig::default(),
+    )
+    .is_err());
+
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .load_client_from_snapshot(client_path.clone(), &key_provider(&target_key), &target_path)
+        .unwrap();
+    for record_path in [b"target_record".to_vec(), b"source_record".to_vec()] {
+        assert!(client
+            .record_exists(&Location::generic(b"vault".to_vec(), record_path))
+            .unwrap());
+    }
+    assert!(stronghold.trust_store().unwrap().is_trusted(&peer.signing_key));
+}
+
+#[test]
+fn test_key_derivation_header() {
+    let client_path = b"client_path".to_vec();
+    let location = Location::generic(b"vault".to_vec(), b"record".to_vec());
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+    let params = |salt: &[u8], time_cost| Argon2Params {
+        variant: Argon2Variant::Argon2id,
+        salt: salt.to_vec(),
+        mem_cost: 64,
+        time_cost,
+        lanes: 1,
+    };
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client
+        .vault(b"vault")
+        .write_secret(location.clone(), b"secret".to_vec())
+        .unwrap();
+    stronghold.write_client(client_path.clone()).unwrap();
+    // parameters that could not be read from the header are rejected
+    assert!(KeyProvider::with_passphrase_argon2_params(b"password".to_vec(), params(b"first salt", 0)).is_err());
+    assert!(KeyProvider::with_passphrase_argon2_params(b"password".to_vec(), params(b"first salt", 1000)).is_err());
+    let keyprovider =
+        KeyProvider::with_passphrase_argon2_params(b"password".to_vec(), params(b"first salt", 1)).unwrap();
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+
+    // The key is derived with the salt and parameters from the header.
+    let keyprovider = KeyProvider::from_password_for_snapshot(&snapshot_path, b"password".to_vec()).unwrap();
+    assert_eq!(
+        keyprovider.key_derivation(),
+        Some(&KeyDerivation::Argon2(params(b"first salt", 1)))
+    );
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .load_client_from_snapshot(client_path.clone(), &keyprovider, &snapshot_path)
+        .unwrap();
+    assert!(client.record_exists(&location).unwrap());
+
+    let wrong_password = KeyProvider::from_password_for_snapshot(&snapshot_path, b"wrong".to_vec()).unwrap();
+    assert!(Stronghold::default()
+        .load_client_from_snapshot(client_path.clone(), &wrong_password, &snapshot_path)
+        .is_err());
+
+    // Committing with stronger parameters updates the header.
+    let keyprovider =
+        KeyProvider::with_passphrase_argon2_params(b"password".to_vec(), params(b"second salt", 2)).unwrap();
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+    let keyprovider = KeyProvider::from_password_for_snapshot(&snapshot_path, b"password".to_vec()).unwrap();
+    assert_eq!(
+        keyprovider.key_derivation(),
+        Some(&KeyDerivation::Argon2(params(b"second salt", 2)))
+    );
+    assert!(Stronghold::default()
+        .load_client_from_snapshot(client_path.clone(), &keyprovider, &snapshot_path)
+        .is_ok());
+
+    // A snapshot that is committed with a raw key does not describe a key derivation.
+    let keyprovider = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+    assert!(matches!(
+        KeyProvider::from_password_for_snapshot(&snapshot_path, b"password".to_vec()),
+        Err(ClientError::MissingKeyDerivation)
+    ));
+}
+
+#[test]
+fn test_key_slots() {
+    let client_path = b"client_path".to_vec();
+    let location = Location::generic(b"vault".to_vec(), b"record".to_vec());
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+    let params = |salt: &[u8]| Argon2Params {
+        variant: Argon2Variant::Argon2id,
+        salt: salt.to_vec(),
+        mem_cost: 64,
+        time_cost: 1,
+        lanes: 1,
+    };
+    let load = |keyprovider: &KeyProvider| {
+        Stronghold::default()
+            .load_client_from_snapshot(client_path.clone(), keyprovider, &snapshot_path)
+            .map(|client| client.record_exists(&location).unwrap())
+    };
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client
+        .vault(b"vault")
+        .write_secret(location.clone(), b"secret".to_vec())
+        .unwrap();
+    stronghold.write_client(client_path.clone()).unwrap();
+    let password = KeyProvider::with_passphrase_argon2_params(b"password".to_vec(), params(b"password salt")).unwrap();
+    stronghold.commit_with_keyprovider(&snapshot_path, &password).unwrap();
+    assert!(stronghold.list_key_slots(&snapshot_path).unwrap().is_empty());
+
+    let recovery_code =
+        KeyProvider::with_passphrase_argon2_params(b"recovery code".to_vec(), params(b"recovery salt")).unwrap();
+    let key_file = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    let recipient_key = x25519::SecretKey::generate().unwrap();
+    let recipient = KeyProvider::try_from(recipient_key.to_bytes().to_vec()).unwrap();
+
+    // The first key slot also adds a slot for the current key.
+    stronghold
+        .add_key_slot(&snapshot_path, &password, NewKeySlot::RecoveryCode(&recovery_code))
+        .unwrap();
+    stronghold
+        .add_key_slot(&snapshot_path, &recovery_code, NewKeySlot::KeyFile(&key_file))
+        .unwrap();
+    stronghold
+        .add_key_slot(
+            &snapshot_path,
+            &key_file,
+            NewKeySlot::Recipient(recipient_key.public_key()),
+        )
+        .unwrap();
+    assert!(matches!(
+        stronghold.add_key_slot(&snapshot_path, &key_file, NewKeySlot::Password(&key_file)),
+        Err(ClientError::MissingKeyDerivation)
+    ));
+    assert_eq!(
+        stronghold.list_key_slots(&snapshot_path).unwrap(),
+        vec![
+            KeySlotKind::Password(KeyDerivation::Argon2(params(b"password salt"))),
+            KeySlotKind::RecoveryCode(KeyDerivation::Argon2(params(b"recovery salt"))),
+            KeySlotKind::KeyFile,
+            KeySlotKind::Recipient(recipient_key.public_key().to_bytes()),
+        ]
+    );
+    for keyprovider in [&password, &recovery_code, &key_file, &recipient] {
+        assert!(load(keyprovider).unwrap());
+    }
+    let recovered = KeyProvider::from_password_for_snapshot(&snapshot_path, b"recovery code".to_vec()).unwrap();
+    assert!(load(&recovered).unwrap());
+    assert!(matches!(
+        KeyProvider::from_password_for_snapshot(&snapshot_path, b"wrong".to_vec()),
+        Err(ClientError::WrongKey)
+    ));
+
+    // Removing a slot revokes its key, the others are still valid.
+    stronghold.remove_key_slot(&snapshot_path, &key_file, 0).unwrap();
+    assert!(matches!(load(&password), Err(ClientError::WrongKey)));
+    assert!(load(&recovery_code).unwrap());
+    assert!(matches!(
+        stronghold.remove_key_slot(&snapshot_path, &password, 0),
+        Err(ClientError::WrongKey)
+    ));
+    assert!(matches!(
+        stronghold.remove_key_slot(&snapshot_path, &key_file, 3),
+        Err(ClientError::KeySlot(_))
+    ));
+
+    // The key slots are kept when the loaded snapshot is committed again.
+    client
+        .vault(b"vault")
+        .write_secret(
+            Location::generic(b"vault".to_vec(), b"other".to_vec()),
+            b"other".to_vec(),
+        )
+        .unwrap();
+    stronghold.write_client(client_path.clone()).unwrap();
+    stronghold.commit_with_keyprovider(&snapshot_path, &recipient).unwrap();
+    assert_eq!(stronghold.list_key_slots(&snapshot_path).unwrap().len(), 3);
+    let client = Stronghold::default()
+        .load_client_from_snapshot(client_path.clone(), &key_file, &snapshot_path)
+        .unwrap();
+    assert!(client
+        .record_exists(&Location::generic(b"vault".to_vec(), b"other".to_vec()))
+        .unwrap());
+
+    // The last key slot can not be removed.
+    stronghold.remove_key_slot(&snapshot_path, &key_file, 0).unwrap();
+    stronghold.remove_key_slot(&snapshot_path, &key_file, 0).unwrap();
+    let err = stronghold.remove_key_slot(&snapshot_path, &recipient, 0).unwrap_err();
+    assert!(matches!(&err, ClientError::KeySlot(e) if e.contains("last key slot")));
+    assert!(load(&recipient).unwrap());
+}
+
+#[test]
+fn test_threshold_unlock() {
+    let client_path = b"client_path".to_vec();
+    let location = Location::generic(b"vault".to_vec(), b"record".to_vec());
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+    let load = |keyprovider: &KeyProvider| {
+        Stronghold::default()
+            .load_client_from_snapshot(client_path.clone(), keyprovider, &snapshot_path)
+            .map(|client| client.record_exists(&location).unwrap())
+    };
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client
+        .vault(b"vault")
+        .write_secret(location.clone(), b"secret".to_vec())
+        .unwrap();
+    stronghold.write_client(client_path.clone()).unwrap();
+    let setup_key = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    stronghold.commit_with_keyprovider(&snapshot_path, &setup_key).unwrap();
+
+    // 2 of 3 officers are required, the setup key is removed afterwards.
+    let officers: Vec<KeyProvider> = (0..3)
+        .map(|i| KeyProvider::with_passphrase_hashed_blake2b(format!("officer {}", i).into_bytes()).unwrap())
+        .collect();
+    let holders: Vec<&KeyProvider> = officers.iter().collect();
+    stronghold
+        .add_key_slot(
+            &snapshot_path,
+            &setup_key,
+            NewKeySlot::Threshold {
+                threshold: 2,
+                holders: &holders,
+            },
+        )
+        .unwrap();
+    stronghold.remove_key_slot(&snapshot_path, &setup_key, 0).unwrap();
+    assert!(load(&setup_key).is_err());
+    for officer in officers.iter() {
+        assert!(load(officer).is_err());
+    }
+
+    let mut unlock = ThresholdUnlock::for_snapshot(&snapshot_path).unwrap();
+    assert_eq!(unlock.remaining(), 2);
+    assert!(matches!(unlock.add_share(&setup_key), Err(ClientError::KeySlot(_))));
+    assert_eq!(unlock.add_share(&officers[2]).unwrap(), 1);
+    assert!(matches!(unlock.add_share(&officers[2]), Err(ClientError::KeySlot(_))));
+    assert!(matches!(unlock.unlock(), Err(ClientError::KeySlot(_))));
+    assert_eq!(unlock.add_share(&officers[0]).unwrap(), 0);
+    let keyprovider = unlock.unlock().unwrap();
+    assert!(load(&keyprovider).unwrap());
+
+    // The reconstructed key also commits the snapshot, keeping the threshold key slot.
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+    let mut unlock = ThresholdUnlock::for_snapshot(&snapshot_path).unwrap();
+    unlock.add_share(&officers[1]).unwrap();
+    unlock.add_share(&officers[0]).unwrap();
+    assert!(load(&unlock.unlock().unwrap()).unwrap());
+}
+
+#[test]
+fn test_unlock_policy() {
+    let client_path = b"client_path".to_vec();
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+    let mut attempts_path = snapshot_path.as_path().as_os_str().to_os_string();
+    attempts_path.push(".attempts");
+    let attempts_path = PathBuf::from(attempts_path);
+
+    let key = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    let wrong_key = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    let stronghold = Stronghold::default();
+    stronghold.create_client(client_path.clone()).unwrap();
+    stronghold.write_client(client_path.clone()).unwrap();
+    stronghold.commit_with_keyprovider(&snapshot_path, &key).unwrap();
+
+    // Without a policy, failed attempts are neither delayed nor counted.
+    for _ in 0..3 {
+        assert!(matches!(
+            stronghold.load_snapshot(&wrong_key, &snapshot_path),
+            Err(ClientError::WrongKey)
+        ));
+    }
+    assert!(!attempts_path.exists());
+    stronghold.load_snapshot(&key, &snapshot_path).unwrap();
+
+    let policy = UnlockPolicy::default()
+        .with_backoff(Duration::from_millis(100), Duration::from_millis(300))
+        .with_wipe_after(4);
+    let policy_stronghold = || {
+        let stronghold = Stronghold::default();
+        stronghold.set_unlock_policy(policy).unwrap();
+        stronghold
+    };
+
+    // A successful attempt after the backoff resets the counter.
+    assert!(matches!(
+        policy_stronghold().load_snapshot(&wrong_key, &snapshot_path),
+        Err(ClientError::WrongKey)
+    ));
+    assert!(attempts_path.exists());
+    assert!(matches!(
+        policy_stronghold().load_snapshot(&key, &snapshot_path),
+        Err(ClientError::UnlockBackoff(_))
+    ));
+    std::thread::sleep(Duration::from_millis(120));
+    policy_stronghold().load_snapshot(&key, &snapshot_path).unwrap();
+    assert!(!attempts_path.exists());
+
+    // The backoff doubles with each failed attempt, up to the maximum.
+    let stronghold = policy_stronghold();
+    assert!(matches!(
+        stronghold.load_snapshot(&wrong_key, &snapshot_path),
+        Err(ClientError::WrongKey)
+    ));
+    std::thread::sleep(Duration::from_millis(120));
+    assert!(matches!(
+        stronghold.load_snapshot(&wrong_key, &snapshot_path),
+        Err(ClientError::WrongKey)
+    ));
+    assert!(matches!(
+        stronghold.load_client_from_snapshot(client_path.clone(), &key, &snapshot_path),
+        Err(ClientError::UnlockBackoff(remaining)) if remaining > Duration::from_millis(100)
+    ));
+    std::thread::sleep(Duration::from_millis(220));
+    assert!(matches!(
+        stronghold.load_snapshot(&wrong_key, &snapshot_path),
+        Err(ClientError::WrongKey)
+    ));
+    assert!(matches!(
+        stronghold.load_snapshot(&key, &snapshot_path),
+        Err(ClientError::UnlockBackoff(remaining)) if remaining <= Duration::from_millis(300)
+    ));
+
+    // The snapshot is wiped after the 4th failed attempt.
+    std::thread::sleep(Duration::from_millis(320));
+    assert!(matches!(
+        stronghold.load_snapshot(&wrong_key, &snapshot_path),
+        Err(ClientError::SnapshotWiped)
+    ));
+    assert!(!snapshot_path.exists());
+    assert!(!attempts_path.exists());
+    assert!(matches!(
+        stronghold.load_snapshot(&key, &snapshot_path),
+        Err(ClientError::SnapshotFileMissing(_))
+    ));
+}
+
+#[test]
+fn test_unlock_policy_paths() {
+    let client_path = b"client_path".to_vec();
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    std::fs::create_dir(&path).unwrap();
+    let dir = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_dir_all(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(dir.join("snapshot"));
+    let attempts_path = dir.join("snapshot.attempts");
+
+    let key = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    let wrong_key = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    let stronghold = Stronghold::default();
+    stronghold.create_client(client_path.clone()).unwrap();
+    stronghold.write_client(client_path).unwrap();
+    stronghold.commit_with_keyprovider(&snapshot_path, &key).unwrap();
+
+    // Every method that unlocks the snapshot file counts failed attempts.
+    let stronghold = Stronghold::default();
+    stronghold
+        .set_unlock_policy(UnlockPolicy::default().with_wipe_after(3))
+        .unwrap();
+    assert!(matches!(
+        stronghold.preview_snapshot_merge(&wrong_key, &snapshot_path, &SyncSnapshotsConfig::default()),
+        Err(ClientError::WrongKey)
+    ));
+    assert!(matches!(
+        stronghold.add_key_slot(&snapshot_path, &wrong_key, NewKeySlot::KeyFile(&key)),
+        Err(ClientError::WrongKey)
+    ));
+    assert!(attempts_path.exists());
+    stronghold
+        .add_key_slot(&snapshot_path, &key, NewKeySlot::KeyFile(&key))
+        .unwrap();
+    assert!(!attempts_path.exists());
+
+    // A corrupted snapshot with key slots is not counted as failed attempt.
+    let mut bytes = std::fs::read(snapshot_path.as_path()).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(snapshot_path.as_path(), &bytes).unwrap();
+    for _ in 0..3 {
+        assert!(matches!(
+            stronghold.load_snapshot(&key, &snapshot_path),
+            Err(ClientError::Inner(_))
+        ));
+    }
+    assert!(!attempts_path.exists());
+    assert!(snapshot_path.exists());
+    bytes[last] ^= 1;
+    std::fs::write(snapshot_path.as_path(), &bytes).unwrap();
+
+    // A backoff beyond the representable time does not overflow.
+    stronghold
+        .set_unlock_policy(UnlockPolicy {
+            backoff: Some(Duration::MAX),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(matches!(
+        stronghold.load_snapshot(&wrong_key, &snapshot_path),
+        Err(ClientError::WrongKey)
+    ));
+    assert!(matches!(
+        stronghold.load_snapshot(&key, &snapshot_path),
+        Err(ClientError::UnlockBackoff(Duration::MAX))
+    ));
+
+    // A corrupted counter is treated as the maximum number of failed attempts.
+    stronghold
+        .set_unlock_policy(UnlockPolicy::default().with_backoff(Duration::from_millis(50), Duration::from_millis(200)))
+        .unwrap();
+    std::fs::write(&attempts_path, b"corrupted").unwrap();
+    assert!(matches!(
+        stronghold.load_snapshot(&key, &snapshot_path),
+        Err(ClientError::UnlockBackoff(remaining)) if remaining > Duration::from_millis(100)
+    ));
+    std::thread::sleep(Duration::from_millis(220));
+    stronghold.load_snapshot(&key, &snapshot_path).unwrap();
+    assert!(!attempts_path.exists());
+
+    // Concurrent attempts of different strongholds can not bypass the counter, only the first one is tried.
+    let wrong_bytes = fixed_random_bytes(32);
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let snapshot_path = snapshot_path.clone();
+            let wrong_bytes = wrong_bytes.clone();
+            std::thread::spawn(move || {
+                let stronghold = Stronghold::default();
+                stronghold
+                    .set_unlock_policy(
+                        UnlockPolicy::default().with_backoff(Duration::from_secs(60), Duration::from_secs(60)),
+                    )
+                    .unwrap();
+                let wrong_key = KeyProvider::try_from(wrong_bytes).unwrap();
+                stronghold.load_snapshot(&wrong_key, &snapshot_path)
+            })
+        })
+        .collect();
+    let results: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+    let count = |f: fn(&Result<(), ClientError>) -> bool| results.iter().filter(|result| f(result)).count();
+    assert_eq!(count(|result| matches!(result, Err(ClientError::WrongKey))), 1);
+    assert_eq!(count(|result| matches!(result, Err(ClientError::UnlockBackoff(_)))), 3);
+}
+
+#[test]
+fn test_composite_key() {
+    let client_path = b"client_path".to_vec();
+    let location = Location::generic(b"vault".to_vec(), b"record".to_vec());
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    std::fs::create_dir(&path).unwrap();
+    let dir = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_dir_all(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(dir.join("snapshot"));
+    let key_file = dir.join("key_file");
+    let other_key_file = dir.join("other_key_file");
+    std::fs::write(&key_file, fixed_random_bytes(64)).unwrap();
+    std::fs::write(&other_key_file, fixed_random_bytes(64)).unwrap();
+    let params = Argon2Params {
+        variant: Argon2Variant::Argon2id,
+        salt: b"composite salt".to_vec(),
+        mem_cost: 64,
+        time_cost: 1,
+        lanes: 1,
+    };
+    let load = |keyprovider: &KeyProvider| {
+        Stronghold::default()
+            .load_client_from_snapshot(client_path.clone(), keyprovider, &snapshot_path)
+            .map(|client| client.record_exists(&location).unwrap())
+    };
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client
+        .vault(b"vault")
+        .write_secret(location.clone(), b"secret".to_vec())
+        .unwrap();
+    stronghold.write_client(client_path.clone()).unwrap();
+    let keyprovider =
+        KeyProvider::with_passphrase_and_key_file(b"password".to_vec(), &key_file, Some(b"pepper"), params.clone())
+            .unwrap();
+    assert_eq!(
+        keyprovider.key_derivation(),
+        Some(&KeyDerivation::Composite {
+            argon2: params.clone(),
+            pepper: true
+        })
+    );
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+
+    let composite =
+        KeyProvider::from_composite_for_snapshot(&snapshot_path, b"password".to_vec(), &key_file, Some(b"pepper"))
+            .unwrap();
+    assert!(load(&composite).unwrap());
+
+    // Each part of the composite key is required.
+    for (password, key_file, pepper) in [
+        (b"wrong".to_vec(), &key_file, Some(&b"pepper"[..])),
+        (b"password".to_vec(), &other_key_file, Some(&b"pepper"[..])),
+        (b"password".to_vec(), &key_file, Some(&b"other pepper"[..])),
+    ] {
+        let keyprovider = KeyProvider::from_composite_for_snapshot(&snapshot_path, password, key_file, pepper).unwrap();
+        assert!(matches!(load(&keyprovider), Err(ClientError::WrongKey)));
+    }
+    assert!(matches!(
+        KeyProvider::from_composite_for_snapshot(&snapshot_path, b"password".to_vec(), &key_file, None),
+        Err(ClientError::PepperMismatch(true))
+    ));
+    assert!(matches!(
+        KeyProvider::from_password_for_snapshot(&snapshot_path, b"password".to_vec()),
+        Err(ClientError::MissingKeyFile)
+    ));
+
+    // A composite key can also unlock a password slot.
+    let password = KeyProvider::with_passphrase_argon2_params(b"password".to_vec(), params.clone()).unwrap();
+    stronghold
+        .add_key_slot(&snapshot_path, &composite, NewKeySlot::Password(&password))
+        .unwrap();
+    let from_password = KeyProvider::from_password_for_snapshot(&snapshot_path, b"password".to_vec()).unwrap();
+    assert!(load(&from_password).unwrap());
+    let composite =
+        KeyProvider::from_composite_for_snapshot(&snapshot_path, b"password".to_vec(), &key_file, Some(b"pepper"))
+            .unwrap();
+    assert!(load(&composite).unwrap());
+    assert!(matches!(
+        KeyProvider::from_composite_for_snapshot(
+            &snapshot_path,
+            b"password".to_vec(),
+            &other_key_file,
+            Some(b"pepper")
+        ),
+        Err(ClientError::WrongKey)
+    ));
+    assert!(matches!(
+        KeyProvider::from_composite_for_snapshot(&snapshot_path, b"password".to_vec(), &key_file, None),
+        Err(ClientError::PepperMismatch(true))
+    ));
+
+    // A header with a tampered memory cost is rejected before any key is derived.
+    let encoded_params = [64u32, 1, 1].map(u32::to_le_bytes).concat();
+    let mut bytes = std::fs::read(snapshot_path.as_path()).unwrap();
+    let mut tampered = 0;
+    while let Some(i) = bytes.windows(encoded_params.len()).position(|w| w == encoded_params) {
+        bytes[i..i + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        tampered += 1;
+    }
+    assert!(tampered > 0);
+    std::fs::write(snapshot_path.as_path(), bytes).unwrap();
+    assert!(matches!(
+        KeyProvider::from_composite_for_snapshot(&snapshot_path, b"password".to_vec(), &key_file, Some(b"pepper")),
+        Err(ClientError::Inner(e)) if e.contains("argon2 memory cost")
+    ));
+}
+
+#[test]
+fn test_idle_lock() {
+    let client_path = b"client_path".to_vec();
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+    let key = fixed_random_bytes(32);
+    let key_location = Location::const_generic(b"snapshot-key".to_vec(), b"snapshot-key".to_vec());
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client.store().insert(b"key".to_vec(), b"value".to_vec(), None).unwrap();
+    stronghold
+        .store_snapshot_key_at_location(KeyProvider::try_from(key.clone()).unwrap(), key_location)
+        .unwrap();
+
+    let locked = Arc::new(AtomicUsize::new(0));
+    let observer = locked.clone();
+    let policy = IdleLockPolicy::new(Duration::from_millis(300))
+        .with_commit(snapshot_path.clone())
+        .on_lock(move |committed| {
+            assert!(committed.is_ok());
+            observer.fetch_add(1, Ordering::SeqCst);
+        });
+    stronghold.set_idle_lock_policy(policy).unwrap();
+
+    // Accessing the client keeps it unlocked.
+    for _ in 0..10 {
+        std::thread::sleep(Duration::from_millis(50));
+        client.store();
+    }
+    assert_eq!(locked.load(Ordering::SeqCst), 0);
+    assert!(stronghold.get_client(client_path.clone()).is_ok());
+
+    // After the timeout the state is committed, and the client is cleared.
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(locked.load(Ordering::SeqCst), 1);
+    assert!(matches!(
+        stronghold.get_client(client_path.clone()),
+        Err(ClientError::ClientDataNotPresent)
+    ));
+    assert!(client.store().get(b"key").unwrap().is_none());
+    assert!(matches!(
+        stronghold.commit(&snapshot_path),
+        Err(ClientError::SnapshotKeyLocationMissing)
+    ));
+
+    // Reopening the client requires the key.
+    let keyprovider = KeyProvider::try_from(key).unwrap();
+    let client = stronghold
+        .load_client_from_snapshot(client_path.clone(), &keyprovider, &snapshot_path)
+        .unwrap();
+    assert_eq!(client.store().get(b"key").unwrap(), Some(b"value".to_vec()));
+
+    // Without a timeout, the client stays loaded.
+    stronghold.set_idle_lock_policy(IdleLockPolicy::default()).unwrap();
+    std::thread::sleep(Duration::from_millis(600));
+    assert_eq!(locked.load(Ordering::SeqCst), 1);
+    assert!(stronghold.get_client(client_path.clone()).is_ok());
+
+    // Locking on demand clears the clients as well.
+    stronghold.lock().unwrap();
+    assert!(stronghold.get_client(client_path.clone()).is_err());
+
+    // A zero timeout locks the clients as soon as they are loaded.
+    let observer = locked.clone();
+    stronghold
+        .set_idle_lock_policy(IdleLockPolicy::new(Duration::ZERO).on_lock(move |_| {
+            observer.fetch_add(1, Ordering::SeqCst);
+        }))
+        .unwrap();
+    stronghold.create_client(client_path.clone()).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(locked.load(Ordering::SeqCst), 2);
+    assert!(stronghold.get_client(client_path).is_err());
+    stronghold.set_idle_lock_policy(IdleLockPolicy::default()).unwrap();
+}
+
+#[test]
+fn test_idle_lock_failed_commit() {
+    let client_path = b"client_path".to_vec();
+    let key_location = Location::const_generic(b"snapshot-key".to_vec(), b"snapshot-key".to_vec());
+    // the parent of the snapshot file can not be created, so the commit fails
+    let snapshot_path = SnapshotPath::from_path("/dev/null/snapshot.stronghold");
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client.store().insert(b"key".to_vec(), b"value".to_vec(), None).unwrap();
+
+    // A policy that commits requires a stored snapshot key.
+    assert!(matches!(
+        stronghold
+            .set_idle_lock_policy(IdleLockPolicy::new(Duration::from_secs(300)).with_commit(snapshot_path.clone())),
+        Err(ClientError::SnapshotKeyLocationMissing)
+    ));
+
+    stronghold
+        .store_snapshot_key_at_location(KeyProvider::try_from(fixed_random_bytes(32)).unwrap(), key_location)
+        .unwrap();
+    let failed = Arc::new(AtomicUsize::new(0));
+    let observer = failed.clone();
+    let policy = IdleLockPolicy::new(Duration::from_secs(300))
+        .with_commit(snapshot_path)
+        .on_lock(move |committed| {
+            assert!(committed.is_err());
+            observer.fetch_add(1, Ordering::SeqCst);
+        });
+    stronghold.set_idle_lock_policy(policy).unwrap();
+
+    // If the commit fails, the clients are kept.
+    assert!(stronghold.lock().is_err());
+    assert_eq!(failed.load(Ordering::SeqCst), 1);
+    assert!(stronghold.get_client(client_path).is_ok());
+    assert_eq!(client.store().get(b"key").unwrap(), Some(b"value".to_vec()));
+    stronghold.set_idle_lock_policy(IdleLockPolicy::default()).unwrap();
+}
+
+#[test]
+fn test_master_key_rotation() {
+    let client_path = b"client_path".to_vec();
+    let vault_path = b"vault_path".to_vec();
+    let key_location = Location::generic(vault_path.clone(), b"key".to_vec());
+    let secret_location = Location::generic(b"other_vault".to_vec(), b"secret".to_vec());
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+    let keyprovider = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client
+        .execute_procedure(GenerateKey {
+            ty: KeyType::Ed25519,
+            output: key_location.clone(),
+        })
+        .unwrap();
+    client
+        .vault(b"other_vault")
+        .write_secret(secret_location, b"secret".to_vec())
+        .unwrap();
+    let public_key = |client: &Client| {
+        client
+            .execute_procedure(crate::procedures::PublicKey {
+                ty: KeyType::Ed25519,
+                private_key: key_location.clone(),
+            })
+            .unwrap()
+    };
+    let expected = public_key(&client);
+
+    // Rotating on demand keeps all vault keys usable.
+    client.rotate_master_key().unwrap();
+    client.rotate_master_key().unwrap();
+    assert_eq!(public_key(&client), expected);
+    assert_eq!(
+        client.vault(b"other_vault").read_secret(b"secret").unwrap(),
+        b"secret".to_vec()
+    );
+
+    // Procedures that only read a vault key rotate a due master key as well.
+    client.set_master_key_rotation(Some(Duration::from_millis(50))).unwrap();
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(client.keystore.read().unwrap().is_rotation_due());
+    assert_eq!(public_key(&client), expected);
+    assert!(!client.keystore.read().unwrap().is_rotation_due());
+
+    // The interval is kept when the state of the client is restored, e.g. by a sync.
+    let state = client.snapshot_state().unwrap();
+    client.clone().restore(state, *client.id()).unwrap();
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(client.keystore.read().unwrap().is_rotation_due());
+    assert_eq!(public_key(&client), expected);
+    assert!(!client.keystore.read().unwrap().is_rotation_due());
+
+    // With an interval, the master key is rotated on access.
+    client.set_master_key_rotation(Some(Duration::ZERO)).unwrap();
+    for _ in 0..3 {
+        assert_eq!(public_key(&client), expected);
+    }
+    stronghold.write_client(client_path.clone()).unwrap();
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .load_client_from_snapshot(client_path, &keyprovider, &snapshot_path)
+        .unwrap();
+    client.rotate_master_key().unwrap();
+    assert_eq!(public_key(&client), expected);
+    assert_eq!(
+        client.vault(b"other_vault").read_secret(b"secret").unwrap(),
+        b"secret".to_vec()
+    );
+}
+
+#[test]
+fn test_vault_protection() {
+    let client_path = b"client_path".to_vec();
+    let cold_path = b"cold".to_vec();
+    let hot_path = b"hot".to_vec();
+    let key_location = Location::generic(cold_path.clone(), b"key".to_vec());
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+    let keyprovider = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    let vault_key = fixed_random_bytes(32);
+    let vault_keyprovider = || KeyProvider::try_from(vault_key.clone()).unwrap();
+    let wrong_keyprovider = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client
+        .execute_procedure(GenerateKey {
+            ty: KeyType::Ed25519,
+            output: key_location.clone(),
+        })
+        .unwrap();
+    client
+        .vault(&hot_path)
+        .write_secret(Location::generic(hot_path.clone(), b"secret".to_vec()), b"hot".to_vec())
+        .unwrap();
+    let public_key = |client: &Client| {
+        client.execute_procedure(crate::procedures::PublicKey {
+            ty: KeyType::Ed25519,
+            private_key: key_location.clone(),
+        })
+    };
+    let expected = public_key(&client).unwrap();
+
+    // A protected vault is locked until it is unlocked with its key.
+    let cold = client.vault(&cold_path);
+    cold.protect(&vault_keyprovider()).unwrap();
+    assert!(cold.is_locked().unwrap());
+    assert!(matches!(public_key(&client), Err(ProcedureError::VaultLocked(_))));
+    assert!(matches!(
+        cold.write_secret(
+            Location::generic(cold_path.clone(), b"secret".to_vec()),
+            b"cold".to_vec()
+        ),
+        Err(ClientError::VaultLocked(_))
+    ));
+    assert!(!client.vault(&hot_path).is_locked().unwrap());
+    assert_eq!(client.vault(&hot_path).read_secret(b"secret").unwrap(), b"hot".to_vec());
+
+    assert!(matches!(cold.unlock(&wrong_keyprovider), Err(ClientError::WrongKey)));
+    cold.unlock(&vault_keyprovider()).unwrap();
+    assert!(!cold.is_locked().unwrap());
+    assert_eq!(public_key(&client).unwrap(), expected);
+    assert!(matches!(
+        client.rename_vault(cold_path.clone(), b"renamed".to_vec(), &[b"key".to_vec()]),
+        Err(ClientError::VaultProtected(_))
+    ));
+    cold.write_secret(
+        Location::generic(cold_path.clone(), b"secret".to_vec()),
+        b"cold".to_vec(),
+    )
+    .unwrap();
+
+    // Changes of an unlocked vault are sealed on commit, the vault stays unlocked.
+    stronghold.write_client(client_path.clone()).unwrap();
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+    assert_eq!(cold.read_secret(b"secret").unwrap(), b"cold".to_vec());
+    cold.lock().unwrap();
+    assert!(cold.is_locked().unwrap());
+
+    // After loading the snapshot, the vault is locked.
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .load_client_from_snapshot(client_path, &keyprovider, &snapshot_path)
+        .unwrap();
+    let cold = client.vault(&cold_path);
+    assert!(cold.is_locked().unwrap());
+    assert!(matches!(public_key(&client), Err(ProcedureError::VaultLocked(_))));
+    assert_eq!(client.vault(&hot_path).read_secret(b"secret").unwrap(), b"hot".to_vec());
+    cold.unlock(&vault_keyprovider()).unwrap();
+    assert_eq!(public_key(&client).unwrap(), expected);
+    assert_eq!(cold.read_secret(b"secret").unwrap(), b"cold".to_vec());
+}
+
+#[test]
+fn test_protected_vaults_are_reserved() {
+    let reserved_path = b"stronghold.protected_vaults".to_vec();
+    let reserved_location = Location::generic(reserved_path.clone(), b"record".to_vec());
+    let cold_path = b"cold".to_vec();
+    let cold_location = Location::generic(cold_path.clone(), b"secret".to_vec());
+    let vault_keyprovider = || KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    fn is_reserved<T>(result: Result<T, ClientError>) -> bool {
+        matches!(result, Err(ClientError::VaultReserved(_)))
+    }
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(b"client_path").unwrap();
+    let cold = client.vault(&cold_path);
+    cold.write_secret(cold_location.clone(), b"cold".to_vec()).unwrap();
+    let keyprovider = vault_keyprovider();
+    cold.protect(&keyprovider).unwrap();
+
+    // The vault that holds the sealed protected vaults can not be reached through the client.
+    let reserved = client.vault(&reserved_path);
+    assert!(is_reserved(
+        reserved.write_secret(reserved_location.clone(), b"data".to_vec())
+    ));
+    assert!(is_reserved(reserved.read_secret(b"record")));
+    assert!(is_reserved(reserved.revoke_secret(b"record")));
+    assert!(is_reserved(reserved.delete_secret(b"record")));
+    assert!(is_reserved(reserved.list_revoked()));
+    assert!(is_reserved(reserved.cleanup()));
+    assert!(is_reserved(reserved.protect(&vault_keyprovider())));
+    assert!(is_reserved(reserved.is_locked()));
+    assert!(is_reserved(reserved.cipher_suite()));
+    assert!(is_reserved(
+        client.set_record_ttl(&reserved_location, Some(Duration::from_secs(1)))
+    ));
+    assert!(is_reserved(
+        client.set_usage_policy(&reserved_location, KeyUsagePolicy::default())
+    ));
+    assert!(is_reserved(client.move_record(&reserved_location, &cold_location)));
+    assert!(is_reserved(client.move_record(
+        &Location::generic(b"hot".to_vec(), b"record".to_vec()),
+        &reserved_location
+    )));
+    assert!(is_reserved(client.rename_vault(
+        reserved_path.clone(),
+        b"other".to_vec(),
+        &[b"record".to_vec()]
+    )));
+    assert!(matches!(
+        client.execute_procedure(GenerateKey {
+            ty: KeyType::Ed25519,
+            output: reserved_location,
+        }),
+        Err(ProcedureError::Engine(_))
+    ));
+
+    // Garbage collection keeps the sealed vault, so it can still be unlocked.
+    client.garbage_collect_all(None).unwrap();
+    client.garbage_collect_expired().unwrap();
+    cold.unlock(&keyprovider).unwrap();
+    assert_eq!(cold.read_secret(b"secret").unwrap(), b"cold".to_vec());
+
+    // An unlocked vault stays unlocked when the state of the client is restored, e.g. by a sync, and keeps the
+    // changes that have been made while it was unlocked.
+    cold.write_secret(cold_location, b"changed".to_vec()).unwrap();
+    client
+        .clone()
+        .restore(client.snapshot_state().unwrap(), *client.id())
+        .unwrap();
+    assert!(!cold.is_locked().unwrap());
+    assert_eq!(cold.read_secret(b"secret").unwrap(), b"changed".to_vec());
+    cold.lock().unwrap();
+    assert!(cold.is_locked().unwrap());
+}
+
+#[test]
+fn test_key_encryption_provider() {
+    let client_path = b"client_path".to_vec();
+    let location = Location::generic(b"vault".to_vec(), b"record".to_vec());
+    let temp_path = |suffix: &str| {
+        let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+        let mut path = std::env::temp_dir();
+        path.push(filename + suffix);
+        Defer::from((path, |path: &'_ PathBuf| {
+            let _ = std::fs::remove_file(path);
+        }))
+    };
+    let snapshot_file = temp_path(".stronghold");
+    let kek_file = temp_path(".kek");
+    let snapshot_path = SnapshotPath::from_path(&*snapshot_file);
+    let load = |keyprovider: &KeyProvider| {
+        Stronghold::default()
+            .load_client_from_snapshot(client_path.clone(), keyprovider, &snapshot_path)
+            .map(|client| client.record_exists(&location).unwrap())
+    };
+
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    client
+        .vault(b"vault")
+        .write_secret(location.clone(), b"secret".to_vec())
+        .unwrap();
+    stronghold.write_client(client_path.clone()).unwrap();
+    let key_file = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    stronghold.commit_with_keyprovider(&snapshot_path, &key_file).unwrap();
+
+    let in_memory = InMemoryKeyEncryptionProvider::new(b"kms".to_vec()).unwrap();
+    let file = FileKeyEncryptionProvider::generate(b"kek file".to_vec(), &*kek_file).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&*kek_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    stronghold
+        .add_key_slot(&snapshot_path, &key_file, NewKeySlot::External(&in_memory))
+        .unwrap();
+    stronghold
+        .add_key_slot(&snapshot_path, &key_file, NewKeySlot::External(&file))
+        .unwrap();
+    stronghold.remove_key_slot(&snapshot_path, &key_file, 0).unwrap();
+    let slots = stronghold.list_key_slots(&snapshot_path).unwrap();
+    assert!(matches!(&slots[..], [
+        KeySlotKind::External { id: first, .. },
+        KeySlotKind::External { id: second, .. },
+    ] if *first == in_memory.id() && *second == file.id()));
+    assert!(matches!(load(&key_file), Err(ClientError::WrongKey)));
+
+    for provider in [&in_memory as &dyn KeyEncryptionProvider, &file] {
+        let keyprovider = KeyProvider::from_key_encryption_provider(&snapshot_path, provider).unwrap();
+        assert!(load(&keyprovider).unwrap());
+    }
+
+    // An existing key encryption key is not replaced.
+    assert!(matches!(
+        FileKeyEncryptionProvider::generate(b"kek file".to_vec(), &*kek_file),
+        Err(ClientError::KeyEncryption(_))
+    ));
+    let keyprovider = KeyProvider::from_key_encryption_provider(&snapshot_path, &file).unwrap();
+    assert!(load(&keyprovider).unwrap());
+
+    // The key slot of a file provider is found by its id, not by the path of the file.
+    let moved_kek_file = temp_path(".kek");
+    std::fs::rename(&*kek_file, &*moved_kek_file).unwrap();
+    let moved = FileKeyEncryptionProvider::new(b"kek file".to_vec(), &*moved_kek_file);
+    let keyprovider = KeyProvider::from_key_encryption_provider(&snapshot_path, &moved).unwrap();
+    assert!(load(&keyprovider).unwrap());
+
+    // Another key encryption key with the same id can not unwrap the key.
+    let other = InMemoryKeyEncryptionProvider::new(b"kms".to_vec()).unwrap();
+    assert!(matches!(
+        KeyProvider::from_key_encryption_provider(&snapshot_path, &other),
+        Err(ClientError::KeyEncryption(_))
+    ));
+    let unknown = InMemoryKeyEncryptionProvider::new(b"tpm".to_vec()).unwrap();
+    assert!(matches!(
+        KeyProvider::from_key_encryption_provider(&snapshot_path, &unknown),
+        Err(ClientError::KeySlot(_))
+    ));
+}
+
+#[test]
+fn test_cipher_suites() {
+    let client_path = b"client_path".to_vec();
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let defer = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot_path = SnapshotPath::from_path(&*defer);
+    let key = fixed_random_bytes(32);
+    let keyprovider = KeyProvider::try_from(key.clone()).unwrap();
+    let key_location = Location::generic(b"aes".to_vec(), b"key".to_vec());
+    let public_key = |client: &Client| {
+        client
+            .execute_procedure(crate::procedures::PublicKey {
+                ty: KeyType::Ed25519,
+                private_key: key_location.clone(),
+            })
+            .unwrap()
+    };
+
+    // Vaults of earlier versions use XChaCha20-Poly1305.
+    let stronghold = Stronghold::default();
+    let client = stronghold.create_client(client_path.clone()).unwrap();
+    let legacy = client.vault(b"legacy");
+    legacy
+        .write_secret(
+            Location::generic(b"legacy".to_vec(), b"secret".to_vec()),
+            b"legacy".to_vec(),
+        )
+        .unwrap();
+    assert_eq!(legacy.cipher_suite().unwrap(), Some(CipherSuite::XChaCha20Poly1305));
+    stronghold.write_client(client_path.clone()).unwrap();
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+
+    // New vaults use the cipher suite of the Stronghold, next to the existing ones.
+    let stronghold = Stronghold::default().with_cipher_suite(CipherSuite::Aes256Gcm);
+    let client = stronghold
+        .load_client_from_snapshot(client_path.clone(), &keyprovider, &snapshot_path)
+        .unwrap();
+    let aes = client.vault(b"aes");
+    assert_eq!(aes.cipher_suite().unwrap(), None);
+    aes.write_secret(Location::generic(b"aes".to_vec(), b"secret".to_vec()), b"aes".to_vec())
+        .unwrap();
+    client
+        .execute_procedure(GenerateKey {
+            ty: KeyType::Ed25519,
+            output: key_location.clone(),
+        })
+        .unwrap();
+    let expected = public_key(&client);
+    assert_eq!(aes.cipher_suite().unwrap(), Some(CipherSuite::Aes256Gcm));
+    assert_eq!(
+        client.vault(b"legacy").cipher_suite().unwrap(),
+        Some(CipherSuite::XChaCha20Poly1305)
+    );
+
+    // A renamed vault keeps its cipher suite.
+    client
+        .vault(b"legacy")
+        .write_secret(
+            Location::generic(b"legacy".to_vec(), b"renamed".to_vec()),
+            b"renamed".to_vec(),
+        )
+        .unwrap();
+    client
+        .rename_vault(b"legacy".as_slice(), b"moved".as_slice(), &["secret", "renamed"])
+        .unwrap();
+    assert_eq!(
+        client.vault(b"moved").cipher_suite().unwrap(),
+        Some(CipherSuite::XChaCha20Poly1305)
+    );
+    stronghold.write_client(client_path.clone()).unwrap();
+    stronghold
+        .commit_with_keyprovider(&snapshot_path, &keyprovider)
+        .unwrap();
+
+    // Vaults of mixed cipher suites are decrypted with their own cipher suite.
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .load_client_from_snapshot(client_path.clone(), &keyprovider, &snapshot_path)
+        .unwrap();
+    assert_eq!(client.vault(b"aes").read_secret(b"secret").unwrap(), b"aes".to_vec());
+    assert_eq!(public_key(&client), expected);
+    assert_eq!(
+        client.vault(b"aes").cipher_suite().unwrap(),
+        Some(CipherSuite::Aes256Gcm)
+    );
+    assert_eq!(
+        client.vault(b"moved").cipher_suite().unwrap(),
+        Some(CipherSuite::XChaCha20Poly1305)
+    );
+
+    // Vaults that are created by a merge keep the cipher suite of the source vault.
+    let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+    let mut path = std::env::temp_dir();
+    path.push(filename);
+    let target_file = Defer::from((path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let target_path = SnapshotPath::from_path(&*target_file);
+    let stronghold = Stronghold::default();
+    stronghold.create_client(client_path.clone()).unwrap();
+    stronghold.write_client(client_path.clone()).unwrap();
+    stronghold.commit_with_keyprovider(&target_path, &keyprovider).unwrap();
+    crate::merge_snapshot_files(
+        &target_path,
+        key.clone().try_into().unwrap(),
+        &snapshot_path,
+        key.try_into().unwrap(),
+        SyncSnapshotsConfig::default(),
+    )
+    .unwrap();
+    let client = Stronghold::default()
+        .load_client_from_snapshot(client_path, &keyprovider, &target_path)
+        .unwrap();
+    assert_eq!(client.vault(b"aes").read_secret(b"secret").unwrap(), b"aes".to_vec());
+    assert_eq!(
+        client.vault(b"aes").cipher_suite().unwrap(),
+        Some(CipherSuite::Aes256Gcm)
+    );
+    assert_eq!(
+        client.vault(b"moved").cipher_suite().unwrap(),
+        Some(CipherSuite::XChaCha20Poly1305)
+    );
+}
+
+#[test]
+fn test_vault_version_is_persisted() {
+    let vault_path = b"vault_path".to_vec();
+    let key_provider = KeyProvider::try_from(fixed_random_bytes(32)).expect("Failed to create keyprovider");
+
+    let filename = base64::encode(fixed_random_bytes(32));
+    let filename = filename.replace('/', "n");
+    let mut snapshot_path = std::env::temp_dir();
+    snapshot_path.push(filename);
+
+    let defer = Defer::from((snapshot_path, |path: &'_ PathBuf| {
+        let _ = std::fs::remove_file(path);
+    }));
+    let snapshot = SnapshotPath::from_path(&*defer);
+
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .create_client("client_path")
+        .expect("Failed to create client");
+    let vault = client.vault(vault_path.clone());
+    assert!(vault
+        .write_secret(
+            Location::const_generic(vault_path.clone(), b"record".to_vec()),
+            b"payload".to_vec()
+        )
+        .is_ok());
+    let version = client.vault_version(vault_path.clone()).unwrap();
+    assert!(version.is_some());
+    assert!(stronghold.commit_with_keyprovider(&snapshot, &key_provider).is_ok());
+    assert_eq!(client.vault_version(vault_path.clone()).unwrap(), version);
+
+    // the version is kept when the client is loaded again
+    let stronghold = Stronghold::default();
+    let client = stronghold
+        .load_client_from_snapshot("client_path", &key_provider, &snapshot)
+        .expect("Failed to load client");
+    assert_eq!(client.vault_version(vault_path).unwrap(), version);
+}
+
+#[test]
+fn test_key_slots_of_other_snapshot() {
+    let temp_path = || {
+        let filename = base64::encode(fixed_random_bytes(32)).replace('/', "n");
+        let mut path = std::env::temp_dir();
+        path.push(filename);
+        Defer::from((path, |path: &'_ PathBuf| {
+            let _ = std::fs::remove_file(path);
+        }))
+    };
+    let (loaded, other) = (temp_path(), temp_path());
+    let (loaded_path, other_path) = (SnapshotPath::from_path(&*loaded), SnapshotPath::from_path(&*other));
+    let key = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    let other_key = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+    let key_file = KeyProvider::try_from(fixed_random_bytes(32)).unwrap();
+
+    let stronghold = Stronghold::default();
+    stronghold.create_client(b"client").unwrap();
+    stronghold.commit_with_keyprovider(&other_path, &other_key).unwrap();
+    stronghold.commit_with_keyprovider(&loaded_path, &key).unwrap();
+
+    // Key slots of another file are not taken over by the loaded snapshot.
+    stronghold
+        .add_key_slot(&other_path, &other_key, NewKeySlot::KeyFile(&key_file))
+        .unwrap();
+    assert_eq!(stronghold.list_key_slots(&other_path).unwrap().len(), 2);
+    stronghold.commit_with_keyprovider(&loaded_path, &key).unwrap();
+    assert!(stronghold.list_key_slots(&loaded_path).unwrap().is_empty());
+}
@@ -9,9 +9,9 @@ use crate::procedures::CompareSecret;
 use crate::{
     procedures::{
         AeadCipher, AeadDecrypt, AeadEncrypt, AesKeyWrapCipher, AesKeyWrapDecrypt, AesKeyWrapEncrypt, BIP39Generate,
-        BIP39Recover, ConcatKdf, CopyRecord, DeriveSecret, Ed25519Sign, GenerateKey, GenerateSecret, Hkdf, KeyType,
-        MnemonicLanguage, PublicKey, Sha2Hash, Slip10Derive, Slip10DeriveInput, Slip10Generate, StrongholdProcedure,
-        WriteVault, X25519DiffieHellman,
+        BIP39Recover, ConcatKdf, CopyRecord, DeriveSecret, Ed25519Sign, GenerateKey, GenerateSecret, Hkdf,
+        KeyOperation, KeyType, KeyUsagePolicy, MnemonicLanguage, PublicKey, Sha2Hash, Slip10Derive, Slip10DeriveInput,
+        Slip10Generate, StrongholdProcedure, WriteVault, X25519DiffieHellman,
     },
     tests::fresh,
     Client, Location, Stronghold,
@@ -852,3 +852,87 @@ fn test_usecase_concatkdf() {
     let result = result.unwrap();
     assert!(result[0] == 1, "failed: ({:?})", result);
 }
+
+#[test]
+fn usecase_usage_limited_keys() {
+    let stronghold: Stronghold = Stronghold::default();
+    let client: Client = stronghold.create_client(b"client_path").unwrap();
+
+    let key_location: Location = fresh::location();
+    let generate_key = GenerateKey {
+        ty: KeyType::Ed25519,
+        output: key_location.clone(),
+    };
+    client.execute_procedure(generate_key).unwrap();
+
+    // one-time signing key
+    let policy = KeyUsagePolicy {
+        remaining_uses: Some(1),
+        allowed_operations: Some(vec![KeyOperation::Sign]),
+    };
+    client.set_usage_policy(&key_location, policy.clone()).unwrap();
+    assert_eq!(client.usage_policy(&key_location).unwrap(), policy);
+
+    // deriving the public key does not count as use
+    let public_key = PublicKey {
+        ty: KeyType::Ed25519,
+        private_key: key_location.clone(),
+    };
+    assert!(client.execute_procedure(public_key.clone()).is_ok());
+    assert!(client.execute_procedure(public_key).is_ok());
+
+    // operations that are not allowed fail and do not count as use
+    let copy = CopyRecord {
+        source: key_location.clone(),
+        target: fresh::location(),
+    };
+    assert!(client.execute_procedure(copy).is_err());
+
+    let sign = Ed25519Sign {
+        private_key: key_location.clone(),
+        msg: b"message".to_vec(),
+    };
+    // procedures that fail do not count as use
+    let invalid_key_location: Location = fresh::location();
+    client
+        .vault(invalid_key_location.vault_path())
+        .write_secret(invalid_key_location.clone(), b"short".to_vec())
+        .unwrap();
+    let limited = KeyUsagePolicy {
+        remaining_uses: Some(1),
+        allowed_operations: None,
+    };
+    client.set_usage_policy(&invalid_key_location, limited.clone()).unwrap();
+    let invalid_sign = Ed25519Sign {
+        private_key: invalid_key_location.clone(),
+        msg: b"message".to_vec(),
+    };
+    assert!(client.execute_procedure(invalid_sign).is_err());
+    let invalid_dh = X25519DiffieHellman {
+        public_key: [0; 32],
+        private_key: invalid_key_location.clone(),
+        shared_key: fresh::location(),
+    };
+    assert!(client.execute_procedure(invalid_dh).is_err());
+    assert_eq!(client.usage_policy(&invalid_key_location).unwrap(), limited);
+
+    assert!(client.execute_procedure(sign.clone()).is_ok());
+    assert_eq!(client.usage_policy(&key_location).unwrap().remaining_uses, Some(0));
+    assert!(client.execute_procedure(sign.clone()).is_err());
+
+    // an empty set of allowed operations prohibits all usage
+    let policy = KeyUsagePolicy {
+        remaining_uses: None,
+        allowed_operations: Some(vec![]),
+    };
+    client.set_usage_policy(&key_location, policy.clone()).unwrap();
+    assert_eq!(client.usage_policy(&key_location).unwrap(), policy);
+    assert!(client.execute_procedure(sign.clone()).is_err());
+
+    // removing the restrictions allows unlimited usage
+    client
+        .set_usage_policy(&key_location, KeyUsagePolicy::default())
+        .unwrap();
+    assert!(client.execute_procedure(sign.clone()).is_ok());
+    assert!(client.execute_procedure(sign).is_ok());
+}
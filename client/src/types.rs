@@ -4,19 +4,29 @@
 //! A collection of relevant interface types to interact with a Stronghold
 
 // modules
+#[cfg(feature = "async")]
+mod asynchronous;
 mod client;
 mod error;
+mod idle_lock;
 mod location;
 mod snapshot;
 mod store;
 mod stronghold;
+mod trust;
+mod unlock;
 mod vault;
 
 // re-export imports
+#[cfg(feature = "async")]
+pub use asynchronous::*;
 pub use client::*;
 pub use error::*;
+pub use idle_lock::*;
 pub use location::*;
 pub use snapshot::*;
 pub use store::*;
 pub use stronghold::*;
+pub use trust::*;
+pub use unlock::*;
 pub use vault::*;
@@ -0,0 +1,200 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Asynchronous wrappers around [`Stronghold`] and [`Client`]. Operations that block on locks or run
+//! expensive procedures are offloaded to a dedicated thread pool, so that they do not stall the executor
+//! of the caller.
+
+use crate::{
+    procedures::{Procedure, ProcedureError, ProcedureOutput, StrongholdProcedure},
+    Client, ClientError, KeyProvider, SnapshotPath, Stronghold,
+};
+use futures::{channel::oneshot, executor::ThreadPool};
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// Future that resolves to the result of a function that has been executed on the blocking thread pool.
+///
+/// If the function panics, the future resolves to [`ClientError::BlockingTaskCancelled`] instead.
+#[pin_project]
+#[must_use = "futures do nothing unless polled"]
+pub struct BlockingTask<T> {
+    #[pin]
+    receiver: oneshot::Receiver<T>,
+}
+
+impl<T, E> Future for BlockingTask<Result<T, E>>
+where
+    E: From<ClientError>,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project()
+            .receiver
+            .poll(cx)
+            .map(|result| result.unwrap_or_else(|_| Err(ClientError::BlockingTaskCancelled.into())))
+    }
+}
+
+/// Runs `f` on the `pool` and returns a future for its result.
+pub(crate) fn spawn_blocking<F, T>(pool: &ThreadPool, f: F) -> BlockingTask<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    pool.spawn_ok(async move {
+        let _ = sender.send(f());
+    });
+    BlockingTask { receiver }
+}
+
+/// Asynchronous interface of a [`Stronghold`]. Blocking operations are executed on a thread pool and
+/// return a [`BlockingTask`] with the same result type as the synchronous operation.
+#[derive(Clone)]
+pub struct AsyncStronghold {
+    inner: Stronghold,
+    pool: ThreadPool,
+}
+
+impl AsyncStronghold {
+    /// Creates a new [`AsyncStronghold`] with a default sized thread pool for blocking operations.
+    ///
+    /// # Example
+    pub fn new(stronghold: Stronghold) -> Result<Self, ClientError> {
+        let pool = ThreadPool::new().map_err(|e| ClientError::Inner(e.to_string()))?;
+        Ok(Self::with_pool(stronghold, pool))
+    }
+
+    /// Creates a new [`AsyncStronghold`] that executes blocking operations on `pool`.
+    ///
+    /// # Example
+    pub fn with_pool(stronghold: Stronghold, pool: ThreadPool) -> Self {
+        Self {
+            inner: stronghold,
+            pool,
+        }
+    }
+
+    /// Returns a reference to the wrapped [`Stronghold`]
+    pub fn stronghold(&self) -> &Stronghold {
+        &self.inner
+    }
+
+    /// Creates a new, empty [`AsyncClient`]. See [`Stronghold::create_client`].
+    ///
+    /// # Example
+    pub fn create_client<P>(&self, client_path: P) -> Result<AsyncClient, ClientError>
+    where
+        P: AsRef<[u8]>,
+    {
+        let client = self.inner.create_client(client_path)?;
+        Ok(self.wrap_client(client))
+    }
+
+    /// Returns an already loaded [`AsyncClient`]. See [`Stronghold::get_client`].
+    ///
+    /// # Example
+    pub fn get_client<P>(&self, client_path: P) -> Result<AsyncClient, ClientError>
+    where
+        P: AsRef<[u8]>,
+    {
+        let client = self.inner.get_client(client_path)?;
+        Ok(self.wrap_client(client))
+    }
+
+    /// Loads a [`AsyncClient`] from the snapshot at `snapshot_path`. See [`Stronghold::load_client_from_snapshot`].
+    ///
+    /// # Example
+    pub fn load_client_from_snapshot<P>(
+        &self,
+        client_path: P,
+        keyprovider: Arc<KeyProvider>,
+        snapshot_path: SnapshotPath,
+    ) -> BlockingTask<Result<AsyncClient, ClientError>>
+    where
+        P: AsRef<[u8]> + Send + 'static,
+    {
+        let this = self.clone();
+        spawn_blocking(&self.pool, move || {
+            let client = this
+                .inner
+                .load_client_from_snapshot(client_path, &keyprovider, &snapshot_path)?;
+            Ok(this.wrap_client(client))
+        })
+    }
+
+    /// Writes the state of all clients into the snapshot at `snapshot_path`. See [`Stronghold::commit`].
+    ///
+    /// # Example
+    pub fn commit(&self, snapshot_path: SnapshotPath) -> BlockingTask<Result<(), ClientError>> {
+        let inner = self.inner.clone();
+        spawn_blocking(&self.pool, move || inner.commit(&snapshot_path))
+    }
+
+    /// Writes the state of all clients into the snapshot at `snapshot_path`, encrypted with the key of
+    /// `keyprovider`. See [`Stronghold::commit_with_keyprovider`].
+    ///
+    /// # Example
+    pub fn commit_with_keyprovider(
+        &self,
+        snapshot_path: SnapshotPath,
+        keyprovider: Arc<KeyProvider>,
+    ) -> BlockingTask<Result<(), ClientError>> {
+        let inner = self.inner.clone();
+        spawn_blocking(&self.pool, move || {
+            inner.commit_with_keyprovider(&snapshot_path, &keyprovider)
+        })
+    }
+
+    fn wrap_client(&self, client: Client) -> AsyncClient {
+        AsyncClient {
+            inner: client,
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+/// Asynchronous interface of a [`Client`]. Procedures are executed on the thread pool of the
+/// [`AsyncStronghold`] that the client has been obtained from.
+#[derive(Clone)]
+pub struct AsyncClient {
+    inner: Client,
+    pool: ThreadPool,
+}
+
+impl AsyncClient {
+    /// Returns a reference to the wrapped [`Client`]
+    pub fn client(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Executes a cryptographic [`Procedure`]. See [`Client::execute_procedure`].
+    ///
+    /// # Example
+    pub fn execute_procedure<P>(&self, procedure: P) -> BlockingTask<Result<P::Output, ProcedureError>>
+    where
+        P: Procedure + Into<StrongholdProcedure> + Send + 'static,
+        P::Output: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        spawn_blocking(&self.pool, move || inner.execute_procedure(procedure))
+    }
+
+    /// Executes a list of cryptographic [`Procedure`]s sequentially. See [`Client::execute_procedure_chained`].
+    ///
+    /// # Example
+    pub fn execute_procedure_chained(
+        &self,
+        procedures: Vec<StrongholdProcedure>,
+    ) -> BlockingTask<Result<Vec<ProcedureOutput>, ProcedureError>> {
+        let inner = self.inner.clone();
+        spawn_blocking(&self.pool, move || inner.execute_procedure_chained(procedures))
+    }
+}
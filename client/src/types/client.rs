@@ -3,25 +3,36 @@
 use super::{location, snapshot};
 
 use crate::{
-    derive_vault_id,
+    derive_record_id, derive_vault_id,
     procedures::{
-        FatalProcedureError, Procedure, ProcedureError, ProcedureOutput, Products, Runner, StrongholdProcedure,
+        FatalProcedureError, KeyUsagePolicy, Procedure, ProcedureError, ProcedureOutput, Products, Runner,
+        StrongholdProcedure, DEFAULT_RANDOM_HINT_SIZE,
     },
-    sync::{KeyProvider, MergePolicy, SyncClients, SyncClientsConfig, SyncSnapshots, SyncSnapshotsConfig},
-    ClientError, ClientState, ClientVault, KeyStore, Location, Provider, RecordError, SnapshotError, Store, Stronghold,
+    sync::{
+        count_changes, select_copies, set_source_versions, ClientSyncReport, KeyProvider, MergePolicy, SyncClients,
+        SyncClientsConfig, SyncProgress, SyncSnapshots, SyncSnapshotsConfig,
+    },
+    Activity, CipherSuite, ClientError, ClientState, ClientVault, KeyStore, Location, Provider, RecordError,
+    SnapshotError, Store, Stronghold, VaultError,
 };
 use crypto::keys::x25519;
 use engine::{
     runtime::memories::buffer::Buffer,
-    vault::{view::Record, BoxProvider, ClientId, DbView, Id, Key, RecordHint, RecordId, VaultId},
+    snapshot::{read_container, write_container, Key as BundleKey},
+    store::Cache,
+    vault::{
+        view::{Record, Vault},
+        BoxProvider, ClientId, DbView, Id, Key, RecordHint, RecordId, VaultId, VaultVersion,
+    },
 };
 use std::{
     collections::HashMap,
+    convert::Infallible,
     error::Error,
     sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
-use stronghold_utils::GuardDebug;
+use stronghold_utils::{random, GuardDebug};
 use zeroize::Zeroize;
 
 #[derive(Clone, GuardDebug)]
@@ -37,6 +48,16 @@ pub struct Client {
 
     // Contains the Record Ids for the most recent Record in each vault.
     pub store: Store,
+
+    // Last access, shared with the Stronghold that manages this client
+    pub(crate) activity: Activity,
+
+    // Keys of the protected vaults that have been unlocked, to seal them again on commit. Always locked before
+    // the keystore and the db, if they are locked together.
+    pub(crate) unlocked_vaults: Arc<RwLock<HashMap<VaultId, crate::KeyProvider>>>,
+
+    // Cipher suite of new vaults, set by the Stronghold that manages this client
+    pub(crate) cipher_suite: CipherSuite,
 }
 
 impl Default for Client {
@@ -46,6 +67,9 @@ impl Default for Client {
             db: Arc::new(RwLock::new(DbView::new())),
             id: ClientId::default(),
             store: Store::default(),
+            activity: Activity::default(),
+            unlocked_vaults: Arc::new(RwLock::new(HashMap::new())),
+            cipher_suite: CipherSuite::default(),
         }
     }
 }
@@ -55,6 +79,7 @@ impl Client {
     ///
     /// # Example
     pub fn store(&self) -> Store {
+        self.activity.touch();
         self.store.clone()
     }
 
@@ -65,6 +90,7 @@ impl Client {
     where
         P: AsRef<[u8]>,
     {
+        self.activity.touch();
         ClientVault {
             client: self.clone(),
             vault_path: vault_path.as_ref().to_vec(),
@@ -84,6 +110,21 @@ impl Client {
         Ok(keystore.vault_exists(vault_id))
     }
 
+    /// Returns the current [`VaultVersion`] of a vault, or `None` if the vault does not exist. Passing the
+    /// version to [`SyncClientsConfig::sync_changes_since`] restricts the next sync from this client to the
+    /// records that changed after it. The version history is only kept in memory and restarts when the
+    /// client is loaded from a snapshot.
+    ///
+    /// # Example
+    pub fn vault_version<P>(&self, vault_path: P) -> Result<Option<VaultVersion>, ClientError>
+    where
+        P: AsRef<[u8]>,
+    {
+        let vault_id = derive_vault_id(vault_path);
+        let db = self.db.read()?;
+        Ok(db.vault_version(vault_id))
+    }
+
     /// Returns Ok(true), if the record exists. Ok(false), if not. An error is being
     /// returned, if inner database could not be unlocked.
     ///
@@ -110,69 +151,512 @@ impl Client {
         select_records: Option<Vec<RecordId>>,
         merge_policy: MergePolicy,
     ) -> Result<(), ClientError> {
-        let source = derive_vault_id(source_path);
-        let target = derive_vault_id(target_path);
-        let select_vaults = vec![source];
-        let map_vaults = [(source, target)].into();
+        let source = derive_vault_id(&source_path);
         let select_records = select_records.map(|vec| [(source, vec)].into()).unwrap_or_default();
-        let mut config = SyncClientsConfig {
-            select_vaults: Some(select_vaults),
+        let config = SyncClientsConfig {
             select_records,
-            map_vaults,
             merge_policy,
+            ..Default::default()
         };
-        let hierarchy = self.get_hierarchy(config.select_vaults.clone())?;
-        let diff = self.get_diff(hierarchy, &config)?;
-        let exported = self.export_entries(diff)?;
-
-        let mut key_store = self.keystore.write()?;
-        let mut db = self.db.write()?;
+        self.sync_vaults_with_config(source_path, target_path, config)
+    }
 
-        for (vid, records) in exported {
-            let mapped_vid = config.map_vaults.remove(&vid).unwrap_or(vid);
-            let old_key = key_store
-                .get_key(vid)
-                .ok_or_else(|| ClientError::Inner(format!("Missing Key for vault {:?}", vid)))?;
-            let new_key = key_store.get_or_insert_key(mapped_vid, Key::random())?;
-            db.import_records(&old_key, &new_key, mapped_vid, records)?
-        }
-        Ok(())
+    /// Synchronize two vaults of the client so that records are copied from `source` to `target`,
+    /// according to `config`. Selected and mapped vaults of the `config` are ignored.
+    ///
+    /// # Warning
+    /// This function is susceptible to data race, use it with caution
+    ///
+    /// # Example
+    pub fn sync_vaults_with_config(
+        &self,
+        source_path: Vec<u8>,
+        target_path: Vec<u8>,
+        mut config: SyncClientsConfig,
+    ) -> Result<(), ClientError> {
+        let source = derive_vault_id(source_path);
+        let target = derive_vault_id(target_path);
+        config.select_vaults = Some(vec![source]);
+        config.map_vaults = [(source, target)].into();
+        self.sync_with(self, config)
     }
 
     /// Synchronize the client with another one so that records are copied from `other` to `self`.
+    /// If revocations are propagated, records that have been revoked at `other` are revoked in `self`.
     /// # Warning
     /// This function is susceptible to data race, use it with caution
     ///
     /// # Example
     pub fn sync_with(&self, other: &Self, config: SyncClientsConfig) -> Result<(), ClientError> {
-        let hierarchy = other.get_hierarchy(config.select_vaults.clone())?;
-        let diff = self.get_diff(hierarchy, &config)?;
-        let exported = other.export_entries(diff)?;
-
-        for (vid, mut records) in exported {
-            if let Some(select_vaults) = config.select_vaults.as_ref() {
-                if !select_vaults.contains(&vid) {
-                    continue;
+        let hierarchy = other.get_hierarchy_since(config.select_vaults.clone(), &config.known_versions)?;
+        let revocations = other.get_revocations(&config)?;
+        let report = self.get_sync_report(hierarchy, revocations, &config)?;
+        let exported = other.export_entries(select_copies(&report))?;
+        let old_keys = {
+            let keystore = other.keystore.read()?;
+            exported
+                .keys()
+                .map(|vid| {
+                    let key = keystore
+                        .get_key(*vid)
+                        .ok_or_else(|| ClientError::Inner(format!("Missing Key for vault {:?}", vid)))?;
+                    Ok((*vid, key))
+                })
+                .collect::<Result<HashMap<_, _>, ClientError>>()?
+        };
+
+        let mut keystore = self.keystore.write()?;
+        let mut db = self.db.write()?;
+        let mut checkpoints = HashMap::new();
+        let result = Self::apply_sync(
+            &mut keystore,
+            &mut db,
+            report,
+            exported,
+            &old_keys,
+            &config,
+            &mut checkpoints,
+        );
+        if result.is_err() {
+            // Roll back the vaults that have already been changed, so that the client is not partially synced.
+            for (vid, (vault, had_key)) in checkpoints {
+                match vault {
+                    Some(vault) => db.vaults.insert(vid, vault),
+                    None => db.vaults.remove(&vid),
+                };
+                if !had_key {
+                    keystore.take_key(vid);
                 }
             }
-            if let Some(select_records) = config.select_records.get(&vid) {
-                records.retain(|(rid, _)| select_records.contains(rid));
+        }
+        result
+    }
+
+    /// Apply the changes of a sync report vault by vault. Before a vault at the target is changed for the
+    /// first time, a checkpoint of it is added to `checkpoints`, together with whether its key existed.
+    fn apply_sync(
+        keystore: &mut KeyStore<Provider>,
+        db: &mut DbView<Provider>,
+        report: ClientSyncReport,
+        mut exported: HashMap<VaultId, Vec<(RecordId, Record)>>,
+        old_keys: &HashMap<VaultId, Key<Provider>>,
+        config: &SyncClientsConfig,
+        checkpoints: &mut HashMap<VaultId, (Option<Vault<Provider>>, bool)>,
+    ) -> Result<(), ClientError> {
+        let total = report.values().map(count_changes).sum();
+        let mut processed = 0;
+        for (vid, vault_report) in report {
+            let vault_records = count_changes(&vault_report);
+            if vault_records == 0 {
+                continue;
+            }
+            if config.control.is_cancelled() {
+                return Err(ClientError::SyncCancelled);
+            }
+            let mapped_vid = config.map_vault(vid);
+            check_vault_unlocked(keystore, db, mapped_vid)?;
+            checkpoints.entry(mapped_vid).or_insert_with(|| {
+                let vault = db.vaults.get(&mapped_vid).map(Vault::checkpoint);
+                (vault, keystore.vault_exists(mapped_vid))
+            });
+
+            if let Some(records) = exported.remove(&vid) {
+                let old_key = old_keys
+                    .get(&vid)
+                    .ok_or_else(|| ClientError::Inner(format!("Missing Key for vault {:?}", vid)))?;
+                // A new vault at the target keeps the cipher suite of the source vault.
+                let new_key =
+                    keystore.get_or_insert_key(mapped_vid, Provider::random_key(Provider::cipher_suite(old_key)?)?)?;
+                db.import_records(old_key, &new_key, mapped_vid, records)?;
+            }
+            if !vault_report.revoked.is_empty() {
+                let key = keystore
+                    .get_key(mapped_vid)
+                    .ok_or_else(|| ClientError::Inner(format!("Missing Key for vault {:?}", mapped_vid)))?;
+                for rid in vault_report.revoked {
+                    db.revoke_record(&key, mapped_vid, rid)?;
+                }
             }
-            let mapped_vid = config.map_vaults.get(&vid).copied().unwrap_or(vid);
-            let old_key = other
-                .keystore
-                .read()?
-                .get_key(vid)
+
+            processed += vault_records;
+            config.control.report(&SyncProgress {
+                client_id: None,
+                vault_id: vid,
+                vault_records,
+                processed,
+                total,
+            });
+        }
+        Ok(())
+    }
+
+    /// Report the changes that [`Client::sync_with`] would apply to `self` when synchronizing
+    /// with `other`, without copying any records.
+    ///
+    /// # Example
+    pub fn preview_sync(&self, other: &Self, config: &SyncClientsConfig) -> Result<ClientSyncReport, ClientError> {
+        let (hierarchy, versions) =
+            other.get_hierarchy_with_versions(config.select_vaults.clone(), &config.known_versions)?;
+        let revocations = other.get_revocations(config)?;
+        let mut report = self.get_sync_report(hierarchy, revocations, config)?;
+        set_source_versions(&mut report, &versions);
+        Ok(report)
+    }
+
+    /// Sets the time-to-live of the record at `location`. Once the `ttl` has passed, the record can
+    /// not be used by procedures anymore and it is revoked and garbage collected on the next commit
+    /// of the [`Stronghold`]. The expiry is persisted in the snapshot and kept if the record is
+    /// updated or moved. `None` removes the expiry.
+    ///
+    /// # Example
+    pub fn set_record_ttl(&self, location: &Location, ttl: Option<Duration>) -> Result<(), ClientError> {
+        let (vault_id, record_id) = location.resolve();
+        check_vault_reserved(vault_id)?;
+
+        let keystore = self.keystore.read()?;
+        let mut db = self.db.write()?;
+
+        let key = keystore
+            .get_key(vault_id)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+        let expires_at = ttl.map(expiry).transpose()?;
+        db.set_record_expiry(&key, vault_id, record_id, expires_at)?;
+        Ok(())
+    }
+
+    /// Rotates the master key that encrypts the vault keys of this client in memory, and re-encrypts all
+    /// vault keys with the new master key. Neither the vaults nor the snapshot are affected.
+    ///
+    /// # Example
+    pub fn rotate_master_key(&self) -> Result<(), ClientError> {
+        let mut keystore = self.keystore.write()?;
+        keystore
+            .rotate_master_key()
+            .map_err(|e| ClientError::Inner(format!("{:?}", e)))
+    }
+
+    /// Sets the interval after which the master key of this client is rotated (see [`Self::rotate_master_key`]).
+    /// The rotation happens on the first use of the vault keys after the interval has passed. `None` disables
+    /// the periodic rotation. The interval is not persisted in the snapshot.
+    ///
+    /// # Example
+    pub fn set_master_key_rotation(&self, interval: Option<Duration>) -> Result<(), ClientError> {
+        self.keystore.write()?.set_rotation_interval(interval);
+        Ok(())
+    }
+
+    /// Restricts the usage of the record at `location` with a [`KeyUsagePolicy`]. Procedures that
+    /// use the record for an operation that is not allowed, or after its remaining uses have been
+    /// consumed, fail without access to the secret. The policy is persisted in the snapshot and kept
+    /// if the record is updated or moved.
+    ///
+    /// # Example
+    pub fn set_usage_policy(&self, location: &Location, policy: KeyUsagePolicy) -> Result<(), ClientError> {
+        let (vault_id, record_id) = location.resolve();
+        check_vault_reserved(vault_id)?;
+
+        let keystore = self.keystore.read()?;
+        let mut db = self.db.write()?;
+
+        let key = keystore
+            .get_key(vault_id)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+        db.set_record_usage(&key, vault_id, record_id, policy.into())?;
+        Ok(())
+    }
+
+    /// Returns the current [`KeyUsagePolicy`] of the record at `location`, including the number of
+    /// remaining uses.
+    ///
+    /// # Example
+    pub fn usage_policy(&self, location: &Location) -> Result<KeyUsagePolicy, ClientError> {
+        let (vault_id, record_id) = location.resolve();
+        check_vault_reserved(vault_id)?;
+
+        let keystore = self.keystore.read()?;
+        let db = self.db.read()?;
+
+        let key = keystore
+            .get_key(vault_id)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+        let usage = db.get_record_usage(&key, vault_id, record_id)?;
+        Ok(usage.into())
+    }
+
+    /// Moves a record from `source` to `target`. The record is re-encrypted with the key of the target
+    /// vault without exposing the plaintext. The target vault is created if it does not exist yet and
+    /// an existing record at `target` is replaced. Both locations may point into the same vault.
+    ///
+    /// The operation holds the write locks on the keystore and the database for its whole duration,
+    /// so other users of the client either observe the record at `source` or at `target`.
+    ///
+    /// # Example
+    pub fn move_record(&self, source: &Location, target: &Location) -> Result<(), ClientError> {
+        let (source_vid, source_rid) = source.resolve();
+        let (target_vid, target_rid) = target.resolve();
+
+        let mut keystore = self.keystore.write()?;
+        let mut db = self.db.write()?;
+
+        check_vault_access(&keystore, &db, source_vid)?;
+        check_vault_access(&keystore, &db, target_vid)?;
+        let old_key = keystore
+            .get_key(source_vid)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(source_vid))?;
+        let target_exists = keystore.vault_exists(target_vid);
+        let new_key = keystore.get_or_insert_key(target_vid, self.new_vault_key()?)?;
+
+        let result = db.move_record(&old_key, source_vid, source_rid, &new_key, target_vid, target_rid);
+        if result.is_err() && !target_exists {
+            keystore.take_key(target_vid);
+        }
+        result?;
+        Ok(())
+    }
+
+    /// Renames the vault at `old_path` to `new_path`. All records are re-encrypted with the key of the
+    /// new vault without exposing the plaintext, and the old vault and its key are removed. Revoked
+    /// records that have not been garbage collected yet are dropped. If a vault already exists at
+    /// `new_path`, the records are merged into it and existing duplicates are replaced.
+    ///
+    /// Each record keeps its record path, so a [`Location`] on `new_path` resolves to the renamed record.
+    /// Since the record paths can not be recovered from the [`RecordId`]s, the paths of all records of the
+    /// vault have to be given in `record_paths`. If a record that has not been revoked is missing, the rename
+    /// fails and the vault is left unchanged.
+    ///
+    /// # Example
+    pub fn rename_vault<P, R>(&self, old_path: P, new_path: P, record_paths: &[R]) -> Result<(), ClientError>
+    where
+        P: AsRef<[u8]>,
+        R: AsRef<[u8]>,
+    {
+        let old_vid = derive_vault_id(old_path.as_ref());
+        let new_vid = derive_vault_id(new_path.as_ref());
+        let record_ids: HashMap<RecordId, RecordId> = record_paths
+            .iter()
+            .map(|record_path| {
+                (
+                    derive_record_id(old_path.as_ref(), record_path.as_ref()),
+                    derive_record_id(new_path.as_ref(), record_path.as_ref()),
+                )
+            })
+            .collect();
+        if self.unlocked_vaults.read()?.contains_key(&old_vid) {
+            return Err(ClientError::VaultProtected(old_vid));
+        }
+
+        let mut keystore = self.keystore.write()?;
+        let mut db = self.db.write()?;
+        check_vault_access(&keystore, &db, old_vid)?;
+        check_vault_access(&keystore, &db, new_vid)?;
+
+        let old_key = keystore
+            .get_key(old_vid)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(old_vid))?;
+        if old_vid == new_vid {
+            return Ok(());
+        }
+        let target_exists = keystore.vault_exists(new_vid);
+        // The renamed vault keeps its cipher suite, unless it is merged into an existing vault.
+        let new_key = keystore.get_or_insert_key(new_vid, Provider::random_key(Provider::cipher_suite(&old_key)?)?)?;
+
+        if let Err(e) = db.rename_vault(&old_key, old_vid, &new_key, new_vid, |id| record_ids.get(&id).copied()) {
+            if !target_exists {
+                keystore.take_key(new_vid);
+            }
+            return Err(e.into());
+        }
+        keystore.take_key(old_vid);
+        Ok(())
+    }
+
+    /// Exports the vault at `vault_path` into a portable bundle, e.g. for the backup of a single account.
+    /// The bundle has the format of a snapshot file and is encrypted with the key of `keyprovider`.
+    /// Revoked records are not exported. The bundle is imported with [`Client::import_vault`].
+    ///
+    /// # Example
+    pub fn export_vault<P>(&self, vault_path: P, keyprovider: &crate::KeyProvider) -> Result<Vec<u8>, ClientError>
+    where
+        P: AsRef<[u8]>,
+    {
+        let vault_id = derive_vault_id(vault_path);
+        check_vault_access(&*self.keystore.read()?, &*self.db.read()?, vault_id)?;
+        let key = self
+            .keystore
+            .read()?
+            .get_key(vault_id)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+        let mut export = DbView::new();
+        {
+            let db = self.db.read()?;
+            let vault = db
+                .vaults
+                .get(&vault_id)
+                .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+            export.vaults.insert(vault_id, vault.clone());
+        }
+        export.garbage_collect_vault(&key, vault_id);
+
+        write_bundle([(vault_id, key)].into(), export, keyprovider)
+    }
+
+    /// Imports a bundle that has been exported with [`Client::export_vault`] into the vault with the
+    /// same path. The bundle is decrypted with the key of `keyprovider`. If a record exists in the bundle
+    /// and in the vault with different content, the [`MergePolicy`] applies.
+    ///
+    /// # Example
+    pub fn import_vault(
+        &self,
+        bytes: &[u8],
+        keyprovider: &crate::KeyProvider,
+        merge_policy: MergePolicy,
+    ) -> Result<(), ClientError> {
+        let (keys, db) = read_bundle(bytes, keyprovider)?;
+        let state: ClientState = (keys, db, Cache::default());
+
+        let config = SyncClientsConfig::new(merge_policy);
+        let hierarchy = state.get_hierarchy(None)?;
+        let report = self.get_sync_report(hierarchy, HashMap::new(), &config)?;
+        let exported = state.export_entries(select_copies(&report))?;
+
+        let mut keystore = self.keystore.write()?;
+        let mut db = self.db.write()?;
+        for (vid, records) in exported {
+            check_vault_access(&keystore, &db, vid)?;
+            let old_key = state
+                .0
+                .get(&vid)
                 .ok_or_else(|| ClientError::Inner(format!("Missing Key for vault {:?}", vid)))?;
+            let new_key = keystore.get_or_insert_key(vid, Provider::random_key(Provider::cipher_suite(old_key)?)?)?;
+            db.import_records(old_key, &new_key, vid, records)?;
+        }
+        Ok(())
+    }
+
+    /// Protects the vault `vault_id` with the key of `keyprovider`, see [`ClientVault::protect`].
+    pub(crate) fn protect_vault(&self, vault_id: VaultId, keyprovider: &crate::KeyProvider) -> Result<(), ClientError> {
+        check_vault_reserved(vault_id)?;
+        self.seal_vault(vault_id, keyprovider)?;
+        self.unlocked_vaults.write()?.remove(&vault_id);
+        self.remove_vault(vault_id)
+    }
 
-            let mut keystore = self.keystore.write()?;
-            let mut db = self.db.write()?;
-            let new_key = keystore.get_or_insert_key(mapped_vid, Key::random())?;
-            db.import_records(&old_key, &new_key, mapped_vid, records)?
+    /// Unlocks the protected vault `vault_id` with the key of `keyprovider`, see [`ClientVault::unlock`].
+    pub(crate) fn unlock_vault(&self, vault_id: VaultId, keyprovider: &crate::KeyProvider) -> Result<(), ClientError> {
+        if !self.is_vault_locked(vault_id)? {
+            return Ok(());
         }
+        let (key, vault) = unseal_vault(&*self.keystore.read()?, &*self.db.read()?, vault_id, keyprovider)?;
+
+        let buffer = keyprovider
+            .try_unlock()
+            .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+        let keyprovider = crate::KeyProvider::from_buffer(&buffer).map_err(|e| ClientError::Inner(e.to_string()))?;
+
+        let mut unlocked_vaults = self.unlocked_vaults.write()?;
+        self.keystore.write()?.insert_key(vault_id, key)?;
+        self.db.write()?.vaults.insert(vault_id, vault);
+        unlocked_vaults.insert(vault_id, keyprovider);
         Ok(())
     }
 
+    /// Seals the unlocked protected vault `vault_id` again and removes it from memory, see [`ClientVault::lock`].
+    pub(crate) fn lock_vault(&self, vault_id: VaultId) -> Result<(), ClientError> {
+        let mut unlocked_vaults = self.unlocked_vaults.write()?;
+        let keyprovider = match unlocked_vaults.get(&vault_id) {
+            Some(keyprovider) => keyprovider,
+            None if self.is_vault_locked(vault_id)? => return Ok(()),
+            None => return Err(ClientError::Inner("vault is not protected".into())),
+        };
+        self.seal_vault(vault_id, keyprovider)?;
+        unlocked_vaults.remove(&vault_id);
+        self.remove_vault(vault_id)
+    }
+
+    /// Creates a random key for a new vault, of the [`CipherSuite`] of the client.
+    pub(crate) fn new_vault_key(&self) -> Result<Key<Provider>, <Provider as BoxProvider>::Error> {
+        Provider::random_key(self.cipher_suite)
+    }
+
+    /// Returns the [`CipherSuite`] of the vault `vault_id`, or `None` if the vault does not exist or is locked.
+    pub(crate) fn vault_cipher_suite(&self, vault_id: VaultId) -> Result<Option<CipherSuite>, ClientError> {
+        check_vault_reserved(vault_id)?;
+        let keystore = self.keystore.read()?;
+        match keystore.get_key(vault_id) {
+            Some(key) => Ok(Some(Provider::cipher_suite(&key)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `true` if the vault `vault_id` is protected and has not been unlocked.
+    pub(crate) fn is_vault_locked(&self, vault_id: VaultId) -> Result<bool, ClientError> {
+        check_vault_reserved(vault_id)?;
+        let keystore = self.keystore.read()?;
+        let db = self.db.read()?;
+        Ok(check_vault_unlocked(&keystore, &db, vault_id).is_err())
+    }
+
+    /// Writes the vault `vault_id` with its key, encrypted with the key of `keyprovider`, into the vault of the
+    /// protected vaults. The vault of the protected vaults is written directly, since it is reserved for the
+    /// client itself.
+    fn seal_vault(&self, vault_id: VaultId, keyprovider: &crate::KeyProvider) -> Result<(), ClientError> {
+        let mut keystore = self.keystore.write()?;
+        let mut db = self.db.write()?;
+        check_vault_unlocked(&keystore, &db, vault_id)?;
+        let key = keystore
+            .get_key(vault_id)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+        let vault = db
+            .vaults
+            .get(&vault_id)
+            .ok_or(VaultError::<Infallible>::VaultNotFound(vault_id))?;
+        let mut sealed = DbView::new();
+        sealed.vaults.insert(vault_id, vault.clone());
+        let sealed = write_bundle([(vault_id, key)].into(), sealed, keyprovider)?;
+
+        let (sealed_vid, sealed_rid) = sealed_vault_location(vault_id).resolve();
+        let sealed_exists = keystore.vault_exists(sealed_vid);
+        let sealed_key = keystore.get_or_insert_key(sealed_vid, self.new_vault_key()?)?;
+        if !sealed_exists {
+            db.init_vault(&sealed_key, sealed_vid);
+        }
+        let random_hint = RecordHint::new(random::variable_bytestring(DEFAULT_RANDOM_HINT_SIZE)).unwrap();
+        db.write(&sealed_key, sealed_vid, sealed_rid, &sealed, random_hint)?;
+        Ok(())
+    }
+
+    /// Removes the vault `vault_id` and its key from memory.
+    fn remove_vault(&self, vault_id: VaultId) -> Result<(), ClientError> {
+        let mut keystore = self.keystore.write()?;
+        let mut db = self.db.write()?;
+        keystore.take_key(vault_id);
+        db.vaults.remove(&vault_id);
+        Ok(())
+    }
+
+    /// Returns the state of the client that is written into the snapshot. Unlocked protected vaults are sealed
+    /// again, so that they are only written in their sealed form.
+    pub(crate) fn snapshot_state(&self) -> Result<ClientState, ClientError> {
+        let unlocked_vaults = self.unlocked_vaults.read()?;
+        for (vault_id, keyprovider) in unlocked_vaults.iter() {
+            self.seal_vault(*vault_id, keyprovider)?;
+        }
+
+        let mut keystore = self.keystore.write()?;
+        let view = self.db.read()?;
+        let store = self.store.cache.read()?;
+
+        // This might be critical, as keystore gets copied into Boxed types, but still safe
+        // we also use cloned data, which might not be ideal.
+        let mut keys = keystore.get_data();
+        let mut view = view.checkpoint();
+        for vault_id in unlocked_vaults.keys() {
+            keys.remove(vault_id);
+            view.vaults.remove(vault_id);
+        }
+        Ok((keys, view, (*store).clone()))
+    }
+
     /// Returns the [`ClientId`] of the client
     ///
     /// # Example
@@ -189,25 +673,39 @@ impl Client {
         self.id = id;
 
         // reload keystore
+        let mut unlocked_vaults = self.unlocked_vaults.write()?;
         let mut keystore = self.keystore.write()?;
         let mut view = self.db.write()?;
         let mut store = self.store.cache.write()?;
 
-        let mut new_keystore = KeyStore::<Provider>::default();
-        new_keystore
+        // rebuilt in place to keep the rotation interval of the master key
+        keystore
             .rebuild_keystore(keys)
             .map_err(|e| ClientError::Inner(e.to_string()))?;
 
-        *keystore = new_keystore;
         *view = db;
         *store = st;
 
+        // protected vaults that have been unlocked stay unlocked, if they can still be unsealed from the new state
+        unlocked_vaults.retain(|vault_id, keyprovider| {
+            let (key, vault) = match unseal_vault(&keystore, &view, *vault_id, keyprovider) {
+                Ok(unsealed) => unsealed,
+                Err(_) => return false,
+            };
+            if keystore.insert_key(*vault_id, key).is_err() {
+                return false;
+            }
+            view.vaults.insert(*vault_id, vault);
+            true
+        });
+
         Ok(())
     }
 
     /// Clears the inner [`Client`] state. This functions should not be called directly
     /// but by calling the function of same name on [`Stronghold`]
     pub(crate) fn clear(&self) -> Result<(), ClientError> {
+        let mut unlocked_vaults = self.unlocked_vaults.write()?;
         let mut ks = self.keystore.write()?;
         let mut view = self.db.write()?;
         let mut store = self.store.cache.write()?;
@@ -215,6 +713,7 @@ impl Client {
         view.clear();
         store.clear();
         ks.clear_keys();
+        unlocked_vaults.clear();
 
         Ok(())
     }
@@ -239,6 +738,7 @@ impl Client {
         &self,
         procedures: Vec<StrongholdProcedure>,
     ) -> core::result::Result<Vec<ProcedureOutput>, ProcedureError> {
+        self.activity.touch();
         let mut out = Vec::new();
         let mut log = Vec::new();
         // Execute the procedures sequentially.
@@ -274,3 +774,116 @@ impl<'a> SyncClients<'a> for Client {
         Ok(KeyProvider::KeyStore(ks))
     }
 }
+
+/// Get the key of the [`crate::KeyProvider`] for encrypting a vault bundle.
+fn unlock_bundle_key(keyprovider: &crate::KeyProvider) -> Result<BundleKey, ClientError> {
+    let buffer = keyprovider
+        .try_unlock()
+        .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+    let key = buffer.borrow();
+    (*key).try_into().map_err(|_| ClientError::IllegalKeySize(32))
+}
+
+/// The vault keys and vaults inside of a bundle.
+type BundleState = (HashMap<VaultId, Key<Provider>>, DbView<Provider>);
+
+/// Serializes the vault `keys` and `db` into a bundle in the format of a snapshot file, that is encrypted with the
+/// key of `keyprovider`.
+fn write_bundle(
+    keys: HashMap<VaultId, Key<Provider>>,
+    db: DbView<Provider>,
+    keyprovider: &crate::KeyProvider,
+) -> Result<Vec<u8>, ClientError> {
+    let mut data = bincode::serialize(&(keys, db)).map_err(SnapshotError::from)?;
+    let bundle_key = unlock_bundle_key(keyprovider)?;
+    let mut bundle = Vec::new();
+    let written = write_container(&data, &mut bundle, &bundle_key, &[]).map_err(SnapshotError::from);
+    data.zeroize();
+    written?;
+    Ok(bundle)
+}
+
+/// Decrypts a bundle that has been written with [`write_bundle`].
+fn read_bundle(bundle: &[u8], keyprovider: &crate::KeyProvider) -> Result<BundleState, ClientError> {
+    let bundle_key = unlock_bundle_key(keyprovider)?;
+    let mut data = read_container(&mut &*bundle, &bundle_key, &[]).map_err(SnapshotError::from)?;
+    let state = bincode::deserialize(&data).map_err(SnapshotError::from);
+    data.zeroize();
+    Ok(state?)
+}
+
+/// Path of the vault that holds the sealed protected vaults of a client, with one record per vault.
+const PROTECTED_VAULTS_PATH: &[u8] = b"stronghold.protected_vaults";
+
+/// Location of the sealed state of the protected vault `vault_id`.
+fn sealed_vault_location(vault_id: VaultId) -> Location {
+    Location::generic(PROTECTED_VAULTS_PATH, vault_id)
+}
+
+/// Decrypts the sealed state of the protected vault `vault_id` with the key of `keyprovider`.
+fn unseal_vault(
+    keystore: &KeyStore<Provider>,
+    db: &DbView<Provider>,
+    vault_id: VaultId,
+    keyprovider: &crate::KeyProvider,
+) -> Result<(Key<Provider>, Vault<Provider>), ClientError> {
+    let (sealed_vid, sealed_rid) = sealed_vault_location(vault_id).resolve();
+    let sealed_key = keystore
+        .get_key(sealed_vid)
+        .ok_or(VaultError::<Infallible>::VaultNotFound(sealed_vid))?;
+    let mut sealed = Vec::new();
+    db.get_guard::<Infallible, _>(&sealed_key, sealed_vid, sealed_rid, |guard| {
+        sealed = guard.borrow().to_vec();
+        Ok(())
+    })?;
+
+    let (mut keys, mut db) = read_bundle(&sealed, keyprovider)?;
+    match (keys.remove(&vault_id), db.vaults.remove(&vault_id)) {
+        (Some(key), Some(vault)) => Ok((key, vault)),
+        _ => Err(ClientError::Inner("sealed vault is corrupted".into())),
+    }
+}
+
+/// Returns the time at which a record with time-to-live `ttl` expires.
+pub(crate) fn expiry(ttl: Duration) -> Result<SystemTime, ClientError> {
+    SystemTime::now()
+        .checked_add(ttl)
+        .ok_or_else(|| ClientError::Inner(format!("time-to-live {:?} is out of range", ttl)))
+}
+
+/// Fails with [`RecordError::VaultLocked`] if the vault `vault_id` is protected with its own key and has not been
+/// unlocked.
+pub(crate) fn check_vault_unlocked(
+    keystore: &KeyStore<Provider>,
+    db: &DbView<Provider>,
+    vault_id: VaultId,
+) -> Result<(), RecordError> {
+    if keystore.vault_exists(vault_id) {
+        return Ok(());
+    }
+    let (sealed_vid, sealed_rid) = sealed_vault_location(vault_id).resolve();
+    if db.contains_record(sealed_vid, sealed_rid) {
+        return Err(RecordError::VaultLocked(vault_id));
+    }
+    Ok(())
+}
+
+/// Fails with [`RecordError::VaultReserved`] if `vault_id` is the vault of the protected vaults, which can only be
+/// accessed by the client itself.
+pub(crate) fn check_vault_reserved(vault_id: VaultId) -> Result<(), RecordError> {
+    if vault_id == derive_vault_id(PROTECTED_VAULTS_PATH) {
+        return Err(RecordError::VaultReserved(vault_id));
+    }
+    Ok(())
+}
+
+/// Fails if the vault `vault_id` can not be accessed through the interface of the client, see
+/// [`check_vault_reserved`] and [`check_vault_unlocked`].
+pub(crate) fn check_vault_access(
+    keystore: &KeyStore<Provider>,
+    db: &DbView<Provider>,
+    vault_id: VaultId,
+) -> Result<(), RecordError> {
+    check_vault_reserved(vault_id)?;
+    check_vault_unlocked(keystore, db, vault_id)
+}
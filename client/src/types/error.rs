@@ -6,10 +6,12 @@ use std::{
     convert::Infallible,
     fmt::Debug,
     sync::{PoisonError, TryLockError},
+    time::Duration,
 };
 
+use crypto::signatures::ed25519;
 use engine::{
-    snapshot::{ReadError as EngineReadError, WriteError as EngineWriteError},
+    snapshot::{KeySlotError as EngineKeySlotError, ReadError as EngineReadError, WriteError as EngineWriteError},
     vault::{
         BoxProvider, ClientId, RecordError as EngineRecordError, RecordId, VaultError as EngineVaultError, VaultId,
     },
@@ -61,6 +63,48 @@ pub enum ClientError {
 
     #[error("Client with id {0:?} has already been loaded before. Can not be loaded twice.")]
     ClientAlreadyLoaded(ClientId),
+
+    #[error("Sync has been cancelled")]
+    SyncCancelled,
+
+    #[error("Remote party is not authenticated")]
+    UnauthenticatedPeer,
+
+    #[error("Blocking task has been cancelled")]
+    BlockingTaskCancelled,
+
+    #[error("Snapshot does not describe how its key has been derived")]
+    MissingKeyDerivation,
+
+    #[error("Snapshot key has been derived with a key file")]
+    MissingKeyFile,
+
+    #[error("Snapshot key has {}been derived with a pepper", if *.0 { "" } else { "not " })]
+    PepperMismatch(bool),
+
+    #[error("Key slot error ({0})")]
+    KeySlot(String),
+
+    #[error("Wrong key for the snapshot")]
+    WrongKey,
+
+    #[error("Too many failed unlock attempts, retry in {0:?}")]
+    UnlockBackoff(Duration),
+
+    #[error("Snapshot has been wiped after too many failed unlock attempts")]
+    SnapshotWiped,
+
+    #[error("Vault {0:?} is locked")]
+    VaultLocked(VaultId),
+
+    #[error("Vault {0:?} is reserved")]
+    VaultReserved(VaultId),
+
+    #[error("Vault {0:?} is protected")]
+    VaultProtected(VaultId),
+
+    #[error("Key encryption provider error ({0})")]
+    KeyEncryption(String),
 }
 
 impl<T> From<TryLockError<T>> for ClientError {
@@ -77,7 +121,11 @@ impl<T> From<PoisonError<T>> for ClientError {
 
 impl<E: Debug> From<VaultError<E>> for ClientError {
     fn from(e: VaultError<E>) -> Self {
-        ClientError::Engine(format!("{:?}", e))
+        match e {
+            VaultError::Record(EngineRecordError::VaultLocked(vault_id)) => ClientError::VaultLocked(vault_id),
+            VaultError::Record(EngineRecordError::VaultReserved(vault_id)) => ClientError::VaultReserved(vault_id),
+            e => ClientError::Engine(format!("{:?}", e)),
+        }
     }
 }
 
@@ -113,6 +161,10 @@ impl From<SnapshotError> for ClientError {
             SnapshotError::Engine(inner) => ClientError::Inner(inner),
             SnapshotError::Provider(inner) => ClientError::Inner(inner),
             SnapshotError::Inner(inner) => ClientError::Inner(inner),
+            SnapshotError::SyncCancelled => ClientError::SyncCancelled,
+            SnapshotError::KeySlot(inner) => ClientError::KeySlot(inner),
+            SnapshotError::WrongKey => ClientError::WrongKey,
+            SnapshotError::UnauthenticatedPeer => ClientError::UnauthenticatedPeer,
         }
     }
 }
@@ -161,6 +213,18 @@ pub enum SnapshotError {
 
     #[error("Inner error: ({0})")]
     Inner(String),
+
+    #[error("sync has been cancelled")]
+    SyncCancelled,
+
+    #[error("key slot error: {0}")]
+    KeySlot(String),
+
+    #[error("wrong key")]
+    WrongKey,
+
+    #[error("the remote party is not authenticated")]
+    UnauthenticatedPeer,
 }
 
 pub type RemoteRecordError = String;
@@ -184,11 +248,44 @@ pub enum RemoteMergeError {
 
     #[error("vault error: {0}")]
     Vault(RemoteVaultError),
+
+    #[error("bundle is signed by an unknown or revoked peer `{0:?}`")]
+    UntrustedPeer([u8; ed25519::PUBLIC_KEY_LENGTH]),
+
+    #[error("invalid bundle signature")]
+    InvalidSignature,
+}
+
+#[derive(DeriveError, Debug)]
+pub enum SyncProtocolError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid message: {0}")]
+    InvalidMessage(String),
+
+    #[error("unexpected message `{0}`")]
+    UnexpectedMessage(&'static str),
+
+    #[error("unsupported protocol version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("remote merge failed: {0}")]
+    Remote(RemoteMergeError),
+
+    #[error("snapshot error: {0}")]
+    Snapshot(#[from] SnapshotError),
+
+    #[error("client error: {0}")]
+    Client(#[from] ClientError),
 }
 
 impl From<ClientError> for SnapshotError {
     fn from(e: ClientError) -> Self {
-        SnapshotError::Inner(format!("{}", e))
+        match e {
+            ClientError::SyncCancelled => SnapshotError::SyncCancelled,
+            e => SnapshotError::Inner(format!("{}", e)),
+        }
     }
 }
 
@@ -226,6 +323,7 @@ impl From<EngineReadError> for SnapshotError {
                 "Unsupported version: expected {:?}, found {:?}.",
                 expected, found
             )),
+            EngineReadError::NoMatchingKeySlot | EngineReadError::DecryptionFailed => SnapshotError::WrongKey,
         }
     }
 }
@@ -236,6 +334,17 @@ impl From<EngineWriteError> for SnapshotError {
             EngineWriteError::Io(io) => SnapshotError::Io(io),
             EngineWriteError::CorruptedData(e) => SnapshotError::CorruptedContent(e),
             EngineWriteError::GenerateRandom(_) => SnapshotError::Io(std::io::ErrorKind::Other.into()),
+            EngineWriteError::NoMatchingKeySlot => SnapshotError::WrongKey,
+        }
+    }
+}
+
+impl From<EngineKeySlotError> for SnapshotError {
+    fn from(e: EngineKeySlotError) -> Self {
+        match e {
+            EngineKeySlotError::Read(e) => e.into(),
+            EngineKeySlotError::Write(e) => e.into(),
+            e => SnapshotError::KeySlot(e.to_string()),
         }
     }
 }
@@ -0,0 +1,117 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::{ClientError, SnapshotPath};
+
+/// Callback that is called after the [`crate::Stronghold`] has been locked, with the result of the commit.
+type LockObserver = Arc<dyn Fn(&Result<(), ClientError>) + Send + Sync>;
+
+/// Policy that locks a [`crate::Stronghold`] after its loaded clients have not been accessed for a while, see
+/// [`crate::Stronghold::set_idle_lock_policy`].
+///
+/// Locking optionally commits the state to a snapshot file, and then clears the snapshot state, the snapshot key
+/// and all loaded clients from memory. Handles to the clients that are still held by the application are emptied
+/// as well, the clients have to be loaded again with [`crate::Stronghold::load_client_from_snapshot`] and a
+/// [`crate::KeyProvider`].
+///
+/// A client is accessed when it is loaded, created or returned by the [`crate::Stronghold`], or when a vault, the
+/// store or a procedure of the client is used.
+///
+/// # Example
+/// ```no_run
+/// use iota_stronghold::{IdleLockPolicy, KeyProvider, Location, SnapshotPath, Stronghold};
+/// use std::time::Duration;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let stronghold = Stronghold::default();
+/// let keyprovider = KeyProvider::try_from(vec![0u8; 32])?;
+/// stronghold.store_snapshot_key_at_location(keyprovider, Location::generic(b"vault".to_vec(), b"key".to_vec()))?;
+/// let policy = IdleLockPolicy::new(Duration::from_secs(300))
+///     .with_commit(SnapshotPath::from_path("wallet.stronghold"))
+///     .on_lock(|committed| println!("locked, committed: {}", committed.is_ok()));
+/// stronghold.set_idle_lock_policy(policy)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default, Clone)]
+pub struct IdleLockPolicy {
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) commit_to: Option<SnapshotPath>,
+    pub(crate) observer: Option<LockObserver>,
+}
+
+impl IdleLockPolicy {
+    /// Locks the [`crate::Stronghold`] after its clients have not been accessed for `timeout`. The idle time is
+    /// checked at most every 10 milliseconds, so a zero `timeout` locks the clients shortly after each access.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..Default::default()
+        }
+    }
+
+    /// Commits the state to `snapshot_path` before locking, with the key that has been stored with
+    /// [`crate::Stronghold::store_snapshot_key_at_location`]. If the commit fails, the [`crate::Stronghold`] stays
+    /// unlocked and locking is retried after the next timeout.
+    pub fn with_commit(mut self, snapshot_path: SnapshotPath) -> Self {
+        self.commit_to = Some(snapshot_path);
+        self
+    }
+
+    /// Calls `f` each time the [`crate::Stronghold`] has been locked, e.g. to show a lock screen. `f` receives
+    /// the result of the commit, which is `Ok` if no commit has been configured. `f` is called with the error
+    /// of a failed commit as well, in which case the [`crate::Stronghold`] has not been locked.
+    pub fn on_lock<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Result<(), ClientError>) + Send + Sync + 'static,
+    {
+        self.observer = Some(Arc::new(f));
+        self
+    }
+}
+
+impl fmt::Debug for IdleLockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdleLockPolicy")
+            .field("timeout", &self.timeout)
+            .field("commit_to", &self.commit_to)
+            .field("observer", &self.observer.is_some())
+            .finish()
+    }
+}
+
+/// The [`IdleLockPolicy`] of a [`crate::Stronghold`], and the generation of its watcher. A watcher stops as soon
+/// as a new policy has been set.
+#[derive(Debug, Default)]
+pub(crate) struct IdleLock {
+    pub(crate) policy: IdleLockPolicy,
+    pub(crate) generation: u64,
+}
+
+/// Time of the last access to the clients of a [`crate::Stronghold`].
+#[derive(Debug, Clone)]
+pub(crate) struct Activity(Arc<RwLock<Instant>>);
+
+impl Default for Activity {
+    fn default() -> Self {
+        Activity(Arc::new(RwLock::new(Instant::now())))
+    }
+}
+
+impl Activity {
+    pub(crate) fn touch(&self) {
+        if let Ok(mut last) = self.0.write() {
+            *last = Instant::now();
+        }
+    }
+
+    pub(crate) fn idle(&self) -> Duration {
+        self.0.read().map(|last| last.elapsed()).unwrap_or_default()
+    }
+}
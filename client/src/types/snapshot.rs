@@ -6,11 +6,16 @@
 
 #![allow(clippy::type_complexity)]
 
-use crypto::keys::x25519;
+use crypto::{keys::x25519, signatures::ed25519};
 use engine::{
-    snapshot::{self, read, read_from as read_from_file, write, write_to as write_to_file, Key},
+    snapshot::{
+        self, read, read_from as read_from_file, read_header, write, write_to_with_header as write_to_file, Header,
+        Key, KeyDerivation, KeySlot,
+    },
     store::Cache,
-    vault::{view::Record, BlobId, BoxProvider, ClientId, DbView, Key as PKey, RecordHint, RecordId, VaultId},
+    vault::{
+        view::Record, BlobId, BoxProvider, ChangeLog, ClientId, DbView, Key as PKey, RecordHint, RecordId, VaultId,
+    },
 };
 use serde::{Deserialize, Serialize};
 use std::{
@@ -25,8 +30,11 @@ use zeroize::Zeroize;
 
 use crate::{
     procedures::{DeriveSecret, X25519DiffieHellman},
-    sync::{self, KeyProvider, SnapshotHierarchy, SyncClients, SyncClientsConfig, SyncSnapshots, SyncSnapshotsConfig},
-    ClientError, KeyStore, Location, Provider, SnapshotError,
+    sync::{
+        self, KeyProvider, SnapshotHierarchy, SnapshotSyncReport, SyncClients, SyncClientsConfig, SyncSnapshots,
+        SyncSnapshotsConfig,
+    },
+    ClientError, KeyStore, Location, PeerIdentity, Provider, RemoteMergeError, SnapshotError, TrustStore,
 };
 
 type EncryptedClientState = (Vec<u8>, Cache<Vec<u8>, Vec<u8>>);
@@ -58,12 +66,82 @@ pub struct Snapshot {
     db: DbView<Provider>,
     // Loaded snapshot states with each client state separately encrypted.
     states: HashMap<ClientId, EncryptedClientState>,
+    // Identities of remote peers, persisted after the snapshot state.
+    trust_store: TrustStore,
+    // Unencrypted header of the snapshot file.
+    header: Header,
+    // File that the header belongs to, i.e. the file that the snapshot has been read from or last written to.
+    header_path: Option<PathBuf>,
+}
+
+/// Export of a state that is signed by the exporting peer.
+#[derive(Deserialize, Serialize)]
+struct SignedBundle {
+    // Signing key of the exporting peer.
+    signer: [u8; ed25519::PUBLIC_KEY_LENGTH],
+    // Ephemeral key that the state was encrypted with.
+    public_key: [u8; x25519::PUBLIC_KEY_LENGTH],
+    // Encrypted state.
+    data: Vec<u8>,
+    // Signature over the recipient's exchange key, `public_key` and `data`.
+    signature: Vec<u8>,
+}
+
+impl SignedBundle {
+    fn message(&self, recipient: &[u8; x25519::PUBLIC_KEY_LENGTH]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(2 * x25519::PUBLIC_KEY_LENGTH + self.data.len());
+        message.extend_from_slice(recipient);
+        message.extend_from_slice(&self.public_key);
+        message.extend_from_slice(&self.data);
+        message
+    }
 }
 
 /// Data structure that is written to the snapshot.
 #[derive(Deserialize, Serialize, Default)]
 pub struct SnapshotState(pub(crate) HashMap<ClientId, ClientState>);
 
+impl SnapshotState {
+    /// Reads the state from the snapshot file at `snapshot_path`, without loading it into a [`Snapshot`].
+    pub fn read_from_snapshot(snapshot_path: &SnapshotPath, key: Key) -> Result<Self, SnapshotError> {
+        let (state, _, _) = read_snapshot_file(snapshot_path, &key)?;
+        Ok(state)
+    }
+
+    /// Histories of changes of the vaults of each client, see [`DbView::histories`].
+    fn histories(&self) -> HashMap<ClientId, HashMap<VaultId, ChangeLog>> {
+        self.0.iter().map(|(cid, (_, db, _))| (*cid, db.histories())).collect()
+    }
+
+    /// Put back the histories of changes of [`SnapshotState::histories`].
+    fn restore_histories(&mut self, histories: HashMap<ClientId, HashMap<VaultId, ChangeLog>>) {
+        for (cid, histories) in histories {
+            if let Some((_, db, _)) = self.0.get_mut(&cid) {
+                db.restore_histories(histories);
+            }
+        }
+    }
+
+    /// Keeps only the records of `select` and drops all other records.
+    fn select(self, select: SnapshotHierarchy<RecordId>) -> Result<Self, SnapshotError> {
+        let select = select
+            .into_iter()
+            .map(|(cid, select)| {
+                let select = select
+                    .into_iter()
+                    .map(|(vid, records)| (vid, records.into_iter().map(|rid| (rid, rid)).collect()))
+                    .collect();
+                (cid, select)
+            })
+            .collect();
+        let exported = self.export_entries(select)?;
+        let old_keys = self.0.into_iter().map(|(cid, state)| (cid, state.0)).collect();
+        let mut selected = SnapshotState::default();
+        selected.import_records(exported, &old_keys, &SyncSnapshotsConfig::default())?;
+        Ok(selected)
+    }
+}
+
 /// A handle for snapshot file locations.
 ///
 /// # Examples
@@ -174,7 +252,8 @@ impl Snapshot {
             None => return Ok((HashMap::default(), DbView::default(), Cache::default())),
         };
         let decrypted = read(&mut encrypted.as_slice(), &key, &[])?;
-        let (keys, db) = bincode::deserialize(&decrypted)?;
+        let (keys, mut db, histories): (_, DbView<Provider>, _) = bincode::deserialize(&decrypted)?;
+        db.restore_histories(histories);
         Ok((keys, db, store.clone()))
     }
 
@@ -202,17 +281,18 @@ impl Snapshot {
         key: Key,
         write_key: Option<(VaultId, RecordId)>,
     ) -> Result<Self, SnapshotError> {
-        let data = read_from_file(snapshot_path.as_path(), &key, &[])?;
-
-        let state = bincode::deserialize(&data)?;
-        Snapshot::from_state(state, key, write_key)
+        let (state, trust_store, header) = read_snapshot_file(snapshot_path, &key)?;
+        let mut snapshot = Snapshot::from_state(state, key, write_key)?;
+        snapshot.trust_store = trust_store;
+        snapshot.header = header;
+        snapshot.set_header_path(snapshot_path);
+        Ok(snapshot)
     }
 
     /// Writes state to the specified named snapshot or the specified path
     /// TODO: Add associated data.
     pub fn write_to_snapshot(&self, snapshot_path: &SnapshotPath, use_key: UseKey) -> Result<(), SnapshotError> {
         let state = self.get_snapshot_state()?;
-        let data = bincode::serialize(&state)?;
 
         let key = match use_key {
             UseKey::Key(k) => k,
@@ -229,7 +309,7 @@ impl Snapshot {
             }
         };
 
-        write_to_file(&data, snapshot_path.as_path(), &key, &[]).map_err(|e| e.into())
+        write_snapshot_file(snapshot_path, &key, &state, &self.trust_store, &self.header)
     }
 
     /// Adds data to the snapshot state hashmap.
@@ -242,7 +322,8 @@ impl Snapshot {
             Cache<Vec<u8>, Vec<u8>>,
         ),
     ) -> Result<(), SnapshotError> {
-        let bytes = bincode::serialize(&(keys, db))?;
+        let histories = db.histories();
+        let bytes = bincode::serialize(&(keys, db, histories))?;
         let vault_id = VaultId(id.0);
         let key: snapshot::Key = random::random();
         let mut buffer = Vec::new();
@@ -302,22 +383,85 @@ impl Snapshot {
         Ok(())
     }
 
+    /// Gets the description of how the snapshot key has been derived from a password, that is written to
+    /// the header of the snapshot file.
+    pub fn key_derivation(&self) -> Option<&KeyDerivation> {
+        self.header.key_derivation.as_ref()
+    }
+
+    /// Sets the description of how the snapshot key has been derived from a password. It should be set
+    /// whenever the snapshot is written with another key.
+    pub fn set_key_derivation(&mut self, key_derivation: Option<KeyDerivation>) {
+        self.header.key_derivation = key_derivation;
+    }
+
+    /// Gets the key slots of the snapshot file. If there are any, the snapshot can only be written with a
+    /// key that unlocks one of them.
+    pub fn key_slots(&self) -> &[KeySlot] {
+        &self.header.key_slots
+    }
+
+    pub(crate) fn set_key_slots(&mut self, key_slots: Vec<KeySlot>) {
+        self.header.key_slots = key_slots;
+    }
+
+    /// Sets the file that the header belongs to, after the snapshot has been written to it.
+    pub(crate) fn set_header_path(&mut self, snapshot_path: &SnapshotPath) {
+        self.header_path = Some(snapshot_path.as_path().to_path_buf());
+    }
+
+    /// Checks if the header belongs to the file at `snapshot_path`.
+    pub(crate) fn has_header_of(&self, snapshot_path: &SnapshotPath) -> bool {
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.header_path.as_deref().map(canonical) == Some(canonical(snapshot_path.as_path()))
+    }
+
+    /// Gets the [`TrustStore`] with the identities of remote peers.
+    pub fn trust_store(&self) -> &TrustStore {
+        &self.trust_store
+    }
+
+    /// Gets the [`TrustStore`] with the identities of remote peers for modification.
+    pub fn trust_store_mut(&mut self) -> &mut TrustStore {
+        &mut self.trust_store
+    }
+
+    /// Gets the [`PeerIdentity`] of the local snapshot, for the ed25519 secret key at `signing_key` and the
+    /// x25519 secret key at `exchange_key`. The identity can be passed to remote peers so that they trust
+    /// the bundles exported with [`Snapshot::export_signed_bundle`].
+    pub fn peer_identity(&self, signing_key: Location, exchange_key: Location) -> Result<PeerIdentity, SnapshotError> {
+        let signing_key = self.with_secret(signing_key, |sk| Ok(ed25519_secret_key(sk)?.public_key().to_bytes()))?;
+        let exchange_key = self.with_secret(exchange_key, |sk| {
+            Ok(x25519::SecretKey::try_from_slice(sk)?.public_key().to_bytes())
+        })?;
+        Ok(PeerIdentity {
+            signing_key,
+            exchange_key,
+        })
+    }
+
     /// Merge another state into the currently loaded snapshot.
-    pub fn merge_state(&mut self, mut state: SnapshotState, config: SyncSnapshotsConfig) -> Result<(), SnapshotError> {
-        let hierarchy = state.get_hierarchy(config.select_clients.clone())?;
-        let diff = self.get_diff(hierarchy, &config)?;
-        let exported = state.export_entries(diff)?;
-        let mut old_keys = HashMap::new();
-        for cid in exported.keys() {
-            let ks = state
-                .0
-                .remove(cid)
-                .ok_or_else(|| SnapshotError::Inner(format!("Missing KeyStore for client {:?}", cid)))?
-                .0;
-            old_keys.insert(*cid, ks);
+    pub fn merge_state(&mut self, state: SnapshotState, config: SyncSnapshotsConfig) -> Result<(), SnapshotError> {
+        self.merge_from(state, &config)
+    }
+
+    /// Report the changes that [`Snapshot::merge_state`] would apply for `state`, without modifying
+    /// the loaded snapshot.
+    pub fn preview_merge(
+        &self,
+        state: &SnapshotState,
+        config: &SyncSnapshotsConfig,
+    ) -> Result<SnapshotSyncReport, SnapshotError> {
+        let (hierarchy, versions) =
+            state.get_hierarchy_since(config.select_clients.clone(), &config.known_versions())?;
+        let revocations = state.get_revocations(config)?;
+        let mut report = self.get_sync_report(hierarchy, revocations, config)?;
+        for (cid, client_report) in report.iter_mut() {
+            if let Some(versions) = versions.get(cid) {
+                sync::set_source_versions(client_report, versions);
+            }
         }
-        self.import_records(exported, &old_keys, &config)?;
-        Ok(())
+        Ok(report)
     }
 
     /// Deserialize, decompress and decrypt a state received from a remote peer and merge
@@ -325,6 +469,11 @@ impl Snapshot {
     ///
     /// It expects that a x25519 key exists at `local_sk` and that the received snapshot file is encrypted
     /// with a shared key create from the public key of `local_sk` and the remote's secret key.
+    ///
+    /// `remote_pk` does not authenticate the remote peer, so the import fails with
+    /// [`SnapshotError::UnauthenticatedPeer`] unless it is allowed with
+    /// [`SyncSnapshotsConfig::allow_unauthenticated_peers`]. Use [`Snapshot::import_signed_bundle`] to
+    /// import from a trusted peer.
     pub fn import_from_serialized_state(
         &mut self,
         bytes: Vec<u8>,
@@ -332,6 +481,9 @@ impl Snapshot {
         remote_pk: x25519::PublicKey,
         config: SyncSnapshotsConfig,
     ) -> Result<(), SnapshotError> {
+        if !config.allow_unauthenticated_peers {
+            return Err(SnapshotError::UnauthenticatedPeer);
+        }
         let (vid, rid) = local_sk.resolve();
         let vault_key = self
             .keystore
@@ -346,9 +498,38 @@ impl Snapshot {
             *decrypted = pt;
             Ok(())
         })?;
+        self.merge_serialized_state(decrypted, None, config)
+    }
+
+    /// Same as [`Snapshot::import_from_serialized_state`], but with a local secret key that is not stored
+    /// in the snapshot. If `request` is given, only the requested records are merged and all other records
+    /// of the received state are ignored.
+    pub(crate) fn import_from_serialized_state_with_key(
+        &mut self,
+        bytes: &[u8],
+        local_sk: &x25519::SecretKey,
+        remote_pk: x25519::PublicKey,
+        request: Option<SnapshotHierarchy<RecordId>>,
+        config: SyncSnapshotsConfig,
+    ) -> Result<(), SnapshotError> {
+        let shared_key = local_sk.diffie_hellman(&remote_pk);
+        let decrypted = engine::snapshot::read(&mut &*bytes, shared_key.as_bytes(), &[])?;
+        self.merge_serialized_state(&decrypted, request, config)
+    }
+
+    /// Decompress and deserialize a decrypted state and merge it into the local state.
+    fn merge_serialized_state(
+        &mut self,
+        decrypted: &[u8],
+        request: Option<SnapshotHierarchy<RecordId>>,
+        config: SyncSnapshotsConfig,
+    ) -> Result<(), SnapshotError> {
         let data =
             engine::snapshot::decompress(decrypted).map_err(|e| SnapshotError::CorruptedContent(e.to_string()))?;
-        let state: SnapshotState = bincode::deserialize(&data)?;
+        let mut state: SnapshotState = bincode::deserialize(&data)?;
+        if let Some(request) = request {
+            state = state.select(request)?;
+        }
         self.merge_state(state, config)
     }
 
@@ -368,6 +549,10 @@ impl Snapshot {
         let mut export = HashMap::new();
         for (cid, select) in select {
             let state = self.get_state(cid)?;
+            let select = select
+                .into_iter()
+                .map(|(vid, records)| (vid, records.into_iter().map(|rid| (rid, rid)).collect()))
+                .collect();
             let exported = state.export_entries(select)?;
             if exported.is_empty() {
                 continue;
@@ -389,12 +574,99 @@ impl Snapshot {
         Ok((pk, buffer))
     }
 
+    /// Export the given hierarchy like [`Snapshot::export_to_serialized_state`] for the `peer` and sign
+    /// the export with the ed25519 secret key at `signing_key`.
+    ///
+    /// The remote peer imports the bundle with [`Snapshot::import_signed_bundle`] if it trusts the
+    /// [`PeerIdentity`] of the local snapshot, see [`Snapshot::peer_identity`].
+    pub fn export_signed_bundle(
+        &self,
+        select: SnapshotHierarchy<RecordId>,
+        peer: &PeerIdentity,
+        signing_key: Location,
+    ) -> Result<Vec<u8>, SnapshotError> {
+        let (public_key, data) =
+            self.export_to_serialized_state(select, x25519::PublicKey::from_bytes(peer.exchange_key))?;
+        let mut bundle = SignedBundle {
+            signer: [0; ed25519::PUBLIC_KEY_LENGTH],
+            public_key: public_key.to_bytes(),
+            data,
+            signature: Vec::new(),
+        };
+        let message = bundle.message(&peer.exchange_key);
+        let (signer, signature) = self.with_secret(signing_key, |sk| {
+            let sk = ed25519_secret_key(sk)?;
+            Ok((sk.public_key().to_bytes(), sk.sign(&message).to_bytes()))
+        })?;
+        bundle.signer = signer;
+        bundle.signature = signature.to_vec();
+        Ok(bincode::serialize(&bundle)?)
+    }
+
+    /// Verify a bundle that has been exported with [`Snapshot::export_signed_bundle`] and merge it into
+    /// the local state.
+    ///
+    /// The bundle is rejected with [`RemoteMergeError::UntrustedPeer`] if its signer is unknown or has
+    /// been revoked in the [`TrustStore`]. It is decrypted with the x25519 secret key at `local_sk`.
+    pub fn import_signed_bundle(
+        &mut self,
+        bytes: &[u8],
+        local_sk: Location,
+        config: SyncSnapshotsConfig,
+    ) -> Result<(), RemoteMergeError> {
+        let bundle: SignedBundle =
+            bincode::deserialize(bytes).map_err(|e| RemoteMergeError::ReadExported(e.to_string()))?;
+        if !self.trust_store.is_trusted(&bundle.signer) {
+            return Err(RemoteMergeError::UntrustedPeer(bundle.signer));
+        }
+        let signer =
+            ed25519::PublicKey::try_from_bytes(bundle.signer).map_err(|_| RemoteMergeError::InvalidSignature)?;
+        let signature: [u8; ed25519::SIGNATURE_LENGTH] = bundle
+            .signature
+            .as_slice()
+            .try_into()
+            .map_err(|_| RemoteMergeError::InvalidSignature)?;
+
+        let local_sk = self
+            .with_secret(local_sk, |sk| Ok(x25519::SecretKey::try_from_slice(sk)?))
+            .map_err(|e| RemoteMergeError::ReadExported(e.to_string()))?;
+        let message = bundle.message(&local_sk.public_key().to_bytes());
+        if !signer.verify(&ed25519::Signature::from_bytes(signature), &message) {
+            return Err(RemoteMergeError::InvalidSignature);
+        }
+
+        let remote_pk = x25519::PublicKey::from_bytes(bundle.public_key);
+        self.import_from_serialized_state_with_key(&bundle.data, &local_sk, remote_pk, None, config)
+            .map_err(|e| RemoteMergeError::ReadExported(e.to_string()))
+    }
+
+    /// Call `f` with the secret that is stored at `location` in the snapshot's vault.
+    fn with_secret<T, F>(&self, location: Location, f: F) -> Result<T, SnapshotError>
+    where
+        F: FnOnce(&[u8]) -> Result<T, SnapshotError>,
+    {
+        let (vid, rid) = location.resolve();
+        let vault_key = self
+            .keystore
+            .get_key(vid)
+            .ok_or_else(|| SnapshotError::Inner("Missing secret key.".to_string()))?;
+        let mut output = None;
+        self.db.get_guard::<SnapshotError, _>(&vault_key, vid, rid, |guard| {
+            output = Some(f(&guard.borrow())?);
+            Ok(())
+        })?;
+        Ok(output.expect("Output was set."))
+    }
+
     /// Clears the state from the [`Snapshot`]. This function shouldn't be called directly,
     /// but from [`crate::Stronghold::clear()`]
     pub(crate) fn clear(&mut self) -> Result<(), SnapshotError> {
         self.keystore.clear_keys();
         self.db.clear();
         self.states.clear();
+        self.trust_store = TrustStore::default();
+        self.header = Header::default();
+        self.header_path = None;
 
         Ok(())
     }
@@ -422,6 +694,13 @@ impl SyncSnapshots for Snapshot {
         self.add_data(cid, state)?;
         Ok(())
     }
+
+    fn replace_state(&mut self, cid: ClientId, state: Option<ClientState>) -> Result<(), SnapshotError> {
+        match state {
+            Some(state) => self.add_data(cid, state),
+            None => self.purge_client(cid),
+        }
+    }
 }
 
 impl SyncSnapshots for SnapshotState {
@@ -444,4 +723,74 @@ impl SyncSnapshots for SnapshotState {
         let state = self.0.entry(cid).or_default();
         f(state)
     }
+
+    fn replace_state(&mut self, cid: ClientId, state: Option<ClientState>) -> Result<(), SnapshotError> {
+        match state {
+            Some(state) => self.0.insert(cid, state),
+            None => self.0.remove(&cid),
+        };
+        Ok(())
+    }
+}
+
+/// Merge the snapshot file at `source_path` into the snapshot file at `target_path`, without loading
+/// either of them into a [`Snapshot`] or [`crate::Client`].
+///
+/// The client states are merged as in [`Snapshot::merge_state`] and the merged state is written back to
+/// `target_path`, encrypted with `target_key`. The trust store and header of the target are kept, the ones
+/// of the source are ignored.
+pub fn merge_snapshot_files(
+    target_path: &SnapshotPath,
+    target_key: Key,
+    source_path: &SnapshotPath,
+    source_key: Key,
+    config: SyncSnapshotsConfig,
+) -> Result<(), SnapshotError> {
+    let (mut target, trust_store, header) = read_snapshot_file(target_path, &target_key)?;
+    let source = SnapshotState::read_from_snapshot(source_path, source_key)?;
+    target.merge_from(source, &config)?;
+    write_snapshot_file(target_path, &target_key, &target, &trust_store, &header)
+}
+
+fn read_snapshot_file(
+    snapshot_path: &SnapshotPath,
+    key: &Key,
+) -> Result<(SnapshotState, TrustStore, Header), SnapshotError> {
+    let data = read_from_file(snapshot_path.as_path(), key, &[])?;
+    let header = read_header(snapshot_path.as_path())?;
+
+    // The trust store and the histories of the vaults are appended to the state and missing in snapshots that
+    // have been written without them.
+    let mut reader = data.as_slice();
+    let mut state: SnapshotState = bincode::deserialize_from(&mut reader)?;
+    let trust_store = if reader.is_empty() {
+        TrustStore::default()
+    } else {
+        bincode::deserialize_from(&mut reader)?
+    };
+    if !reader.is_empty() {
+        state.restore_histories(bincode::deserialize_from(&mut reader)?);
+    }
+    Ok((state, trust_store, header))
+}
+
+fn write_snapshot_file(
+    snapshot_path: &SnapshotPath,
+    key: &Key,
+    state: &SnapshotState,
+    trust_store: &TrustStore,
+    header: &Header,
+) -> Result<(), SnapshotError> {
+    let mut data = bincode::serialize(state)?;
+    bincode::serialize_into(&mut data, trust_store)?;
+    bincode::serialize_into(&mut data, &state.histories())?;
+    write_to_file(&data, snapshot_path.as_path(), key, &[], header).map_err(|e| e.into())
+}
+
+fn ed25519_secret_key(bytes: &[u8]) -> Result<ed25519::SecretKey, SnapshotError> {
+    let bytes: [u8; ed25519::SECRET_KEY_LENGTH] = bytes
+        .get(..ed25519::SECRET_KEY_LENGTH)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| SnapshotError::Inner("Invalid ed25519 secret key.".to_string()))?;
+    Ok(ed25519::SecretKey::from_bytes(bytes))
 }
@@ -2,16 +2,26 @@
 // SPDX-License-Identifier: Apache-2.0
 use crate::{
     procedures::Runner,
-    sync::{SnapshotHierarchy, SyncSnapshots, SyncSnapshotsConfig},
-    Client, ClientError, ClientState, KeyProvider, LoadFromPath, Location, RemoteMergeError, RemoteVaultError,
-    Snapshot, SnapshotPath, Store, UseKey,
+    sync::{
+        sync_state_with_peer, SnapshotHierarchy, SnapshotSyncReport, SyncRole, SyncSnapshots, SyncSnapshotsConfig,
+        SyncState,
+    },
+    Activity, CipherSuite, Client, ClientError, ClientState, IdleLock, IdleLockPolicy, KeyEncryptionProvider,
+    KeyProvider, LoadFromPath, Location, PeerIdentity, RemoteMergeError, RemoteVaultError, Snapshot, SnapshotError,
+    SnapshotPath, SnapshotState, Store, SyncProtocolError, TrustStore, UnlockPolicy, UseKey,
+};
+use crypto::{keys::x25519, signatures::ed25519, utils::rand};
+use engine::{
+    snapshot::{read_header, update_key_slots, Key, KeySlot, KeySlotError, KeySlotKind},
+    vault::ClientId,
 };
-use crypto::keys::x25519;
-use engine::vault::ClientId;
 use std::{
     collections::{hash_map::Entry, HashMap},
+    io::{Read, Write},
     ops::Deref,
     sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    thread,
+    time::{Duration, SystemTime},
 };
 use stronghold_utils::GuardDebug;
 use zeroize::Zeroize;
@@ -27,19 +37,12 @@ macro_rules! write_with_clientid {
             None => return Err(ClientError::ClientDataNotPresent),
         };
 
-        let mut keystore_guard = client.keystore.write()?;
-        let view = client.db.read()?;
-        let store = client.store.cache.read()?;
-
         // we need some compatibility code here. Keyprovider stores encrypted vec
         // by snapshot requires a mapping to Key<Provider>
+        let state = client.snapshot_state()?;
 
-        let keystore = keystore_guard.get_data();
-
-        // This might be critical, as keystore gets copied into Boxed types, but still safe
-        // we also use cloned data, which might not be ideal.
         ($snapshot)
-            .add_data(($client_id), (keystore, (*view).clone(), (*store).clone()))
+            .add_data(($client_id), state)
             .map_err(|e| ClientError::Inner(e.to_string()))?;
     }};
 }
@@ -49,7 +52,7 @@ macro_rules! write_with_clientid {
 /// ending at the end of a function
 /// # Example
 macro_rules! load_snapshot {
-    ($snapshot:expr, $snapshot_path:expr, $keyprovider:expr) => {{
+    ($snapshot:expr, $snapshot_path:expr, $keyprovider:expr, $unlock_policy:expr) => {{
         {
             if !($snapshot_path).exists() {
                 let path = ($snapshot_path)
@@ -66,13 +69,54 @@ macro_rules! load_snapshot {
                 .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
             let buffer_ref = buffer.borrow().deref().try_into().unwrap();
 
-            *($snapshot) = Snapshot::read_from_snapshot(($snapshot_path), buffer_ref, None)
-                .map_err(|e| ClientError::Inner(e.to_string()))?;
+            *($snapshot) = ($unlock_policy).attempt(($snapshot_path), || {
+                Snapshot::read_from_snapshot(($snapshot_path), buffer_ref, None)
+            })?;
             // END CRITICAL SECTION
         }
     }};
 }
 
+/// Policy for the automatic removal of revoked records, that is applied each time the
+/// [`Stronghold`] state is committed. Until a revoked record has been garbage collected,
+/// it can be restored with [`crate::ClientVault::restore_secret`]. Records that expired
+/// (see [`Client::set_record_ttl`]) are removed on every commit, independent of the policy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GarbageCollectPolicy {
+    /// Revoked records are only removed explicitly, by the [`crate::procedures::GarbageCollect`]
+    /// procedure or [`crate::ClientVault::cleanup`].
+    #[default]
+    Manual,
+    /// Garbage collect all vaults of all loaded clients on every n-th commit. A value of
+    /// `0` or `1` collects on every commit.
+    EveryNthCommit(usize),
+    /// Garbage collect on each commit all records that have been revoked longer than the
+    /// given grace period ago.
+    GracePeriod(Duration),
+}
+
+/// The key of a new key slot, see [`Stronghold::add_key_slot`].
+pub enum NewKeySlot<'a> {
+    /// A key that has been derived from a password. The [`KeyProvider`] has to know its key derivation.
+    Password(&'a KeyProvider),
+    /// A key that has been derived from a recovery code. The [`KeyProvider`] has to know its key derivation.
+    RecoveryCode(&'a KeyProvider),
+    /// A raw key, e.g. read from a key file.
+    KeyFile(&'a KeyProvider),
+    /// The public key of a recipient, who can unlock the snapshot with a [`KeyProvider`] of the
+    /// corresponding secret key.
+    Recipient(x25519::PublicKey),
+    /// The keys of several holders, of which any `threshold` unlock the snapshot together, see
+    /// [`crate::ThresholdUnlock`].
+    Threshold {
+        threshold: u8,
+        holders: &'a [&'a KeyProvider],
+    },
+    /// A key encryption provider of an external system, which wraps a random key of the slot. The snapshot is
+    /// unlocked with [`KeyProvider::from_key_encryption_provider`].
+    External(&'a dyn KeyEncryptionProvider),
+}
+
 /// The Stronghold is a secure storage for sensitive data. Secrets that are stored inside
 /// a Stronghold can never be read, but only be accessed via cryptographic procedures. Data inside
 /// a Stronghold is heavily protected by the `Runtime` by either being encrypted at rest, having
@@ -92,6 +136,24 @@ pub struct Stronghold {
 
     /// Optional key location for writing to [`Snapshot`]
     key_location: Arc<RwLock<Option<Location>>>,
+
+    /// Policy for garbage collecting revoked records on commit
+    gc_policy: Arc<RwLock<GarbageCollectPolicy>>,
+
+    /// Number of commits since the last automatic garbage collection
+    commits_since_gc: Arc<RwLock<usize>>,
+
+    /// Policy against guessing the snapshot key
+    unlock_policy: Arc<RwLock<UnlockPolicy>>,
+
+    /// Policy for locking the loaded clients after inactivity
+    idle_lock: Arc<RwLock<IdleLock>>,
+
+    /// Last access to the loaded clients
+    activity: Activity,
+
+    /// Cipher suite of new vaults
+    cipher_suite: CipherSuite,
 }
 
 impl Stronghold {
@@ -107,6 +169,163 @@ impl Stronghold {
         self.store.clone()
     }
 
+    /// Encrypts the vaults that are created from now on with `cipher_suite`. Existing vaults keep their
+    /// [`CipherSuite`], so a snapshot can contain vaults of several cipher suites. This includes vaults that
+    /// are renamed, synchronized or imported from a bundle, whose copy keeps the cipher suite of the original.
+    ///
+    /// The cipher suite applies to clients that are loaded or created afterwards, and should therefore be
+    /// chosen before any client is loaded.
+    ///
+    /// # Example
+    /// ```
+    /// use iota_stronghold::{CipherSuite, Stronghold};
+    ///
+    /// let stronghold = Stronghold::default().with_cipher_suite(CipherSuite::Aes256Gcm);
+    /// ```
+    pub fn with_cipher_suite(mut self, cipher_suite: CipherSuite) -> Self {
+        self.cipher_suite = cipher_suite;
+        self
+    }
+
+    /// Sets the [`GarbageCollectPolicy`] that is applied to all loaded clients on each commit.
+    ///
+    /// # Example
+    pub fn set_garbage_collect_policy(&self, policy: GarbageCollectPolicy) -> Result<(), ClientError> {
+        *self.gc_policy.write()? = policy;
+        *self.commits_since_gc.write()? = 0;
+        Ok(())
+    }
+
+    /// Sets the [`UnlockPolicy`] that is applied when a snapshot file is unlocked.
+    pub fn set_unlock_policy(&self, policy: UnlockPolicy) -> Result<(), ClientError> {
+        *self.unlock_policy.write()? = policy;
+        Ok(())
+    }
+
+    /// Sets the [`IdleLockPolicy`], and starts a background thread that locks the [`Stronghold`] with
+    /// [`Self::lock`] once its clients have not been accessed for the timeout of the policy. The thread stops
+    /// when another policy is set, or when all other handles to the [`Stronghold`] have been dropped.
+    ///
+    /// A policy that commits the state fails with [`ClientError::SnapshotKeyLocationMissing`] if no snapshot key
+    /// has been stored with [`Self::store_snapshot_key_at_location`].
+    pub fn set_idle_lock_policy(&self, policy: IdleLockPolicy) -> Result<(), ClientError> {
+        if policy.commit_to.is_some() && self.key_location.read()?.is_none() {
+            return Err(ClientError::SnapshotKeyLocationMissing);
+        }
+        let timeout = policy.timeout;
+        let generation = {
+            let mut idle_lock = self.idle_lock.write()?;
+            idle_lock.policy = policy;
+            idle_lock.generation += 1;
+            idle_lock.generation
+        };
+        self.activity.touch();
+        if let Some(timeout) = timeout {
+            let stronghold = self.clone();
+            thread::spawn(move || stronghold.watch_idle(generation, timeout));
+        }
+        Ok(())
+    }
+
+    /// Locks the [`Stronghold`] according to the [`IdleLockPolicy`]: the state is committed if configured,
+    /// then the snapshot state, the snapshot key and all loaded clients are cleared as with [`Self::clear`], and
+    /// the callback of the policy is called. The clients have to be loaded again with
+    /// [`Self::load_client_from_snapshot`].
+    ///
+    /// If the commit fails, the [`Stronghold`] is not cleared, so that no uncommitted change is lost, and the
+    /// error of the commit is returned. Commit and clear happen under the same locks, so that no change to the
+    /// clients in between is lost.
+    pub fn lock(&self) -> Result<(), ClientError> {
+        let policy = self.idle_lock.read()?.policy.clone();
+        let (committed, cleared) = {
+            let mut snapshot = self.snapshot.write()?;
+            let mut clients = self.clients.write()?;
+            let committed = match &policy.commit_to {
+                Some(snapshot_path) => self.commit_clients(&mut snapshot, &clients, snapshot_path),
+                None => Ok(()),
+            };
+            let cleared = match committed {
+                Ok(()) => self.clear_clients(&mut snapshot, &mut clients),
+                Err(_) => Ok(()),
+            };
+            (committed, cleared)
+        };
+        if let Some(observer) = &policy.observer {
+            observer(&committed);
+        }
+        committed.and(cleared)
+    }
+
+    /// Locks the [`Stronghold`] each time its loaded clients have been idle for `timeout`, until the policy of
+    /// `generation` has been replaced.
+    fn watch_idle(self, generation: u64, timeout: Duration) {
+        // upper bound for sleeping, so that the thread notices a replaced policy or a dropped stronghold in time
+        const POLL_INTERVAL: Duration = Duration::from_secs(1);
+        // lower bound for sleeping, so that a zero or tiny timeout does not keep the thread busy
+        const MIN_INTERVAL: Duration = Duration::from_millis(10);
+
+        loop {
+            let replaced = self
+                .idle_lock
+                .read()
+                .map(|idle_lock| idle_lock.generation != generation)
+                .unwrap_or(true);
+            if replaced || Arc::strong_count(&self.idle_lock) == 1 {
+                return;
+            }
+
+            let idle = self.activity.idle();
+            if idle < timeout {
+                thread::sleep((timeout - idle).clamp(MIN_INTERVAL, POLL_INTERVAL));
+                continue;
+            }
+            let has_clients = self.clients.read().map(|clients| !clients.is_empty()).unwrap_or(false);
+            if has_clients {
+                // errors are reported to the observer of the policy
+                let _ = self.lock();
+            }
+            thread::sleep(timeout.clamp(MIN_INTERVAL, POLL_INTERVAL));
+        }
+    }
+
+    /// Lets `client` create new vaults with the [`CipherSuite`] of the [`Stronghold`], and lets the
+    /// [`Stronghold`] track the accesses to `client` for the [`IdleLockPolicy`].
+    fn attach_client(&self, client: &mut Client) {
+        client.cipher_suite = self.cipher_suite;
+        client.activity = self.activity.clone();
+        self.activity.touch();
+    }
+
+    /// Removes the expired records of all `clients` and garbage collects their revoked records according to
+    /// the configured [`GarbageCollectPolicy`].
+    fn apply_garbage_collect_policy(&self, clients: &HashMap<ClientId, Client>) -> Result<(), ClientError> {
+        for client in clients.values() {
+            client.garbage_collect_expired()?;
+        }
+        let policy = *self.gc_policy.read()?;
+        let revoked_before = match policy {
+            GarbageCollectPolicy::Manual => return Ok(()),
+            GarbageCollectPolicy::EveryNthCommit(n) => {
+                let mut commits = self.commits_since_gc.write()?;
+                *commits += 1;
+                if *commits < n {
+                    return Ok(());
+                }
+                *commits = 0;
+                None
+            }
+            GarbageCollectPolicy::GracePeriod(grace_period) => Some(
+                SystemTime::now()
+                    .checked_sub(grace_period)
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+            ),
+        };
+        for client in clients.values() {
+            client.garbage_collect_all(revoked_before)?;
+        }
+        Ok(())
+    }
+
     /// Load the state of a [`Snapshot`] at given `snapshot_path`.
     ///
     /// The [`Snapshot`] is secured in memory and may be used to load further
@@ -129,7 +348,8 @@ impl Stronghold {
         let mut snapshot = self.snapshot.write()?;
         let mut clients = self.clients.write()?;
 
-        load_snapshot!(snapshot, snapshot_path, keyprovider);
+        let unlock_policy = *self.unlock_policy.read()?;
+        load_snapshot!(snapshot, snapshot_path, keyprovider, unlock_policy);
 
         // If a client has already been loaded returns an error
         if clients.contains_key(&client_id) {
@@ -142,6 +362,7 @@ impl Stronghold {
 
         // Load the client state
         client.restore(client_state, client_id)?;
+        self.attach_client(&mut client);
 
         // insert client as ref into Strongholds client ref
         clients.insert(client_id, client.clone());
@@ -178,6 +399,7 @@ impl Stronghold {
 
         // Load the client state
         client.restore(client_state, client_id)?;
+        self.attach_client(&mut client);
 
         // insert client as ref into Strongholds client ref
         clients.insert(client_id, client.clone());
@@ -194,10 +416,12 @@ impl Stronghold {
     {
         let client_id = ClientId::load_from_path(client_path.as_ref(), client_path.as_ref());
         let clients = self.clients.read()?;
-        clients
+        let client = clients
             .get(&client_id)
             .cloned()
-            .ok_or(ClientError::ClientDataNotPresent)
+            .ok_or(ClientError::ClientDataNotPresent)?;
+        self.activity.touch();
+        Ok(client)
     }
 
     /// Unload the client from the clients currently managed by
@@ -226,10 +450,77 @@ impl Stronghold {
     /// Load the state of a [`Snapshot`] at given `snapshot_path`. The [`Snapshot`]
     /// is secured in memory.
     ///
+    /// Fails with [`ClientError::WrongKey`] if the key does not unlock the snapshot. Failed attempts
+    /// are limited by the configured [`UnlockPolicy`].
+    ///
     /// # Example
     pub fn load_snapshot(&self, keyprovider: &KeyProvider, snapshot_path: &SnapshotPath) -> Result<(), ClientError> {
         let mut snapshot = self.snapshot.write()?;
-        load_snapshot!(snapshot, snapshot_path, keyprovider);
+        let unlock_policy = *self.unlock_policy.read()?;
+        load_snapshot!(snapshot, snapshot_path, keyprovider, unlock_policy);
+        Ok(())
+    }
+
+    /// Report the changes that merging the snapshot at `snapshot_path` into the currently loaded
+    /// [`Snapshot`] would apply, per client and vault. Neither the loaded state nor the file is modified.
+    ///
+    /// Like [`Stronghold::load_snapshot`], failed attempts are limited by the configured [`UnlockPolicy`].
+    ///
+    /// # Example
+    pub fn preview_snapshot_merge(
+        &self,
+        keyprovider: &KeyProvider,
+        snapshot_path: &SnapshotPath,
+        config: &SyncSnapshotsConfig,
+    ) -> Result<SnapshotSyncReport, ClientError> {
+        if !snapshot_path.exists() {
+            let path = snapshot_path
+                .as_path()
+                .to_str()
+                .ok_or_else(|| ClientError::Inner("Cannot display path as string".to_string()))?;
+            return Err(ClientError::SnapshotFileMissing(path.to_string()));
+        }
+        let buffer = keyprovider
+            .try_unlock()
+            .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+        let key = buffer
+            .borrow()
+            .deref()
+            .try_into()
+            .map_err(|_| ClientError::IllegalKeySize(32))?;
+        let unlock_policy = *self.unlock_policy.read()?;
+        let state = unlock_policy.attempt(snapshot_path, || SnapshotState::read_from_snapshot(snapshot_path, key))?;
+
+        let snapshot = self.snapshot.read()?;
+        Ok(snapshot.preview_merge(&state, config)?)
+    }
+
+    /// Synchronize the state of all clients with a remote party over `stream`, e.g. a socket
+    /// to another device. See [`Snapshot::sync_with_peer`], the remote party is not authenticated and the sync
+    /// has to be allowed with [`SyncSnapshotsConfig::allow_unauthenticated_peers`].
+    ///
+    /// The state of the loaded clients is written into the [`Snapshot`] before each step of the sync and
+    /// reloaded after the received records have been merged. The [`Snapshot`] is not locked while waiting for
+    /// the remote party. Clients that have only been received from the remote party can be loaded with
+    /// [`Stronghold::load_client`]. The result is not persisted until the [`Stronghold`] is committed.
+    ///
+    /// # Example
+    pub fn sync_with_peer<S>(
+        &self,
+        stream: &mut S,
+        role: SyncRole,
+        config: SyncSnapshotsConfig,
+    ) -> Result<SnapshotSyncReport, SyncProtocolError>
+    where
+        S: Read + Write,
+    {
+        sync_state_with_peer(&mut &*self, stream, role, config)
+    }
+
+    fn write_clients(snapshot: &mut Snapshot, clients: &HashMap<ClientId, Client>) -> Result<(), ClientError> {
+        for client_id in clients.keys() {
+            write_with_clientid!(*client_id, snapshot, clients);
+        }
         Ok(())
     }
 
@@ -252,6 +543,27 @@ impl Stronghold {
         Ok(())
     }
 
+    /// Trust the remote peer, so that bundles signed by it are accepted by [`Snapshot::import_signed_bundle`].
+    /// The [`TrustStore`] is persisted with the next commit of the [`Stronghold`].
+    pub fn trust_peer(&self, identity: PeerIdentity) -> Result<(), ClientError> {
+        let mut snapshot = self.snapshot.write()?;
+        snapshot.trust_store_mut().trust(identity);
+        Ok(())
+    }
+
+    /// Revoke the remote peer with the given signing key, so that bundles signed by it are rejected.
+    /// Returns `false` if the peer is unknown.
+    pub fn revoke_peer(&self, signing_key: &[u8; ed25519::PUBLIC_KEY_LENGTH]) -> Result<bool, ClientError> {
+        let mut snapshot = self.snapshot.write()?;
+        Ok(snapshot.trust_store_mut().revoke(signing_key))
+    }
+
+    /// Returns a copy of the [`TrustStore`] of the loaded [`Snapshot`].
+    pub fn trust_store(&self) -> Result<TrustStore, ClientError> {
+        let snapshot = self.snapshot.read()?;
+        Ok(snapshot.trust_store().clone())
+    }
+
     /// Creates a new, empty [`Client`]
     ///
     /// # Example
@@ -260,10 +572,11 @@ impl Stronghold {
         P: AsRef<[u8]>,
     {
         let client_id = ClientId::load_from_path(client_path.as_ref(), client_path.as_ref());
-        let client = Client {
+        let mut client = Client {
             id: client_id,
             ..Default::default()
         };
+        self.attach_client(&mut client);
 
         // insert client as ref into Strongholds client ref
         let mut clients = self.clients.write()?;
@@ -292,6 +605,7 @@ impl Stronghold {
 
         let mut snapshot = self.snapshot.write()?;
         let clients = self.clients.read()?;
+        self.apply_garbage_collect_policy(&clients)?;
 
         let ids: Vec<ClientId> = clients.iter().map(|(id, _)| *id).collect();
 
@@ -306,9 +620,11 @@ impl Stronghold {
         let buffer_ref = buffer.borrow();
         let key = buffer_ref.deref();
 
+        snapshot.set_key_derivation(keyprovider.key_derivation().cloned());
         snapshot
             .write_to_snapshot(snapshot_path, UseKey::Key(key.try_into().unwrap()))
             .map_err(|e| ClientError::Inner(e.to_string()))?;
+        snapshot.set_header_path(snapshot_path);
 
         Ok(())
     }
@@ -317,6 +633,18 @@ impl Stronghold {
     ///
     /// # Example
     pub fn commit(&self, snapshot_path: &SnapshotPath) -> Result<(), ClientError> {
+        let mut snapshot = self.snapshot.write()?;
+        let clients = self.clients.read()?;
+        self.commit_clients(&mut snapshot, &clients, snapshot_path)
+    }
+
+    /// Writes `clients` into `snapshot` and the [`Snapshot`] file, with the locks of both held by the caller.
+    fn commit_clients(
+        &self,
+        snapshot: &mut Snapshot,
+        clients: &HashMap<ClientId, Client>,
+        snapshot_path: &SnapshotPath,
+    ) -> Result<(), ClientError> {
         if !snapshot_path.exists() {
             let path = snapshot_path.as_path().parent().ok_or_else(|| {
                 ClientError::SnapshotFileMissing("Parent directory of snapshot file does not exist".to_string())
@@ -328,13 +656,8 @@ impl Stronghold {
             }
         }
 
-        let mut snapshot = self.snapshot.write()?;
-        let clients = self.clients.read()?;
-        let ids: Vec<ClientId> = clients.iter().map(|(id, _)| *id).collect();
-
-        for client_id in ids {
-            write_with_clientid!(client_id, snapshot, clients);
-        }
+        self.apply_garbage_collect_policy(clients)?;
+        Self::write_clients(snapshot, clients)?;
 
         // CRITICAL SECTION
         let loc = self.key_location.read().map_err(|_| ClientError::LockAcquireFailed)?;
@@ -347,7 +670,171 @@ impl Stronghold {
         snapshot
             .write_to_snapshot(snapshot_path, UseKey::Stored(key_location.clone()))
             .map_err(|e| ClientError::Inner(e.to_string()))?;
+        snapshot.set_header_path(snapshot_path);
+
+        Ok(())
+    }
+
+    /// Adds a key slot to the snapshot file at `snapshot_path`, so that it can also be unlocked with the key
+    /// of `slot`. The snapshot is unlocked with `keyprovider`.
+    ///
+    /// Only the header of the snapshot file is rewritten, the encrypted content stays untouched. A snapshot
+    /// without key slots is re-encrypted once with a new master key, and gets a first key slot for
+    /// `keyprovider`. If the loaded [`Snapshot`] has been read from or last committed to `snapshot_path`, its
+    /// key slots are replaced by the ones of the file, so that they are kept on the next commit.
+    ///
+    /// Failed attempts to unlock the snapshot are limited by the configured [`UnlockPolicy`].
+    pub fn add_key_slot(
+        &self,
+        snapshot_path: &SnapshotPath,
+        keyprovider: &KeyProvider,
+        slot: NewKeySlot<'_>,
+    ) -> Result<(), ClientError> {
+        let (kind, slot_keyprovider) = match slot {
+            NewKeySlot::Password(slot_keyprovider) => {
+                let key_derivation = slot_keyprovider
+                    .key_derivation()
+                    .cloned()
+                    .ok_or(ClientError::MissingKeyDerivation)?;
+                (KeySlotKind::Password(key_derivation), slot_keyprovider)
+            }
+            NewKeySlot::RecoveryCode(slot_keyprovider) => {
+                let key_derivation = slot_keyprovider
+                    .key_derivation()
+                    .cloned()
+                    .ok_or(ClientError::MissingKeyDerivation)?;
+                (KeySlotKind::RecoveryCode(key_derivation), slot_keyprovider)
+            }
+            NewKeySlot::KeyFile(slot_keyprovider) => (KeySlotKind::KeyFile, slot_keyprovider),
+            NewKeySlot::Recipient(public_key) => {
+                return self.update_key_slots(snapshot_path, keyprovider, |master_key, slots| {
+                    slots.push(KeySlot::for_recipient(master_key, public_key.to_bytes())?);
+                    Ok(())
+                });
+            }
+            NewKeySlot::Threshold { threshold, holders } => {
+                let public_keys = holders
+                    .iter()
+                    .map(|holder| {
+                        let buffer = holder
+                            .try_unlock()
+                            .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+                        let mut key: Key = buffer
+                            .borrow()
+                            .deref()
+                            .try_into()
+                            .map_err(|_| ClientError::IllegalKeySize(32))?;
+                        let public_key = x25519::SecretKey::from_bytes(key).public_key().to_bytes();
+                        key.zeroize();
+                        Ok(public_key)
+                    })
+                    .collect::<Result<Vec<_>, ClientError>>()?;
+                return self.update_key_slots(snapshot_path, keyprovider, |master_key, slots| {
+                    slots.push(KeySlot::for_threshold(master_key, threshold, &public_keys)?);
+                    Ok(())
+                });
+            }
+            NewKeySlot::External(provider) => {
+                let mut slot_key: Key = [0; 32];
+                rand::fill(&mut slot_key).map_err(|e| ClientError::Inner(e.to_string()))?;
+                let kind = provider.wrap_key(&slot_key).map(|sealed_key| KeySlotKind::External {
+                    id: provider.id(),
+                    sealed_key,
+                });
+                let result = kind.and_then(|kind| {
+                    self.update_key_slots(snapshot_path, keyprovider, |master_key, slots| {
+                        slots.push(KeySlot::new(kind, master_key, &slot_key)?);
+                        Ok(())
+                    })
+                });
+                slot_key.zeroize();
+                return result;
+            }
+        };
+
+        let buffer = slot_keyprovider
+            .try_unlock()
+            .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+        let mut slot_key: Key = buffer
+            .borrow()
+            .deref()
+            .try_into()
+            .map_err(|_| ClientError::IllegalKeySize(32))?;
+        let result = self.update_key_slots(snapshot_path, keyprovider, |master_key, slots| {
+            slots.push(KeySlot::new(kind, master_key, &slot_key)?);
+            Ok(())
+        });
+        slot_key.zeroize();
+        result
+    }
+
+    /// Lists the key slots of the snapshot file at `snapshot_path`, without unlocking it. The position of a
+    /// key slot in the list is its index for [`Stronghold::remove_key_slot`].
+    pub fn list_key_slots(&self, snapshot_path: &SnapshotPath) -> Result<Vec<KeySlotKind>, ClientError> {
+        let header = read_header(snapshot_path.as_path()).map_err(SnapshotError::from)?;
+        Ok(header.key_slots.into_iter().map(|slot| slot.kind).collect())
+    }
+
+    /// Removes the key slot at `index` from the snapshot file at `snapshot_path`, which is unlocked with
+    /// `keyprovider`. The last key slot of a snapshot can not be removed.
+    ///
+    /// Like [`Stronghold::add_key_slot`], only the header of the snapshot file is rewritten, and failed attempts
+    /// to unlock the snapshot are limited by the configured [`UnlockPolicy`].
+    pub fn remove_key_slot(
+        &self,
+        snapshot_path: &SnapshotPath,
+        keyprovider: &KeyProvider,
+        index: usize,
+    ) -> Result<(), ClientError> {
+        self.update_key_slots(snapshot_path, keyprovider, |_, slots| {
+            if index >= slots.len() {
+                return Err(KeySlotError::NotFound(index));
+            }
+            slots.remove(index);
+            Ok(())
+        })
+    }
+
+    fn update_key_slots<F>(
+        &self,
+        snapshot_path: &SnapshotPath,
+        keyprovider: &KeyProvider,
+        f: F,
+    ) -> Result<(), ClientError>
+    where
+        F: FnOnce(&Key, &mut Vec<KeySlot>) -> Result<(), KeySlotError>,
+    {
+        if !snapshot_path.exists() {
+            let path = snapshot_path
+                .as_path()
+                .to_str()
+                .ok_or_else(|| ClientError::Inner("Cannot display path as string".to_string()))?;
+            return Err(ClientError::SnapshotFileMissing(path.to_string()));
+        }
+
+        let mut snapshot = self.snapshot.write()?;
+        let unlock_policy = *self.unlock_policy.read()?;
 
+        // CRITICAL SECTION
+        let buffer = keyprovider
+            .try_unlock()
+            .map_err(|e| ClientError::Inner(format!("{:?}", e)))?;
+        let mut key: Key = buffer
+            .borrow()
+            .deref()
+            .try_into()
+            .map_err(|_| ClientError::IllegalKeySize(32))?;
+        let header = unlock_policy.attempt(snapshot_path, || {
+            update_key_slots(snapshot_path.as_path(), &key, &[], f).map_err(SnapshotError::from)
+        });
+        key.zeroize();
+        // END CRITICAL SECTION
+
+        let header = header?;
+        // The key slots of another file must not be written to the loaded snapshot's file on the next commit.
+        if snapshot.has_header_of(snapshot_path) {
+            snapshot.set_key_slots(header.key_slots);
+        }
         Ok(())
     }
 
@@ -371,8 +858,18 @@ impl Stronghold {
     /// snapshot file. Use [`Self::load_client_from_snapshot`] to reload any [`Client`] and
     /// [`Snapshot`] state
     pub fn clear(&self) -> Result<(), ClientError> {
-        self.snapshot.write()?.clear()?;
+        let mut snapshot = self.snapshot.write()?;
         let mut clients = self.clients.write()?;
+        self.clear_clients(&mut snapshot, &mut clients)
+    }
+
+    /// Clears `snapshot`, `clients` and the session state, with the locks of both held by the caller.
+    fn clear_clients(
+        &self,
+        snapshot: &mut Snapshot,
+        clients: &mut HashMap<ClientId, Client>,
+    ) -> Result<(), ClientError> {
+        snapshot.clear()?;
         self.store.clear()?;
         self.key_location.write()?.take();
         for (_, client) in clients.drain() {
@@ -381,3 +878,30 @@ impl Stronghold {
         Ok(())
     }
 }
+
+impl SyncState for &Stronghold {
+    fn read<T, F>(&mut self, f: F) -> Result<T, SyncProtocolError>
+    where
+        F: FnOnce(&Snapshot) -> Result<T, SyncProtocolError>,
+    {
+        let mut snapshot = self.snapshot.write().map_err(ClientError::from)?;
+        let clients = self.clients.read().map_err(ClientError::from)?;
+        Stronghold::write_clients(&mut snapshot, &clients)?;
+        f(&snapshot)
+    }
+
+    fn update<T, F>(&mut self, f: F) -> Result<T, SyncProtocolError>
+    where
+        F: FnOnce(&mut Snapshot) -> Result<T, SyncProtocolError>,
+    {
+        let mut snapshot = self.snapshot.write().map_err(ClientError::from)?;
+        let clients = self.clients.read().map_err(ClientError::from)?;
+        Stronghold::write_clients(&mut snapshot, &clients)?;
+        let output = f(&mut snapshot)?;
+        for (client_id, client) in clients.iter() {
+            let state = snapshot.get_state(*client_id)?;
+            client.clone().restore(state, *client_id)?;
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Identities of remote peers that the local [`crate::Snapshot`] accepts signed export bundles from.
+
+use crypto::{keys::x25519, signatures::ed25519};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Public identity of a remote peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PeerIdentity {
+    /// Ed25519 public key that the peer signs its export bundles with.
+    pub signing_key: [u8; ed25519::PUBLIC_KEY_LENGTH],
+
+    /// X25519 public key that export bundles for the peer are encrypted for.
+    pub exchange_key: [u8; x25519::PUBLIC_KEY_LENGTH],
+}
+
+/// Trust status of a [`PeerIdentity`] in the [`TrustStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerStatus {
+    /// Bundles signed by the peer are accepted.
+    Trusted,
+
+    /// The peer has been revoked, bundles signed by it are rejected.
+    Revoked,
+}
+
+/// Store of the peer identities that are known to the local snapshot, keyed by their signing key.
+/// The store is persisted in the snapshot file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustStore {
+    peers: HashMap<[u8; ed25519::PUBLIC_KEY_LENGTH], (PeerIdentity, PeerStatus)>,
+}
+
+impl TrustStore {
+    /// Trust the peer. A previously revoked peer is trusted again.
+    pub fn trust(&mut self, identity: PeerIdentity) {
+        self.peers.insert(identity.signing_key, (identity, PeerStatus::Trusted));
+    }
+
+    /// Revoke the peer with the given signing key. Returns `false` if the peer is unknown.
+    pub fn revoke(&mut self, signing_key: &[u8; ed25519::PUBLIC_KEY_LENGTH]) -> bool {
+        match self.peers.get_mut(signing_key) {
+            Some((_, status)) => {
+                *status = PeerStatus::Revoked;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the peer with the given signing key from the store, so that it is unknown.
+    pub fn remove(&mut self, signing_key: &[u8; ed25519::PUBLIC_KEY_LENGTH]) -> Option<PeerIdentity> {
+        self.peers.remove(signing_key).map(|(identity, _)| identity)
+    }
+
+    /// Get the identity and status of the peer with the given signing key.
+    pub fn get(&self, signing_key: &[u8; ed25519::PUBLIC_KEY_LENGTH]) -> Option<(PeerIdentity, PeerStatus)> {
+        self.peers.get(signing_key).copied()
+    }
+
+    /// Check if bundles signed with `signing_key` are accepted.
+    pub fn is_trusted(&self, signing_key: &[u8; ed25519::PUBLIC_KEY_LENGTH]) -> bool {
+        matches!(self.peers.get(signing_key), Some((_, PeerStatus::Trusted)))
+    }
+
+    /// List all known peers with their status.
+    pub fn list(&self) -> Vec<(PeerIdentity, PeerStatus)> {
+        self.peers.values().copied().collect()
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{ClientError, SnapshotError, SnapshotPath};
+
+/// Policy against guessing the key of a snapshot through the methods of [`crate::Stronghold`] that unlock a
+/// snapshot file: [`crate::Stronghold::load_snapshot`], [`crate::Stronghold::load_client_from_snapshot`],
+/// [`crate::Stronghold::preview_snapshot_merge`], [`crate::Stronghold::add_key_slot`] and
+/// [`crate::Stronghold::remove_key_slot`].
+///
+/// If any protection is enabled, failed unlock attempts are counted in a file next to the snapshot file
+/// (the snapshot path with an `.attempts` suffix), and the counter is reset on the next successful unlock.
+/// The attempts of a process are serialized, so that concurrent attempts can not bypass the counter. A counter
+/// file that can not be read is treated as if the maximum number of attempts had failed: the next attempt is
+/// delayed by the maximum backoff, and the next failed attempt wipes the snapshot if wiping is enabled.
+/// An attempt fails if the key does not unlock any key slot of the snapshot, or, for snapshots without key
+/// slots, if the snapshot can not be decrypted with it. In the latter case a corrupted snapshot file also
+/// counts as failed attempt, because it can not be told apart from a wrong key.
+///
+/// The counter protects against guessing through these methods, not against an attacker that can modify
+/// the files directly. Functions that unlock a snapshot file without a [`crate::Stronghold`], like
+/// [`crate::merge_snapshot_files`] and [`crate::KeyProvider::from_password_for_snapshot`], are not limited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UnlockPolicy {
+    /// Delay after the first failed attempt, during which further attempts fail with
+    /// [`ClientError::UnlockBackoff`]. The delay doubles with each further failed attempt.
+    pub backoff: Option<Duration>,
+
+    /// Upper bound of the delay between attempts.
+    pub max_backoff: Option<Duration>,
+
+    /// Number of failed attempts after which the snapshot file is overwritten and deleted.
+    pub wipe_after: Option<u32>,
+}
+
+impl UnlockPolicy {
+    /// Enables an exponential backoff that starts at `backoff` and doubles up to `max_backoff`.
+    pub fn with_backoff(mut self, backoff: Duration, max_backoff: Duration) -> Self {
+        self.backoff = Some(backoff);
+        self.max_backoff = Some(max_backoff);
+        self
+    }
+
+    /// Wipes the snapshot file after `attempts` failed attempts.
+    pub fn with_wipe_after(mut self, attempts: u32) -> Self {
+        self.wipe_after = Some(attempts);
+        self
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.backoff.is_some() || self.wipe_after.is_some()
+    }
+
+    fn delay(&self, failed: u32) -> Duration {
+        let backoff = match self.backoff {
+            Some(backoff) if failed > 0 => backoff,
+            _ => return Duration::ZERO,
+        };
+        let delay = backoff
+            .checked_mul(2u32.saturating_pow(failed - 1))
+            .unwrap_or(Duration::MAX);
+        match self.max_backoff {
+            Some(max_backoff) => delay.min(max_backoff),
+            None => delay,
+        }
+    }
+
+    /// Runs the unlock attempt `f` on the snapshot at `snapshot_path` according to the policy. A failed attempt
+    /// is one that fails with [`SnapshotError::WrongKey`].
+    pub(crate) fn attempt<T, F>(&self, snapshot_path: &SnapshotPath, f: F) -> Result<T, ClientError>
+    where
+        F: FnOnce() -> Result<T, SnapshotError>,
+    {
+        if !self.is_enabled() {
+            return f().map_err(ClientError::from);
+        }
+
+        // the counter is read, checked and written as one step
+        let _lock = ATTEMPTS_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let attempts_path = attempts_path(snapshot_path);
+        let now = SystemTime::now();
+        let mut attempts = match UnlockAttempts::read(&attempts_path) {
+            Some(attempts) => attempts,
+            None => {
+                let attempts = UnlockAttempts {
+                    failed: u32::MAX,
+                    last_failure: now,
+                };
+                attempts.write(&attempts_path)?;
+                attempts
+            }
+        };
+        let remaining = match attempts.last_failure.checked_add(self.delay(attempts.failed)) {
+            Some(retry_at) => retry_at.duration_since(now).unwrap_or_default(),
+            None => Duration::MAX,
+        };
+        if !remaining.is_zero() {
+            return Err(ClientError::UnlockBackoff(remaining));
+        }
+
+        match f() {
+            Ok(value) => {
+                if attempts.failed > 0 {
+                    remove_file(&attempts_path)?;
+                }
+                Ok(value)
+            }
+            Err(SnapshotError::WrongKey) => {
+                attempts.failed = attempts.failed.saturating_add(1);
+                attempts.last_failure = now;
+                if matches!(self.wipe_after, Some(n) if attempts.failed >= n) {
+                    wipe(snapshot_path)?;
+                    remove_file(&attempts_path)?;
+                    return Err(ClientError::SnapshotWiped);
+                }
+                attempts.write(&attempts_path)?;
+                Err(ClientError::WrongKey)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Serializes the unlock attempts of the process, see [`UnlockPolicy::attempt`].
+static ATTEMPTS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Failed unlock attempts, persisted next to the snapshot file.
+struct UnlockAttempts {
+    failed: u32,
+    last_failure: SystemTime,
+}
+
+impl UnlockAttempts {
+    /// Reads the counter, or returns `None` if it exists but can not be read or is corrupted.
+    fn read(path: &PathBuf) -> Option<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Some(UnlockAttempts {
+                    failed: 0,
+                    last_failure: UNIX_EPOCH,
+                })
+            }
+            Err(_) => return None,
+        };
+        if bytes.len() != 12 {
+            return None;
+        }
+        let failed = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+        let last_failure = u64::from_le_bytes(bytes[4..].try_into().unwrap());
+        Some(UnlockAttempts {
+            failed,
+            last_failure: UNIX_EPOCH.checked_add(Duration::from_millis(last_failure))?,
+        })
+    }
+
+    fn write(&self, path: &PathBuf) -> Result<(), ClientError> {
+        let last_failure = self
+            .last_failure
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut bytes = self.failed.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&last_failure.to_le_bytes());
+
+        // the counter is replaced atomically, so that it is never left partially written
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        let write_file = || -> std::io::Result<()> {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp_path)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, path)
+        };
+        write_file().map_err(|e| ClientError::Inner(e.to_string()))
+    }
+}
+
+fn attempts_path(snapshot_path: &SnapshotPath) -> PathBuf {
+    let mut path = snapshot_path.as_path().as_os_str().to_os_string();
+    path.push(".attempts");
+    path.into()
+}
+
+fn remove_file(path: &PathBuf) -> Result<(), ClientError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(ClientError::Inner(e.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Overwrite the snapshot file with zeros before deleting it.
+fn wipe(snapshot_path: &SnapshotPath) -> Result<(), ClientError> {
+    let path = snapshot_path.as_path();
+    let wipe_file = || -> std::io::Result<()> {
+        let len = fs::metadata(path)?.len() as usize;
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.write_all(&vec![0u8; len])?;
+        file.sync_all()?;
+        fs::remove_file(path)
+    };
+    wipe_file().map_err(|e| ClientError::Inner(e.to_string()))
+}
@@ -1,8 +1,9 @@
 // Copyright 2020-2022 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{derive_vault_id, procedures::Runner, Client, ClientError, Location};
-use engine::vault::VaultId;
+use crate::{derive_vault_id, expiry, procedures::Runner, CipherSuite, Client, ClientError, KeyProvider, Location};
+use engine::vault::{RecordId, VaultId};
+use std::time::Duration;
 
 pub const DEFAULT_RANDOM_HINT_SIZE: usize = 24;
 
@@ -27,6 +28,21 @@ impl ClientVault {
         Ok(())
     }
 
+    /// Writes a secret into the vault that expires after `ttl`. See [`Client::set_record_ttl`]. The secret and
+    /// its expiry are written in one step.
+    ///
+    /// # Example
+    pub fn write_secret_with_ttl(
+        &self,
+        location: Location,
+        payload: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), ClientError> {
+        let expires_at = expiry(ttl)?;
+        self.client.write_record(&location, payload, Some(expires_at))?;
+        Ok(())
+    }
+
     /// Deletes a secret from the vault
     ///
     /// # Example
@@ -58,6 +74,42 @@ impl ClientVault {
         Ok(())
     }
 
+    /// Undoes the revocation of a secret. This is only possible as long as the
+    /// revoked record has not been garbage collected.
+    ///
+    /// # Example
+    pub fn restore_secret<P>(&self, record_path: P) -> Result<(), ClientError>
+    where
+        P: AsRef<[u8]>,
+    {
+        let location = Location::Generic {
+            record_path: record_path.as_ref().to_vec(),
+            vault_path: self.vault_path.clone(),
+        };
+        self.client.restore_data(&location)
+    }
+
+    /// Moves a secret to another record path inside this vault. The secret is re-encrypted under its
+    /// new [`RecordId`] without exposing the plaintext. Use [`Client::move_record`] to move a secret
+    /// into another vault.
+    ///
+    /// # Example
+    pub fn move_secret<P>(&self, from: P, to: P) -> Result<(), ClientError>
+    where
+        P: AsRef<[u8]>,
+    {
+        let source = Location::generic(self.vault_path.clone(), from.as_ref().to_vec());
+        let target = Location::generic(self.vault_path.clone(), to.as_ref().to_vec());
+        self.client.move_record(&source, &target)
+    }
+
+    /// Returns the [`RecordId`]s of all revoked records that have not been garbage collected yet
+    ///
+    /// # Example
+    pub fn list_revoked(&self) -> Result<Vec<RecordId>, ClientError> {
+        self.client.list_revoked(self.id())
+    }
+
     /// Collects revoked records and deletes them
     ///
     /// # Example
@@ -67,6 +119,50 @@ impl ClientVault {
         Ok(result)
     }
 
+    /// Protects the vault with the key of `keyprovider`, in addition to the key of the snapshot. The vault and
+    /// its key are sealed with the key of `keyprovider` and removed from memory, and are only written into the
+    /// snapshot in sealed form. Until the vault is unlocked with [`Self::unlock`], procedures and operations on
+    /// it fail with [`ClientError::VaultLocked`] or [`crate::procedures::ProcedureError::VaultLocked`].
+    ///
+    /// Protecting an unlocked vault again changes its key. The sealed vaults are stored as records in a
+    /// reserved vault of the client.
+    ///
+    /// # Example
+    pub fn protect(&self, keyprovider: &KeyProvider) -> Result<(), ClientError> {
+        self.client.protect_vault(self.id(), keyprovider)
+    }
+
+    /// Unlocks the protected vault with the key of `keyprovider`. Fails with [`ClientError::WrongKey`] if the key
+    /// does not match. The vault stays unlocked until [`Self::lock`] is called or the client is cleared, and is
+    /// sealed again with the same key on each commit.
+    ///
+    /// # Example
+    pub fn unlock(&self, keyprovider: &KeyProvider) -> Result<(), ClientError> {
+        self.client.unlock_vault(self.id(), keyprovider)
+    }
+
+    /// Seals the unlocked protected vault with its current content and removes it from memory.
+    ///
+    /// # Example
+    pub fn lock(&self) -> Result<(), ClientError> {
+        self.client.lock_vault(self.id())
+    }
+
+    /// Returns `true` if the vault is protected and has not been unlocked.
+    ///
+    /// # Example
+    pub fn is_locked(&self) -> Result<bool, ClientError> {
+        self.client.is_vault_locked(self.id())
+    }
+
+    /// Returns the [`CipherSuite`] that encrypts the records of the vault, or `None` if the vault does not exist
+    /// yet or is locked. See [`crate::Stronghold::with_cipher_suite`].
+    ///
+    /// # Example
+    pub fn cipher_suite(&self) -> Result<Option<CipherSuite>, ClientError> {
+        self.client.vault_cipher_suite(self.id())
+    }
+
     pub fn id(&self) -> VaultId {
         derive_vault_id(self.vault_path.clone())
     }
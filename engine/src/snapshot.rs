@@ -7,7 +7,10 @@
 //! the Stronghold ecosystem.
 //!
 //! The format has a header with version and magic bytes to appease applications
-//! wishing to provide file-type detection.
+//! wishing to provide file-type detection. It is followed by an unencrypted but
+//! authenticated [`Header`] that describes how the key has been derived from a password.
+//! The header may also hold [`KeySlot`]s, that each wrap the master key of the snapshot for
+//! another key, e.g. a recovery code or the public key of a recipient.
 //!
 //! The data stored within a snapshot is considered opaque and uses 256 bit keys.
 //! It provides recommended ways to derive the snapshot encryption key from a user
@@ -25,7 +28,10 @@
 
 mod compression;
 pub mod files;
+mod header;
+pub mod shamir;
 
 mod logic;
 pub use compression::{compress, decompress, Lz4DecodeError};
+pub use header::*;
 pub use logic::*;
@@ -0,0 +1,451 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crypto::{keys::x25519, utils::rand};
+use runtime::memories::buffer::Buffer;
+use zeroize::Zeroize;
+
+use crate::snapshot::{
+    logic::{as_key, KEY_SIZE},
+    read_guarded, seal, shamir, write, Key, ReadError, WriteError,
+};
+
+/// Unencrypted header of a snapshot, that follows the magic and version bytes. The header is authenticated
+/// as part of the associated data of the snapshot, so it can be read without the key but not be modified.
+/// The key slots are excluded from it, they are authenticated each on its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    /// Describes how the snapshot key has been derived from a password, if it has been.
+    pub key_derivation: Option<KeyDerivation>,
+
+    /// Key slots that wrap the master key that the snapshot is encrypted with. If there are no key
+    /// slots, the snapshot is directly encrypted with the snapshot key.
+    pub key_slots: Vec<KeySlot>,
+}
+
+/// A key slot wraps the master key of a snapshot so that it can be unlocked with another key. The wrapped
+/// master key is encrypted with the same envelope as the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySlot {
+    /// The kind of the key that unlocks the slot.
+    pub kind: KeySlotKind,
+    wrapped_key: Vec<u8>,
+}
+
+/// Kind of the key that unlocks a [`KeySlot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySlotKind {
+    /// A key that is derived from a password.
+    Password(KeyDerivation),
+    /// A key that is derived from a recovery code.
+    RecoveryCode(KeyDerivation),
+    /// A raw key, e.g. read from a key file.
+    KeyFile,
+    /// The secret key of the given x25519 public key.
+    Recipient([u8; x25519::PUBLIC_KEY_LENGTH]),
+    /// A key that is reconstructed from `threshold` of the `shares`, see [`KeySlot::for_threshold`].
+    Threshold {
+        /// Number of shares that are required to reconstruct the key.
+        threshold: u8,
+        /// The shares of the holders, each encrypted for the public key of its holder.
+        shares: Vec<Vec<u8>>,
+    },
+    /// A key that is sealed by an external key encryption provider, e.g. a KMS or a TPM.
+    External {
+        /// Identifies the provider and its key encryption key.
+        id: Vec<u8>,
+        /// The key of the slot, sealed by the provider.
+        sealed_key: Vec<u8>,
+    },
+}
+
+/// Descriptor of the function that derived the snapshot key from a password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyDerivation {
+    /// The password truncated or zero-padded to 32 bytes.
+    Truncated,
+    /// The `blake2b` hash of the password.
+    Blake2b,
+    /// The `argon2` hash of the password.
+    Argon2(Argon2Params),
+    /// The `argon2` hash of the password combined with the contents of a key file, and optionally a pepper.
+    Composite {
+        /// Parameters of the `argon2` hash.
+        argon2: Argon2Params,
+        /// Whether a pepper has been mixed into the key.
+        pepper: bool,
+    },
+}
+
+/// Parameters of an `argon2` key derivation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// The argon2 variant.
+    pub variant: Argon2Variant,
+    /// Salt that is hashed together with the password.
+    pub salt: Vec<u8>,
+    /// Memory cost in KiB.
+    pub mem_cost: u32,
+    /// Number of iterations.
+    pub time_cost: u32,
+    /// Degree of parallelism.
+    pub lanes: u32,
+}
+
+/// Variant of the `argon2` hash function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argon2Variant {
+    Argon2d,
+    Argon2i,
+    Argon2id,
+}
+
+/// Upper bounds for the `argon2` parameters, so that a tampered header can not make the key derivation exhaust
+/// memory or time before the header is authenticated.
+const MAX_ARGON2_MEM_COST: u32 = 4 * 1024 * 1024;
+const MAX_ARGON2_TIME_COST: u32 = 64;
+const MAX_ARGON2_LANES: u32 = 64;
+
+const TAG_NONE: u8 = 0;
+const TAG_TRUNCATED: u8 = 1;
+const TAG_BLAKE2B: u8 = 2;
+const TAG_ARGON2: u8 = 3;
+const TAG_COMPOSITE: u8 = 4;
+
+const TAG_PASSWORD: u8 = 0;
+const TAG_RECOVERY_CODE: u8 = 1;
+const TAG_KEY_FILE: u8 = 2;
+const TAG_RECIPIENT: u8 = 3;
+const TAG_THRESHOLD: u8 = 4;
+const TAG_EXTERNAL: u8 = 5;
+
+impl Header {
+    /// Encode the header into bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.authenticated_bytes();
+        if !self.key_slots.is_empty() {
+            bytes.extend_from_slice(&(self.key_slots.len() as u32).to_le_bytes());
+            for slot in &self.key_slots {
+                bytes.extend_from_slice(&slot.kind.to_bytes());
+                bytes.extend_from_slice(&(slot.wrapped_key.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(&slot.wrapped_key);
+            }
+        }
+        bytes
+    }
+
+    /// Decode a header that has been encoded with [`Header::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReadError> {
+        let mut reader = HeaderReader(bytes);
+        let key_derivation = match reader.u8()? {
+            TAG_NONE => None,
+            _ => {
+                reader = HeaderReader(bytes);
+                Some(reader.key_derivation()?)
+            }
+        };
+        let mut key_slots = Vec::new();
+        if !reader.0.is_empty() {
+            for _ in 0..reader.u32()? {
+                let kind = reader.key_slot_kind()?;
+                let len = reader.u32()? as usize;
+                let wrapped_key = reader.take(len)?.to_vec();
+                key_slots.push(KeySlot { kind, wrapped_key });
+            }
+        }
+        Ok(Header {
+            key_derivation,
+            key_slots,
+        })
+    }
+
+    /// The part of the encoded header that is authenticated together with the snapshot.
+    pub(crate) fn authenticated_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match &self.key_derivation {
+            None => bytes.push(TAG_NONE),
+            Some(key_derivation) => key_derivation.encode(&mut bytes),
+        }
+        bytes
+    }
+
+    /// Get the key that the snapshot is encrypted with: the master key from the first key slot that `key`
+    /// unlocks, or `key` itself if there are no key slots. The key is returned in guarded memory.
+    pub fn unlock(&self, key: &Key) -> Result<Buffer<u8>, ReadError> {
+        if self.key_slots.is_empty() {
+            return Ok(Buffer::alloc(key, key.len()));
+        }
+        self.key_slots
+            .iter()
+            .find_map(|slot| slot.unwrap_key(key).ok())
+            .ok_or(ReadError::NoMatchingKeySlot)
+    }
+}
+
+impl KeyDerivation {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            KeyDerivation::Truncated => bytes.push(TAG_TRUNCATED),
+            KeyDerivation::Blake2b => bytes.push(TAG_BLAKE2B),
+            KeyDerivation::Argon2(params) => {
+                bytes.push(TAG_ARGON2);
+                params.encode(bytes);
+            }
+            KeyDerivation::Composite { argon2, pepper } => {
+                bytes.push(TAG_COMPOSITE);
+                bytes.push(*pepper as u8);
+                argon2.encode(bytes);
+            }
+        }
+    }
+}
+
+impl Argon2Params {
+    /// Fails if the memory cost, the time cost or the lanes are zero or exceed the limits that are accepted when
+    /// a header is read.
+    pub fn check_limits(&self) -> Result<(), String> {
+        for (name, value, max) in [
+            ("memory cost", self.mem_cost, MAX_ARGON2_MEM_COST),
+            ("time cost", self.time_cost, MAX_ARGON2_TIME_COST),
+            ("lanes", self.lanes, MAX_ARGON2_LANES),
+        ] {
+            if value == 0 || value > max {
+                return Err(format!("argon2 {} {} is not in 1..={}", name, value, max));
+            }
+        }
+        Ok(())
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.variant as u8);
+        bytes.extend_from_slice(&self.mem_cost.to_le_bytes());
+        bytes.extend_from_slice(&self.time_cost.to_le_bytes());
+        bytes.extend_from_slice(&self.lanes.to_le_bytes());
+        bytes.extend_from_slice(&(self.salt.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.salt);
+    }
+}
+
+impl KeySlot {
+    /// Create a key slot of the given kind, that wraps `master_key` so that it can be unlocked with `key`.
+    /// For a [`KeySlotKind::Recipient`], `key` has to be the secret key of the recipient, see
+    /// [`KeySlot::for_recipient`] to create the slot from the public key only.
+    pub fn new(kind: KeySlotKind, master_key: &Key, key: &Key) -> Result<Self, WriteError> {
+        if let KeySlotKind::Recipient(public_key) = &kind {
+            let sk = x25519::SecretKey::from_bytes(*key);
+            if &sk.public_key().to_bytes() != public_key {
+                return Err(WriteError::CorruptedData("key does not match the recipient".into()));
+            }
+        }
+        let mut wrapped_key = Vec::new();
+        write(master_key, &mut wrapped_key, key, &kind.to_bytes())?;
+        Ok(KeySlot { kind, wrapped_key })
+    }
+
+    /// Create a key slot that wraps `master_key` for the recipient with the given x25519 public key.
+    pub fn for_recipient(master_key: &Key, public_key: [u8; x25519::PUBLIC_KEY_LENGTH]) -> Result<Self, WriteError> {
+        let kind = KeySlotKind::Recipient(public_key);
+        let mut wrapped_key = Vec::new();
+        let public_key = x25519::PublicKey::from_bytes(public_key);
+        seal(master_key, &mut wrapped_key, &public_key, &kind.to_bytes())?;
+        Ok(KeySlot { kind, wrapped_key })
+    }
+
+    /// Create a key slot that can be unlocked with any `threshold` of the keys of the holders with the given
+    /// x25519 public keys. The master key is wrapped with a random key, that is split with Shamir's secret
+    /// sharing into one share per holder. Each share is encrypted for its holder.
+    pub fn for_threshold(
+        master_key: &Key,
+        threshold: u8,
+        holders: &[[u8; x25519::PUBLIC_KEY_LENGTH]],
+    ) -> Result<Self, WriteError> {
+        let count = u8::try_from(holders.len())
+            .map_err(|_| WriteError::CorruptedData(format!("too many holders: {}", holders.len())))?;
+
+        let mut threshold_key = Buffer::<u8>::zero(KEY_SIZE);
+        rand::fill(&mut threshold_key.borrow_mut()).map_err(|e| WriteError::GenerateRandom(format!("{}", e)))?;
+        let threshold_key = threshold_key.borrow();
+        let threshold_key = as_key(&threshold_key);
+        let mut shares = shamir::split(threshold_key, threshold, count)?;
+
+        let mut encrypted_shares = Vec::with_capacity(shares.len());
+        for (share, public_key) in shares.iter().zip(holders) {
+            let mut encrypted = Vec::new();
+            seal(
+                share,
+                &mut encrypted,
+                &x25519::PublicKey::from_bytes(*public_key),
+                &[TAG_THRESHOLD, threshold],
+            )?;
+            encrypted_shares.push(encrypted);
+        }
+        shares.zeroize();
+
+        let kind = KeySlotKind::Threshold {
+            threshold,
+            shares: encrypted_shares,
+        };
+        KeySlot::new(kind, master_key, threshold_key)
+    }
+
+    /// Decrypt the share of a threshold key slot that belongs to the holder of `key`, and return it together
+    /// with its index. Returns `None` if this is no threshold key slot or if `key` does not belong to any holder.
+    /// The share is decrypted directly into guarded memory.
+    pub fn decrypt_share(&self, key: &Key) -> Option<(usize, Buffer<u8>)> {
+        match &self.kind {
+            KeySlotKind::Threshold { threshold, shares } => shares.iter().enumerate().find_map(|(index, share)| {
+                read_guarded(&mut share.as_slice(), key, &[TAG_THRESHOLD, *threshold])
+                    .ok()
+                    .map(|share| (index, share))
+            }),
+            _ => None,
+        }
+    }
+
+    /// Unwrap the master key with `key`. The master key is decrypted directly into guarded memory.
+    pub fn unwrap_key(&self, key: &Key) -> Result<Buffer<u8>, ReadError> {
+        let master_key = read_guarded(&mut self.wrapped_key.as_slice(), key, &self.kind.to_bytes())?;
+        if master_key.borrow().len() != KEY_SIZE {
+            return Err(ReadError::CorruptedContent("invalid master key".into()));
+        }
+        Ok(master_key)
+    }
+}
+
+impl KeySlotKind {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            KeySlotKind::Password(key_derivation) => {
+                bytes.push(TAG_PASSWORD);
+                key_derivation.encode(&mut bytes);
+            }
+            KeySlotKind::RecoveryCode(key_derivation) => {
+                bytes.push(TAG_RECOVERY_CODE);
+                key_derivation.encode(&mut bytes);
+            }
+            KeySlotKind::KeyFile => bytes.push(TAG_KEY_FILE),
+            KeySlotKind::Recipient(public_key) => {
+                bytes.push(TAG_RECIPIENT);
+                bytes.extend_from_slice(public_key);
+            }
+            KeySlotKind::Threshold { threshold, shares } => {
+                bytes.push(TAG_THRESHOLD);
+                bytes.push(*threshold);
+                bytes.extend_from_slice(&(shares.len() as u32).to_le_bytes());
+                for share in shares {
+                    bytes.extend_from_slice(&(share.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(share);
+                }
+            }
+            KeySlotKind::External { id, sealed_key } => {
+                bytes.push(TAG_EXTERNAL);
+                bytes.extend_from_slice(&(id.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(id);
+                bytes.extend_from_slice(&(sealed_key.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(sealed_key);
+            }
+        }
+        bytes
+    }
+}
+
+struct HeaderReader<'a>(&'a [u8]);
+
+impl<'a> HeaderReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ReadError> {
+        if self.0.len() < n {
+            return Err(corrupted("header is too short".to_string()));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ReadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ReadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("slice has length 4")))
+    }
+
+    fn key_derivation(&mut self) -> Result<KeyDerivation, ReadError> {
+        let key_derivation = match self.u8()? {
+            TAG_TRUNCATED => KeyDerivation::Truncated,
+            TAG_BLAKE2B => KeyDerivation::Blake2b,
+            TAG_ARGON2 => KeyDerivation::Argon2(self.argon2_params()?),
+            TAG_COMPOSITE => {
+                let pepper = match self.u8()? {
+                    0 => false,
+                    1 => true,
+                    v => return Err(corrupted(format!("invalid pepper flag {}", v))),
+                };
+                KeyDerivation::Composite {
+                    argon2: self.argon2_params()?,
+                    pepper,
+                }
+            }
+            tag => return Err(corrupted(format!("unknown key derivation {}", tag))),
+        };
+        Ok(key_derivation)
+    }
+
+    fn argon2_params(&mut self) -> Result<Argon2Params, ReadError> {
+        let variant = match self.u8()? {
+            0 => Argon2Variant::Argon2d,
+            1 => Argon2Variant::Argon2i,
+            2 => Argon2Variant::Argon2id,
+            v => return Err(corrupted(format!("unknown argon2 variant {}", v))),
+        };
+        let mem_cost = self.u32()?;
+        let time_cost = self.u32()?;
+        let lanes = self.u32()?;
+        let salt_len = self.u32()? as usize;
+        let salt = self.take(salt_len)?.to_vec();
+        let params = Argon2Params {
+            variant,
+            salt,
+            mem_cost,
+            time_cost,
+            lanes,
+        };
+        params.check_limits().map_err(corrupted)?;
+        Ok(params)
+    }
+
+    fn key_slot_kind(&mut self) -> Result<KeySlotKind, ReadError> {
+        let kind = match self.u8()? {
+            TAG_PASSWORD => KeySlotKind::Password(self.key_derivation()?),
+            TAG_RECOVERY_CODE => KeySlotKind::RecoveryCode(self.key_derivation()?),
+            TAG_KEY_FILE => KeySlotKind::KeyFile,
+            TAG_RECIPIENT => {
+                let public_key = self.take(x25519::PUBLIC_KEY_LENGTH)?;
+                KeySlotKind::Recipient(public_key.try_into().expect("slice has public key length"))
+            }
+            TAG_THRESHOLD => {
+                let threshold = self.u8()?;
+                let mut shares = Vec::new();
+                for _ in 0..self.u32()? {
+                    let len = self.u32()? as usize;
+                    shares.push(self.take(len)?.to_vec());
+                }
+                KeySlotKind::Threshold { threshold, shares }
+            }
+            TAG_EXTERNAL => {
+                let len = self.u32()? as usize;
+                let id = self.take(len)?.to_vec();
+                let len = self.u32()? as usize;
+                let sealed_key = self.take(len)?.to_vec();
+                KeySlotKind::External { id, sealed_key }
+            }
+            tag => return Err(corrupted(format!("unknown key slot {}", tag))),
+        };
+        Ok(kind)
+    }
+}
+
+fn corrupted(msg: String) -> ReadError {
+    ReadError::CorruptedContent(format!("invalid header: {}", msg))
+}
@@ -13,22 +13,33 @@ use crypto::{
     keys::x25519,
     utils::rand,
 };
+use runtime::memories::buffer::Buffer;
 use thiserror::Error as DeriveError;
+use zeroize::Zeroize;
 
-use crate::snapshot::{compress, decompress};
+use crate::snapshot::{compress, decompress, Header, KeySlot, KeySlotKind};
 
 /// Magic bytes (bytes 0-4 in a snapshot file) aka PARTI
 pub const MAGIC: [u8; 5] = [0x50, 0x41, 0x52, 0x54, 0x49];
 
 /// Current version bytes (bytes 5-6 in a snapshot file)
-pub const VERSION: [u8; 2] = [0x2, 0x0];
-// pub const OLD_VERSION: [u8; 2] = [0x2, 0x0];
+pub const VERSION: [u8; 2] = [0x3, 0x0];
+/// Version bytes of snapshots without [`Header`], which can still be read.
+pub const OLD_VERSION: [u8; 2] = [0x2, 0x0];
 
 /// Key size for the ephemeral key
-const KEY_SIZE: usize = 32;
+pub(crate) const KEY_SIZE: usize = 32;
 /// Key type alias.
 pub type Key = [u8; KEY_SIZE];
 
+/// Returns the [`Key`] in guarded memory that has been checked to have the key size.
+pub(crate) fn as_key(key: &[u8]) -> &Key {
+    key.try_into().expect("guarded key has the key size")
+}
+
+/// Maximum size of the encoded [`Header`].
+const MAX_HEADER_SIZE: usize = 0x10000;
+
 /// Nonce size for XChaCha20Poly1305
 const NONCE_SIZE: usize = XChaCha20Poly1305::NONCE_LENGTH;
 /// Nonce type alias
@@ -47,6 +58,12 @@ pub enum ReadError {
 
     #[error("unsupported version: expected `{expected:?}`, found `{found:?}`")]
     UnsupportedVersion { expected: [u8; 2], found: [u8; 2] },
+
+    #[error("the key does not unlock any key slot")]
+    NoMatchingKeySlot,
+
+    #[error("decryption failed: wrong key or corrupted content")]
+    DecryptionFailed,
 }
 
 #[derive(Debug, DeriveError)]
@@ -59,11 +76,47 @@ pub enum WriteError {
 
     #[error("corrupted data: {0}")]
     CorruptedData(String),
+
+    #[error("the key does not unlock any key slot")]
+    NoMatchingKeySlot,
+}
+
+#[derive(Debug, DeriveError)]
+pub enum KeySlotError {
+    #[error("read error: {0}")]
+    Read(#[from] ReadError),
+
+    #[error("write error: {0}")]
+    Write(#[from] WriteError),
+
+    #[error("key slot {0} does not exist")]
+    NotFound(usize),
+
+    #[error("the last key slot can not be removed")]
+    LastKeySlot,
 }
 
 /// Encrypt the opaque plaintext bytestring using the specified [`Key`] and optional associated data
 /// and writes the ciphertext to the specifed output
 pub fn write<O: Write>(plain: &[u8], output: &mut O, key: &Key, associated_data: &[u8]) -> Result<(), WriteError> {
+    // secret key now expects an array
+    let mut key_bytes = [0u8; x25519::SECRET_KEY_LENGTH];
+    key_bytes.clone_from_slice(key);
+
+    // get `x25519` secret key from public key.
+    let pk = x25519::SecretKey::from_bytes(key_bytes).public_key();
+
+    seal(plain, output, &pk, associated_data)
+}
+
+/// Encrypt the opaque plaintext bytestring for the given public key, so that it can be [`read`][self::read]
+/// with the corresponding secret key, and writes the ciphertext to the specified output.
+pub fn seal<O: Write>(
+    plain: &[u8],
+    output: &mut O,
+    pk: &x25519::PublicKey,
+    associated_data: &[u8],
+) -> Result<(), WriteError> {
     // create ephemeral key pair.
     let ephemeral_key = x25519::SecretKey::generate().map_err(|e| WriteError::GenerateRandom(format!("{}", e)))?;
 
@@ -75,17 +128,10 @@ pub fn write<O: Write>(plain: &[u8], output: &mut O, key: &Key, associated_data:
     // write public key into output.
     output.write_all(&ephemeral_pk_bytes)?;
 
-    // secret key now expects an array
-    let mut key_bytes = [0u8; x25519::SECRET_KEY_LENGTH];
-    key_bytes.clone_from_slice(key);
-
-    // get `x25519` secret key from public key.
-    let pk = x25519::SecretKey::from_bytes(key_bytes).public_key();
-
     let pk_bytes = pk.to_bytes();
 
     // do a diffie_hellman exchange to make a shared secret key.
-    let shared = ephemeral_key.diffie_hellman(&pk);
+    let shared = ephemeral_key.diffie_hellman(pk);
 
     // compute the nonce using the ephemeral keys.
     let nonce = {
@@ -116,6 +162,34 @@ pub fn write<O: Write>(plain: &[u8], output: &mut O, key: &Key, associated_data:
 /// Read ciphertext from the input, decrypts it using the specified key and the associated data
 /// specified during encryption and returns the plaintext
 pub fn read<I: Read>(input: &mut I, key: &Key, associated_data: &[u8]) -> Result<Vec<u8>, ReadError> {
+    open(input, key, |shared, nonce, tag, ct| {
+        // create plain text buffer.
+        let mut pt = vec![0; ct.len()];
+
+        // decrypt the ciphertext into the plain text buffer.
+        XChaCha20Poly1305::try_decrypt(shared, nonce, associated_data, &mut pt, ct, tag)
+            .map_err(|_| ReadError::DecryptionFailed)?;
+        Ok(pt)
+    })
+}
+
+/// Like [`read`], but decrypts the plaintext directly into guarded memory, e.g. for keys and shares of keys.
+pub fn read_guarded<I: Read>(input: &mut I, key: &Key, associated_data: &[u8]) -> Result<Buffer<u8>, ReadError> {
+    open(input, key, |shared, nonce, tag, ct| {
+        let mut pt = Buffer::<u8>::zero(ct.len());
+        XChaCha20Poly1305::try_decrypt(shared, nonce, associated_data, &mut pt.borrow_mut(), ct, tag)
+            .map_err(|_| ReadError::DecryptionFailed)?;
+        Ok(pt)
+    })
+}
+
+/// Read the envelope of [`write`] from the input, and call `decrypt` with the shared key, the nonce, the tag
+/// and the ciphertext.
+fn open<I, T, F>(input: &mut I, key: &Key, decrypt: F) -> Result<T, ReadError>
+where
+    I: Read,
+    F: FnOnce(&[u8], &Nonce, &[u8], &[u8]) -> Result<T, ReadError>,
+{
     // create ephemeral private key.
     let mut ephemeral_pk = [0; x25519::PUBLIC_KEY_LENGTH];
     // get ephemeral private key from input.
@@ -130,6 +204,7 @@ pub fn read<I: Read>(input: &mut I, key: &Key, associated_data: &[u8]) -> Result
 
     // get x25519 key pair from ephemeral private key.
     let sk = x25519::SecretKey::from_bytes(key_bytes);
+    key_bytes.zeroize();
     let pk = sk.public_key();
 
     // diffie hellman to create the shared secret.
@@ -152,14 +227,10 @@ pub fn read<I: Read>(input: &mut I, key: &Key, associated_data: &[u8]) -> Result
     let mut ct = Vec::new();
     input.read_to_end(&mut ct)?;
 
-    // create plain text buffer.
-    let mut pt = vec![0; ct.len()];
-
-    // decrypt the ciphertext into the plain text buffer.
-    XChaCha20Poly1305::try_decrypt(&shared.to_bytes(), &nonce, associated_data, &mut pt, &ct, &tag)
-        .map_err(|e| ReadError::CorruptedContent(format!("Decryption failed: {}", e)))?;
-
-    Ok(pt)
+    let mut shared = shared.to_bytes();
+    let result = decrypt(&shared, &nonce, &tag, &ct);
+    shared.zeroize();
+    result
 }
 
 /// Atomically encrypt, add magic and version bytes as file-header, and [`write`][self::write] the specified
@@ -169,11 +240,29 @@ pub fn read<I: Read>(input: &mut I, key: &Key, associated_data: &[u8]) -> Result
 /// filename with a salted suffix). This is currently known to be problematic if the path is a
 /// symlink and/or if the target path resides in a directory without user write permission.
 pub fn write_to(plain: &[u8], path: &Path, key: &Key, associated_data: &[u8]) -> Result<(), WriteError> {
+    write_to_with_header(plain, path, key, associated_data, &Header::default())
+}
+
+/// Like [`write_to`], but with the given [`Header`] written after the magic and version bytes.
+pub fn write_to_with_header(
+    plain: &[u8],
+    path: &Path,
+    key: &Key,
+    associated_data: &[u8],
+    header: &Header,
+) -> Result<(), WriteError> {
+    write_atomically(path, |f| {
+        write_container_with_header(plain, f, key, associated_data, header)
+    })
+}
+
+fn write_atomically<F>(path: &Path, write: F) -> Result<(), WriteError>
+where
+    F: FnOnce(&mut File) -> Result<(), WriteError>,
+{
     // TODO: if path exists and is a symlink, resolve it and then append the salt
     // TODO: if the sibling tempfile isn't writeable (e.g. directory permissions), write to
 
-    let compressed_plain = compress(plain);
-
     let mut salt = [0u8; 6];
     rand::fill(&mut salt).map_err(|e| WriteError::GenerateRandom(format!("{}", e)))?;
 
@@ -183,10 +272,7 @@ pub fn write_to(plain: &[u8], path: &Path, key: &Key, associated_data: &[u8]) ->
     let tmp = Path::new(&s);
 
     let mut f = OpenOptions::new().write(true).create_new(true).open(tmp)?;
-    // write magic and version bytes
-    f.write_all(&MAGIC)?;
-    f.write_all(&VERSION)?;
-    write(&compressed_plain, &mut f, key, associated_data)?;
+    write(&mut f)?;
     f.sync_all()?;
 
     rename(tmp, path)?;
@@ -198,13 +284,145 @@ pub fn write_to(plain: &[u8], path: &Path, key: &Key, associated_data: &[u8]) ->
 pub fn read_from(path: &Path, key: &Key, associated_data: &[u8]) -> Result<Vec<u8>, ReadError> {
     let mut f: File = OpenOptions::new().read(true).open(path)?;
     check_min_file_len(&mut f)?;
+    read_container(&mut f, key, associated_data)
+}
+
+/// Read the [`Header`] of the snapshot file at the specified path, without decrypting the snapshot.
+/// Snapshots of the [`OLD_VERSION`] have an empty header.
+pub fn read_header(path: &Path) -> Result<Header, ReadError> {
+    let mut f: File = OpenOptions::new().read(true).open(path)?;
+    check_min_file_len(&mut f)?;
+    let (header, _) = check_header(&mut f)?;
+    Ok(header)
+}
+
+/// Compress, encrypt and [`write`][self::write] the specified plaintext to the output with the magic and
+/// version bytes as header. This is the format of a snapshot file, e.g. for writing it into a buffer.
+pub fn write_container<O: Write>(
+    plain: &[u8],
+    output: &mut O,
+    key: &Key,
+    associated_data: &[u8],
+) -> Result<(), WriteError> {
+    write_container_with_header(plain, output, key, associated_data, &Header::default())
+}
+
+/// Like [`write_container`], but with the given [`Header`] written after the magic and version bytes.
+///
+/// If the header has key slots, the plaintext is encrypted with the master key that `key` unlocks.
+pub fn write_container_with_header<O: Write>(
+    plain: &[u8],
+    output: &mut O,
+    key: &Key,
+    associated_data: &[u8],
+    header: &Header,
+) -> Result<(), WriteError> {
+    let key = header.unlock(key).map_err(|_| WriteError::NoMatchingKeySlot)?;
+    let key = key.borrow();
+    let compressed_plain = compress(plain);
+    write_header(output, header)?;
+
+    // the header is authenticated together with the associated data
+    write(
+        &compressed_plain,
+        output,
+        as_key(&key),
+        &[&header.authenticated_bytes(), associated_data].concat(),
+    )
+}
+
+/// Check the header, [`read`][self::read], and decompress the ciphertext from the input that has been written
+/// with [`write_container`] or [`write_container_with_header`].
+pub fn read_container<I: Read>(input: &mut I, key: &Key, associated_data: &[u8]) -> Result<Vec<u8>, ReadError> {
     // check the header for structure.
-    check_header(&mut f)?;
-    let pt = read(&mut f, key, associated_data)?;
+    let (header, authenticated) = check_header(input)?;
+    let key = header.unlock(key)?;
+    let pt = read(
+        input,
+        as_key(&key.borrow()),
+        &[&authenticated, associated_data].concat(),
+    )
+    .map_err(|e| match e {
+        // the key has been verified by a key slot, so the content must have been modified
+        ReadError::DecryptionFailed if !header.key_slots.is_empty() => {
+            ReadError::CorruptedContent("decryption failed".into())
+        }
+        e => e,
+    })?;
 
     decompress(&pt).map_err(|e| ReadError::CorruptedContent(format!("Decompression failed: {}", e)))
 }
 
+/// Update the key slots of the snapshot file at the specified path with `f`, which is called with the
+/// master key and the current key slots. `key` has to unlock one of the key slots.
+///
+/// Only the header of the snapshot is rewritten, the encrypted content stays untouched. The exception is a
+/// snapshot without key slots: it is re-encrypted once with a new master key, that is wrapped in a first
+/// key slot for `key`.
+pub fn update_key_slots<F>(path: &Path, key: &Key, associated_data: &[u8], f: F) -> Result<Header, KeySlotError>
+where
+    F: FnOnce(&Key, &mut Vec<KeySlot>) -> Result<(), KeySlotError>,
+{
+    let mut input: File = OpenOptions::new().read(true).open(path).map_err(ReadError::Io)?;
+    check_min_file_len(&mut input)?;
+    let (mut header, authenticated) = check_header(&mut input)?;
+    let mut body = Vec::new();
+    input.read_to_end(&mut body).map_err(ReadError::Io)?;
+    drop(input);
+
+    let master_key = if header.key_slots.is_empty() {
+        let compressed_plain = read(&mut body.as_slice(), key, &[&authenticated, associated_data].concat())?;
+
+        let mut master_key = Buffer::<u8>::zero(KEY_SIZE);
+        rand::fill(&mut master_key.borrow_mut()).map_err(|e| WriteError::GenerateRandom(format!("{}", e)))?;
+        let kind = match &header.key_derivation {
+            Some(key_derivation) => KeySlotKind::Password(key_derivation.clone()),
+            None => KeySlotKind::KeyFile,
+        };
+        header
+            .key_slots
+            .push(KeySlot::new(kind, as_key(&master_key.borrow()), key)?);
+
+        body.clear();
+        write(
+            &compressed_plain,
+            &mut body,
+            as_key(&master_key.borrow()),
+            &[&header.authenticated_bytes(), associated_data].concat(),
+        )?;
+        master_key
+    } else {
+        header.unlock(key)?
+    };
+
+    f(as_key(&master_key.borrow()), &mut header.key_slots)?;
+    if header.key_slots.is_empty() {
+        return Err(KeySlotError::LastKeySlot);
+    }
+
+    write_atomically(path, |output| {
+        write_header(output, &header)?;
+        output.write_all(&body)?;
+        Ok(())
+    })?;
+    Ok(header)
+}
+
+/// Write the magic and version bytes, followed by the length-prefixed header.
+fn write_header<O: Write>(output: &mut O, header: &Header) -> Result<(), WriteError> {
+    let header = header.to_bytes();
+    if header.len() > MAX_HEADER_SIZE {
+        return Err(WriteError::CorruptedData("header too large".into()));
+    }
+    let header_len = header.len() as u32;
+
+    output.write_all(&MAGIC)?;
+    output.write_all(&VERSION)?;
+    output.write_all(&header_len.to_le_bytes())?;
+    output.write_all(&header)?;
+    Ok(())
+}
+
 fn check_min_file_len(input: &mut File) -> Result<(), ReadError> {
     let min = MAGIC.len() + VERSION.len() + x25519::PUBLIC_KEY_LENGTH + XChaCha20Poly1305::TAG_LENGTH;
     if input.metadata()?.len() >= min as u64 {
@@ -214,8 +432,9 @@ fn check_min_file_len(input: &mut File) -> Result<(), ReadError> {
     }
 }
 
-/// Checks the header for a specific structure; explicitly the magic and version bytes.
-fn check_header<I: Read>(input: &mut I) -> Result<(), ReadError> {
+/// Checks the header for a specific structure; explicitly the magic and version bytes, and reads the
+/// [`Header`] together with its authenticated bytes.
+fn check_header<I: Read>(input: &mut I) -> Result<(Header, Vec<u8>), ReadError> {
     // check the magic bytes
     let mut magic = [0u8; 5];
     input.read_exact(&mut magic)?;
@@ -227,6 +446,9 @@ fn check_header<I: Read>(input: &mut I) -> Result<(), ReadError> {
     let mut version = [0u8; 2];
     input.read_exact(&mut version)?;
 
+    if version == OLD_VERSION {
+        return Ok((Header::default(), Vec::new()));
+    }
     if version != VERSION {
         return Err(ReadError::UnsupportedVersion {
             expected: VERSION,
@@ -234,12 +456,24 @@ fn check_header<I: Read>(input: &mut I) -> Result<(), ReadError> {
         });
     }
 
-    Ok(())
+    let mut header_len = [0u8; 4];
+    input.read_exact(&mut header_len)?;
+    let header_len = u32::from_le_bytes(header_len) as usize;
+    if header_len > MAX_HEADER_SIZE {
+        return Err(ReadError::CorruptedContent("header too large".into()));
+    }
+    let mut header = vec![0u8; header_len];
+    input.read_exact(&mut header)?;
+
+    let header = Header::from_bytes(&header)?;
+    let authenticated = header.authenticated_bytes();
+    Ok((header, authenticated))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::snapshot::{Argon2Params, Argon2Variant, KeyDerivation};
     use stronghold_utils::{
         random,
         test_utils::{corrupt, corrupt_file_at},
@@ -269,6 +503,224 @@ mod test {
         assert_eq!(bs0, read);
     }
 
+    #[test]
+    fn test_write_read_container() {
+        let key: Key = random_key();
+        let bs0 = random_bytestring();
+        let ad = random_bytestring();
+
+        let mut buf = Vec::new();
+        write_container(&bs0, &mut buf, &key, &ad).unwrap();
+        assert_eq!(&buf[..MAGIC.len()], &MAGIC);
+        let read = read_container(&mut buf.as_slice(), &key, &ad).unwrap();
+        assert_eq!(bs0, read);
+
+        buf[0] ^= 1;
+        assert!(matches!(
+            read_container(&mut buf.as_slice(), &key, &ad),
+            Err(ReadError::InvalidFile)
+        ));
+    }
+
+    #[test]
+    fn test_header() {
+        let f = tempfile::tempdir().unwrap();
+        let pb = f.path().join("snapshot");
+
+        let key: Key = random_key();
+        let bs0 = random_bytestring();
+        let header = Header {
+            key_derivation: Some(KeyDerivation::Argon2(Argon2Params {
+                variant: Argon2Variant::Argon2id,
+                salt: random_bytestring(),
+                mem_cost: 4096,
+                time_cost: 3,
+                lanes: 1,
+            })),
+            ..Default::default()
+        };
+        write_to_with_header(&bs0, &pb, &key, &[], &header).unwrap();
+        assert_eq!(read_header(&pb).unwrap(), header);
+        assert_eq!(read_from(&pb, &key, &[]).unwrap(), bs0);
+
+        // the header is authenticated
+        let mut bytes = std::fs::read(&pb).unwrap();
+        let header_start = MAGIC.len() + VERSION.len() + 4;
+        let header_end = header_start + header.to_bytes().len();
+        bytes[header_start + 18] ^= 1;
+        assert_ne!(Header::from_bytes(&bytes[header_start..header_end]).unwrap(), header);
+        assert!(read_container(&mut bytes.as_slice(), &key, &[]).is_err());
+
+        let header = Header {
+            key_derivation: Some(KeyDerivation::Composite {
+                argon2: Argon2Params {
+                    variant: Argon2Variant::Argon2i,
+                    salt: random_bytestring(),
+                    mem_cost: 64,
+                    time_cost: 1,
+                    lanes: 2,
+                },
+                pepper: true,
+            }),
+            ..Default::default()
+        };
+        write_to_with_header(&bs0, &pb, &key, &[], &header).unwrap();
+        assert_eq!(read_header(&pb).unwrap(), header);
+
+        write_to(&bs0, &pb, &key, &[]).unwrap();
+        assert_eq!(read_header(&pb).unwrap(), Header::default());
+
+        // argon2 parameters that are zero or exceed the limits are rejected before the key is derived
+        let bounded = |mem_cost, time_cost, lanes| Argon2Params {
+            variant: Argon2Variant::Argon2id,
+            salt: random_bytestring(),
+            mem_cost,
+            time_cost,
+            lanes,
+        };
+        for params in [
+            bounded(0, 1, 1),
+            bounded(64, 0, 1),
+            bounded(64, 1, 0),
+            bounded(u32::MAX, 1, 1),
+            bounded(64, u32::MAX, 1),
+            bounded(64, 1, u32::MAX),
+        ] {
+            for key_derivation in [
+                KeyDerivation::Argon2(params.clone()),
+                KeyDerivation::Composite {
+                    argon2: params,
+                    pepper: false,
+                },
+            ] {
+                let header = Header {
+                    key_derivation: Some(key_derivation),
+                    ..Default::default()
+                };
+                write_to_with_header(&bs0, &pb, &key, &[], &header).unwrap();
+                assert!(matches!(read_header(&pb), Err(ReadError::CorruptedContent(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_slots() {
+        let f = tempfile::tempdir().unwrap();
+        let pb = f.path().join("snapshot");
+
+        let key: Key = random_key();
+        let recovery_key: Key = random_key();
+        let recipient_key = x25519::SecretKey::generate().unwrap();
+        let ad = random_bytestring();
+        let bs0 = random_bytestring();
+        write_to(&bs0, &pb, &key, &ad).unwrap();
+
+        // the first key slot re-encrypts the snapshot with a master key
+        let header = update_key_slots(&pb, &key, &ad, |master_key, slots| {
+            slots.push(KeySlot::new(
+                KeySlotKind::RecoveryCode(KeyDerivation::Blake2b),
+                master_key,
+                &recovery_key,
+            )?);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(header.key_slots.len(), 2);
+        assert_eq!(header.key_slots[0].kind, KeySlotKind::KeyFile);
+        assert_eq!(read_header(&pb).unwrap(), header);
+        assert_eq!(read_from(&pb, &key, &ad).unwrap(), bs0);
+        assert_eq!(read_from(&pb, &recovery_key, &ad).unwrap(), bs0);
+
+        // further key slots do not touch the encrypted content
+        let body_len =
+            |bytes: &[u8]| bytes.len() - MAGIC.len() - VERSION.len() - 4 - read_header(&pb).unwrap().to_bytes().len();
+        let before = std::fs::read(&pb).unwrap();
+        let before = before[before.len() - body_len(&before)..].to_vec();
+        let public_key = recipient_key.public_key().to_bytes();
+        let external_key: Key = random_key();
+        let external = KeySlotKind::External {
+            id: b"kms".to_vec(),
+            sealed_key: random_bytestring(),
+        };
+        update_key_slots(&pb, &recovery_key, &ad, |master_key, slots| {
+            slots.push(KeySlot::for_recipient(master_key, public_key)?);
+            slots.push(KeySlot::new(external.clone(), master_key, &external_key)?);
+            slots.remove(0);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(read_header(&pb).unwrap().key_slots[2].kind, external);
+        assert_eq!(read_from(&pb, &external_key, &ad).unwrap(), bs0);
+        let after = std::fs::read(&pb).unwrap();
+        assert_eq!(&after[after.len() - body_len(&after)..], &before[..]);
+        assert_eq!(read_from(&pb, &recipient_key.to_bytes(), &ad).unwrap(), bs0);
+        assert!(matches!(read_from(&pb, &key, &ad), Err(ReadError::NoMatchingKeySlot)));
+
+        // writing keeps the master key of the key slots
+        let bs1 = random_bytestring();
+        write_to_with_header(&bs1, &pb, &recovery_key, &ad, &read_header(&pb).unwrap()).unwrap();
+        assert_eq!(read_from(&pb, &recipient_key.to_bytes(), &ad).unwrap(), bs1);
+        assert!(matches!(
+            write_to_with_header(&bs1, &pb, &key, &ad, &read_header(&pb).unwrap()),
+            Err(WriteError::NoMatchingKeySlot)
+        ));
+
+        assert!(matches!(
+            update_key_slots(&pb, &recovery_key, &ad, |_, slots| {
+                slots.clear();
+                Ok(())
+            }),
+            Err(KeySlotError::LastKeySlot)
+        ));
+        assert_eq!(read_from(&pb, &recovery_key, &ad).unwrap(), bs1);
+
+        // with a key that unlocks a key slot, a modified content is not mistaken for a wrong key
+        let mut bytes = std::fs::read(&pb).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        std::fs::write(&pb, bytes).unwrap();
+        assert!(matches!(
+            read_from(&pb, &recovery_key, &ad),
+            Err(ReadError::CorruptedContent(_))
+        ));
+    }
+
+    #[test]
+    fn test_threshold_key_slot() {
+        let f = tempfile::tempdir().unwrap();
+        let pb = f.path().join("snapshot");
+
+        let key: Key = random_key();
+        let holders: Vec<Key> = (0..3).map(|_| random_key()).collect();
+        let public_keys: Vec<_> = holders
+            .iter()
+            .map(|key| x25519::SecretKey::from_bytes(*key).public_key().to_bytes())
+            .collect();
+        let bs0 = random_bytestring();
+        write_to(&bs0, &pb, &key, &[]).unwrap();
+        let header = update_key_slots(&pb, &key, &[], |master_key, slots| {
+            slots.push(KeySlot::for_threshold(master_key, 2, &public_keys)?);
+            Ok(())
+        })
+        .unwrap();
+        let slot = &header.key_slots[1];
+
+        let (i0, share0) = slot.decrypt_share(&holders[2]).unwrap();
+        let (i1, share1) = slot.decrypt_share(&holders[0]).unwrap();
+        assert_eq!((i0, i1), (2, 0));
+        assert!(slot.decrypt_share(&key).is_none());
+        assert!(header.key_slots[0].decrypt_share(&holders[0]).is_none());
+
+        let mut threshold_key: Key = [0u8; KEY_SIZE];
+        crate::snapshot::shamir::combine(&[&share0.borrow(), &share1.borrow()], &mut threshold_key).unwrap();
+        assert_eq!(read_from(&pb, &threshold_key, &[]).unwrap(), bs0);
+        for holder in holders.iter() {
+            assert!(read_from(&pb, holder, &[]).is_err());
+        }
+
+        assert!(KeySlot::for_threshold(&key, 4, &public_keys).is_err());
+    }
+
     #[test]
     #[should_panic]
     fn test_corrupted_read_write() {
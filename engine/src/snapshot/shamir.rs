@@ -0,0 +1,146 @@
+// Copyright 2020-2022 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shamir's secret sharing over GF(2^8), used to split the key of a threshold [`KeySlot`][crate::snapshot::KeySlot]
+//! into shares of which any `threshold` reconstruct the key.
+//!
+//! A share is encoded as its x-coordinate followed by one y-coordinate per byte of the secret. The field
+//! arithmetic does not branch on or index by secret data.
+
+use crypto::utils::rand;
+use zeroize::Zeroize;
+
+use crate::snapshot::{ReadError, WriteError};
+
+/// Split `secret` into `count` shares, of which any `threshold` reconstruct the secret with [`combine`].
+pub fn split(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<Vec<u8>>, WriteError> {
+    if threshold == 0 || threshold > count {
+        return Err(WriteError::CorruptedData(format!(
+            "invalid threshold {} for {} shares",
+            threshold, count
+        )));
+    }
+
+    let mut shares: Vec<Vec<u8>> = (1..=count)
+        .map(|x| {
+            let mut share = Vec::with_capacity(secret.len() + 1);
+            share.push(x);
+            share
+        })
+        .collect();
+
+    // coefficients of the random polynomial for one byte of the secret; the constant term is the byte itself
+    let mut coefficients = vec![0u8; threshold as usize];
+    for byte in secret {
+        coefficients[0] = *byte;
+        rand::fill(&mut coefficients[1..]).map_err(|e| WriteError::GenerateRandom(format!("{}", e)))?;
+        for share in shares.iter_mut() {
+            let y = evaluate(&coefficients, share[0]);
+            share.push(y);
+        }
+    }
+    coefficients.zeroize();
+
+    Ok(shares)
+}
+
+/// Reconstruct the secret from `shares` that have been created with [`split`], and write it into `secret`.
+/// At least as many shares as the threshold have to be given, otherwise the result is a random value.
+pub fn combine(shares: &[&[u8]], secret: &mut [u8]) -> Result<(), ReadError> {
+    if shares.is_empty() {
+        return Err(ReadError::CorruptedContent("no shares to combine".into()));
+    }
+    for (i, share) in shares.iter().enumerate() {
+        if share.len() != secret.len() + 1 {
+            return Err(ReadError::CorruptedContent("share has an invalid length".into()));
+        }
+        if share[0] == 0 || shares[..i].iter().any(|other| other[0] == share[0]) {
+            return Err(ReadError::CorruptedContent("invalid or duplicate share".into()));
+        }
+    }
+
+    // lagrange interpolation at x = 0
+    let basis: Vec<u8> = shares
+        .iter()
+        .map(|share| {
+            let xi = share[0];
+            shares
+                .iter()
+                .filter(|other| other[0] != xi)
+                .fold(1, |acc, other| mul(acc, mul(other[0], inv(other[0] ^ xi))))
+        })
+        .collect();
+    for (k, byte) in secret.iter_mut().enumerate() {
+        *byte = shares
+            .iter()
+            .zip(basis.iter())
+            .fold(0, |acc, (share, l)| acc ^ mul(share[k + 1], *l));
+    }
+    Ok(())
+}
+
+/// Evaluate the polynomial with the given coefficients at `x`.
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients.iter().rev().fold(0, |acc, c| mul(acc, x) ^ c)
+}
+
+/// Multiplication in GF(2^8) with the reduction polynomial x^8 + x^4 + x^3 + x + 1.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse in GF(2^8), computed as `a^254`.
+fn inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    for bit in 0..8 {
+        if (254u8 >> bit) & 1 == 1 {
+            result = mul(result, power);
+        }
+        power = mul(power, power);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stronghold_utils::random;
+
+    #[test]
+    fn test_field() {
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inv(a)), 1);
+        }
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+    }
+
+    #[test]
+    fn test_split_combine() {
+        let secret = random::fixed_bytestring(32);
+        let shares = split(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        let mut combined = vec![0u8; secret.len()];
+        for subset in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let subset: Vec<&[u8]> = subset.iter().map(|i| shares[*i].as_slice()).collect();
+            combine(&subset, &mut combined).unwrap();
+            assert_eq!(combined, secret);
+        }
+
+        let subset: Vec<&[u8]> = shares[..2].iter().map(|s| s.as_slice()).collect();
+        combine(&subset, &mut combined).unwrap();
+        assert_ne!(combined, secret);
+
+        let subset: Vec<&[u8]> = vec![&shares[0], &shares[0], &shares[1]];
+        assert!(combine(&subset, &mut combined).is_err());
+        assert!(split(&secret, 4, 3).is_err());
+    }
+}
@@ -25,5 +25,5 @@ pub use crate::vault::{
     base64::{Base64Decodable, Base64Encodable},
     crypto_box::{BoxProvider, Decrypt, DecryptError, Encrypt, Key, NCKey},
     types::utils::{BlobId, ChainId, ClientId, Id, InvalidLength, RecordHint, RecordId, VaultId},
-    view::{DbView, RecordError, VaultError},
+    view::{ChangeLog, DbView, RecordError, RecordMetadata, RecordUsage, VaultError, VaultVersion},
 };
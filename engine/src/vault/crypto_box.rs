@@ -21,6 +21,13 @@ pub trait BoxProvider: 'static + Sized + Ord + PartialOrd {
     /// defines the size of the Nonce combined with the Ad for the [`BoxProvider`].
     fn box_overhead() -> usize;
 
+    /// checks whether a [`Key`] of length `len` can be loaded. Providers whose keys carry additional
+    /// information, e.g. the cipher suite they are used with, can accept further lengths than
+    /// [`BoxProvider::box_key_len`].
+    fn is_valid_key_len(len: usize) -> bool {
+        len == Self::box_key_len()
+    }
+
     /// seals some data into the crypto box using the [`Key`] and the associated data.
     fn box_seal(key: &Key<Self>, ad: &[u8], data: &[u8]) -> Result<Vec<u8>, Self::Error>;
 
@@ -68,11 +75,11 @@ impl<T: BoxProvider> Key<T> {
 
     /// attempts to load a key from inputted data
     ///
-    /// Return `None` if the key length doesn't match [`BoxProvider::box_key_len`].
+    /// Return `None` if the key length is not valid for the provider, see [`BoxProvider::is_valid_key_len`].
     pub fn load(key: Vec<u8>) -> Option<Self> {
-        if key.len() == T::box_key_len() {
+        if T::is_valid_key_len(key.len()) {
             Some(Self {
-                key: Buffer::alloc(key.as_slice(), T::box_key_len()),
+                key: Buffer::alloc(key.as_slice(), key.len()),
                 _box_provider: PhantomData,
             })
         } else {
@@ -194,6 +201,21 @@ impl<T: BoxProvider> NCKey<T> {
         }
     }
 
+    /// attempts to load a key from a guarded [`Buffer`], without copying it into unguarded memory
+    ///
+    /// Return `None` if the key length doesn't match [`BoxProvider::box_key_len`].
+    pub fn load_buffer(key: &Buffer<u8>) -> Option<Self> {
+        if key.len() == T::box_key_len() {
+            Some(Self {
+                key: NonContiguousMemory::alloc(&key.borrow(), T::box_key_len(), NC_CONFIGURATION)
+                    .unwrap_or_else(|e| panic!("{}", e)),
+                _box_provider: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
     pub fn encrypt_key<AD: AsRef<[u8]>>(&self, data: &Key<T>, ad: AD) -> Result<Vec<u8>, T::Error> {
         let key = Key {
             key: self.key.unlock().unwrap_or_else(|e| panic!("{}", e)),
@@ -78,6 +78,19 @@ pub struct DataTransaction {
 
     /// a record hint
     pub record_hint: RecordHint,
+
+    /// time of expiry in seconds since the unix epoch. Zero if the record does not expire.
+    pub expires_at: Val,
+
+    /// number of remaining uses plus one. Zero if the usage of the record is unlimited.
+    pub remaining_uses: Val,
+
+    /// bit set of the operations the record may be used for. Zero if all operations are allowed.
+    pub allowed_operations: Val,
+
+    /// time of the last modification of the data in seconds since the unix epoch. Zero for records that
+    /// were created before the timestamp was introduced.
+    pub modified_at: Val,
 }
 
 /// a typed transaction
@@ -95,11 +108,22 @@ pub struct RevocationTransaction {
 
     /// id identifer
     pub id: ChainId,
+
+    /// time of the revocation in seconds since the unix epoch. Zero for revocations that were
+    /// created before the timestamp was introduced.
+    pub revoked_at: Val,
 }
 
 impl DataTransaction {
-    /// Create a new data transaction from a [`ChainId`], a len, a [`BlobId`] and a [`RecordHint`].
-    pub fn new<L: Into<Val>>(id: ChainId, len: L, blob: BlobId, record_hint: RecordHint) -> Transaction {
+    /// Create a new data transaction from a [`ChainId`], a len, a [`BlobId`], a [`RecordHint`] and the expiry
+    /// timestamp.
+    pub fn new<L: Into<Val>, E: Into<Val>>(
+        id: ChainId,
+        len: L,
+        blob: BlobId,
+        record_hint: RecordHint,
+        expires_at: E,
+    ) -> Transaction {
         let mut transaction = Transaction::default();
         let view: &mut Self = transaction.view_mut();
 
@@ -108,6 +132,7 @@ impl DataTransaction {
         view.id = id;
         view.blob = blob;
         view.record_hint = record_hint;
+        view.expires_at = expires_at.into();
         transaction
     }
 }
@@ -120,12 +145,13 @@ impl TypedTransaction for DataTransaction {
 
 impl RevocationTransaction {
     /// create a new revocation transaction.
-    pub fn new(id: ChainId) -> Transaction {
+    pub fn new<T: Into<Val>>(id: ChainId, revoked_at: T) -> Transaction {
         let mut transaction = Transaction::default();
         let view: &mut Self = transaction.view_mut();
 
         view.type_id = (TransactionType::Revocation as u64).into();
         view.id = id;
+        view.revoked_at = revoked_at.into();
         transaction
     }
 }
@@ -151,7 +177,11 @@ impl Transaction {
     }
 }
 
-const TRANSACTION_MAX_BYTES: usize = 112;
+const TRANSACTION_MAX_BYTES: usize = 128;
+
+/// Size of the transactions before the [`DataTransaction`] was extended. Legacy transactions are padded
+/// with zeros, so that the new fields have their default value.
+const LEGACY_TRANSACTION_BYTES: usize = 112;
 
 impl Default for Transaction {
     fn default() -> Self {
@@ -160,9 +190,13 @@ impl Default for Transaction {
 }
 impl TryFrom<Vec<u8>> for Transaction {
     type Error = ();
-    fn try_from(vec: Vec<u8>) -> Result<Self, Self::Error> {
+    fn try_from(mut vec: Vec<u8>) -> Result<Self, Self::Error> {
         match vec.len() {
             TRANSACTION_MAX_BYTES => Ok(Self(vec)),
+            LEGACY_TRANSACTION_BYTES => {
+                vec.resize(TRANSACTION_MAX_BYTES, 0);
+                Ok(Self(vec))
+            }
             _ => Err(()),
         }
     }
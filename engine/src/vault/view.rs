@@ -6,12 +6,19 @@ use crate::vault::{
     types::{
         transactions::{DataTransaction, RevocationTransaction, SealedBlob, SealedTransaction},
         utils::{BlobId, ChainId, RecordHint, RecordId, VaultId},
+        AsView, AsViewMut,
     },
 };
 
+use crypto::utils::rand;
 use runtime::memories::buffer::Buffer;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, convert::Infallible, fmt::Debug};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    fmt::Debug,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use thiserror::Error as DeriveError;
 
 use super::{crypto_box::DecryptError, types::transactions::Transaction};
@@ -45,10 +52,84 @@ pub enum RecordError<TProvErr: Debug> {
     #[error("no record with `{0:?}`")]
     RecordNotFound(ChainId),
 
+    #[error("record `{0:?}` has not been revoked")]
+    NotRevoked(ChainId),
+
+    #[error("record `{0:?}` has expired")]
+    RecordExpired(ChainId),
+
+    #[error("record `{0:?}` may not be used for this operation")]
+    OperationNotAllowed(ChainId),
+
+    #[error("record `{0:?}` has reached its usage limit")]
+    UsageLimitReached(ChainId),
+
+    #[error("vault `{0:?}` is locked")]
+    VaultLocked(VaultId),
+
+    #[error("vault `{0:?}` is reserved")]
+    VaultReserved(VaultId),
+
+    #[error("record `{0:?}` has no id in the target vault")]
+    NoTargetId(ChainId),
+
     #[error("Lock is poisoned")]
     LockPoisoned,
 }
 
+/// Restrictions on the usage of a [`Record`]. The restrictions are stored encrypted together with the
+/// record and persist in the snapshot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordUsage {
+    /// Number of remaining uses, or `None` if the usage is unlimited.
+    pub remaining_uses: Option<u64>,
+
+    /// Bit set of the operations that the [`Record`] may be used for. The meaning of the bits is defined by
+    /// the user of the vault. Zero allows all operations.
+    pub allowed_operations: u64,
+}
+
+impl RecordUsage {
+    /// Check if the `operation` bits are contained in the allowed operations.
+    pub fn allows(&self, operation: u64) -> bool {
+        self.allowed_operations == 0 || self.allowed_operations & operation == operation
+    }
+}
+
+/// Non-secret metadata of a [`Record`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordMetadata {
+    /// The [`BlobId`] of the data, which changes each time the data is updated.
+    pub blob_id: BlobId,
+
+    /// The [`RecordHint`] of the record.
+    pub hint: RecordHint,
+
+    /// Length of the unencrypted data.
+    pub len: u64,
+
+    /// Time of the last modification of the data in seconds since the unix epoch. Zero if the record was
+    /// written before the modification time was tracked.
+    pub modified_at: u64,
+
+    /// Time of expiry in seconds since the unix epoch, or `None` if the record does not expire.
+    pub expires_at: Option<u64>,
+}
+
+/// Version of a [`Vault`] in its history of changes. Each change of a record increments the counter, so that
+/// the records that changed since a known version can be listed without decrypting all records of the vault.
+///
+/// The history is only tracked in memory: a [`Vault`] that is loaded from a snapshot or cloned starts a new
+/// history with a different `replica`. Versions of different histories can not be compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct VaultVersion {
+    /// Random identifier of the history of changes.
+    pub replica: u64,
+
+    /// Number of changes in the history.
+    pub counter: u64,
+}
+
 /// A view over the data inside of a collection of [`Vault`] types.
 #[derive(Deserialize, Serialize, Clone, Default)]
 pub struct DbView<P: BoxProvider> {
@@ -61,6 +142,22 @@ pub struct DbView<P: BoxProvider> {
 pub struct Vault<P: BoxProvider> {
     key: Key<P>,
     entries: HashMap<ChainId, Record>,
+    // Not part of the serialized vault to keep the format of existing snapshots, it is persisted apart from
+    // the records with [`DbView::histories`].
+    #[serde(skip)]
+    changes: ChangeLog,
+}
+
+/// Log of the changes of the records in a [`Vault`], see [`DbView::histories`].
+#[derive(Serialize, Deserialize)]
+pub struct ChangeLog {
+    replica: u64,
+    counter: u64,
+    /// Counter value of the last change of each record.
+    changed_at: HashMap<ChainId, u64>,
+    /// Record of each counter value in `changed_at`, to find the changes after a version without
+    /// visiting all records.
+    changes: BTreeMap<u64, ChainId>,
 }
 
 /// A bit of data inside of a [`Vault`].
@@ -106,6 +203,26 @@ impl<P: BoxProvider> DbView<P> {
         vault.add_or_update_record(key, rid.0, data, record_hint)
     }
 
+    /// Write a record like [`DbView::write`] that expires at `expires_at` (see [`DbView::set_record_expiry`]).
+    /// The data and the expiry are written in one step.
+    pub fn write_with_expiry(
+        &mut self,
+        key: &Key<P>,
+        vid: VaultId,
+        rid: RecordId,
+        data: &[u8],
+        record_hint: RecordHint,
+        expires_at: SystemTime,
+    ) -> Result<(), RecordError<P::Error>> {
+        if !self.vaults.contains_key(&vid) {
+            self.init_vault(key, vid);
+        }
+
+        let vault = self.vaults.get_mut(&vid).expect("Vault was initiated");
+        let expires_at = unix_timestamp(expires_at).max(1);
+        vault.write_record(key, rid.0, data, record_hint, Some(expires_at))
+    }
+
     /// Lists all of the [`RecordHint`] values and [`RecordId`] values for the given [`Vault`].
     pub fn list_hints_and_ids(&self, key: &Key<P>, vid: VaultId) -> Vec<(RecordId, RecordHint)> {
         if let Some(vault) = self.vaults.get(&vid) {
@@ -220,6 +337,106 @@ impl<P: BoxProvider> DbView<P> {
         Ok(())
     }
 
+    /// Move a [`Record`] to another location. The [`Record`] is re-encrypted with the key of the target
+    /// [`Vault`], the plaintext is never exposed. In case of a duplicated record at the target, the existing
+    /// record is dropped in favor of the moved one.
+    pub fn move_record(
+        &mut self,
+        old_key: &Key<P>,
+        old_vid: VaultId,
+        old_rid: RecordId,
+        new_key: &Key<P>,
+        new_vid: VaultId,
+        new_rid: RecordId,
+    ) -> Result<(), VaultError<P::Error>> {
+        let old_vault = self.vaults.get(&old_vid).ok_or(VaultError::VaultNotFound(old_vid))?;
+        old_vault.check_key(old_key)?;
+        let mut record = old_vault
+            .export_record(&old_rid)
+            .ok_or(RecordError::RecordNotFound(old_rid.0))?;
+        record.update_meta(old_key, old_rid.0, new_key, new_rid.0)?;
+
+        if (old_vid, old_rid) == (new_vid, new_rid) {
+            return Ok(());
+        }
+        if !self.vaults.contains_key(&new_vid) {
+            self.init_vault(new_key, new_vid);
+        }
+        let new_vault = self.vaults.get_mut(&new_vid).expect("Vault was initiated.");
+        new_vault.extend(new_key, [(new_rid.0, record)])?;
+
+        let old_vault = self.vaults.get_mut(&old_vid).expect("Vault exists.");
+        old_vault.remove_entries(&[old_rid.0]);
+        Ok(())
+    }
+
+    /// Move all records of a [`Vault`] into the [`Vault`] `new_vid` and remove the old one. Each record gets the
+    /// [`RecordId`] that `new_id` returns for its old id, and is re-encrypted with `new_key`. Revoked records are
+    /// garbage collected. In case the target [`Vault`] already exists, the records are merged into it and existing
+    /// duplicates are dropped.
+    ///
+    /// Fails with [`RecordError::NoTargetId`] without changing any vault, if `new_id` returns `None` for a record
+    /// that has not been revoked.
+    pub fn rename_vault<F>(
+        &mut self,
+        old_key: &Key<P>,
+        old_vid: VaultId,
+        new_key: &Key<P>,
+        new_vid: VaultId,
+        new_id: F,
+    ) -> Result<(), VaultError<P::Error>>
+    where
+        F: Fn(RecordId) -> Option<RecordId>,
+    {
+        let old_vault = self.vaults.get(&old_vid).ok_or(VaultError::VaultNotFound(old_vid))?;
+        old_vault.check_key(old_key)?;
+        if old_vid == new_vid {
+            return Ok(());
+        }
+
+        let mut records = Vec::new();
+        for (&id, record) in old_vault.entries.iter() {
+            if record.revoke.is_some() {
+                continue;
+            }
+            let target_id = new_id(RecordId(id)).ok_or(RecordError::NoTargetId(id))?.0;
+            let mut record = record.clone();
+            record.update_meta(old_key, id, new_key, target_id)?;
+            records.push((target_id, record));
+        }
+
+        if !self.vaults.contains_key(&new_vid) {
+            self.init_vault(new_key, new_vid);
+        }
+        let new_vault = self.vaults.get_mut(&new_vid).expect("Vault was initiated.");
+        new_vault.extend(new_key, records)?;
+
+        self.vaults.remove(&old_vid);
+        Ok(())
+    }
+
+    /// Remove the revocation transaction from a [`Record`] that has not been garbage collected yet.
+    pub fn restore_record(&mut self, key: &Key<P>, vid: VaultId, rid: RecordId) -> Result<(), VaultError<P::Error>> {
+        let vault = self.vaults.get_mut(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        vault.restore(key, rid.0)?;
+        Ok(())
+    }
+
+    /// List the [`RecordId`]s of all revoked records in the [`Vault`] that have not been garbage collected yet.
+    pub fn list_revoked_records(&self, key: &Key<P>, vid: VaultId) -> Result<Vec<RecordId>, VaultError<P::Error>> {
+        let vault = self.vaults.get(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        let revoked = vault.list_revoked(key)?;
+        Ok(revoked)
+    }
+
+    /// List the [`RecordId`]s of all revoked records in the [`Vault`] together with the time of their revocation in
+    /// seconds since the unix epoch.
+    pub fn list_revocations(&self, key: &Key<P>, vid: VaultId) -> Result<Vec<(RecordId, u64)>, VaultError<P::Error>> {
+        let vault = self.vaults.get(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        let revocations = vault.list_revocations(key)?;
+        Ok(revocations)
+    }
+
     /// Garbage collect a [`Vault`]. Deletes any records that contain revocation transactions.
     pub fn garbage_collect_vault(&mut self, key: &Key<P>, vid: VaultId) {
         if let Some(vault) = self.vaults.get_mut(&vid) {
@@ -229,6 +446,123 @@ impl<P: BoxProvider> DbView<P> {
         }
     }
 
+    /// Garbage collect a [`Vault`], but only delete records that have been revoked before `revoked_before`.
+    /// The revocation time is tracked with a precision of seconds.
+    pub fn garbage_collect_vault_revoked_before(
+        &mut self,
+        key: &Key<P>,
+        vid: VaultId,
+        revoked_before: SystemTime,
+    ) -> Result<(), RecordError<P::Error>> {
+        if let Some(vault) = self.vaults.get_mut(&vid) {
+            vault.garbage_collect_revoked_before(key, unix_timestamp(revoked_before))?;
+        }
+        Ok(())
+    }
+
+    /// Sets the time after which the [`Record`] expires. An expired [`Record`] can not be used anymore and is
+    /// removed with [`DbView::garbage_collect_vault_expired`]. `None` removes the expiry.
+    pub fn set_record_expiry(
+        &mut self,
+        key: &Key<P>,
+        vid: VaultId,
+        rid: RecordId,
+        expires_at: Option<SystemTime>,
+    ) -> Result<(), VaultError<P::Error>> {
+        let vault = self.vaults.get_mut(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        // never store zero for an existing expiry, since it is interpreted as "no expiry".
+        let expires_at = expires_at.map(|t| unix_timestamp(t).max(1)).unwrap_or_default();
+        vault.set_expiry(key, rid.0, expires_at)?;
+        Ok(())
+    }
+
+    /// Revokes and garbage collects the records of a [`Vault`] that have expired at `now`.
+    pub fn garbage_collect_vault_expired(
+        &mut self,
+        key: &Key<P>,
+        vid: VaultId,
+        now: SystemTime,
+    ) -> Result<(), RecordError<P::Error>> {
+        if let Some(vault) = self.vaults.get_mut(&vid) {
+            vault.garbage_collect_expired(key, unix_timestamp(now))?;
+        }
+        Ok(())
+    }
+
+    /// Gets the [`RecordUsage`] of a [`Record`].
+    pub fn get_record_usage(
+        &self,
+        key: &Key<P>,
+        vid: VaultId,
+        rid: RecordId,
+    ) -> Result<RecordUsage, VaultError<P::Error>> {
+        let vault = self.vaults.get(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        Ok(vault.get_usage(key, rid.0)?)
+    }
+
+    /// Sets the [`RecordUsage`] of a [`Record`]. The usage restrictions are kept if the record is
+    /// updated, moved or synchronized.
+    pub fn set_record_usage(
+        &mut self,
+        key: &Key<P>,
+        vid: VaultId,
+        rid: RecordId,
+        usage: RecordUsage,
+    ) -> Result<(), VaultError<P::Error>> {
+        let vault = self.vaults.get_mut(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        vault.set_usage(key, rid.0, usage)?;
+        Ok(())
+    }
+
+    /// Enforces the [`RecordUsage`] of the specified records for `operation` and counts a use of each of them.
+    /// Either all records may be used and the use is counted for all of them, or an error is returned and
+    /// none of the records is modified.
+    pub fn use_records<E, const N: usize>(
+        &mut self,
+        ids: &[(Key<P>, VaultId, RecordId); N],
+        operation: u64,
+    ) -> Result<(), VaultError<P::Error, E>>
+    where
+        E: Debug,
+    {
+        self.check_records(ids, operation)?;
+        self.consume_uses(ids)
+    }
+
+    /// Enforces the [`RecordUsage`] of the specified records for `operation`, without counting a use. Returns
+    /// `true` if any of the records has a limited number of uses, which then has to be counted with
+    /// [`DbView::consume_uses`] once the records have been used.
+    pub fn check_records<E, const N: usize>(
+        &self,
+        ids: &[(Key<P>, VaultId, RecordId); N],
+        operation: u64,
+    ) -> Result<bool, VaultError<P::Error, E>>
+    where
+        E: Debug,
+    {
+        let mut limited = false;
+        for (key, vid, rid) in ids {
+            let vault = self.vaults.get(vid).ok_or(VaultError::VaultNotFound(*vid))?;
+            limited |= vault.check_usage(key, rid.0, operation).map_err(VaultError::Record)?;
+        }
+        Ok(limited)
+    }
+
+    /// Counts a use of each of the specified records that has a limited number of uses.
+    pub fn consume_uses<E, const N: usize>(
+        &mut self,
+        ids: &[(Key<P>, VaultId, RecordId); N],
+    ) -> Result<(), VaultError<P::Error, E>>
+    where
+        E: Debug,
+    {
+        for (key, vid, rid) in ids {
+            let vault = self.vaults.get_mut(vid).ok_or(VaultError::VaultNotFound(*vid))?;
+            vault.consume_use(key, rid.0).map_err(VaultError::Record)?;
+        }
+        Ok(())
+    }
+
     /// Clears the entire [`Vault`] from memory.
     pub fn clear(&mut self) {
         self.vaults.clear();
@@ -259,6 +593,69 @@ impl<P: BoxProvider> DbView<P> {
             .and_then(|v| v.list_entries(key).map_err(|e| e.into()))
     }
 
+    /// List [`RecordId`] and [`RecordMetadata`] of all entries in the vault that have not been revoked.
+    pub fn list_records_with_metadata(
+        &self,
+        key: &Key<P>,
+        vid: VaultId,
+    ) -> Result<Vec<(RecordId, RecordMetadata)>, VaultError<P::Error>> {
+        let vault = self.vaults.get(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        Ok(vault.list_metadata(key)?)
+    }
+
+    /// Get the [`RecordMetadata`] of a [`Record`].
+    pub fn get_record_metadata(
+        &self,
+        key: &Key<P>,
+        vid: VaultId,
+        rid: RecordId,
+    ) -> Result<RecordMetadata, VaultError<P::Error>> {
+        let vault = self.vaults.get(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        Ok(vault.get_metadata(key, rid.0)?)
+    }
+
+    /// Get the current [`VaultVersion`] of a [`Vault`].
+    pub fn vault_version(&self, vid: VaultId) -> Option<VaultVersion> {
+        self.vaults.get(&vid).map(|v| v.version())
+    }
+
+    /// Copies the [`DbView`] together with the history of changes of each [`Vault`], see
+    /// [`Vault::checkpoint`].
+    pub fn checkpoint(&self) -> DbView<P> {
+        let vaults = self.vaults.iter().map(|(vid, v)| (*vid, v.checkpoint())).collect();
+        Self { vaults }
+    }
+
+    /// Get the history of changes of each [`Vault`]. The histories are not part of the serialized
+    /// [`DbView`], they have to be persisted separately and put back with [`DbView::restore_histories`]
+    /// to keep the [`VaultVersion`]s.
+    pub fn histories(&self) -> HashMap<VaultId, ChangeLog> {
+        self.vaults.iter().map(|(vid, v)| (*vid, v.changes.copy())).collect()
+    }
+
+    /// Put back the histories of changes that have been taken with [`DbView::histories`]. Histories of vaults
+    /// that don't exist are ignored.
+    pub fn restore_histories(&mut self, histories: HashMap<VaultId, ChangeLog>) {
+        for (vid, changes) in histories {
+            if let Some(vault) = self.vaults.get_mut(&vid) {
+                vault.changes = changes;
+            }
+        }
+    }
+
+    /// List [`RecordId`] and [`RecordMetadata`] of the entries in the vault that have been added or updated
+    /// after `since` and that have not been revoked. If `since` is not a version of the vault's current history,
+    /// all entries are listed.
+    pub fn list_records_changed_since(
+        &self,
+        key: &Key<P>,
+        vid: VaultId,
+        since: &VaultVersion,
+    ) -> Result<Vec<(RecordId, RecordMetadata)>, VaultError<P::Error>> {
+        let vault = self.vaults.get(&vid).ok_or(VaultError::VaultNotFound(vid))?;
+        Ok(vault.list_metadata_since(key, since)?)
+    }
+
     /// Clone the all records from all vaults without removing them.
     pub fn export_all(&self) -> HashMap<VaultId, Vec<(RecordId, Record)>> {
         self.vaults
@@ -287,7 +684,8 @@ impl<P: BoxProvider> DbView<P> {
     }
 
     /// Import records to the [`Vault`]. In case of duplicated records, the existing record is dropped in favor of the
-    /// new one. Re-encrypt the records with the new key.
+    /// new one. Re-encrypt the records with the new key and store them under the given [`RecordId`], which may differ
+    /// from the id that the record had in its source vault.
     pub fn import_records(
         &mut self,
         old_key: &Key<P>,
@@ -299,9 +697,7 @@ impl<P: BoxProvider> DbView<P> {
             self.init_vault(new_key, vid);
         }
         for (rid, record) in &mut records {
-            record
-                .update_meta(old_key, (*rid).into(), new_key, (*rid).into())
-                .unwrap();
+            record.update_meta(old_key, record.id, new_key, (*rid).into()).unwrap();
         }
         let vault = self.vaults.get_mut(&vid).expect("Vault was initiated.");
         vault.extend(new_key, records.into_iter().map(|(rid, r)| (rid.0, r)))
@@ -316,6 +712,7 @@ impl<P: BoxProvider> Vault<P> {
         Self {
             entries,
             key: key.clone(),
+            changes: ChangeLog::default(),
         }
     }
 
@@ -327,16 +724,32 @@ impl<P: BoxProvider> Vault<P> {
         id: ChainId,
         data: &[u8],
         record_hint: RecordHint,
+    ) -> Result<(), RecordError<P::Error>> {
+        self.write_record(key, id, data, record_hint, None)
+    }
+
+    /// Adds or updates a record, and replaces its expiry if `expires_at` is given.
+    fn write_record(
+        &mut self,
+        key: &Key<P>,
+        id: ChainId,
+        data: &[u8],
+        record_hint: RecordHint,
+        expires_at: Option<u64>,
     ) -> Result<(), RecordError<P::Error>> {
         self.check_key(key)?;
         let blob_id = BlobId::random::<P>().map_err(RecordError::Provider)?;
         if let Some(entry) = self.entries.get_mut(&id) {
             // TODO: double-check that using a new blob-id does not break the old snapshot format.
-            entry.update_data(key, id, data, blob_id)?
+            entry.update_data(key, id, data, blob_id, expires_at)?
         } else {
-            let entry = Record::new(key, id, blob_id, data, record_hint).map_err(RecordError::Provider)?;
+            let mut entry = Record::new(key, id, blob_id, data, record_hint).map_err(RecordError::Provider)?;
+            if let Some(expires_at) = expires_at {
+                entry.set_expires_at(key, id, expires_at)?;
+            }
             self.entries.insert(id, entry);
         }
+        self.changes.record(id);
 
         Ok(())
     }
@@ -348,7 +761,10 @@ impl<P: BoxProvider> Vault<P> {
         I: IntoIterator<Item = (ChainId, Record)>,
     {
         self.check_key(key)?;
-        self.entries.extend(entries.into_iter());
+        for (id, record) in entries {
+            self.entries.insert(id, record);
+            self.changes.record(id);
+        }
         Ok(())
     }
 
@@ -383,6 +799,62 @@ impl<P: BoxProvider> Vault<P> {
         Ok(buf)
     }
 
+    /// List the [`RecordId`]s and [`RecordMetadata`] of the entries stored in this [`Vault`].
+    fn list_metadata(&self, key: &Key<P>) -> Result<Vec<(RecordId, RecordMetadata)>, RecordError<P::Error>> {
+        self.check_key(key)?;
+        let mut buf = Vec::new();
+        for (&id, record) in self.entries.iter() {
+            if record.revoke.is_some() {
+                continue;
+            }
+            buf.push((id.into(), record.get_metadata(key)?))
+        }
+        Ok(buf)
+    }
+
+    /// List the [`RecordId`]s and [`RecordMetadata`] of the entries that changed after `since`. Falls back to
+    /// all entries if `since` is not a version of the current history.
+    fn list_metadata_since(
+        &self,
+        key: &Key<P>,
+        since: &VaultVersion,
+    ) -> Result<Vec<(RecordId, RecordMetadata)>, RecordError<P::Error>> {
+        if !self.changes.contains(since) {
+            return self.list_metadata(key);
+        }
+        self.check_key(key)?;
+        let mut buf = Vec::new();
+        for id in self.changes.changed_since(since.counter) {
+            match self.entries.get(&id) {
+                Some(record) if record.revoke.is_none() => buf.push((id.into(), record.get_metadata(key)?)),
+                _ => {}
+            }
+        }
+        Ok(buf)
+    }
+
+    /// Gets the current [`VaultVersion`] of the [`Vault`].
+    pub fn version(&self) -> VaultVersion {
+        self.changes.version()
+    }
+
+    /// Copies the [`Vault`] together with its history of changes, so that it can be put back to undo
+    /// later changes without affecting its [`VaultVersion`]. A [`Clone`] starts a new history instead.
+    pub fn checkpoint(&self) -> Vault<P> {
+        Vault {
+            key: self.key.clone(),
+            entries: self.entries.clone(),
+            changes: self.changes.copy(),
+        }
+    }
+
+    /// Gets the [`RecordMetadata`] of the record with the given [`ChainId`].
+    pub fn get_metadata(&self, key: &Key<P>, id: ChainId) -> Result<RecordMetadata, RecordError<P::Error>> {
+        self.check_key(key)?;
+        let entry = self.entries.get(&id).ok_or(RecordError::RecordNotFound(id))?;
+        entry.get_metadata(key)
+    }
+
     /// Check if the [`Vault`] contains a [`Record`].
     fn contains_record(&self, rid: RecordId) -> bool {
         self.entries.values().any(|entry| entry.check_id(rid))
@@ -397,6 +869,39 @@ impl<P: BoxProvider> Vault<P> {
         Ok(())
     }
 
+    /// Removes the revocation of a [`Record`] by its [`ChainId`].
+    pub fn restore(&mut self, key: &Key<P>, id: ChainId) -> Result<(), RecordError<P::Error>> {
+        self.check_key(key)?;
+        let entry = self.entries.get_mut(&id).ok_or(RecordError::RecordNotFound(id))?;
+        entry.restore(key, id)?;
+        self.changes.record(id);
+        Ok(())
+    }
+
+    /// List the [`RecordId`]s of the revoked entries stored in this [`Vault`].
+    fn list_revoked(&self, key: &Key<P>) -> Result<Vec<RecordId>, RecordError<P::Error>> {
+        self.check_key(key)?;
+        let revoked = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.revoke.is_some())
+            .map(|(&id, _)| id.into())
+            .collect();
+        Ok(revoked)
+    }
+
+    /// List the [`RecordId`]s of the revoked entries stored in this [`Vault`] with the time of their revocation.
+    fn list_revocations(&self, key: &Key<P>) -> Result<Vec<(RecordId, u64)>, RecordError<P::Error>> {
+        self.check_key(key)?;
+        let mut buf = Vec::new();
+        for (&id, entry) in self.entries.iter() {
+            if let Some(revoked_at) = entry.get_revoked_at(key)? {
+                buf.push((id.into(), revoked_at));
+            }
+        }
+        Ok(buf)
+    }
+
     /// Gets the decrypted [`Buffer`] from the [`Record`]
     pub fn get_guard(&self, key: &Key<P>, id: ChainId) -> Result<Buffer<u8>, RecordError<P::Error>> {
         self.check_key(key)?;
@@ -415,9 +920,35 @@ impl<P: BoxProvider> Vault<P> {
             .collect();
 
         // remove the garbage entries from the database.
-        garbage.iter().for_each(|c| {
-            self.entries.remove(c);
-        });
+        self.remove_entries(&garbage);
+    }
+
+    /// Removes the entries from the [`Vault`] and forgets their changes.
+    fn remove_entries(&mut self, ids: &[ChainId]) {
+        for id in ids {
+            self.entries.remove(id);
+            self.changes.forget(id);
+        }
+    }
+
+    /// Garbage collects the revoked entries whose revocation timestamp is not newer than `revoked_before`
+    /// (in seconds since the unix epoch).
+    pub fn garbage_collect_revoked_before(
+        &mut self,
+        key: &Key<P>,
+        revoked_before: u64,
+    ) -> Result<(), RecordError<P::Error>> {
+        self.check_key(key)?;
+        let mut garbage = Vec::new();
+        for (id, entry) in self.entries.iter() {
+            if let Some(revoked_at) = entry.get_revoked_at(key)? {
+                if revoked_at <= revoked_before {
+                    garbage.push(*id);
+                }
+            }
+        }
+        self.remove_entries(&garbage);
+        Ok(())
     }
 
     /// Gets the [`BlobId`] of the record with the given [`ChainId`].
@@ -429,6 +960,60 @@ impl<P: BoxProvider> Vault<P> {
             .and_then(|r| r.get_blob_id(key, id))
     }
 
+    /// Sets the time of expiry of the [`Record`] in seconds since the unix epoch. Zero removes the expiry.
+    pub fn set_expiry(&mut self, key: &Key<P>, id: ChainId, expires_at: u64) -> Result<(), RecordError<P::Error>> {
+        self.check_key(key)?;
+        let entry = self.entries.get_mut(&id).ok_or(RecordError::RecordNotFound(id))?;
+        entry.set_expires_at(key, id, expires_at)
+    }
+
+    /// Gets the [`RecordUsage`] of the [`Record`].
+    pub fn get_usage(&self, key: &Key<P>, id: ChainId) -> Result<RecordUsage, RecordError<P::Error>> {
+        self.check_key(key)?;
+        let entry = self.entries.get(&id).ok_or(RecordError::RecordNotFound(id))?;
+        entry.get_usage(key)
+    }
+
+    /// Sets the [`RecordUsage`] of the [`Record`].
+    pub fn set_usage(&mut self, key: &Key<P>, id: ChainId, usage: RecordUsage) -> Result<(), RecordError<P::Error>> {
+        self.check_key(key)?;
+        let entry = self.entries.get_mut(&id).ok_or(RecordError::RecordNotFound(id))?;
+        entry.set_usage(key, id, usage)
+    }
+
+    /// Checks if the [`Record`] may be used for `operation`, and returns whether its number of uses is limited.
+    fn check_usage(&self, key: &Key<P>, id: ChainId, operation: u64) -> Result<bool, RecordError<P::Error>> {
+        self.check_key(key)?;
+        let entry = self.entries.get(&id).ok_or(RecordError::RecordNotFound(id))?;
+        entry.check_usage(key, operation)
+    }
+
+    /// Counts a use of the [`Record`].
+    fn consume_use(&mut self, key: &Key<P>, id: ChainId) -> Result<(), RecordError<P::Error>> {
+        self.check_key(key)?;
+        let entry = self.entries.get_mut(&id).ok_or(RecordError::RecordNotFound(id))?;
+        entry.consume_use(key, id)
+    }
+
+    /// Revokes and garbage collects all entries that have expired at `now` (in seconds since the unix epoch).
+    /// Entries that have already been revoked are left to the regular garbage collection.
+    pub fn garbage_collect_expired(&mut self, key: &Key<P>, now: u64) -> Result<(), RecordError<P::Error>> {
+        self.check_key(key)?;
+        let mut garbage = Vec::new();
+        for (id, entry) in self.entries.iter() {
+            if entry.revoke.is_some() {
+                continue;
+            }
+            if let Some(expires_at) = entry.get_expires_at(key)? {
+                if is_expired(expires_at, now) {
+                    garbage.push(*id);
+                }
+            }
+        }
+        self.remove_entries(&garbage);
+        Ok(())
+    }
+
     fn check_key(&self, key: &Key<P>) -> Result<(), RecordError<P::Error>> {
         if key == &self.key {
             Ok(())
@@ -438,6 +1023,71 @@ impl<P: BoxProvider> Vault<P> {
     }
 }
 
+impl ChangeLog {
+    /// Counts a change of the record.
+    fn record(&mut self, id: ChainId) {
+        self.counter += 1;
+        if let Some(previous) = self.changed_at.insert(id, self.counter) {
+            self.changes.remove(&previous);
+        }
+        self.changes.insert(self.counter, id);
+    }
+
+    /// Forgets the changes of a removed record.
+    fn forget(&mut self, id: &ChainId) {
+        if let Some(previous) = self.changed_at.remove(id) {
+            self.changes.remove(&previous);
+        }
+    }
+
+    /// Copies the log without starting a new history, unlike [`Clone`].
+    fn copy(&self) -> ChangeLog {
+        ChangeLog {
+            replica: self.replica,
+            counter: self.counter,
+            changed_at: self.changed_at.clone(),
+            changes: self.changes.clone(),
+        }
+    }
+
+    fn version(&self) -> VaultVersion {
+        VaultVersion {
+            replica: self.replica,
+            counter: self.counter,
+        }
+    }
+
+    /// Check if `version` is part of this history.
+    fn contains(&self, version: &VaultVersion) -> bool {
+        version.replica == self.replica && version.counter <= self.counter
+    }
+
+    /// List the records whose last change happened after `counter`.
+    fn changed_since(&self, counter: u64) -> impl Iterator<Item = ChainId> + '_ {
+        self.changes.range(counter.saturating_add(1)..).map(|(_, &id)| id)
+    }
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        let mut buf = [0u8; 8];
+        rand::fill(&mut buf).expect("Unable to fill buffer");
+        ChangeLog {
+            replica: u64::from_le_bytes(buf),
+            counter: 0,
+            changed_at: HashMap::new(),
+            changes: BTreeMap::new(),
+        }
+    }
+}
+
+// A clone may diverge from the original, therefore it starts a new history.
+impl Clone for ChangeLog {
+    fn clone(&self) -> Self {
+        ChangeLog::default()
+    }
+}
+
 impl Record {
     // create a new [`Record`].
     pub fn new<P: BoxProvider>(
@@ -448,7 +1098,9 @@ impl Record {
         hint: RecordHint,
     ) -> Result<Record, P::Error> {
         let len = data.len() as u64;
-        let dtx = DataTransaction::new(id, len, blob, hint);
+        let mut dtx = DataTransaction::new(id, len, blob, hint, 0u64);
+        let view: &mut DataTransaction = dtx.view_mut();
+        view.modified_at = unix_timestamp(SystemTime::now()).into();
 
         let blob: SealedBlob = data.encrypt(key, blob)?;
         let data = dtx.encrypt(key, id)?;
@@ -508,6 +1160,10 @@ impl Record {
             RecordError::CorruptedContent("Could not type decrypted transaction as data-transaction".into())
         })?;
 
+        if is_expired(tx.expires_at.u64(), unix_timestamp(SystemTime::now())) {
+            return Err(RecordError::RecordExpired(id));
+        }
+
         let blob = SealedBlob::from(self.blob.as_ref())
             .decrypt(key, tx.blob)
             .expect("Unable to decrypt blob");
@@ -546,28 +1202,145 @@ impl Record {
         id: ChainId,
         new_data: &[u8],
         new_blob: BlobId,
+        expires_at: Option<u64>,
     ) -> Result<(), RecordError<P::Error>> {
+        // create a new sealed blob with the new_data.
+        let blob: SealedBlob = new_data.encrypt(key, new_blob).map_err(RecordError::Provider)?;
+
+        // update the sealed transaction with the new_data length, and the expiry if given.
+        self.update_data_transaction(key, id, |tx| {
+            tx.len = (new_data.len() as u64).into();
+            tx.blob = new_blob;
+            tx.modified_at = unix_timestamp(SystemTime::now()).into();
+            if let Some(expires_at) = expires_at {
+                tx.expires_at = expires_at.into();
+            }
+        })?;
+        self.blob = blob;
+
+        Ok(())
+    }
+
+    /// Get the decrypted data transaction of the [`Record`].
+    fn get_data_transaction<P: BoxProvider>(&self, key: &Key<P>) -> Result<Transaction, RecordError<P::Error>> {
+        let tx = self.get_transaction(key)?;
+        if tx.typed::<DataTransaction>().is_none() {
+            return Err(RecordError::CorruptedContent(
+                "Could not type decrypted transaction as data-transaction".into(),
+            ));
+        }
+        Ok(tx)
+    }
+
+    /// Apply `f` to the data transaction of an existing [`Record`] and re-encrypt it.
+    fn update_data_transaction<P, F>(&mut self, key: &Key<P>, id: ChainId, f: F) -> Result<(), RecordError<P::Error>>
+    where
+        P: BoxProvider,
+        F: FnOnce(&mut DataTransaction),
+    {
         // check if ids match
         if self.id != id {
             return Err(RecordError::RecordNotFound(id));
         }
 
-        let tx = self.get_transaction(key)?;
-        let tx = tx.typed::<DataTransaction>().ok_or_else(|| {
-            RecordError::CorruptedContent("Could not type decrypted transaction as data-transaction".into())
-        })?;
+        let mut tx = self.get_data_transaction(key)?;
+        f(tx.view_mut());
+        self.data = tx.encrypt(key, id).map_err(RecordError::Provider)?;
 
-        // create a new sealed blob with the new_data.
-        let blob: SealedBlob = new_data.encrypt(key, new_blob).map_err(RecordError::Provider)?;
+        Ok(())
+    }
 
-        // create a new sealed transaction with the new_data length.
-        let dtx = DataTransaction::new(tx.id, new_data.len() as u64, new_blob, tx.record_hint);
-        let data = dtx.encrypt(key, tx.id).map_err(RecordError::Provider)?;
+    /// Get the time of expiry in seconds since the unix epoch, or `None` if the [`Record`] does not expire.
+    fn get_expires_at<P: BoxProvider>(&self, key: &Key<P>) -> Result<Option<u64>, RecordError<P::Error>> {
+        let tx = self.get_data_transaction(key)?;
+        let tx: &DataTransaction = tx.view();
+        match tx.expires_at.u64() {
+            0 => Ok(None),
+            expires_at => Ok(Some(expires_at)),
+        }
+    }
 
-        self.blob = blob;
-        self.data = data;
+    /// Get the [`RecordMetadata`] of the [`Record`].
+    fn get_metadata<P: BoxProvider>(&self, key: &Key<P>) -> Result<RecordMetadata, RecordError<P::Error>> {
+        let tx = self.get_data_transaction(key)?;
+        let tx: &DataTransaction = tx.view();
+        let expires_at = match tx.expires_at.u64() {
+            0 => None,
+            expires_at => Some(expires_at),
+        };
+        Ok(RecordMetadata {
+            blob_id: tx.blob,
+            hint: tx.record_hint,
+            len: tx.len.u64(),
+            modified_at: tx.modified_at.u64(),
+            expires_at,
+        })
+    }
 
-        Ok(())
+    /// Set the time of expiry of an existing [`Record`] in seconds since the unix epoch. Zero removes the expiry.
+    fn set_expires_at<P: BoxProvider>(
+        &mut self,
+        key: &Key<P>,
+        id: ChainId,
+        expires_at: u64,
+    ) -> Result<(), RecordError<P::Error>> {
+        self.update_data_transaction(key, id, |tx| tx.expires_at = expires_at.into())
+    }
+
+    /// Get the [`RecordUsage`] of the [`Record`].
+    fn get_usage<P: BoxProvider>(&self, key: &Key<P>) -> Result<RecordUsage, RecordError<P::Error>> {
+        let tx = self.get_data_transaction(key)?;
+        let tx: &DataTransaction = tx.view();
+        let remaining_uses = match tx.remaining_uses.u64() {
+            0 => None,
+            n => Some(n - 1),
+        };
+        Ok(RecordUsage {
+            remaining_uses,
+            allowed_operations: tx.allowed_operations.u64(),
+        })
+    }
+
+    /// Set the [`RecordUsage`] of an existing [`Record`].
+    fn set_usage<P: BoxProvider>(
+        &mut self,
+        key: &Key<P>,
+        id: ChainId,
+        usage: RecordUsage,
+    ) -> Result<(), RecordError<P::Error>> {
+        let remaining_uses = usage.remaining_uses.map(|n| n.saturating_add(1)).unwrap_or_default();
+        self.update_data_transaction(key, id, |tx| {
+            tx.remaining_uses = remaining_uses.into();
+            tx.allowed_operations = usage.allowed_operations.into();
+        })
+    }
+
+    /// Check if the [`Record`] may be used for `operation`.
+    fn check_usage<P: BoxProvider>(&self, key: &Key<P>, operation: u64) -> Result<bool, RecordError<P::Error>> {
+        let tx = self.get_data_transaction(key)?;
+        let tx: &DataTransaction = tx.view();
+        if is_expired(tx.expires_at.u64(), unix_timestamp(SystemTime::now())) {
+            return Err(RecordError::RecordExpired(self.id));
+        }
+        let usage = self.get_usage(key)?;
+        if !usage.allows(operation) {
+            return Err(RecordError::OperationNotAllowed(self.id));
+        }
+        if usage.remaining_uses == Some(0) {
+            return Err(RecordError::UsageLimitReached(self.id));
+        }
+        Ok(usage.remaining_uses.is_some())
+    }
+
+    /// Decrement the number of remaining uses of the [`Record`], if its usage is limited.
+    fn consume_use<P: BoxProvider>(&mut self, key: &Key<P>, id: ChainId) -> Result<(), RecordError<P::Error>> {
+        if self.get_usage(key)?.remaining_uses.is_none() {
+            return Ok(());
+        }
+        self.update_data_transaction(key, id, |tx| {
+            let remaining_uses = tx.remaining_uses.u64().saturating_sub(1).max(1);
+            tx.remaining_uses = remaining_uses.into();
+        })
     }
 
     /// Update the key and id of an existing [`Record`].
@@ -583,25 +1356,23 @@ impl Record {
             return Err(RecordError::RecordNotFound(old_id));
         }
 
-        let tx = self.get_transaction(old_key)?;
-        let typed_tx = tx.typed::<DataTransaction>().ok_or_else(|| {
-            RecordError::CorruptedContent("Could not type decrypted transaction as data-transaction".into())
-        })?;
+        let mut tx = self.get_data_transaction(old_key)?;
+        let typed_tx: &mut DataTransaction = tx.view_mut();
+        typed_tx.id = new_id;
+        let blob_id = typed_tx.blob;
 
         // Re-encrypt the blob with the new key.
         let updated_blob = SealedBlob::from(self.blob.as_ref())
-            .decrypt(old_key, typed_tx.blob)
+            .decrypt(old_key, blob_id)
             .map_err(|e| match e {
                 DecryptError::Provider(e) => RecordError::Provider(e),
                 DecryptError::Invalid => unreachable!("Vec<u8>: TryFrom<Vec<u8>> is infallible."),
             })?
-            .encrypt(new_key, typed_tx.blob)
+            .encrypt(new_key, blob_id)
             .map_err(RecordError::Provider)?;
 
         // Re-encrypt meta data with new key.
-        let updated_data = DataTransaction::new(new_id, typed_tx.len, typed_tx.blob, typed_tx.record_hint)
-            .encrypt(new_key, new_id)
-            .map_err(RecordError::Provider)?;
+        let updated_data = tx.encrypt(new_key, new_id).map_err(RecordError::Provider)?;
 
         self.blob = updated_blob;
         self.data = updated_data;
@@ -619,7 +1390,7 @@ impl Record {
 
         // check if revoke transaction already exists.
         if self.revoke.is_none() {
-            let revoke = RevocationTransaction::new(self.id)
+            let revoke = RevocationTransaction::new(self.id, unix_timestamp(SystemTime::now()))
                 .encrypt(key, self.id)
                 .map_err(RecordError::Provider)?;
             self.revoke = Some(revoke);
@@ -627,4 +1398,48 @@ impl Record {
 
         Ok(())
     }
+
+    // remove the revocation transaction from the [`Record`].
+    fn restore<P: BoxProvider>(&mut self, key: &Key<P>, id: ChainId) -> Result<(), RecordError<P::Error>> {
+        // check if id and id match.
+        if self.id != id {
+            return Err(RecordError::RecordNotFound(id));
+        }
+
+        // only the owner of the key may undo the revocation.
+        if self.get_revoked_at(key)?.is_none() {
+            return Err(RecordError::NotRevoked(id));
+        }
+        self.revoke = None;
+
+        Ok(())
+    }
+
+    /// Get the time of the revocation in seconds since the unix epoch, or `None` if the [`Record`] was not revoked.
+    fn get_revoked_at<P: BoxProvider>(&self, key: &Key<P>) -> Result<Option<u64>, RecordError<P::Error>> {
+        let revoke = match self.revoke.as_ref() {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        let tx: Transaction = revoke.decrypt(key, self.id).map_err(|err| match err {
+            DecryptError::Invalid => {
+                RecordError::CorruptedContent("Could not convert bytes into transaction structure".into())
+            }
+            DecryptError::Provider(e) => RecordError::Provider(e),
+        })?;
+        let tx = tx.typed::<RevocationTransaction>().ok_or_else(|| {
+            RecordError::CorruptedContent("Could not type decrypted transaction as revocation-transaction".into())
+        })?;
+        Ok(Some(tx.revoked_at.u64()))
+    }
+}
+
+/// Checks if the expiry timestamp `expires_at` has been reached at `now`. Zero is never expired.
+fn is_expired(expires_at: u64, now: u64) -> bool {
+    expires_at != 0 && expires_at <= now
+}
+
+/// Converts the [`SystemTime`] into seconds since the unix epoch.
+pub(crate) fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
 }
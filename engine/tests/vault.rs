@@ -2,11 +2,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod utils;
-use std::convert::Infallible;
+use std::{
+    convert::Infallible,
+    time::{Duration, SystemTime},
+};
 
 use utils::provider::Provider;
 
-use engine::vault::{DbView, Key, RecordHint, RecordId, VaultId};
+use engine::vault::{DbView, Key, RecordError, RecordHint, RecordId, RecordUsage, VaultError, VaultId, VaultVersion};
 
 #[test]
 fn test_vaults() {
@@ -140,3 +143,367 @@ fn test_vaults() {
     })
     .unwrap();
 }
+
+#[test]
+fn test_restore_revoked_record() {
+    let mut view: DbView<Provider> = DbView::new();
+
+    let key0 = Key::random();
+    let vid0 = VaultId::random::<Provider>().unwrap();
+    let rid0 = RecordId::random::<Provider>().unwrap();
+    let rid01 = RecordId::random::<Provider>().unwrap();
+
+    view.write(&key0, vid0, rid0, b"test0", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+    view.write(&key0, vid0, rid01, b"test01", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+
+    // restoring a record that was not revoked fails
+    assert!(view.restore_record(&key0, vid0, rid0).is_err());
+
+    view.revoke_record(&key0, vid0, rid0).unwrap();
+    view.revoke_record(&key0, vid0, rid01).unwrap();
+    assert!(!view.contains_record(vid0, rid0));
+
+    let mut revoked = view.list_revoked_records(&key0, vid0).unwrap();
+    revoked.sort();
+    let mut expected = vec![rid0, rid01];
+    expected.sort();
+    assert_eq!(revoked, expected);
+
+    // restoring with the wrong key fails
+    assert!(view.restore_record(&Key::random(), vid0, rid0).is_err());
+
+    view.restore_record(&key0, vid0, rid0).unwrap();
+    assert!(view.contains_record(vid0, rid0));
+    assert_eq!(view.list_revoked_records(&key0, vid0).unwrap(), vec![rid01]);
+
+    view.get_guard::<Infallible, _>(&key0, vid0, rid0, |g| {
+        assert_eq!(b"test0", &(*g.borrow()));
+
+        Ok(())
+    })
+    .unwrap();
+
+    // records revoked after the given time are kept
+    let earlier = SystemTime::now() - Duration::from_secs(60);
+    view.garbage_collect_vault_revoked_before(&key0, vid0, earlier).unwrap();
+    assert_eq!(view.list_revoked_records(&key0, vid0).unwrap(), vec![rid01]);
+
+    let later = SystemTime::now() + Duration::from_secs(60);
+    view.garbage_collect_vault_revoked_before(&key0, vid0, later).unwrap();
+    assert!(view.list_revoked_records(&key0, vid0).unwrap().is_empty());
+
+    // garbage collected records can not be restored anymore
+    assert!(view.restore_record(&key0, vid0, rid01).is_err());
+    assert!(view.contains_record(vid0, rid0));
+}
+
+#[test]
+fn test_move_record_and_rename_vault() {
+    let mut view: DbView<Provider> = DbView::new();
+
+    let key0 = Key::random();
+    let vid0 = VaultId::random::<Provider>().unwrap();
+    let rid0 = RecordId::random::<Provider>().unwrap();
+    let rid01 = RecordId::random::<Provider>().unwrap();
+
+    let key1 = Key::random();
+    let vid1 = VaultId::random::<Provider>().unwrap();
+    let rid1 = RecordId::random::<Provider>().unwrap();
+
+    view.write(&key0, vid0, rid0, b"test0", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+    view.write(&key0, vid0, rid01, b"test01", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+
+    // moving with the wrong key fails and keeps the record in place
+    assert!(view.move_record(&key1, vid0, rid0, &key1, vid1, rid1).is_err());
+    assert!(view.contains_record(vid0, rid0));
+
+    view.move_record(&key0, vid0, rid0, &key1, vid1, rid1).unwrap();
+    assert!(!view.contains_record(vid0, rid0));
+    assert!(view.contains_record(vid1, rid1));
+
+    view.get_guard::<Infallible, _>(&key1, vid1, rid1, |g| {
+        assert_eq!(b"test0", &(*g.borrow()));
+        Ok(())
+    })
+    .unwrap();
+
+    // moving a revoked record fails
+    view.revoke_record(&key0, vid0, rid01).unwrap();
+    assert!(view.move_record(&key0, vid0, rid01, &key1, vid1, rid0).is_err());
+    view.restore_record(&key0, vid0, rid01).unwrap();
+
+    let key2 = Key::random();
+    let vid2 = VaultId::random::<Provider>().unwrap();
+
+    // renaming fails if a record has no id in the target vault
+    assert!(view.rename_vault(&key0, vid0, &key2, vid2, |_| None).is_err());
+    assert!(view.contains_record(vid0, rid01));
+    assert!(!view.contains_vault(&vid2));
+
+    let rid2 = RecordId::random::<Provider>().unwrap();
+    view.rename_vault(&key0, vid0, &key2, vid2, |id| (id == rid01).then_some(rid2))
+        .unwrap();
+    assert!(!view.contains_vault(&vid0));
+    assert!(view.contains_record(vid2, rid2));
+
+    view.get_guard::<Infallible, _>(&key2, vid2, rid2, |g| {
+        assert_eq!(b"test01", &(*g.borrow()));
+        Ok(())
+    })
+    .unwrap();
+
+    // merging into an existing vault with the wrong key fails
+    assert!(view.rename_vault(&key2, vid2, &key2, vid1, Some).is_err());
+    assert!(view.contains_record(vid2, rid2));
+
+    view.rename_vault(&key2, vid2, &key1, vid1, Some).unwrap();
+    assert!(view.contains_record(vid1, rid2));
+    assert!(view.contains_record(vid1, rid1));
+}
+
+#[test]
+fn test_record_expiry() {
+    let mut view: DbView<Provider> = DbView::new();
+
+    let key0 = Key::random();
+    let vid0 = VaultId::random::<Provider>().unwrap();
+    let rid0 = RecordId::random::<Provider>().unwrap();
+    let rid01 = RecordId::random::<Provider>().unwrap();
+    let rid02 = RecordId::random::<Provider>().unwrap();
+
+    view.write(&key0, vid0, rid0, b"test0", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+    view.write(&key0, vid0, rid01, b"test01", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+    view.write(&key0, vid0, rid02, b"test02", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+
+    let past = SystemTime::now() - Duration::from_secs(10);
+    let future = SystemTime::now() + Duration::from_secs(3600);
+    view.set_record_expiry(&key0, vid0, rid0, Some(past)).unwrap();
+    view.set_record_expiry(&key0, vid0, rid01, Some(future)).unwrap();
+
+    // expired records can not be accessed
+    assert!(matches!(
+        view.get_guard::<Infallible, _>(&key0, vid0, rid0, |_| Ok(())),
+        Err(VaultError::Record(RecordError::RecordExpired(_)))
+    ));
+    assert!(view.get_guard::<Infallible, _>(&key0, vid0, rid01, |_| Ok(())).is_ok());
+
+    // the expiry is kept when the record is moved
+    let vid1 = VaultId::random::<Provider>().unwrap();
+    let rid1 = RecordId::random::<Provider>().unwrap();
+    view.move_record(&key0, vid0, rid0, &key0, vid1, rid1).unwrap();
+    assert!(view.get_guard::<Infallible, _>(&key0, vid1, rid1, |_| Ok(())).is_err());
+
+    // revoked records are not affected
+    view.set_record_expiry(&key0, vid0, rid02, Some(past)).unwrap();
+    view.revoke_record(&key0, vid0, rid02).unwrap();
+
+    view.garbage_collect_vault_expired(&key0, vid1, SystemTime::now())
+        .unwrap();
+    view.garbage_collect_vault_expired(&key0, vid0, SystemTime::now())
+        .unwrap();
+    assert!(!view.contains_record(vid1, rid1));
+    assert!(view.contains_record(vid0, rid01));
+    assert_eq!(view.list_revoked_records(&key0, vid0).unwrap(), vec![rid02]);
+
+    view.garbage_collect_vault_expired(&key0, vid0, future).unwrap();
+    assert!(!view.contains_record(vid0, rid01));
+}
+
+#[test]
+fn test_record_usage() {
+    let mut view: DbView<Provider> = DbView::new();
+
+    let key0 = Key::random();
+    let vid0 = VaultId::random::<Provider>().unwrap();
+    let rid0 = RecordId::random::<Provider>().unwrap();
+    let rid01 = RecordId::random::<Provider>().unwrap();
+
+    view.write(&key0, vid0, rid0, b"test0", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+    view.write(&key0, vid0, rid01, b"test01", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+
+    // records are unrestricted by default
+    assert_eq!(
+        view.get_record_usage(&key0, vid0, rid0).unwrap(),
+        RecordUsage::default()
+    );
+    view.use_records::<Infallible, 2>(&[(key0.clone(), vid0, rid0), (key0.clone(), vid0, rid01)], 1)
+        .unwrap();
+
+    let usage = RecordUsage {
+        remaining_uses: Some(1),
+        allowed_operations: 0b01,
+    };
+    view.set_record_usage(&key0, vid0, rid0, usage).unwrap();
+
+    // the usage is kept when the data is updated
+    view.write(&key0, vid0, rid0, b"test1", RecordHint::new(b"hint").unwrap())
+        .unwrap();
+    assert_eq!(view.get_record_usage(&key0, vid0, rid0).unwrap(), usage);
+
+    // operations that are not allowed are rejected for all records without counting a use
+    assert!(matches!(
+        view.use_records::<Infallible, 2>(&[(key0.clone(), vid0, rid01), (key0.clone(), vid0, rid0)], 0b10),
+        Err(VaultError::Record(RecordError::OperationNotAllowed(_)))
+    ));
+    assert_eq!(view.get_record_usage(&key0, vid0, rid0).unwrap(), usage);
+
+    view.use_records::<Infallible, 1>(&[(key0.clone(), vid0, rid0)], 0b01)
+        .unwrap();
+    assert_eq!(
+        view.get_record_usage(&key0, vid0, rid0).unwrap().remaining_uses,
+        Some(0)
+    );
+    assert!(matches!(
+        view.use_records::<Infallible, 1>(&[(key0.clone(), vid0, rid0)], 0b01),
+        Err(VaultError::Record(RecordError::UsageLimitReached(_)))
+    ));
+
+    // the usage is kept when the record is moved
+    let rid1 = RecordId::random::<Provider>().unwrap();
+    view.move_record(&key0, vid0, rid0, &key0, vid0, rid1).unwrap();
+    assert!(view
+        .use_records::<Infallible, 1>(&[(key0.clone(), vid0, rid1)], 0b01)
+        .is_err());
+}
+
+#[test]
+fn test_record_metadata() {
+    let mut view: DbView<Provider> = DbView::new();
+
+    let key0 = Key::random();
+    let vid0 = VaultId::random::<Provider>().unwrap();
+    let rid0 = RecordId::random::<Provider>().unwrap();
+    let hint = RecordHint::new(b"hint").unwrap();
+
+    let before = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    view.write(&key0, vid0, rid0, b"test0", hint).unwrap();
+
+    let meta = view.get_record_metadata(&key0, vid0, rid0).unwrap();
+    assert_eq!(meta.hint, hint);
+    assert_eq!(meta.len, 5);
+    assert_eq!(meta.expires_at, None);
+    assert!(meta.modified_at >= before);
+    assert_eq!(meta.blob_id, view.get_blob_id(&key0, vid0, rid0).unwrap());
+
+    let future = SystemTime::now() + Duration::from_secs(3600);
+    view.set_record_expiry(&key0, vid0, rid0, Some(future)).unwrap();
+    let list = view.list_records_with_metadata(&key0, vid0).unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].0, rid0);
+    assert!(list[0].1.expires_at.is_some());
+
+    // updating the data changes the blob id but keeps the remaining metadata
+    view.write(&key0, vid0, rid0, b"test01", hint).unwrap();
+    let updated = view.get_record_metadata(&key0, vid0, rid0).unwrap();
+    assert_ne!(updated.blob_id, meta.blob_id);
+    assert_eq!(updated.len, 6);
+    assert!(updated.modified_at >= meta.modified_at);
+    assert_eq!(updated.expires_at, list[0].1.expires_at);
+
+    // revoked records are not listed
+    view.revoke_record(&key0, vid0, rid0).unwrap();
+    assert!(view.list_records_with_metadata(&key0, vid0).unwrap().is_empty());
+}
+
+#[test]
+fn test_records_changed_since() {
+    let mut view: DbView<Provider> = DbView::new();
+
+    let key0 = Key::random();
+    let vid0 = VaultId::random::<Provider>().unwrap();
+    let rid0 = RecordId::random::<Provider>().unwrap();
+    let rid1 = RecordId::random::<Provider>().unwrap();
+    let hint = RecordHint::new(b"hint").unwrap();
+
+    assert!(view.vault_version(vid0).is_none());
+    view.write(&key0, vid0, rid0, b"test0", hint).unwrap();
+    view.write(&key0, vid0, rid1, b"test1", hint).unwrap();
+    let v0 = view.vault_version(vid0).unwrap();
+    assert_eq!(v0.counter, 2);
+    assert!(view.list_records_changed_since(&key0, vid0, &v0).unwrap().is_empty());
+
+    // only the updated record is listed
+    view.write(&key0, vid0, rid1, b"test11", hint).unwrap();
+    let changed = view.list_records_changed_since(&key0, vid0, &v0).unwrap();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].0, rid1);
+    assert_eq!(changed[0].1, view.get_record_metadata(&key0, vid0, rid1).unwrap());
+
+    // revoked records are not listed, restored ones are listed again
+    let v1 = view.vault_version(vid0).unwrap();
+    view.revoke_record(&key0, vid0, rid1).unwrap();
+    assert!(view.list_records_changed_since(&key0, vid0, &v0).unwrap().is_empty());
+    view.restore_record(&key0, vid0, rid1).unwrap();
+    let changed = view.list_records_changed_since(&key0, vid0, &v1).unwrap();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].0, rid1);
+
+    // a version of another history lists all records
+    let mut clone = view.clone();
+    assert_ne!(clone.vault_version(vid0).unwrap().replica, v0.replica);
+    assert_eq!(clone.list_records_changed_since(&key0, vid0, &v0).unwrap().len(), 2);
+    let unknown = VaultVersion {
+        replica: v0.replica,
+        counter: v0.counter + 100,
+    };
+    assert_eq!(view.list_records_changed_since(&key0, vid0, &unknown).unwrap().len(), 2);
+
+    // garbage collected records are not listed
+    let v2 = clone.vault_version(vid0).unwrap();
+    clone.write(&key0, vid0, rid0, b"test00", hint).unwrap();
+    clone.revoke_record(&key0, vid0, rid0).unwrap();
+    clone.garbage_collect_vault(&key0, vid0);
+    assert!(clone.list_records_changed_since(&key0, vid0, &v2).unwrap().is_empty());
+
+    // a checkpoint keeps the history
+    let v3 = view.vault_version(vid0).unwrap();
+    let checkpoint = view.vaults[&vid0].checkpoint();
+    view.write(&key0, vid0, rid0, b"test000", hint).unwrap();
+    view.vaults.insert(vid0, checkpoint);
+    assert_eq!(view.vault_version(vid0).unwrap(), v3);
+    assert!(view.list_records_changed_since(&key0, vid0, &v3).unwrap().is_empty());
+    view.get_guard::<Infallible, _>(&key0, vid0, rid0, |g| {
+        assert_eq!(b"test0", &(*g.borrow()));
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_restore_histories() {
+    let mut view: DbView<Provider> = DbView::new();
+
+    let key0 = Key::random();
+    let vid0 = VaultId::random::<Provider>().unwrap();
+    let rid0 = RecordId::random::<Provider>().unwrap();
+    let rid1 = RecordId::random::<Provider>().unwrap();
+    let hint = RecordHint::new(b"hint").unwrap();
+
+    view.write(&key0, vid0, rid0, b"test0", hint).unwrap();
+    let v0 = view.vault_version(vid0).unwrap();
+    view.write(&key0, vid0, rid1, b"test1", hint).unwrap();
+
+    // a copy starts a new history, unless the histories are put back
+    let mut restored = view.clone();
+    assert_ne!(restored.vault_version(vid0), view.vault_version(vid0));
+    restored.restore_histories(view.histories());
+    assert_eq!(restored.vault_version(vid0), view.vault_version(vid0));
+    let changed = restored.list_records_changed_since(&key0, vid0, &v0).unwrap();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].0, rid1);
+
+    // a checkpoint of the view keeps the histories of all vaults
+    assert_eq!(view.checkpoint().vault_version(vid0), view.vault_version(vid0));
+}